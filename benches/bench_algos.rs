@@ -1,7 +1,9 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion};
 use float_ord::FloatOrd;
 use simple_grad::*;
 
+// Left out of the benchmark group below; enable it there to run it
+#[allow(dead_code)]
 fn vec_ops(c: &mut Criterion) {
     let dims = 100;
     let mut embeddings = Vec::new();
@@ -9,7 +11,7 @@ fn vec_ops(c: &mut Criterion) {
     for i in 0..100000 {
         let mut row = Vec::with_capacity(dims);
         for dim in 0..dims {
-            row.push((i*dim) as f32);
+            row.push((i*dim) as DType);
         }
         embeddings.push(Variable::new(row));
     }
@@ -30,7 +32,7 @@ fn bench_attention(c: &mut Criterion) {
     for i in 0..20 {
         let mut row = Vec::with_capacity(dims);
         for dim in 0..dims {
-            row.push((i*dim) as f32);
+            row.push((i*dim) as DType);
         }
         embeddings.push((Variable::new(row), 1));
     }
@@ -62,7 +64,7 @@ pub fn attention_mean<'a>(
     let att = compute_attention_softmax(attention_matrix, attention_dims);
 
     let summed_weights = att.sum_all();
-    let n = items.len() as f32;
+    let n = items.len() as DType;
     items.into_iter().enumerate()
         .map(|(i, (at_i, _c))| at_i.value * summed_weights.slice(i, 1))
         .collect::<Vec<_>>().sum_all() / n
@@ -79,7 +81,7 @@ fn compute_attention_matrix(
     for i in 0..items.len() {
         let (j_start, j_end) = match window {
             Some(size) => {
-                let start = if size > i { 0 } else {i - size };
+                let start = i.saturating_sub(size);
                 let stop = (i + size + 1).min(items.len());
                 (start, stop)
             },
@@ -90,10 +92,10 @@ fn compute_attention_matrix(
         let row = &mut scaled[i];
         for j in j_start..j_end {
             let (at_j, jc) = &items[j];
-            let mut dot_i_j = (&at_i.query).dot(&at_j.key);
+            let mut dot_i_j = at_i.query.dot(&at_j.key);
             let num = ic * jc;
             if num >= 1 && window.is_none() {
-                dot_i_j = dot_i_j * (num as f32);
+                dot_i_j *= num as DType;
             }
             row[j] = dot_i_j;
         }
@@ -107,7 +109,7 @@ fn compute_attention_softmax(
     d_k: usize
 ) -> Vec<ANode> {
     // Compute softmax
-    let d_k = Constant::scalar((d_k as f32).sqrt());
+    let d_k = Constant::scalar((d_k as DType).sqrt());
 
     // Compute softmax for each feature
     let mut att = Vec::with_capacity(attention_matrix.len());
@@ -124,8 +126,9 @@ fn softmax(numers: ANode) -> ANode {
 
     let max_value = numers.value().iter()
         .max_by_key(|v| FloatOrd(**v))
+        .copied()
         .expect("Shouldn't be non-zero!");
-    let mv = Constant::scalar(*max_value);
+    let mv = Constant::scalar(max_value);
     let n = (numers - &mv).exp();
     &n / n.sum()
 }
@@ -139,9 +142,9 @@ struct Attention {
 
 impl Attention {
     fn new(node: &ANode, attention_dims: usize) -> Self {
-        let query = get_query_vec(&node, attention_dims);
-        let key = get_key_vec(&node, attention_dims);
-        let value = get_value_vec(&node, attention_dims);
+        let query = get_query_vec(node, attention_dims);
+        let key = get_key_vec(node, attention_dims);
+        let value = get_value_vec(node, attention_dims);
         Attention {query, key, value}
     }
 }
@@ -0,0 +1,8 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if std::env::var_os("CARGO_FEATURE_BLAS").is_some() {
+        println!("cargo:rerun-if-env-changed=SIMPLE_GRAD_BLAS");
+        let lib = std::env::var("SIMPLE_GRAD_BLAS").unwrap_or_else(|_| "openblas".into());
+        println!("cargo:rustc-link-lib={}", lib);
+    }
+}
@@ -0,0 +1,116 @@
+// Bindings to the CBLAS gemm routines. Linked against OpenBLAS; set
+// SIMPLE_GRAD_BLAS at build time to link a different CBLAS provider.
+
+use std::os::raw::c_int;
+
+use crate::DType;
+
+const ROW_MAJOR: c_int = 101;
+const NO_TRANS: c_int = 111;
+const TRANS: c_int = 112;
+
+// Products smaller than this (in multiply-adds) stay on the native loops,
+// where the call overhead would dominate.
+pub(crate) const MIN_WORK: usize = 32 * 32 * 32;
+
+extern "C" {
+    #[cfg(not(feature = "f64"))]
+    fn cblas_sgemm(
+        layout: c_int, trans_a: c_int, trans_b: c_int,
+        m: c_int, n: c_int, k: c_int,
+        alpha: f32, a: *const f32, lda: c_int,
+        b: *const f32, ldb: c_int,
+        beta: f32, c: *mut f32, ldc: c_int
+    );
+
+    #[cfg(feature = "f64")]
+    fn cblas_dgemm(
+        layout: c_int, trans_a: c_int, trans_b: c_int,
+        m: c_int, n: c_int, k: c_int,
+        alpha: f64, a: *const f64, lda: c_int,
+        b: *const f64, ldb: c_int,
+        beta: f64, c: *mut f64, ldc: c_int
+    );
+}
+
+// c[m,n] += op(a)[m,k] * op(b)[k,n], all row-major
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gemm(
+    trans_a: bool, trans_b: bool,
+    m: usize, n: usize, k: usize,
+    a: &[DType], b: &[DType], c: &mut [DType]
+) {
+    if a.len() < m * k || b.len() < k * n || c.len() < m * n {
+        panic!("gemm buffers too small for a {}x{}x{} product!", m, k, n);
+    }
+    let lda = if trans_a { m } else { k };
+    let ldb = if trans_b { k } else { n };
+    let ta = if trans_a { TRANS } else { NO_TRANS };
+    let tb = if trans_b { TRANS } else { NO_TRANS };
+    unsafe {
+        #[cfg(not(feature = "f64"))]
+        cblas_sgemm(ROW_MAJOR, ta, tb, m as c_int, n as c_int, k as c_int,
+            1., a.as_ptr(), lda as c_int, b.as_ptr(), ldb as c_int,
+            1., c.as_mut_ptr(), n as c_int);
+
+        #[cfg(feature = "f64")]
+        cblas_dgemm(ROW_MAJOR, ta, tb, m as c_int, n as c_int, k as c_int,
+            1., a.as_ptr(), lda as c_int, b.as_ptr(), ldb as c_int,
+            1., c.as_mut_ptr(), n as c_int);
+    }
+}
+
+#[cfg(test)]
+mod blas_tests {
+    use super::*;
+    use crate::vecops::{matmul, matmul_at, matmul_bt};
+
+    // out[m,n] += op(a)[m,k] * op(b)[k,n] with plain loops
+    #[allow(clippy::too_many_arguments)]
+    fn naive(trans_a: bool, trans_b: bool, m: usize, n: usize, k: usize, a: &[DType], b: &[DType], out: &mut [DType]) {
+        for i in 0..m {
+            for j in 0..n {
+                out[i*n + j] += (0..k).map(|p| {
+                    let ai = if trans_a { a[p*m + i] } else { a[i*k + p] };
+                    let bj = if trans_b { b[j*k + p] } else { b[p*n + j] };
+                    ai * bj
+                }).sum::<DType>();
+            }
+        }
+    }
+
+    fn assert_close(a: &[DType], b: &[DType]) {
+        for (ai, bi) in a.iter().zip(b.iter()) {
+            assert!((ai - bi).abs() < 1e-3 * (1. + bi.abs()), "{} != {}", ai, bi);
+        }
+    }
+
+    #[test]
+    fn test_matches_native() {
+        let (m, k, n) = (40, 50, 60);
+        assert!(m * k * n >= MIN_WORK);
+        let values = |len: usize, seed: usize| -> Vec<DType> {
+            (0..len).map(|i| ((i * 7 + seed) % 13) as DType / 13. - 0.5).collect()
+        };
+        let (a, b, g) = (values(m * k, 1), values(k * n, 2), values(m * n, 3));
+
+        // Results accumulate into whatever is already there
+        let mut out = values(m * n, 4);
+        let mut expected = out.clone();
+        matmul(&a, &b, &mut out, m, k, n);
+        naive(false, false, m, n, k, &a, &b, &mut expected);
+        assert_close(&out, &expected);
+
+        let mut out = values(m * k, 5);
+        let mut expected = out.clone();
+        matmul_bt(&g, &b, &mut out, m, n, k);
+        naive(false, true, m, k, n, &g, &b, &mut expected);
+        assert_close(&out, &expected);
+
+        let mut out = values(k * n, 6);
+        let mut expected = out.clone();
+        matmul_at(&a, &g, &mut out, m, k, n);
+        naive(true, false, k, n, m, &a, &g, &mut expected);
+        assert_close(&out, &expected);
+    }
+}
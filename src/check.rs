@@ -0,0 +1,86 @@
+use crate::{ANode,DType,Graph};
+
+// Result of comparing backprop against finite differences: the largest
+// relative error over the elements of each input, in input order.
+#[derive(Clone,Debug,PartialEq)]
+pub struct GradCheck {
+    pub max_errors: Vec<DType>,
+    pub tol: DType
+}
+
+impl GradCheck {
+    pub fn passed(&self) -> bool {
+        self.max_errors.iter().all(|e| *e <= self.tol)
+    }
+}
+
+// Summed in f64 whatever DType is, so the cast is a no-op with the f64 feature
+#[allow(clippy::unnecessary_cast)]
+fn total(node: &ANode) -> f64 {
+    node.value().iter().map(|v| *v as f64).sum()
+}
+
+// Checks the gradients of sum(build(inputs)) with respect to each input, a
+// variable, against central differences with step `eps`. Errors are
+// |analytic - numeric| / max(1, |analytic|, |numeric|), so near-zero
+// gradients are compared absolutely. `build` is rerun for every perturbed
+// element, so keep the inputs small.
+pub fn gradcheck<F>(build: F, inputs: &[ANode], eps: DType, tol: DType) -> GradCheck
+where
+    F: Fn(&[ANode]) -> ANode
+{
+    let mut graph = Graph::new();
+    graph.backward(&build(inputs));
+
+    let max_errors = inputs.iter().map(|input| {
+        let analytic = match graph.get_grad(input) {
+            Some(g) => g.to_vec(),
+            None => vec![0.; input.value().len()]
+        };
+        let mut max_error: DType = 0.;
+        for (i, a) in analytic.iter().enumerate() {
+            let x = input.value()[i];
+            input.update(|v| v[i] = x + eps);
+            let up = total(&build(inputs));
+            input.update(|v| v[i] = x - eps);
+            let down = total(&build(inputs));
+            input.update(|v| v[i] = x);
+
+            let numeric = ((up - down) as DType) / (2. * eps);
+            let error = (a - numeric).abs() / a.abs().max(numeric.abs()).max(1.);
+            max_error = max_error.max(error);
+        }
+        max_error
+    }).collect();
+    GradCheck { max_errors, tol }
+}
+
+#[cfg(test)]
+mod check_tests {
+    use super::*;
+    use crate::{Variable,Pow};
+
+    #[test]
+    fn test_gradcheck() {
+        let inputs = [Variable::new(vec![0.5, 1.5]), Variable::with_shape(vec![1., -2., 0.5, 3.], &[2, 2])];
+        let check = gradcheck(|x| x[1].matmul(&x[0]).tanh() * (&x[0]).pow(2.), &inputs, 1e-3, 1e-2);
+        assert_eq!(check.max_errors.len(), 2);
+        assert!(check.passed(), "{:?}", check);
+        assert_eq!(inputs[0].value(), &[0.5, 1.5]);
+    }
+
+    #[test]
+    fn test_gradcheck_wrong_grad() {
+        // A custom gradient off by a factor of two is caught
+        let x = Variable::new(vec![1., 2.]);
+        let check = gradcheck(|x| {
+            (&x[0] * &x[0]).with_custom_grad(&x[..1], |inputs, _out, grad, child_grads| {
+                for ((g, xi), gi) in child_grads[0].iter_mut().zip(inputs[0]).zip(grad) {
+                    *g = gi * xi;
+                }
+            })
+        }, &[x], 1e-3, 1e-2);
+        assert!(!check.passed());
+        assert!((check.max_errors[0] - 0.5).abs() < 1e-2);
+    }
+}
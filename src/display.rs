@@ -0,0 +1,97 @@
+use std::fmt;
+
+use hashbrown::HashMap;
+
+use crate::{ANode,NodeIdx};
+
+// Prints the graph as an infix expression. Variables are named after their
+// ids, scalar constants by value; subexpressions used more than once are
+// bound to a name on their own line first.
+impl fmt::Display for ANode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parents = HashMap::new();
+        let mut stack = vec![self.clone()];
+        while let Some(node) = stack.pop() {
+            for child in node.get_children().unwrap_or(&[]).iter() {
+                let count = parents.entry(child.get_id()).or_insert(0);
+                *count += 1;
+                if *count == 1 {
+                    stack.push(child.clone());
+                }
+            }
+        }
+
+        let mut printer = Printer { parents, labels: HashMap::new(), lines: Vec::new() };
+        let expr = printer.render(self);
+        for line in printer.lines.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        write!(f, "{}", expr)
+    }
+}
+
+struct Printer {
+    parents: HashMap<NodeIdx, usize>,
+    labels: HashMap<NodeIdx, String>,
+    lines: Vec<String>
+}
+
+impl Printer {
+    fn render(&mut self, node: &ANode) -> String {
+        if let Some(label) = self.labels.get(&node.get_id()) {
+            return label.clone()
+        }
+
+        let children = node.get_children().unwrap_or(&[]);
+        if children.is_empty() {
+            return leaf_name(node)
+        }
+
+        let args: Vec<_> = children.iter().map(|c| self.render(c)).collect();
+        let expr = match (node.op_name(), args.as_slice()) {
+            ("AddN", [l, r]) => format!("({} + {})", l, r),
+            ("Subtract", [l, r]) => format!("({} - {})", l, r),
+            ("Multiply", [l, r]) => format!("({} * {})", l, r),
+            ("Divide", [l, r]) => format!("({} / {})", l, r),
+            ("Power", [l, r]) => format!("({} ^ {})", l, r),
+            ("Negate", [x]) => format!("-{}", x),
+            ("SumVec", _) => format!("sum({})", args.join(", ")),
+            (name, _) => format!("{}({})", name.to_lowercase(), args.join(", "))
+        };
+
+        if self.parents.get(&node.get_id()).copied().unwrap_or(0) > 1 {
+            let label = format!("t{}", self.lines.len());
+            self.lines.push(format!("{} = {}", label, expr));
+            self.labels.insert(node.get_id(), label.clone());
+            label
+        } else {
+            expr
+        }
+    }
+}
+
+fn leaf_name(node: &ANode) -> String {
+    match (node.op_name(), &*node.value()) {
+        ("Variable", _) => format!("x{}", node.get_id().0),
+        (_, [v]) => format!("{}", v),
+        (_, _) => format!("c{}", node.get_id().0)
+    }
+}
+
+#[cfg(test)]
+mod display_tests {
+    use crate::{Variable,Constant,Pow};
+
+    #[test]
+    fn test_display() {
+        let x = Variable::new(vec![1., 2.]);
+        let xi = x.get_id().0;
+        let h = (&x + 2.).pow(2.);
+        assert_eq!(format!("{}", h), format!("((x{} + 2) ^ 2)", xi));
+
+        let c = Constant::new(vec![1., 2.]);
+        let out = (&h * &h).sum() - c.exp().sum();
+        let expected = format!("t0 = ((x{} + 2) ^ 2)\n(sum((t0 * t0)) - sum(exp(c{})))", xi, c.get_id().0);
+        assert_eq!(format!("{}", out), expected);
+    }
+}
@@ -0,0 +1,200 @@
+use std::cell::Cell;
+use std::fmt;
+
+use crate::{ANode,DType};
+use crate::shape::BroadcastIndex;
+
+// What ln, division and powers do with inputs outside their domain: ln of a
+// non-positive value, a zero denominator or zero raised to a negative power.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum DomainPolicy {
+    // Let the NaNs and infinities through, as plain float arithmetic does
+    Propagate,
+    // Pull ln inputs up to eps, and denominators and zero bases of negative
+    // powers out to +/-eps. Clamped values get no gradient.
+    Clamp(DType),
+    // Panic when the op is built, naming it and the offending element
+    Panic
+}
+
+thread_local! {
+    static POLICY: Cell<DomainPolicy> = const { Cell::new(DomainPolicy::Propagate) };
+}
+
+// Like the other graph settings, the policy is per thread and applies to ops
+// built after it is set.
+pub fn set_domain_policy(policy: DomainPolicy) {
+    POLICY.with(|p| p.set(policy));
+}
+
+pub fn domain_policy() -> DomainPolicy {
+    POLICY.with(|p| p.get())
+}
+
+// The first element an op couldn't handle
+#[derive(Clone,Debug,PartialEq)]
+pub struct DomainError {
+    pub op: &'static str,
+    pub index: usize,
+    pub values: Vec<DType>
+}
+
+impl fmt::Display for DomainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.op {
+            "Divide" => write!(f, "Divide by zero at index {}", self.index),
+            "Power" => write!(f, "Power of {} to {} at index {}", self.values[0], self.values[1], self.index),
+            op => write!(f, "{} of {} at index {}", op, self.values[0], self.index)
+        }
+    }
+}
+
+impl std::error::Error for DomainError {}
+
+pub(crate) fn check_ln(x: &ANode) -> Result<(), DomainError> {
+    match x.value().iter().position(|v| *v <= 0.) {
+        Some(index) => Err(DomainError { op: "Ln", index, values: vec![x.value()[index]] }),
+        None => Ok(())
+    }
+}
+
+pub(crate) fn check_div(y: &ANode) -> Result<(), DomainError> {
+    match y.value().iter().position(|v| *v == 0.) {
+        Some(index) => Err(DomainError { op: "Divide", index, values: vec![0.] }),
+        None => Ok(())
+    }
+}
+
+// Zero to a negative power, or a negative base to a fractional one
+pub(crate) fn check_pow(base: &ANode, exp: &ANode) -> Result<(), DomainError> {
+    let out = match base.shape().broadcast(&exp.shape()) {
+        Some(out) => out,
+        None => return Ok(())
+    };
+    let (bv, ev) = (base.value(), exp.value());
+    let pairs = BroadcastIndex::new(&base.shape(), &out).zip(BroadcastIndex::new(&exp.shape(), &out));
+    for (index, (bi, ei)) in pairs.enumerate() {
+        let (b, e) = (bv[bi], ev[ei]);
+        if (b == 0. && e < 0.) || (b < 0. && e.fract() != 0.) {
+            return Err(DomainError { op: "Power", index, values: vec![b, e] })
+        }
+    }
+    Ok(())
+}
+
+fn clamp_away(x: ANode, eps: DType) -> ANode {
+    x.map_with_grad(
+        move |v| if v.abs() < eps { eps.copysign(v) } else { v },
+        move |v| if v.abs() < eps { 0. } else { 1. })
+}
+
+fn enforce(check: Result<(), DomainError>) {
+    if let Err(e) = check {
+        panic!("{}!", e);
+    }
+}
+
+// Apply the current policy to op inputs as the ops are built
+pub(crate) fn guard_ln(x: ANode) -> ANode {
+    match domain_policy() {
+        DomainPolicy::Propagate => x,
+        DomainPolicy::Clamp(eps) => x.map_with_grad(
+            move |v| v.max(eps),
+            move |v| if v < eps { 0. } else { 1. }),
+        DomainPolicy::Panic => {
+            enforce(check_ln(&x));
+            x
+        }
+    }
+}
+
+pub(crate) fn guard_div(y: ANode) -> ANode {
+    match domain_policy() {
+        DomainPolicy::Propagate => y,
+        DomainPolicy::Clamp(eps) => clamp_away(y, eps),
+        DomainPolicy::Panic => {
+            enforce(check_div(&y));
+            y
+        }
+    }
+}
+
+pub(crate) fn guard_pow(base: ANode, exp: &ANode) -> ANode {
+    match domain_policy() {
+        DomainPolicy::Propagate => base,
+        // Only needed when something is raised to a negative power
+        DomainPolicy::Clamp(eps) if exp.value().iter().any(|e| *e < 0.) => clamp_away(base, eps),
+        DomainPolicy::Clamp(_) => base,
+        DomainPolicy::Panic => {
+            enforce(check_pow(&base, exp));
+            base
+        }
+    }
+}
+
+#[cfg(test)]
+mod domain_tests {
+    use super::*;
+    use crate::{Graph,Variable,Constant,Pow};
+
+    // Resets the policy even if the test panics
+    struct Policy;
+
+    impl Policy {
+        fn set(policy: DomainPolicy) -> Self {
+            set_domain_policy(policy);
+            Policy
+        }
+    }
+
+    impl Drop for Policy {
+        fn drop(&mut self) {
+            set_domain_policy(DomainPolicy::Propagate);
+        }
+    }
+
+    #[test]
+    fn test_propagate() {
+        let x = Variable::new(vec![0., 1.]);
+        let out = (1. / &x).sum();
+        assert!(out.value()[0].is_infinite());
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert!(graph.get_grad(&x).unwrap()[0].is_infinite());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let _policy = Policy::set(DomainPolicy::Clamp(0.25));
+        let x = Variable::new(vec![0., -2., 2.]);
+        assert_eq!(x.ln().value()[..2], [(0.25 as DType).ln(); 2]);
+        assert_eq!((1. / &x).value(), &[4., -0.5, 0.5]);
+        assert_eq!(Constant::new(vec![0.]).pow(-1.).value(), &[4.]);
+
+        // The clamped element gets no gradient, the rest the usual one
+        let mut graph = Graph::new();
+        graph.backward(&(1. / &x).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., -0.25, -0.25]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Divide by zero at index 1!")]
+    fn test_panic() {
+        let _policy = Policy::set(DomainPolicy::Panic);
+        let _ = Constant::new(vec![1., 2.]) / Constant::new(vec![1., 0.]);
+    }
+
+    #[test]
+    fn test_checked() {
+        let x = Variable::new(vec![1., -1.]);
+        let err = x.checked_ln().err().unwrap();
+        assert_eq!((err.op, err.index), ("Ln", 1));
+        assert_eq!(err.to_string(), "Ln of -1 at index 1");
+        assert!(x.checked_div(vec![2., 0.]).is_err());
+        assert_eq!(x.checked_div(2.).unwrap().value(), &[0.5, -0.5]);
+
+        let err = x.checked_pow(0.5).err().unwrap();
+        assert_eq!(err.to_string(), "Power of -1 to 0.5 at index 1");
+        assert_eq!(x.checked_pow(2.).unwrap().value(), &[1., 1.]);
+    }
+}
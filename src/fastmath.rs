@@ -0,0 +1,129 @@
+// Polynomial approximations of the transcendental functions used by the
+// elementwise ops. They operate on f32 bit patterns, stay within about 1e-6
+// relative error of libm, and avoid libm calls so the slice loops in vecops
+// can be inlined and vectorized.
+
+const LOG2_E: f32 = std::f32::consts::LOG2_E;
+const LN_2_HI: f32 = 0.693_145_75;
+const LN_2_LO: f32 = 1.428_606_8e-6;
+
+#[inline(always)]
+pub fn exp_f32(x: f32) -> f32 {
+    if x.is_nan() {
+        return x
+    } else if x > 88.73 {
+        return f32::INFINITY
+    } else if x < -104.0 {
+        return 0.0
+    }
+
+    // e^x = 2^n * e^r, |r| <= ln(2) / 2
+    let n = (x * LOG2_E).round();
+    let r = x - n * LN_2_HI - n * LN_2_LO;
+
+    // Degree 6 Taylor/minimax polynomial for e^r
+    let p = 1.0 + r * (1.0 + r * (0.5 + r * (0.166_666_67
+        + r * (0.041_666_668 + r * (0.008_333_334 + r * 0.001_388_888_9)))));
+
+    // Scale by 2^n in two steps so n = 128 or n = -150 stays in the exponent range
+    let half = (n as i32) / 2;
+    let s1 = f32::from_bits(((half + 127) as u32) << 23);
+    let s2 = f32::from_bits((((n as i32 - half) + 127) as u32) << 23);
+    p * s1 * s2
+}
+
+#[inline(always)]
+pub fn ln_f32(x: f32) -> f32 {
+    if x.is_nan() || x < 0.0 {
+        return f32::NAN
+    } else if x == 0.0 {
+        return f32::NEG_INFINITY
+    } else if x.is_infinite() {
+        return x
+    }
+
+    // Normalize subnormals so the exponent extraction below is valid
+    let (x, bias) = if x < f32::MIN_POSITIVE { (x * 8_388_608.0, -23) } else { (x, 0) };
+
+    // x = m * 2^e with m in [sqrt(1/2), sqrt(2))
+    let bits = x.to_bits();
+    let mut e = ((bits >> 23) & 0xff) as i32 - 127 + bias;
+    let mut m = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+    if m > std::f32::consts::SQRT_2 {
+        m *= 0.5;
+        e += 1;
+    }
+
+    // ln(m) = 2 atanh(s), s = (m - 1) / (m + 1)
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let p = 2.0 * s * (1.0 + s2 * (0.333_333_34 + s2 * (0.2 + s2 * (0.142_857_15 + s2 * 0.111_111_11))));
+    p + e as f32 * LN_2_HI + e as f32 * LN_2_LO
+}
+
+#[inline(always)]
+pub fn tanh_f32(x: f32) -> f32 {
+    if x.abs() < 0.5 {
+        // 1 - 2 / (e^2x + 1) cancels badly near zero, so use the odd series
+        let x2 = x * x;
+        x * (1.0 + x2 * (-0.333_333_34 + x2 * (0.133_333_33 + x2 * (-0.053_968_254
+            + x2 * (0.021_869_488 + x2 * (-0.008_863_236 + x2 * (0.003_592_128
+            + x2 * -0.001_455_834_8)))))))
+    } else if x > 9.0 {
+        1.0
+    } else if x < -9.0 {
+        -1.0
+    } else {
+        1.0 - 2.0 / (exp_f32(2.0 * x) + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod fastmath_tests {
+    use super::*;
+
+    fn rel_err(a: f32, b: f32) -> f32 {
+        if a == b { 0. } else { (a - b).abs() / b.abs().max(1e-30) }
+    }
+
+    #[test]
+    fn test_exp() {
+        for i in -800..800 {
+            let x = i as f32 * 0.1;
+            assert!(rel_err(exp_f32(x), x.exp()) < 1e-6, "exp({})", x);
+        }
+        for i in 0..16 {
+            let x = -88. - i as f32;
+            assert!(rel_err(exp_f32(x), x.exp()) < 1e-2, "exp({})", x);
+        }
+        assert_eq!(exp_f32(-1000.), 0.);
+        assert_eq!(exp_f32(f32::NEG_INFINITY), 0.);
+        assert_eq!(exp_f32(100.), f32::INFINITY);
+        assert_eq!(exp_f32(f32::INFINITY), f32::INFINITY);
+        assert!(exp_f32(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_ln() {
+        for i in 1..2000 {
+            let x = i as f32 * 0.37;
+            assert!((ln_f32(x) - x.ln()).abs() < 1e-6 * x.ln().abs().max(1.), "ln({})", x);
+        }
+        assert!((ln_f32(1e-40) - 1e-40f32.ln()).abs() < 1e-4);
+        assert_eq!(ln_f32(0.), f32::NEG_INFINITY);
+        assert!(ln_f32(-1.).is_nan());
+    }
+
+    #[test]
+    fn test_tanh() {
+        for i in -200..200 {
+            let x = i as f32 * 0.05;
+            assert!((tanh_f32(x) - x.tanh()).abs() < 1e-6, "tanh({})", x);
+        }
+        for i in 1..1000 {
+            let x = i as f32 * 1e-3;
+            assert!(rel_err(tanh_f32(x), x.tanh()) < 1e-6, "tanh({})", x);
+            assert!(rel_err(tanh_f32(-x), (-x).tanh()) < 1e-6, "tanh({})", -x);
+        }
+    }
+}
@@ -1,27 +1,111 @@
 use std::rc::Rc;
 use std::ops::Add;
+use std::fmt;
 
 use std::cell::UnsafeCell;
-use hashbrown::HashMap;
-use hashbrown::hash_map::Entry;
-use crate::{DType,ANode,NodeIdx,Node};
+use hashbrown::{HashMap,HashSet};
+use crate::{DType,ANode,NodeIdx,Node,Shape,Plan,Constant,Value};
+use crate::plan::evaluation_order;
+use crate::introspect::walk;
+use crate::optimize::{fold_constants,fuse_elementwise};
+use crate::onnx;
 use crate::vecops::iadd;
 use crate::pool::{allocate_vec,MPVec};
 
-#[derive(Debug)]
 pub struct Graph {
     gradients: HashMap<NodeIdx, MPVec>,
+    sparse_gradients: HashMap<NodeIdx, HashMap<usize, MPVec>>,
+    // Scratch space for child gradients, kept between backward passes so
+    // training loops don't reallocate it every step.
+    space: Vec<DType>,
+    retained: HashMap<NodeIdx, MPVec>,
+    // Differentiable gradients from backward_with_graph
+    grad_nodes: HashMap<NodeIdx, ANode>,
+    // Parents-first order of a frozen graph, keyed by its root
+    topology: Option<(NodeIdx, Vec<ANode>, HashSet<NodeIdx>)>,
+    accumulate: bool,
+    // Run on gradients as they pass through; leaf hooks once per backward
+    hooks: HashMap<NodeIdx, Vec<Hook>>,
+    leaf_hooks: HashMap<NodeIdx, Vec<Hook>>,
     nan_check: bool
 }
 
+type Hook = Box<dyn FnMut(&mut [DType])>;
+
+// Dense and sparse gradients set aside while a pass accumulates
+type Stash = (HashMap<NodeIdx, MPVec>, HashMap<NodeIdx, HashMap<usize, MPVec>>);
+
+// Building gradients out of nodes reached an op which can't express its
+// gradient that way
+#[derive(Clone,Debug,PartialEq)]
+pub struct GradGraphError {
+    pub op: &'static str,
+    pub id: NodeIdx
+}
+
+impl fmt::Display for GradGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (node {}) does not support higher order gradients", self.op, self.id.0)
+    }
+}
+
+impl std::error::Error for GradGraphError {}
+
+impl fmt::Debug for Graph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Graph")
+            .field("gradients", &self.gradients)
+            .field("sparse_gradients", &self.sparse_gradients)
+            .field("topology", &self.topology.as_ref().map(|(root, _, _)| root))
+            .field("accumulate", &self.accumulate)
+            .field("hooks", &(self.hooks.len() + self.leaf_hooks.len()))
+            .field("nan_check", &self.nan_check)
+            .finish()
+    }
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
 impl Graph {
     pub fn new() -> Self {
         Graph {
             gradients: HashMap::new(),
+            sparse_gradients: HashMap::new(),
+            space: Vec::new(),
+            retained: HashMap::new(),
+            grad_nodes: HashMap::new(),
+            topology: None,
+            accumulate: false,
+            hooks: HashMap::new(),
+            leaf_hooks: HashMap::new(),
             nan_check: false
         }
     }
 
+    // Calls `hook` with the complete gradient of `node` during backward,
+    // before it moves on to the children; changes made to it stick. Leaves
+    // get theirs once the pass is done.
+    pub fn register_hook<F: FnMut(&mut [DType]) + 'static>(&mut self, node: &ANode, hook: F) {
+        let hooks = if node.is_leaf() { &mut self.leaf_hooks } else { &mut self.hooks };
+        hooks.entry(node.get_id()).or_default().push(Box::new(hook));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+        self.leaf_hooks.clear();
+    }
+
+    // When set, each backward pass adds its gradients into those already held
+    // rather than mixing with them mid-pass, for micro-batching. Clear them
+    // with zero_grads between steps.
+    pub fn accumulate_grads(&mut self, accumulate: bool) {
+        self.accumulate = accumulate;
+    }
+
     #[inline]
     pub fn debug_nan(&mut self, check: bool)  {
         self.nan_check = check;
@@ -32,14 +116,109 @@ impl Graph {
         self.gradients.get(&node.get_id()).map(|v| v.as_ref())
     }
 
+    #[inline]
+    pub fn get_sparse_grad(&self, node: &ANode) -> Option<&HashMap<usize, MPVec>> {
+        self.get_sparse_grad_by_id(node.get_id())
+    }
+
+    #[inline]
+    pub fn get_sparse_grad_by_id(&self, idx: NodeIdx) -> Option<&HashMap<usize, MPVec>> {
+        self.sparse_gradients.get(&idx)
+    }
+
+    pub fn grads_iter(&self) -> impl Iterator<Item=(NodeIdx, &[DType])> {
+        self.gradients.iter().map(|(idx, g)| (*idx, g.as_slice()))
+    }
+
+    // Hands over the dense gradients without copying, leaving none behind
+    pub fn take_grads(&mut self) -> impl Iterator<Item=(NodeIdx, Vec<DType>)> + '_ {
+        self.gradients.drain().map(|(idx, g)| (idx, g.into_vec()))
+    }
+
+    // Describes the graph under `end_node` in Graphviz's DOT format, with
+    // edges running from children to parents. Nodes requiring gradients are
+    // filled in.
+    pub fn to_dot(&self, end_node: &ANode) -> String {
+        let mut out = String::from("digraph {\n");
+        let mut seen = HashSet::new();
+        let mut stack = vec![end_node.clone()];
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node.get_id()) {
+                continue
+            }
+            let style = if node.requires_grad() { ", style=filled" } else { "" };
+            let shape = if node.is_leaf() { "box" } else { "ellipse" };
+            out.push_str(&format!("    n{} [label=\"{}\\n{:?}\", shape={}{}];\n",
+                node.get_id().0, node.op_name(), node.shape().dims(), shape, style));
+            for child in node.get_children().unwrap_or(&[]).iter() {
+                out.push_str(&format!("    n{} -> n{};\n", child.get_id().0, node.get_id().0));
+                stack.push(child.clone());
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // L2 norm of each held gradient, sparse tables included, along with the
+    // norm over all of them together.
+    pub fn grad_norms(&self) -> (Vec<(NodeIdx, DType)>, DType) {
+        let sq = |g: &[DType]| g.iter().map(|gi| gi * gi).sum::<DType>();
+        let mut norms: Vec<_> = self.gradients.iter()
+            .map(|(idx, g)| (*idx, sq(g)))
+            .collect();
+        norms.extend(self.sparse_gradients.iter().map(|(idx, rows)| {
+            (*idx, rows.values().map(|g| sq(g)).sum::<DType>())
+        }));
+        let total = norms.iter().map(|(_, n)| n).sum::<DType>().sqrt();
+        norms.iter_mut().for_each(|(_, n)| *n = n.sqrt());
+        (norms, total)
+    }
+
+    #[inline]
+    pub fn get_grad_node(&self, node: &ANode) -> Option<&ANode> {
+        self.grad_nodes.get(&node.get_id())
+    }
+
     #[inline]
     pub fn zero_grads(&mut self) {
         self.gradients.clear();
+        self.sparse_gradients.clear();
+        self.grad_nodes.clear();
     }
 
     #[inline]
     pub fn clear_memory(&mut self) {
         self.gradients.clear();
+        self.sparse_gradients.clear();
+        self.grad_nodes.clear();
+        self.space = Vec::new();
+    }
+
+    // Re-evaluates every node under `end_node` from the current leaf values,
+    // as set by `set_value`. Compile the graph instead to skip the traversal
+    // on every call.
+    pub fn forward(&mut self, end_node: &ANode) {
+        Plan::new(end_node).forward();
+    }
+
+    // Returns an equivalent graph with constant subexpressions folded into
+    // single constants and elementwise chains fused. Values of constants are
+    // baked in at this point.
+    pub fn optimize(&self, end_node: &ANode) -> ANode {
+        fuse_elementwise(&fold_constants(end_node))
+    }
+
+    // Writes the graph under `end_node` as an ONNX model. Only the basic
+    // elementwise ops and sums can be exported; anything else is an
+    // InvalidInput error and nothing is written.
+    pub fn export_onnx<P: AsRef<std::path::Path>>(&self, end_node: &ANode, path: P) -> std::io::Result<()> {
+        onnx::export_onnx(end_node, path)
+    }
+
+    // Flattens the graph under `end_node` into a plan which can be re-run
+    // after updating leaf values.
+    pub fn compile(&self, end_node: &ANode) -> Plan {
+        Plan::new(end_node)
     }
 
     pub fn stats(&self, node: &ANode) -> GraphStats {
@@ -58,13 +237,20 @@ impl Graph {
         if self.gradients.contains_key(&n_idx)  {
             self.gradients.remove(&n_idx).unwrap()
         } else {
-            self.get_temp_space(node.value().len())
+            self.get_temp_space(node)
         }
     }
 
     #[inline]
-    fn get_temp_space(&mut self, size: usize) -> MPVec {
-        allocate_vec(size)
+    fn get_temp_space(&mut self, node: &ANode) -> MPVec {
+        let size = node.value().len();
+        match self.retained.remove(&node.get_id()) {
+            Some(mut v) if v.len() == size => {
+                v.fill(0.);
+                v
+            },
+            _ => allocate_vec(size)
+        }
     }
 
     #[inline]
@@ -72,105 +258,521 @@ impl Graph {
         self.gradients.insert(node.get_id(), grad);
     }
 
+    // Adds `grad` into the gradient held for `node`
+    pub(crate) fn accumulate_grad(&mut self, node: &ANode, grad: &[DType]) {
+        self.add_or_update_grad(node, &mut grad.to_vec());
+    }
+
+    // Adds `grad`, one row after another, into the sparse gradient of `node`
+    pub(crate) fn accumulate_sparse_grad(&mut self, node: &ANode, rows: &[usize], grad: &[DType]) {
+        self.add_sparse_grad(node.get_id(), rows, grad);
+    }
+
     #[inline]
-    fn add_or_update_grad(&mut self, node: &ANode, grad: &mut [f32]) {
-        match self.gradients.entry(node.get_id()) {
-            Entry::Occupied(mut entry) => {
-                iadd(entry.get_mut(), grad);
-            },
-            Entry::Vacant(mut entry) => {
-                let mut v = allocate_vec(grad.len());
-                v[..].clone_from_slice(grad);
+    fn add_or_update_grad(&mut self, node: &ANode, grad: &mut [DType]) {
+        if let Some(v) = self.gradients.get_mut(&node.get_id()) {
+            iadd(v, grad);
+        } else {
+            let mut v = self.get_temp_space(node);
+            v[..].clone_from_slice(grad);
+            self.gradients.insert(node.get_id(), v);
+        }
+    }
 
-                entry.insert(v);
+    fn add_sparse_grad(&mut self, table: NodeIdx, rows: &[usize], grad: &[DType]) {
+        let width = grad.len() / rows.len().max(1);
+        let table_grads = self.sparse_gradients.entry(table)
+            .or_default();
+
+        for (row, g) in rows.iter().zip(grad.chunks(width)) {
+            let row_grad = table_grads.entry(*row).or_insert_with(|| allocate_vec(width));
+            iadd(row_grad, g);
+        }
+    }
+    
+    // Sets aside the held gradients when accumulating, so the pass starts clean
+    fn stash_grads(&mut self) -> Option<Stash> {
+        if self.accumulate {
+            Some((std::mem::take(&mut self.gradients), std::mem::take(&mut self.sparse_gradients)))
+        } else {
+            None
+        }
+    }
+
+    // Runs leaf hooks on the gradients of the pass, then folds the stashed
+    // gradients back in
+    fn end_pass(&mut self, stash: Option<Stash>) {
+        for (idx, hooks) in self.leaf_hooks.iter_mut() {
+            if let Some(g) = self.gradients.get_mut(idx) {
+                hooks.iter_mut().for_each(|h| h(g));
+            }
+        }
+
+        let (dense, sparse) = match stash {
+            Some(s) => s,
+            None => return
+        };
+        for (idx, grad) in dense {
+            match self.gradients.get_mut(&idx) {
+                Some(v) => iadd(v, &grad),
+                None => { self.gradients.insert(idx, grad); }
+            }
+        }
+        for (table, rows) in sparse {
+            let table_grads = self.sparse_gradients.entry(table).or_default();
+            for (row, grad) in rows {
+                match table_grads.get_mut(&row) {
+                    Some(v) => iadd(v, &grad),
+                    None => { table_grads.insert(row, grad); }
+                }
             }
         }
     }
 
-    
+    // Caches the traversal order of the graph under `end_node`, so repeated
+    // backward passes from it skip the walk and visit each node exactly once.
+    // The graph must not change shape while frozen.
+    pub fn freeze_topology(&mut self, end_node: &ANode) {
+        let live = live_nodes(end_node);
+        let mut order = evaluation_order(end_node);
+        order.retain(|n| live.contains(&n.get_id()));
+        order.reverse();
+        self.topology = Some((end_node.get_id(), order, live));
+    }
+
+    pub fn unfreeze_topology(&mut self) {
+        self.topology = None;
+    }
+
+    // Nodes are visited one at a time in reverse topological order. With the
+    // `rayon` feature the gradient kernels inside a node are split across
+    // threads, but sibling branches are not processed concurrently.
     pub fn backward(&mut self, end_node: &ANode) {
-        let out = Run::new(end_node);
         // dz/dz of course is 1
+        self.backward_seeded(end_node, |g| g.fill(1.));
+    }
+
+    // Runs backward from `end_node` using `seed` as its gradient rather than
+    // ones, giving the vector-Jacobian product seed^T J.
+    pub fn backward_with(&mut self, end_node: &ANode, seed: &[DType]) {
+        if seed.len() != end_node.value().len() {
+            panic!("Seed of length {} does not match node of length {}!", seed.len(), end_node.value().len());
+        }
+        self.backward_seeded(end_node, |g| g.copy_from_slice(seed));
+    }
+
+    // Like backward, but only walks nodes with a path down to one of
+    // `targets`; everything else is skipped.
+    pub fn backward_wrt(&mut self, end_node: &ANode, targets: &[&ANode]) {
+        let stash = self.stash_grads();
+        self.backward_wrt_pass(end_node, targets);
+        self.end_pass(stash);
+    }
+
+    fn backward_wrt_pass(&mut self, end_node: &ANode, targets: &[&ANode]) {
+        let mut order = evaluation_order(end_node);
+        let mut wanted: HashSet<NodeIdx> = targets.iter().map(|t| t.get_id()).collect();
+        order.retain(|node| {
+            let keep = node.get_children().unwrap_or(&[]).iter()
+                .any(|c| wanted.contains(&c.get_id()));
+            if keep {
+                wanted.insert(node.get_id());
+            }
+            keep
+        });
+        if !wanted.contains(&end_node.get_id()) {
+            return
+        }
+        order.reverse();
+        self.backward_frozen(end_node, &order, &wanted, |g| g.fill(1.));
+    }
+
+    // Backpropagates from several roots in a single pass, seeding each with
+    // its weight; the same as calling backward on their weighted sum.
+    pub fn backward_multi(&mut self, roots: &[(&ANode, DType)]) {
+        let stash = self.stash_grads();
+        let out = Roots::new(roots);
+        let z_grad = self.get_or_create_grad(&out);
+        let live = live_nodes(&out);
+
+        self.add_grad(&out, z_grad);
+        self.propagate_from(&out, &live);
+        self.end_pass(stash);
+    }
+
+    fn backward_seeded<F: FnOnce(&mut [DType])>(&mut self, end_node: &ANode, seed: F) {
+        let stash = self.stash_grads();
+        self.backward_seeded_pass(end_node, seed);
+        self.end_pass(stash);
+    }
+
+    fn backward_seeded_pass<F: FnOnce(&mut [DType])>(&mut self, end_node: &ANode, seed: F) {
+        if let Some((root, order, live)) = self.topology.take() {
+            if root == end_node.get_id() {
+                self.backward_frozen(end_node, &order, &live, seed);
+                self.topology = Some((root, order, live));
+                return
+            }
+            self.topology = Some((root, order, live));
+        }
+
+        let out = Run::new(end_node);
         let mut z_grad = self.get_or_create_grad(&out);
-        z_grad.fill(1f32);
-        
+        seed(&mut z_grad);
+        let live = live_nodes(&out);
+        self.add_grad(&out, z_grad);
+        self.propagate_from(&out, &live);
+    }
+
+    // The depth first walk hands gradients down in pieces as they arrive,
+    // while hooks need a node's whole gradient; with any registered, nodes
+    // are visited parents first instead, as with a frozen topology.
+    fn propagate_from(&mut self, out: &ANode, live: &HashSet<NodeIdx>) {
         // Allocate once
         let mut temp_grads = Vec::new();
-        self.add_grad(&out, z_grad);
-        let mut space = UnsafeCell::new(Vec::new());
-        self.recurse(&out, &mut temp_grads, &mut space);
+        let space = UnsafeCell::new(std::mem::take(&mut self.space));
+        if self.hooks.is_empty() {
+            self.recurse(out, &mut temp_grads, &space, live);
+        } else {
+            let mut order = evaluation_order(out);
+            order.retain(|n| live.contains(&n.get_id()));
+            for node in order.iter().rev() {
+                self.propagate(node, &mut temp_grads, &space, live);
+            }
+        }
+        self.space = space.into_inner();
+    }
+
+    // Runs backward by building the gradients out of nodes, so they can be
+    // differentiated themselves; fetch them with `get_grad_node`. Their values
+    // are also accumulated into the regular gradients.
+    // Fails on ops which can't express their gradient as nodes.
+    pub fn backward_with_graph(&mut self, end_node: &ANode) -> Result<(), GradGraphError> {
+        grad_graphs(end_node, |node, grad| {
+            if node.requires_grad() {
+                self.store_grad_node(node, grad);
+            }
+        })
+    }
+
+    // Jacobian of `end_node` with respect to `var`, one row per output
+    // element. Built from a forward pass per element of `var`.
+    pub fn jacobian(&self, end_node: &ANode, var: &ANode) -> Vec<Vec<DType>> {
+        let (m, n) = (end_node.value().len(), var.value().len());
+        let mut jac = vec![vec![0.; n]; m];
+        let mut seed = vec![0.; n];
+        for j in 0..n {
+            seed[j] = 1.;
+            let col = self.jvp(end_node, &[(var, &seed)]);
+            jac.iter_mut().zip(col.iter()).for_each(|(row, c)| row[j] = *c);
+            seed[j] = 0.;
+        }
+        jac
+    }
+
+    // Hessian-vector product of `loss` with respect to `var`, computed
+    // reverse-over-reverse: differentiates <grad(loss), v> a second time.
+    pub fn hvp(&self, loss: &ANode, var: &ANode, v: &[DType]) -> Result<Vec<DType>, GradGraphError> {
+        if v.len() != var.value().len() {
+            panic!("Vector of length {} does not match node of length {}!", v.len(), var.value().len());
+        }
+        let mut graph = Graph::new();
+        graph.backward_with_graph(loss)?;
+        let dvar = match graph.get_grad_node(var) {
+            Some(g) => g.clone(),
+            None => return Ok(vec![0.; v.len()])
+        };
+
+        let v = Constant::with_shape(v.to_vec(), var.shape().dims());
+        let mut graph = Graph::new();
+        graph.backward(&(dvar * v).sum());
+        Ok(match graph.get_grad(var) {
+            Some(g) => g.to_vec(),
+            None => vec![0.; var.value().len()]
+        })
+    }
+
+    // Forward mode: pushes the given leaf tangents through the graph and
+    // returns the directional derivative of `end_node`. Leaves without a seed
+    // are held constant.
+    pub fn jvp(&self, end_node: &ANode, seeds: &[(&ANode, &[DType])]) -> Vec<DType> {
+        let mut tangents: HashMap<NodeIdx, MPVec> = HashMap::new();
+        for (leaf, t) in seeds.iter() {
+            if t.len() != leaf.value().len() {
+                panic!("Tangent of length {} does not match node of length {}!", t.len(), leaf.value().len());
+            }
+            let mut v = allocate_vec(t.len());
+            v.copy_from_slice(t);
+            tangents.insert(leaf.get_id(), v);
+        }
+
+        for node in evaluation_order(end_node).iter() {
+            let children = node.get_children().unwrap_or(&[]);
+            let zeros: Vec<MPVec> = children.iter()
+                .filter(|c| !tangents.contains_key(&c.get_id()))
+                .map(|c| allocate_vec(c.value().len()))
+                .collect();
+            let mut zi = zeros.iter();
+            let child_tangents: Vec<&[DType]> = children.iter().map(|c| {
+                match tangents.get(&c.get_id()) {
+                    Some(t) => t.as_slice(),
+                    None => zi.next().unwrap().as_slice()
+                }
+            }).collect();
+
+            let t = match node.compute_jvp(&child_tangents) {
+                Some(t) => t,
+                None => panic!("Node {:?} does not support forward mode!", node.get_id())
+            };
+            tangents.insert(node.get_id(), t);
+        }
+
+        match tangents.remove(&end_node.get_id()) {
+            Some(t) => t.to_vec(),
+            None => vec![0.; end_node.value().len()]
+        }
+    }
+
+    fn store_grad_node(&mut self, node: &ANode, grad: ANode) {
+        self.add_or_update_grad(node, &mut grad.value().to_vec());
+        self.grad_nodes.insert(node.get_id(), grad);
     }
 
-    fn get_mut_slices<'a,'b>(
+    fn backward_frozen<F>(&mut self, end_node: &ANode, order: &[ANode], live: &HashSet<NodeIdx>, seed: F)
+    where
+        F: FnOnce(&mut [DType])
+    {
+        let mut z_grad = allocate_vec(end_node.value().len());
+        seed(&mut z_grad);
+        self.add_or_update_grad(end_node, &mut z_grad);
+
+        let mut temp_grads = Vec::new();
+        let space = UnsafeCell::new(std::mem::take(&mut self.space));
+        for node in order.iter() {
+            self.propagate(node, &mut temp_grads, &space, live);
+        }
+        self.space = space.into_inner();
+    }
+
+    // Like backward, but first zeroes the gradients of the previous call. Any
+    // node seen again, such as a parameter, gets its old buffer back instead of
+    // a fresh allocation; buffers that go unclaimed return to the pool.
+    pub fn backward_reusing(&mut self, end_node: &ANode) {
+        self.retained.extend(self.gradients.drain());
+        self.sparse_gradients.clear();
+        self.backward(end_node);
+        self.retained.clear();
+    }
+
+    fn get_mut_slices(
         &self,
         nodes: &[ANode],
         space: &UnsafeCell<Vec<DType>>, 
-        buff: &mut Vec<&'a mut [DType]>
+        buff: &mut Vec<&mut [DType]>
     ) {
         buff.clear();
         let size = nodes.iter().map(|n| n.value().len()).sum::<usize>();
         unsafe {
-            let mut s = &mut *space.get();
+            let s = &mut *space.get();
             while s.len() < size + 1 {
                 s.push(0.);
             }
-            (&mut s[..size]).fill(0.);
+            s[..size].fill(0.);
         }
 
         let mut offset = 0;
         for node in nodes {
             let len = node.value().len();
             unsafe {
-                buff.push(&mut (*space.get())[offset..offset+len]);
+                buff.push(&mut (&mut *space.get())[offset..offset+len]);
             }
             offset += len;
         }
     }
 
-    fn recurse(&mut self, node: &ANode, temp_grads: &mut Vec<&mut [DType]>, space: &UnsafeCell<Vec<DType>>) {
-        if !node.is_leaf() {
-            let node_grad = self.get_or_create_grad(node);
+    // Propagates to the children once a node's gradient is complete, which
+    // means every parent has already been processed. Values and gradients
+    // are released as soon as their node is done, provided the caller hands
+    // over the graph and holds no other references into it; only gradients
+    // of nodes requiring them are kept.
+    pub fn backward_consuming(&mut self, end_node: ANode) {
+        let out = Run::new(&end_node);
+        drop(end_node);
+
+        // Count parents of each node
+        let mut pending: HashMap<NodeIdx, usize> = HashMap::new();
+        let mut stack = vec![out.clone()];
+        while let Some(node) = stack.pop() {
             if let Some(children) = node.get_children() {
-                self.get_mut_slices(children, space, temp_grads);
+                for child in children.iter() {
+                    let count = pending.entry(child.get_id()).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        stack.push(child.clone());
+                    }
+                }
+            }
+        }
 
-                node.compute_grad(&node_grad, temp_grads.as_mut_slice());
+        let mut z_grad = self.get_or_create_grad(&out);
+        z_grad.fill(1.);
+        self.add_grad(&out, z_grad);
+        let live = live_nodes(&out);
 
-                if self.nan_check {
-                    for (i, grad) in temp_grads.iter().enumerate() {
-                        for gi in grad.iter() {
-                            if gi.is_nan() {
-                                eprintln!("Nan detected with id {:?}, child {}", node.get_id(), i);
-                                panic!()
-                            }
-                        }
+        let mut temp_grads = Vec::new();
+        let space = UnsafeCell::new(std::mem::take(&mut self.space));
+        let mut ready = vec![out];
+        while let Some(node) = ready.pop() {
+            if !node.is_leaf() && live.contains(&node.get_id()) {
+                self.propagate(&node, &mut temp_grads, &space, &live);
+            }
+            if let Some(children) = node.get_children() {
+                for child in children.iter() {
+                    let count = pending.get_mut(&child.get_id()).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(child.clone());
                     }
                 }
+            }
+        }
+        self.space = space.into_inner();
+        self.end_pass(None);
+    }
 
-                // Update grads
+    fn propagate(
+        &mut self,
+        node: &ANode,
+        temp_grads: &mut Vec<&mut [DType]>,
+        space: &UnsafeCell<Vec<DType>>,
+        live: &HashSet<NodeIdx>
+    ) {
+        let mut node_grad = self.get_or_create_grad(node);
+        if let Some(hooks) = self.hooks.get_mut(&node.get_id()) {
+            hooks.iter_mut().for_each(|h| h(&mut node_grad));
+        }
+        if let Some(children) = node.get_children() {
+            self.get_mut_slices(children, space, temp_grads);
 
-                // Re-add gradients
-                children.iter().zip(temp_grads.drain(..)).for_each(|(c, g)| {
-                    self.add_or_update_grad(c, g);
-                });
+            node.compute_grad(&node_grad, temp_grads.as_mut_slice());
 
-                if node.requires_grad() {
-                    self.add_grad(node, node_grad);
+            if self.nan_check {
+                for (i, grad) in temp_grads.iter().enumerate() {
+                    for gi in grad.iter() {
+                        if gi.is_nan() {
+                            eprintln!("Nan detected with id {:?}, child {}", node.get_id(), i);
+                            panic!()
+                        }
+                    }
                 }
+            }
 
-                // Run children
-                for child in children.iter() {
-                    self.recurse(child, temp_grads, space);
+            // Update grads
+
+            // Re-add gradients, skipping branches which can't lead to one
+            children.iter().zip(temp_grads.drain(..)).for_each(|(c, g)| {
+                if live.contains(&c.get_id()) {
+                    self.add_or_update_grad(c, g);
                 }
+            });
+
+        } else if let Some((table, rows)) = node.sparse_rows() {
+            self.add_sparse_grad(table, rows, &node_grad);
+        }
+
+        if node.requires_grad() {
+            self.add_grad(node, node_grad);
+        }
+    }
+
+    fn recurse(
+        &mut self,
+        node: &ANode,
+        temp_grads: &mut Vec<&mut [DType]>,
+        space: &UnsafeCell<Vec<DType>>,
+        live: &HashSet<NodeIdx>
+    ) {
+        // Nothing new has arrived if a previous visit already took the gradient
+        if !node.is_leaf() && self.gradients.contains_key(&node.get_id()) {
+            self.propagate(node, temp_grads, space, live);
 
-            } else {
-                if node.requires_grad() {
-                    self.add_grad(node, node_grad);
+            // Run children
+            if let Some(children) = node.get_children() {
+                for child in children.iter() {
+                    self.recurse(child, temp_grads, space, live);
                 }
             }
         }
     }
 
+}
 
+// Nodes under `root` with a path down to something collecting gradients;
+// backward has no reason to visit the rest.
+fn live_nodes(root: &ANode) -> HashSet<NodeIdx> {
+    let mut live = HashSet::new();
+    walk(root, &mut |node: &ANode| {
+        let leads = node.requires_grad() || node.sparse_rows().is_some() ||
+            node.get_children().unwrap_or(&[]).iter().any(|c| live.contains(&c.get_id()));
+        if leads {
+            live.insert(node.get_id());
+        }
+    });
+    live
+}
+
+// Builds the gradient of `end_node` with respect to every node under it out
+// of nodes, handing each to `emit` once complete: inner nodes as they're
+// reached, leaves at the end.
+fn grad_graphs<F: FnMut(&ANode, ANode)>(end_node: &ANode, mut emit: F) -> Result<(), GradGraphError> {
+    let dims = end_node.shape();
+    let ones = Constant::with_shape(vec![1.; dims.size()], dims.dims());
+    let mut grads: HashMap<NodeIdx, (ANode, ANode)> = HashMap::new();
+    grads.insert(end_node.get_id(), (end_node.clone(), ones));
+
+    let mut order = evaluation_order(end_node);
+    order.reverse();
+    for node in order.iter() {
+        let grad = match grads.remove(&node.get_id()) {
+            Some((_, g)) => g,
+            None => continue
+        };
+        let child_grads = match node.compute_grad_graph(&grad) {
+            Some(cg) => cg,
+            None => return Err(GradGraphError { op: node.op_name(), id: node.get_id() })
+        };
+        let children = node.get_children().unwrap_or(&[]);
+        for (child, cg) in children.iter().zip(child_grads) {
+            let g = match grads.remove(&child.get_id()) {
+                Some((_, prev)) => prev + cg,
+                None => cg
+            };
+            grads.insert(child.get_id(), (child.clone(), g));
+        }
+        emit(node, grad);
+    }
+
+    // Whatever remains belongs to leaves
+    for (_, (leaf, grad)) in grads {
+        emit(&leaf, grad);
+    }
+    Ok(())
+}
+
+// The derivative of `out`, summed over its elements, with respect to `x` as
+// an expression graph of its own. It can be printed, evaluated or itself
+// differentiated; `x` needn't require gradients.
+pub fn symbolic_grad(out: &ANode, x: &ANode) -> Result<ANode, GradGraphError> {
+    let mut dx = None;
+    grad_graphs(out, |node, grad| {
+        if node.get_id() == x.get_id() {
+            dx = Some(grad);
+        }
+    })?;
+    Ok(dx.unwrap_or_else(|| {
+        let dims = x.shape();
+        Constant::with_shape(vec![0.; dims.size()], dims.dims())
+    }))
 }
 
 pub(crate) struct Run(NodeIdx, Vec<ANode>);
@@ -183,7 +785,7 @@ impl Run {
 }
 
 impl Node for Run {
-    fn get_id(&self) -> NodeIdx { self.0.clone() }
+    fn get_id(&self) -> NodeIdx { self.0 }
 
     fn get_children(&self) -> Option<&[ANode]> { 
         Some(&self.1)
@@ -191,15 +793,51 @@ impl Node for Run {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.1[0].value()
+    fn value(&self) -> Value<'_> {
+        self.1[0].value()
+    }
+
+    fn shape(&self) -> Shape {
+        self.1[0].shape()
     }
 
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], results: &mut [&mut [DType]]) {
-        let mut out = &mut results[0];
-        out.fill(1f32);
+        results[0].copy_from_slice(grad);
+    }
+}
+
+// Joins the roots of backward_multi, handing each its weight as gradient
+struct Roots(NodeIdx, Vec<ANode>, Vec<DType>);
+
+impl Roots {
+    fn new(roots: &[(&ANode, DType)]) -> ANode {
+        let nodes = roots.iter().map(|(n, _)| (*n).clone()).collect();
+        let weights = roots.iter().map(|(_, w)| *w).collect();
+        ANode::new(Rc::new(Roots(NodeIdx::new(), nodes, weights)))
+    }
+}
+
+impl Node for Roots {
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(&self.1)
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> { Value::slice(&[]) }
+
+    fn shape(&self) -> Shape {
+        Shape::vector(0)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, _grad: &[DType], results: &mut [&mut [DType]]) {
+        results.iter_mut().zip(self.2.iter()).for_each(|(r, w)| r.fill(*w));
     }
 }
 
@@ -213,10 +851,6 @@ impl GraphStats {
     fn new(ops: usize, memory: usize) -> Self {
         GraphStats {ops, memory}
     }
-
-    fn zero() -> Self {
-        GraphStats::new(0, 0)
-    }
 }
 
 impl Add for GraphStats {
@@ -236,6 +870,357 @@ mod graph_tests {
     use super::*;
     use crate::*;
 
+    #[test]
+    fn test_sparse_grads() {
+        let table = Variable::with_shape(vec![0., 1., 2., 3., 4., 5.], &[3, 2]);
+        let rows = table.embedding(&[2, 0, 2]);
+        let out = rows * vec![1., 2.];
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        assert!(graph.get_grad(&table).is_none());
+        let sparse = graph.get_sparse_grad(&table).unwrap();
+        assert_eq!(sparse.len(), 2);
+        assert_eq!(sparse[&0].as_slice(), &[1., 2.]);
+        assert_eq!(sparse[&2].as_slice(), &[2., 4.]);
+    }
+
+    #[test]
+    fn test_backward_reusing() {
+        let x = Variable::new(vec![1., 2.]);
+        let mut graph = Graph::new();
+        let mut ptr = None;
+        for _ in 0..3 {
+            let out = (&x * &x).sum();
+            graph.backward_reusing(&out);
+            let grad = graph.get_grad(&x).unwrap();
+            assert_eq!(grad, &[2., 4.]);
+            if let Some(p) = ptr {
+                assert_eq!(p, grad.as_ptr());
+            }
+            ptr = Some(grad.as_ptr());
+        }
+    }
+
+    #[test]
+    fn test_backward_consuming() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = x.exp();
+        let weak = Rc::downgrade(&y.0);
+        let out = (&y * &y + &y).sum();
+        drop(y);
+
+        let mut expected = Graph::new();
+        expected.backward(&out);
+
+        let mut graph = Graph::new();
+        graph.backward_consuming(out);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(graph.get_grad(&x), expected.get_grad(&x));
+    }
+
+    #[test]
+    fn test_forward() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::scalar(2.);
+        let out = (&x * &c).sum();
+        assert_eq!(out.value(), &[6.]);
+
+        let mut graph = Graph::new();
+        x.set_value(&[3., 4.]);
+        c.set_value(&[0.5]);
+        graph.forward(&out);
+        assert_eq!(out.value(), &[3.5]);
+
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_freeze_topology() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = x.exp();
+        let out = (&y * &y + &y).sum();
+
+        let mut expected = Graph::new();
+        expected.backward(&out);
+
+        let mut graph = Graph::new();
+        graph.freeze_topology(&out);
+        for _ in 0..2 {
+            graph.zero_grads();
+            graph.backward(&out);
+            assert_eq!(graph.get_grad(&x), expected.get_grad(&x));
+        }
+    }
+
+    #[test]
+    fn test_backward_with_graph() {
+        // f(x) = sum(x^3 + sin(x) * x)
+        let x = Variable::new(vec![0.5, 2.]);
+        let out = ((&x * &x * &x) + x.sin() * &x).sum();
+
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&out).unwrap();
+        let dx = graph.get_grad_node(&x).unwrap().clone();
+        let expected = x.value().iter().map(|xi| 3. * xi * xi + xi.cos() * xi + xi.sin()).collect::<Vec<_>>();
+        for (a, b) in dx.value().iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert_eq!(graph.get_grad(&x).unwrap().as_slice(), &*dx.value());
+
+        // f''(x) = 6x + 2cos(x) - x sin(x)
+        let mut graph = Graph::new();
+        graph.backward(&dx.sum());
+        let ddx = graph.get_grad(&x).unwrap();
+        for (a, xi) in ddx.iter().zip(x.value().iter()) {
+            assert!((a - (6. * xi + 2. * xi.cos() - xi * xi.sin())).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_backward_with() {
+        let x = Variable::new(vec![1., 2.]);
+        let w = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[3, 2]);
+        let out = w.matmul(&x);
+
+        let mut graph = Graph::new();
+        graph.backward_with(&out, &[1., 0., -1.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[-4., -4.]);
+        assert_eq!(graph.get_grad(&w).unwrap(), &[1., 2., 0., 0., -1., -2.]);
+
+        // Frozen topologies honor the seed as well
+        let mut graph = Graph::new();
+        graph.freeze_topology(&out);
+        graph.backward_with(&out, &[0., 2., 0.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[6., 8.]);
+    }
+
+    #[test]
+    fn test_backward_multi() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3.]);
+        let h1 = (&x * &x).sum();
+        let h2 = (&x * &y).sum();
+
+        let mut graph = Graph::new();
+        graph.backward_multi(&[(&h1, 1.), (&h2, 0.5)]);
+
+        let mut expected = Graph::new();
+        expected.backward(&(h1 + h2 * 0.5));
+        assert_eq!(graph.get_grad(&x), expected.get_grad(&x));
+        assert_eq!(graph.get_grad(&y), expected.get_grad(&y));
+    }
+
+    #[test]
+    fn test_backward_wrt() {
+        let x = Variable::new(vec![1., 2.]);
+        let frozen = Variable::new(vec![3., 4.]);
+        let out = ((&x * &x) + frozen.exp() * &frozen).sum();
+
+        let mut graph = Graph::new();
+        graph.backward_wrt(&out, &[&x]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[2., 4.]);
+        assert_eq!(graph.get_grad(&frozen), None);
+
+        // Unrelated targets leave the graph untouched
+        let other = Variable::new(vec![1.]);
+        let mut graph = Graph::new();
+        graph.backward_wrt(&out, &[&other]);
+        assert_eq!(graph.get_grad(&x), None);
+    }
+
+    #[test]
+    fn test_accumulate_grads() {
+        let x = Variable::new(vec![1., 2.]);
+        // Intermediate nodes holding gradients mustn't be propagated twice
+        let h = (&x * 2.).require_grad();
+        let batches = [vec![1., 1.], vec![2., 3.]];
+
+        let mut graph = Graph::new();
+        graph.accumulate_grads(true);
+        for b in batches.iter() {
+            let out = (&h * &Constant::new(b.clone())).sum();
+            graph.backward(&out);
+        }
+        assert_eq!(graph.get_grad(&h).unwrap(), &[3., 4.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[6., 8.]);
+
+        graph.zero_grads();
+        graph.backward(&(&x * &x).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[2., 4.]);
+    }
+
+    #[test]
+    fn test_grad_norms() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![2.]);
+        let out = (&x * 3.).sum() + (&y * 4.).sum();
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        let (mut norms, total) = graph.grad_norms();
+        norms.sort_by_key(|(idx, _)| *idx);
+        assert_eq!(norms.len(), 2);
+        assert!((norms[0].1 - (18 as DType).sqrt()).abs() < 1e-5);
+        assert_eq!(norms[1], (y.get_id(), 4.));
+        assert!((total - (34 as DType).sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_take_grads() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![2.]);
+        let out = (&x * &y).sum();
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.grads_iter().count(), 2);
+
+        let mut grads: Vec<_> = graph.take_grads().collect();
+        grads.sort_by_key(|(idx, _)| *idx);
+        assert_eq!(grads, vec![(x.get_id(), vec![2., 2.]), (y.get_id(), vec![3.])]);
+        assert_eq!(graph.get_grad(&x), None);
+    }
+
+    #[test]
+    fn test_register_hook() {
+        let x = Variable::new(vec![1., -2.]);
+        let h = &x * 3.;
+        let out = (&h * &h).sum();
+
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let mut graph = Graph::new();
+        graph.register_hook(&h, move |g| {
+            log.borrow_mut().extend_from_slice(g);
+            g.iter_mut().for_each(|gi| *gi = gi.clamp(-1., 1.));
+        });
+        graph.register_hook(&x, |g| g.iter_mut().for_each(|gi| *gi *= 2.));
+        graph.backward(&out);
+
+        assert_eq!(*seen.borrow(), vec![6., -12.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[6., -6.]);
+    }
+
+    #[test]
+    fn test_hook_sees_whole_grad() {
+        let x = Variable::new(vec![1.]);
+        let y = &x * 3.;
+        let z = (&y + y.exp()).sum();
+        let expected = 1. + (3 as DType).exp();
+
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let mut graph = Graph::new();
+        graph.register_hook(&y, move |g| {
+            log.borrow_mut().extend_from_slice(g);
+            g.iter_mut().for_each(|gi| *gi = gi.clamp(-2., 2.));
+        });
+        graph.backward(&z);
+        assert_eq!(seen.borrow().len(), 1);
+        assert!((seen.borrow()[0] - expected).abs() < 1e-4);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[6.]);
+
+        // Same again over a frozen topology
+        graph.zero_grads();
+        graph.freeze_topology(&z);
+        graph.backward(&z);
+        assert_eq!(seen.borrow().len(), 2);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[6.]);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::new(vec![3., 4.]);
+        let out = (&x * &c).sum();
+
+        let dot = Graph::new().to_dot(&out);
+        let (xi, ci, oi) = (x.get_id().0, c.get_id().0, out.get_id().0);
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(&format!("n{} [label=\"Variable\\n[2]\", shape=box, style=filled];", xi)));
+        assert!(dot.contains(&format!("n{} [label=\"Constant\\n[2]\", shape=box];", ci)));
+        assert!(dot.contains(&format!("n{} [label=\"SumVec\\n[1]\", shape=ellipse];", oi)));
+        assert_eq!(dot.matches("->").count(), 3);
+    }
+
+    #[test]
+    fn test_prune_dead_branches() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::new(vec![3., 4.]);
+        let dead = c.exp() * 2.;
+        let out = (&x * &dead).sum() + dead.sum();
+
+        let live = live_nodes(&out);
+        assert!(live.contains(&x.get_id()));
+        assert!(!live.contains(&dead.get_id()));
+        assert!(!live.contains(&c.get_id()));
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap().as_slice(), &*dead.value());
+        assert_eq!(graph.grads_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_jacobian() {
+        let x = Variable::new(vec![1., 2.]);
+        let w = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[3, 2]);
+        let out = w.matmul(&x) * &x.sum();
+
+        // d/dx_j (Wx)_i * s = W_ij * s + (Wx)_i
+        let jac = Graph::new().jacobian(&out, &x);
+        let wx = [5., 11., 17.];
+        assert_eq!(jac.len(), 3);
+        for (i, row) in jac.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                let expected = w.value()[i * 2 + j] * 3. + wx[i];
+                assert!((v - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hvp() {
+        // f(x, y) = x^2 y + y^3, H = [[2y, 2x], [2x, 6y]]
+        let x = Variable::new(vec![1.5, -2.]);
+        let xs = x.slice(0, 1);
+        let ys = x.slice(1, 1);
+        let loss = (&xs * &xs * &ys + &ys * &ys * &ys).sum();
+
+        let hv = Graph::new().hvp(&loss, &x, &[1., 2.]).unwrap();
+        let (a, b) = (1.5, -2.);
+        assert!((hv[0] - (2. * b + 2. * 2. * a)).abs() < 1e-4);
+        assert!((hv[1] - (2. * a + 2. * 6. * b)).abs() < 1e-4);
+
+        // Linear losses have no curvature
+        let loss = (&x * 3.).sum();
+        assert_eq!(Graph::new().hvp(&loss, &x, &[1., 1.]).unwrap(), vec![0., 0.]);
+    }
+
+    #[test]
+    fn test_jvp() {
+        let x = Variable::new(vec![0.5, 2.]);
+        let w = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let out = w.matmul(x.tanh()) * &x;
+
+        // Compare against reverse mode, one output at a time
+        let dir = [1., -1.];
+        let t = Graph::new().jvp(&out, &[(&x, &dir)]);
+        for (i, ti) in t.iter().enumerate() {
+            let mut graph = Graph::new();
+            graph.backward(&out.slice(i, 1));
+            let g = graph.get_grad(&x).unwrap();
+            let expected = g[0] * dir[0] + g[1] * dir[1];
+            assert!((ti - expected).abs() < 1e-5);
+        }
+
+        // Unseeded leaves are constant
+        assert_eq!(Graph::new().jvp(&out, &[]), vec![0., 0.]);
+    }
+
     #[test]
     fn test_add() {
         let x = Variable::new(vec![0., 1.]);
@@ -246,4 +1231,45 @@ mod graph_tests {
         assert_eq!(stats.ops, 3);
         assert_eq!(stats.memory, 6);
     }
+    #[test]
+    fn test_symbolic_grad() {
+        // f(x) = sum(x^3 * w), with w frozen
+        let x = Variable::new(vec![0.5, 2.]);
+        let w = Variable::new(vec![1., 3.]);
+        w.set_requires_grad(false);
+        let out = (x.cube() * &w).sum();
+
+        // 3x^2 w, then 6x w
+        let dx = symbolic_grad(&out, &x).unwrap();
+        assert_eq!(dx.value(), &[0.75, 36.]);
+        let ddx = symbolic_grad(&dx.sum(), &x).unwrap();
+        assert_eq!(ddx.value(), &[3., 36.]);
+
+        // Frozen leaves and inner nodes work too
+        assert_eq!(symbolic_grad(&out, &w).unwrap().value(), &[0.125, 8.]);
+        let cubed = x.cube();
+        let out = (&cubed * 2.).sum();
+        assert_eq!(symbolic_grad(&out, &cubed).unwrap().value(), &[2., 2.]);
+
+        // The derivative is a graph which follows the inputs
+        let dx = symbolic_grad(&out, &x).unwrap();
+        assert!(dx.node_count() > 1);
+        x.set_value(&[1., 3.]);
+        assert_eq!(Graph::new().compile(&dx).forward().value(), &[6., 54.]);
+
+        // Nodes out of reach get zeros
+        let y = Variable::new(vec![1., 2., 3.]);
+        assert_eq!(symbolic_grad(&out, &y).unwrap().value(), &[0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_grad_graph_unsupported() {
+        let x = Variable::new(vec![1., 2.]);
+        let m = x.map(|v| v * 2.);
+        let out = (&m * &x).sum();
+        let err = symbolic_grad(&out, &x).err().unwrap();
+        assert_eq!(err, GradGraphError { op: m.op_name(), id: m.get_id() });
+        assert!(Graph::new().backward_with_graph(&out).is_err());
+        assert!(Graph::new().hvp(&out, &x, &[1., 1.]).is_err());
+    }
 }
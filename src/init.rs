@@ -0,0 +1,112 @@
+use crate::{ANode,Variable,DType};
+use crate::rand::Rng;
+
+impl Variable {
+    // Standard normal values
+    pub fn randn(len: usize, seed: u64) -> ANode {
+        normal(&[len], 0., 1., seed)
+    }
+
+    // Uniform values in [0, 1)
+    pub fn rand(len: usize, seed: u64) -> ANode {
+        uniform(&[len], 0., 1., seed)
+    }
+}
+
+// Values in [low, high). Rounding can land a draw on `high` itself, in which
+// case it's drawn again.
+pub fn uniform(dims: &[usize], low: DType, high: DType, seed: u64) -> ANode {
+    let mut rng = Rng::new(seed);
+    let values = (0..dims.iter().product())
+        .map(|_| loop {
+            let v = low + (high - low) * rng.uniform() as DType;
+            if v < high || high <= low {
+                break v
+            }
+        })
+        .collect();
+    Variable::with_shape(values, dims)
+}
+
+pub fn normal(dims: &[usize], mean: DType, std: DType, seed: u64) -> ANode {
+    let mut rng = Rng::new(seed);
+    let values = (0..dims.iter().product())
+        .map(|_| mean + std * rng.normal() as DType)
+        .collect();
+    Variable::with_shape(values, dims)
+}
+
+// The following return [fan_out, fan_in] weights, as Linear expects.
+
+// Glorot: U(-a, a) with a = sqrt(6 / (fan_in + fan_out)), keeping activation
+// variance steady through tanh-like layers
+pub fn xavier_uniform(fan_in: usize, fan_out: usize, seed: u64) -> ANode {
+    let a = (6. / (fan_in + fan_out) as DType).sqrt();
+    uniform(&[fan_out, fan_in], -a, a, seed)
+}
+
+pub fn xavier_normal(fan_in: usize, fan_out: usize, seed: u64) -> ANode {
+    let std = (2. / (fan_in + fan_out) as DType).sqrt();
+    normal(&[fan_out, fan_in], 0., std, seed)
+}
+
+// He: N(0, 2 / fan_in), for layers followed by relus
+pub fn kaiming_normal(fan_in: usize, fan_out: usize, seed: u64) -> ANode {
+    let std = (2. / fan_in as DType).sqrt();
+    normal(&[fan_out, fan_in], 0., std, seed)
+}
+
+pub fn kaiming_uniform(fan_in: usize, fan_out: usize, seed: u64) -> ANode {
+    let a = (6. / fan_in as DType).sqrt();
+    uniform(&[fan_out, fan_in], -a, a, seed)
+}
+
+#[cfg(test)]
+mod init_tests {
+    use super::*;
+
+    fn moments(xs: &[DType]) -> (DType, DType) {
+        let n = xs.len() as DType;
+        let mean = xs.iter().sum::<DType>() / n;
+        (mean, xs.iter().map(|x| (x - mean) * (x - mean)).sum::<DType>() / n)
+    }
+
+    #[test]
+    fn test_seeded() {
+        let x = Variable::randn(100, 1);
+        assert_eq!(x.shape().dims(), &[100]);
+        assert_eq!(x.value(), Variable::randn(100, 1).value());
+        assert_ne!(x.value(), Variable::randn(100, 2).value());
+
+        let u = Variable::rand(100, 1);
+        assert!(u.value().iter().all(|v| (0. ..1.).contains(v)));
+    }
+
+    #[test]
+    fn test_uniform_excludes_high() {
+        // One step apart, so about half the draws round up to high
+        let low: DType = 1e8;
+        let high = DType::from_bits(low.to_bits() + 1);
+        let x = uniform(&[1000], low, high, 5);
+        assert!(x.value().iter().all(|v| *v == low));
+    }
+
+    #[test]
+    fn test_fan_scaling() {
+        let w = xavier_uniform(30, 20, 3);
+        assert_eq!(w.shape().dims(), &[20, 30]);
+        let a = (6. / 50. as DType).sqrt();
+        assert!(w.value().iter().all(|v| v.abs() <= a));
+
+        let w = kaiming_normal(200, 100, 3);
+        assert_eq!(w.shape().dims(), &[100, 200]);
+        let (mean, var) = moments(&w.value());
+        assert!(mean.abs() < 0.01);
+        assert!((var - 0.01).abs() < 0.001);
+
+        let (_, var) = moments(&xavier_normal(200, 200, 4).value());
+        assert!((var - 0.005).abs() < 0.0005);
+        let (_, var) = moments(&kaiming_uniform(200, 100, 5).value());
+        assert!((var - 0.01).abs() < 0.001);
+    }
+}
@@ -0,0 +1,130 @@
+// Conversions to and from other crates' arrays. Values are copied in row
+// major order either way, and like Vec<DType> arrays convert to constants.
+
+#[cfg(feature = "ndarray")]
+mod ndarray_conv {
+    use ::ndarray::{Array1,ArrayD,IxDyn};
+
+    use crate::{ANode,Constant,DType};
+
+    impl From<Array1<DType>> for ANode {
+        fn from(a: Array1<DType>) -> ANode {
+            Constant::new(a.into_iter().collect())
+        }
+    }
+
+    impl From<ArrayD<DType>> for ANode {
+        fn from(a: ArrayD<DType>) -> ANode {
+            // Zero dimensional arrays become SimpleGrad's [1] scalars
+            let dims = if a.ndim() == 0 { vec![1] } else { a.shape().to_vec() };
+            Constant::with_shape(a.iter().copied().collect(), &dims)
+        }
+    }
+
+    impl ANode {
+        pub fn to_ndarray(&self) -> ArrayD<DType> {
+            ArrayD::from_shape_vec(IxDyn(self.shape().dims()), self.value().to_vec())
+                .expect("Node shape always matches its value length!")
+        }
+    }
+}
+
+// nalgebra stores matrices column major, so they are transposed on the way
+// in and out
+#[cfg(feature = "nalgebra")]
+mod nalgebra_conv {
+    use ::nalgebra::{DMatrix,DVector};
+
+    use crate::{ANode,Constant,DType};
+
+    impl From<DVector<DType>> for ANode {
+        fn from(v: DVector<DType>) -> ANode {
+            Constant::new(v.as_slice().to_vec())
+        }
+    }
+
+    impl From<DMatrix<DType>> for ANode {
+        fn from(m: DMatrix<DType>) -> ANode {
+            Constant::with_shape(m.transpose().as_slice().to_vec(), &[m.nrows(), m.ncols()])
+        }
+    }
+
+    impl ANode {
+        pub fn to_dvector(&self) -> DVector<DType> {
+            match self.shape().dims() {
+                [_] => DVector::from_column_slice(&self.value()),
+                dims => panic!("to_dvector expects a 1-D node, got {:?}!", dims)
+            }
+        }
+
+        pub fn to_dmatrix(&self) -> DMatrix<DType> {
+            match self.shape().dims() {
+                [r, c] => DMatrix::from_row_slice(*r, *c, &self.value()),
+                dims => panic!("to_dmatrix expects a 2-D node, got {:?}!", dims)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod interop_tests {
+    use crate::{ANode,Graph,Variable};
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_ndarray() {
+        use ::ndarray::{arr0,arr1,arr2};
+
+        let x: ANode = arr1(&[1., 2., 3.]).into();
+        assert_eq!(x.shape().dims(), &[3]);
+        assert_eq!(x.value(), &[1., 2., 3.]);
+
+        // Transposed views are read in logical order
+        let m = arr2(&[[1., 2., 3.], [4., 5., 6.]]).reversed_axes().into_dyn();
+        let t: ANode = m.clone().into();
+        assert_eq!(t.shape().dims(), &[3, 2]);
+        assert_eq!(t.value(), &[1., 4., 2., 5., 3., 6.]);
+        assert_eq!(t.to_ndarray(), m);
+
+        let s: ANode = arr0(4.).into_dyn().into();
+        assert_eq!(s.value(), &[4.]);
+
+        let w = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let out = w.matmul(x.slice(0, 2)).sum();
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&out).unwrap();
+        let grad = graph.get_grad_node(&w).unwrap().to_ndarray();
+        assert_eq!(grad, arr2(&[[1., 2.], [1., 2.]]).into_dyn());
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn test_nalgebra() {
+        use ::nalgebra::{DMatrix,DVector};
+
+        let m = DMatrix::from_row_slice(2, 3, &[1., 2., 3., 4., 5., 6.]);
+        let a: ANode = m.clone().into();
+        assert_eq!(a.shape().dims(), &[2, 3]);
+        assert_eq!(a.value(), &[1., 2., 3., 4., 5., 6.]);
+        assert_eq!(a.to_dmatrix(), m);
+
+        let v: ANode = DVector::from_column_slice(&[1., -1., 2.]).into();
+        assert_eq!(a.matmul(&v).to_dvector(), &m * DVector::from_column_slice(&[1., -1., 2.]));
+
+        // Gradients of |Mx|^2 with respect to x, 2 M^T M x
+        let x = Variable::new(vec![1., 0., -1.]);
+        let y = a.matmul(&x);
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&(&y * &y).sum()).unwrap();
+        let grad = graph.get_grad_node(&x).unwrap().to_dvector();
+        let xv = x.to_dvector();
+        assert_eq!(grad, m.transpose() * &m * xv * 2.);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    #[should_panic(expected = "to_dmatrix expects a 2-D node")]
+    fn test_nalgebra_shape_mismatch() {
+        Variable::new(vec![1., 2.]).to_dmatrix();
+    }
+}
@@ -0,0 +1,87 @@
+use hashbrown::{HashMap,HashSet};
+
+use crate::{ANode,NodeIdx};
+
+pub trait Visitor {
+    fn visit(&mut self, node: &ANode);
+}
+
+impl <F: FnMut(&ANode)> Visitor for F {
+    fn visit(&mut self, node: &ANode) {
+        self(node)
+    }
+}
+
+// Visits every node under `root` once, leaves included, children before
+// parents.
+pub(crate) fn walk<V: Visitor + ?Sized>(root: &ANode, visitor: &mut V) {
+    let mut seen = HashSet::new();
+    let mut stack = vec![(root.clone(), false)];
+    while let Some((node, expanded)) = stack.pop() {
+        if expanded {
+            visitor.visit(&node);
+        } else if seen.insert(node.get_id()) {
+            let children = node.get_children().map(|c| c.to_vec()).unwrap_or_default();
+            stack.push((node, true));
+            stack.extend(children.into_iter().rev().map(|c| (c, false)));
+        }
+    }
+}
+
+// Longest path in edges from `root` down to a leaf
+pub(crate) fn depth(root: &ANode) -> usize {
+    let mut depths: HashMap<NodeIdx, usize> = HashMap::new();
+    walk(root, &mut |node: &ANode| {
+        let d = node.get_children().unwrap_or(&[]).iter()
+            .map(|c| depths[&c.get_id()] + 1)
+            .max()
+            .unwrap_or(0);
+        depths.insert(node.get_id(), d);
+    });
+    depths[&root.get_id()]
+}
+
+// Reverse edges of a graph, since nodes only know their children
+pub struct Parents(HashMap<NodeIdx, Vec<ANode>>);
+
+impl Parents {
+    pub(crate) fn new(root: &ANode) -> Self {
+        let mut parents: HashMap<NodeIdx, Vec<ANode>> = HashMap::new();
+        walk(root, &mut |node: &ANode| {
+            for child in node.get_children().unwrap_or(&[]).iter() {
+                parents.entry(child.get_id()).or_default().push(node.clone());
+            }
+        });
+        Parents(parents)
+    }
+
+    pub fn get(&self, node: &ANode) -> &[ANode] {
+        self.0.get(&node.get_id()).map(|p| p.as_slice()).unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod introspect_tests {
+    use super::*;
+    use crate::{Variable,Constant};
+
+    #[test]
+    fn test_walk() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::new(vec![3., 4.]);
+        let h = &x * &c;
+        let out = (&h + &h.exp()).sum();
+
+        let mut names = Vec::new();
+        out.walk(&mut |n: &ANode| names.push(n.op_name()));
+        assert_eq!(names, vec!["Variable", "Constant", "Multiply", "Exp", "AddN", "SumVec"]);
+        assert_eq!(out.node_count(), 6);
+        assert_eq!(out.depth(), 4);
+        assert_eq!(x.depth(), 0);
+
+        let parents = out.parents();
+        let ids: Vec<_> = parents.get(&h).iter().map(|p| p.op_name()).collect();
+        assert_eq!(ids, vec!["Exp", "AddN"]);
+        assert!(parents.get(&out).is_empty());
+    }
+}
@@ -2,31 +2,108 @@
 
 //trace_macros!(true);
 
+// Op constructors hand back the ANode wrapping the op rather than the op
+#![allow(clippy::new_ret_no_self)]
+
 mod graph;
 mod vecops;
 mod ops;
 mod pool;
-
-pub use graph::Graph;
-pub use ops::{Variable,Constant};
-pub use pool::{clear_pool, use_shared_pool, MPVec};
+mod shape;
+mod parallel;
+mod plan;
+mod display;
+mod introspect;
+mod optimize;
+mod state;
+mod onnx;
+mod npy;
+mod rand;
+mod domain;
+mod check;
+mod parse;
+mod tape;
+pub mod optim;
+pub mod losses;
+pub mod nn;
+pub mod init;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(all(feature = "fastmath", not(feature = "f64")))]
+mod fastmath;
+#[cfg(feature = "blas")]
+mod blas;
+
+#[cfg(feature = "half")]
+mod storage;
+#[cfg(any(feature = "ndarray", feature = "nalgebra"))]
+mod interop;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+// Features which can't work on wasm32 fail here rather than at link or run time
+#[cfg(all(target_arch = "wasm32", feature = "blas"))]
+compile_error!("The blas feature links a system CBLAS and is not available on wasm32");
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics"), feature = "rayon"))]
+compile_error!("The rayon feature needs threads, which this wasm32 target lacks");
+
+pub use graph::{Graph,GradGraphError,symbolic_grad};
+pub use check::{gradcheck, GradCheck};
+pub use parse::{parse_expr, ParseError};
+pub use tape::Tape;
+pub use plan::Plan;
+pub use state::StateDict;
+pub use introspect::{Visitor,Parents};
+pub use ops::{Variable,Constant,Value,use_inplace_forward};
+pub use pool::{clear_pool, use_shared_pool, set_pool_limit, pool_stats, PoolStats, MPVec};
+pub use shape::{Shape,ShapeError};
+pub use domain::{DomainPolicy,DomainError,set_domain_policy,domain_policy};
+pub use vecops::{Summation, set_summation, summation};
+pub use parallel::{set_parallel_threshold, parallel_threshold};
+#[cfg(feature = "serde")]
+pub use serialize::{SavedGraph, LoadedGraph, SavedGraphError};
+#[cfg(feature = "half")]
+pub use storage::{HalfTable, HalfFormat};
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::rc::Rc;
-use std::ops::{Add,Sub,Mul,Div,Deref,Neg};
+use std::cell::Cell;
+use std::ops::{Add,Sub,Mul,Div,Deref,Neg,AddAssign,SubAssign,MulAssign,DivAssign};
 
 use crate::ops::*;
 
 static GLOBAL_HANDLE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+// Ids are handed to each thread in blocks, so threads building graphs at the
+// same time only touch the shared counter once per block.
+const ID_BLOCK: usize = 1024;
+
+thread_local! {
+    static NEXT_ID: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
 #[derive(Clone,Copy,Eq,Hash,PartialEq,Ord,PartialOrd,Debug)]
 pub struct NodeIdx(usize);
 
-type DType = f32;
+#[cfg(not(feature = "f64"))]
+pub type DType = f32;
+
+#[cfg(feature = "f64")]
+pub type DType = f64;
 
 impl NodeIdx {
     fn new() -> Self {
-        NodeIdx(GLOBAL_HANDLE_COUNT.fetch_add(1, Ordering::SeqCst))
+        NEXT_ID.with(|ids| {
+            let (mut next, mut end) = ids.get();
+            if next == end {
+                next = GLOBAL_HANDLE_COUNT.fetch_add(ID_BLOCK, Ordering::Relaxed);
+                end = next + ID_BLOCK;
+            }
+            ids.set((next + 1, end));
+            NodeIdx(next)
+        })
     }
 }
 
@@ -38,15 +115,89 @@ pub trait Node {
 
     fn get_children(&self) -> Option<&[ANode]>;
 
-    fn value(&self) -> &[DType];
+    // Nodes whose values this one depends on without passing gradients back
+    // to them, such as the source of a detach. Forward passes recompute them
+    // first.
+    fn value_sources(&self) -> Option<&[ANode]> { None }
+
+    // Borrows the node's values; see Value. This used to return &[DType]:
+    // values now live behind a RefCell so plans can recompute them in place.
+    // Value derefs to a slice, so callers that need one can write
+    // `&*node.value()`, and implementations wrap borrowed slices with
+    // Value::slice.
+    fn value(&self) -> Value<'_>;
+
+    fn shape(&self) -> Shape {
+        Shape::vector(self.value().len())
+    }
 
     fn requires_grad(&self) -> bool;
 
+    // Name of the op, for printing graphs
+    fn op_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    // Nodes which gather rows of a leaf table can report their gradients
+    // sparsely, by row, rather than through a table-sized dense buffer.
+    fn sparse_rows(&self) -> Option<(NodeIdx, &[usize])> { None }
+
+    // Re-evaluates the value from the children's current values
+    fn recompute(&self) { }
+
+    // Replaces a leaf's value; dependent nodes pick it up when recomputed.
+    // Like update_value, panics while the value is borrowed.
+    fn set_value(&self, _value: &[DType]) {
+        panic!("Only leaf values can be set!");
+    }
+
+    // Edits a variable's value in place
+    fn update_value(&self, _f: &mut dyn FnMut(&mut [DType])) {
+        panic!("Only variables can be updated!");
+    }
+
+    // Toggles whether a leaf collects gradients
+    fn set_requires_grad(&self, _requires_grad: bool) {
+        panic!("Only variables can be frozen!");
+    }
+
+    fn op_args(&self) -> OpArgs { OpArgs::default() }
+
+    // Leaves whose value is fixed, which optimization passes may bake in
+    fn is_constant(&self) -> bool { false }
+
+    // Builds the same op over new children. None if the op can't be rebuilt,
+    // such as those holding closures.
+    fn rebuild(&self, _children: &[ANode]) -> Option<ANode> { None }
+
+    // Builds the gradients of the children as nodes themselves, so they can
+    // be differentiated again. None if the op doesn't support it.
+    fn compute_grad_graph(&self, _grad: &ANode) -> Option<Vec<ANode>> { None }
+
+    // Forward mode: the tangent of this node given the tangents of its
+    // children. None if the op doesn't support it.
+    fn compute_jvp(&self, _tangents: &[&[DType]]) -> Option<MPVec> { None }
+
+    // Whether a leaf under this node collects gradients, in which case its
+    // value can't be given up for in-place forward evaluation
+    fn leads_to_grad(&self) -> bool {
+        self.requires_grad() || self.sparse_rows().is_some() ||
+            self.get_children().unwrap_or(&[]).iter().any(|c| c.leads_to_grad())
+    }
+
+    // Gives up the node's value buffer for in-place forward evaluation
+    fn take_value(&mut self) -> Option<MPVec> { None }
+
     //fn compute_grad(&self, _grad: &[DType], _results: &mut [MPVec]) { }
     fn compute_grad(&self, _grad: &[DType], _results: &mut [&mut [DType]]) { }
 
 }
 
+// Nodes, and the Graphs holding their gradients, are neither Send nor Sync:
+// they share values through Rc and Cell, and draw on per thread memory pools
+// and settings. Worker threads can each build and differentiate graphs of
+// their own at the same time, passing plain values between them.
 #[derive(Clone)]
 pub struct ANode(Rc<dyn Node>);
 
@@ -55,8 +206,107 @@ impl ANode {
         ANode(n)
     }
 
-    pub fn dot(&self, other: &ANode) -> ANode {
-        SumVec::new(self * other)
+    pub fn dot<N: Into<ANode>>(&self, other: N) -> ANode {
+        SumVec::new(self * other.into())
+    }
+
+    pub fn matmul<N: Into<ANode>>(&self, other: N) -> ANode {
+        MatMul::new(self.clone(), other.into())
+    }
+
+    pub fn batch_matmul<N: Into<ANode>>(&self, other: N) -> ANode {
+        BatchMatMul::new(self.clone(), other.into())
+    }
+
+    pub fn outer<N: Into<ANode>>(&self, other: N) -> ANode {
+        Outer::new(self.clone(), other.into())
+    }
+
+    // Elementwise ops returning an error, rather than panicking, when the
+    // shapes don't broadcast
+    pub fn try_add<N: Into<ANode>>(&self, other: N) -> Result<ANode, ShapeError> {
+        self.try_binary("AddN", other.into(), AddN::new)
+    }
+
+    pub fn try_sub<N: Into<ANode>>(&self, other: N) -> Result<ANode, ShapeError> {
+        self.try_binary("Subtract", other.into(), Subtract::new)
+    }
+
+    pub fn try_mul<N: Into<ANode>>(&self, other: N) -> Result<ANode, ShapeError> {
+        self.try_binary("Multiply", other.into(), Multiply::new)
+    }
+
+    pub fn try_div<N: Into<ANode>>(&self, other: N) -> Result<ANode, ShapeError> {
+        self.try_binary("Divide", other.into(), Divide::new)
+    }
+
+    fn try_binary<F>(&self, op: &'static str, other: ANode, f: F) -> Result<ANode, ShapeError>
+    where
+        F: FnOnce(ANode, ANode) -> ANode
+    {
+        check_broadcast(op, self, &other)?;
+        Ok(f(self.clone(), other))
+    }
+
+    // ln, division and powers returning an error for inputs outside their
+    // domain, whatever the domain policy
+    pub fn checked_ln(&self) -> Result<ANode, DomainError> {
+        domain::check_ln(self)?;
+        Ok(self.ln())
+    }
+
+    pub fn checked_div<N: Into<ANode>>(&self, other: N) -> Result<ANode, DomainError> {
+        let other = other.into();
+        domain::check_div(&other)?;
+        Ok(self / other)
+    }
+
+    pub fn checked_pow<N: Into<ANode>>(&self, exp: N) -> Result<ANode, DomainError> {
+        let exp = exp.into();
+        domain::check_pow(self, &exp)?;
+        Ok(self.pow(exp))
+    }
+
+    // Copies this graph with the given leaves swapped for new ones of the
+    // same shape, recomputing everything they feed. The original is untouched.
+    pub fn substitute(&self, pairs: &[(&ANode, &ANode)]) -> ANode {
+        optimize::substitute(self, pairs)
+    }
+
+    pub fn transpose(&self) -> ANode {
+        Transpose::new(self.clone())
+    }
+
+    pub fn permute(&self, axes: &[usize]) -> ANode {
+        Permute::new(self.clone(), axes)
+    }
+
+    pub fn reshape(&self, dims: &[usize]) -> ANode {
+        Reshape::new(self.clone(), dims)
+    }
+
+    pub fn conv2d<N: Into<ANode>>(&self, filters: N, stride: usize) -> ANode {
+        Conv2d::new(self.clone(), filters.into(), stride)
+    }
+
+    pub fn max_pool2d(&self, kernel: usize, stride: usize) -> ANode {
+        MaxPool2d::new(self.clone(), kernel, stride)
+    }
+
+    pub fn avg_pool2d(&self, kernel: usize, stride: usize) -> ANode {
+        AvgPool2d::new(self.clone(), kernel, stride)
+    }
+
+    pub fn embedding(&self, rows: &[usize]) -> ANode {
+        Embedding::new(self.clone(), rows)
+    }
+
+    pub fn diag(&self) -> ANode {
+        Diag::new(self.clone())
+    }
+
+    pub fn trace(&self) -> ANode {
+        self.diag().sum()
     }
 
     pub fn ln(&self) -> ANode {
@@ -68,7 +318,7 @@ impl ANode {
     }
 
     pub fn sin(&self) -> ANode {
-        Cos::new(self.clone())
+        Sin::new(self.clone())
     }
 
     pub fn tanh(&self) -> ANode {
@@ -79,33 +329,228 @@ impl ANode {
         Exp::new(self.clone())
     }
 
+    // max(0, x), with a gradient of 0 at 0
+    pub fn relu(&self) -> ANode {
+        Maximum::new(Constant::scalar(0.), self.clone())
+    }
+
+    // Softmax over the last axis
+    pub fn softmax(&self) -> ANode {
+        Softmax::new(self.clone())
+    }
+
+    // x ^ c for a constant c; unlike pow, negative bases work with integer
+    // exponents
+    pub fn pow_scalar(&self, c: DType) -> ANode {
+        PowScalar::new(self.clone(), c)
+    }
+
+    pub fn square(&self) -> ANode {
+        self.pow_scalar(2.)
+    }
+
+    pub fn cube(&self) -> ANode {
+        self.pow_scalar(3.)
+    }
+
+    // Replaces NaNs and infinities, zeroing their gradients
+    pub fn nan_to_num(&self, nan: DType, posinf: DType, neginf: DType) -> ANode {
+        NanToNum::new(self.clone(), nan, posinf, neginf)
+    }
+
     pub fn sum(&self) -> ANode {
         SumVec::new(self.clone())
     }
 
+    pub fn sum_axis(&self, axis: usize) -> ANode {
+        SumAxis::new(self.clone(), axis)
+    }
+
+    pub fn broadcast_to(&self, dims: &[usize]) -> ANode {
+        BroadcastTo::new(self.clone(), dims)
+    }
+
+    pub fn sum_to(&self, dims: &[usize]) -> ANode {
+        SumTo::new(self.clone(), dims)
+    }
+
+    // Updates a variable's values in place. Nodes already built on it keep
+    // their values until recomputed or rebuilt. Panics while its value is
+    // borrowed.
+    pub fn update<F: FnOnce(&mut [DType])>(&self, f: F) {
+        let mut f = Some(f);
+        self.0.update_value(&mut |v| if let Some(f) = f.take() { f(v) });
+    }
+
+    // Stops a variable from collecting gradients
+    pub fn freeze(&self) {
+        self.0.set_requires_grad(false);
+    }
+
+    pub fn unfreeze(&self) {
+        self.0.set_requires_grad(true);
+    }
+
+    // Visits each node of the graph once, children before parents
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        introspect::walk(self, visitor)
+    }
+
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        self.walk(&mut |_: &ANode| count += 1);
+        count
+    }
+
+    pub fn depth(&self) -> usize {
+        introspect::depth(self)
+    }
+
+    pub fn parents(&self) -> Parents {
+        Parents::new(self)
+    }
+
+    // Same value, but a leaf without gradient: backward stops here
+    pub fn detach(&self) -> ANode {
+        Detach::new(self.clone())
+    }
+
+    // Applies `f` going forward while passing gradients through untouched
+    pub fn straight_through<F>(&self, f: F) -> ANode
+    where
+        F: Fn(DType) -> DType + 'static
+    {
+        StraightThrough::new(self.clone(), f)
+    }
+
+    // Applies `f` elementwise, differentiating it numerically
+    pub fn map<F>(&self, f: F) -> ANode
+    where
+        F: Fn(DType) -> DType + 'static
+    {
+        Map::new(self.clone(), Box::new(f), None)
+    }
+
+    // Applies `f` elementwise with `df` as its derivative
+    pub fn map_with_grad<F, D>(&self, f: F, df: D) -> ANode
+    where
+        F: Fn(DType) -> DType + 'static,
+        D: Fn(DType) -> DType + 'static
+    {
+        Map::new(self.clone(), Box::new(f), Some(Box::new(df)))
+    }
+
+    // Replaces the gradient of the subgraph from `inputs` up to this node
+    // with `f(inputs, output, grad, input_grads)`
+    pub fn with_custom_grad<F>(&self, inputs: &[ANode], f: F) -> ANode
+    where
+        F: Fn(&[&[DType]], &[DType], &[DType], &mut [&mut [DType]]) + 'static
+    {
+        CustomGrad::new(self.clone(), inputs, f)
+    }
+
+    // Identity going forward; gradients come back scaled by -lambda
+    pub fn grad_reverse(&self, lambda: DType) -> ANode {
+        GradReverse::new(self.clone(), lambda)
+    }
+
     pub fn slice(&self, start: usize, len: usize) -> ANode {
         Slice::new(self.clone(), start, len)
     }
 
+    pub fn split(&self, sizes: &[usize]) -> Vec<ANode> {
+        let total = sizes.iter().sum::<usize>();
+        if total != self.value().len() {
+            panic!("Split sizes sum to {} but vector has length {}!", total, self.value().len());
+        }
+        let mut start = 0;
+        sizes.iter().map(|len| {
+            let s = self.slice(start, *len);
+            start += len;
+            s
+        }).collect()
+    }
+
+    pub fn flip(&self) -> ANode {
+        Flip::new(self.clone())
+    }
+
+    pub fn repeat(&self, n: usize) -> ANode {
+        Repeat::new(self.clone(), n)
+    }
+
+    // Splits into at most `n` pieces of equal length, the last of which may
+    // be shorter; an empty vector gives none.
+    pub fn chunk(&self, n: usize) -> Vec<ANode> {
+        if n == 0 {
+            panic!("Cannot split a vector into 0 chunks!");
+        }
+        let len = self.value().len();
+        if len == 0 {
+            return Vec::new()
+        }
+        let size = len.div_ceil(n);
+        let mut sizes = vec![size; len / size];
+        if !len.is_multiple_of(size) {
+            sizes.push(len % size);
+        }
+        self.split(&sizes)
+    }
+
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     fn require_grad(self) -> ANode {
         ANode(Rc::new(RequiresGrad::new(self.0)))
     }
 
 }
 
+// Plain values become constants
+impl From<DType> for ANode {
+    fn from(v: DType) -> ANode {
+        Constant::scalar(v)
+    }
+}
+
+impl From<Vec<DType>> for ANode {
+    fn from(v: Vec<DType>) -> ANode {
+        Constant::new(v)
+    }
+}
+
+impl From<&[DType]> for ANode {
+    fn from(v: &[DType]) -> ANode {
+        Constant::new(v.to_vec())
+    }
+}
+
+impl From<&ANode> for ANode {
+    fn from(n: &ANode) -> ANode {
+        n.clone()
+    }
+}
+
+// Values usable directly as the right hand side of ANode operators. Kept
+// apart from Into<ANode> since ANode converts into itself, which would
+// overlap the ANode-ANode operator impls.
 trait FromConstant {
     fn convert(self) -> ANode; 
 }
 
-impl FromConstant for f32 {
+impl FromConstant for DType {
+    fn convert(self) -> ANode {
+        self.into()
+    }
+}
+
+impl FromConstant for Vec<DType> {
     fn convert(self) -> ANode {
-        Constant::scalar(self)
+        self.into()
     }
 }
 
-impl FromConstant for Vec<f32> {
+impl FromConstant for &[DType] {
     fn convert(self) -> ANode {
-        Constant::new(self)
+        self.into()
     }
 }
 
@@ -178,14 +623,14 @@ impl Add for ANode {
     }
 }
 
-impl Add<ANode> for f32 {
+impl Add<ANode> for DType {
     type Output = ANode;
     fn add(self, rhs: ANode) -> Self::Output {
         rhs + self.convert()
     }
 }
 
-impl Add<ANode> for Vec<f32> {
+impl Add<ANode> for Vec<DType> {
     type Output = ANode;
     fn add(self, rhs: ANode) -> Self::Output {
         rhs + self.convert()
@@ -194,8 +639,8 @@ impl Add<ANode> for Vec<f32> {
 
 convert_binops! { impl Add, add for ANode, ANode }
 forward_ref_binop! { impl Add, add for ANode, ANode }
-forward_ref_binop! { impl Add, add for f32, ANode }
-forward_ref_binop! { impl Add, add for Vec<f32>, ANode }
+forward_ref_binop! { impl Add, add for DType, ANode }
+forward_ref_binop! { impl Add, add for Vec<DType>, ANode }
 
 impl Sub for ANode {
     type Output = ANode;
@@ -204,14 +649,14 @@ impl Sub for ANode {
     }
 }
 
-impl Sub<ANode> for f32 {
+impl Sub<ANode> for DType {
     type Output = ANode;
     fn sub(self, rhs: ANode) -> Self::Output {
         self.convert() - rhs
     }
 }
 
-impl Sub<ANode> for Vec<f32> {
+impl Sub<ANode> for Vec<DType> {
     type Output = ANode;
     fn sub(self, rhs: ANode) -> Self::Output {
         self.convert() - rhs
@@ -220,8 +665,8 @@ impl Sub<ANode> for Vec<f32> {
 
 convert_binops! { impl Sub, sub for ANode, ANode }
 forward_ref_binop! { impl Sub, sub for ANode, ANode }
-forward_ref_binop! { impl Sub, sub for f32, ANode }
-forward_ref_binop! { impl Sub, sub for Vec<f32>, ANode }
+forward_ref_binop! { impl Sub, sub for DType, ANode }
+forward_ref_binop! { impl Sub, sub for Vec<DType>, ANode }
 
 impl Mul for ANode {
     type Output = ANode;
@@ -230,14 +675,14 @@ impl Mul for ANode {
     }
 }
 
-impl Mul<ANode> for f32 {
+impl Mul<ANode> for DType {
     type Output = ANode;
     fn mul(self, rhs: ANode) -> Self::Output {
         self.convert() * rhs
     }
 }
 
-impl Mul<ANode> for Vec<f32> {
+impl Mul<ANode> for Vec<DType> {
     type Output = ANode;
     fn mul(self, rhs: ANode) -> Self::Output {
         self.convert() * rhs
@@ -246,8 +691,8 @@ impl Mul<ANode> for Vec<f32> {
 
 convert_binops! {    impl Mul, mul for ANode, ANode }
 forward_ref_binop! { impl Mul, mul for ANode, ANode }
-forward_ref_binop! { impl Mul, mul for f32, ANode }
-forward_ref_binop! { impl Mul, mul for Vec<f32>, ANode }
+forward_ref_binop! { impl Mul, mul for DType, ANode }
+forward_ref_binop! { impl Mul, mul for Vec<DType>, ANode }
 
 impl Div for ANode {
     type Output = ANode;
@@ -256,14 +701,14 @@ impl Div for ANode {
     }
 }
 
-impl Div<ANode> for f32 {
+impl Div<ANode> for DType {
     type Output = ANode;
     fn div(self, rhs: ANode) -> Self::Output {
         self.convert() / rhs
     }
 }
 
-impl Div<ANode> for Vec<f32> {
+impl Div<ANode> for Vec<DType> {
     type Output = ANode;
     fn div(self, rhs: ANode) -> Self::Output {
         self.convert() / rhs
@@ -272,8 +717,24 @@ impl Div<ANode> for Vec<f32> {
 
 convert_binops!    { impl Div, div for ANode, ANode }
 forward_ref_binop! { impl Div, div for ANode, ANode }
-forward_ref_binop! { impl Div, div for f32, ANode }
-forward_ref_binop! { impl Div, div for Vec<f32>, ANode }
+forward_ref_binop! { impl Div, div for DType, ANode }
+forward_ref_binop! { impl Div, div for Vec<DType>, ANode }
+
+// `x += y` rebinds x to a new node; the node x used to be is untouched
+macro_rules! assign_binop {
+    (impl $imp:ident, $method:ident, $op:tt) => {
+        impl <N: Into<ANode>> $imp<N> for ANode {
+            fn $method(&mut self, rhs: N) {
+                *self = self.clone() $op rhs.into();
+            }
+        }
+    };
+}
+
+assign_binop! { impl AddAssign, add_assign, + }
+assign_binop! { impl SubAssign, sub_assign, - }
+assign_binop! { impl MulAssign, mul_assign, * }
+assign_binop! { impl DivAssign, div_assign, / }
 
 impl Neg for ANode {
     type Output = ANode;
@@ -301,14 +762,14 @@ impl Pow for ANode {
     }
 }
 
-impl Pow<ANode> for f32 {
+impl Pow<ANode> for DType {
     type Output = ANode;
     fn pow(self, rhs: ANode) -> Self::Output {
         self.convert().pow(rhs)
     }
 }
 
-impl Pow<ANode> for Vec<f32> {
+impl Pow<ANode> for Vec<DType> {
     type Output = ANode;
     fn pow(self, rhs: ANode) -> Self::Output {
         self.convert().pow(rhs)
@@ -317,12 +778,25 @@ impl Pow<ANode> for Vec<f32> {
 
 convert_binops!    { impl Pow, pow for ANode, ANode }
 forward_ref_binop! { impl Pow, pow for ANode, ANode }
-forward_ref_binop! { impl Pow, pow for f32, ANode }
-forward_ref_binop! { impl Pow, pow for Vec<f32>, ANode }
+forward_ref_binop! { impl Pow, pow for DType, ANode }
+forward_ref_binop! { impl Pow, pow for Vec<DType>, ANode }
+
+pub fn concat(nodes: &[ANode]) -> ANode {
+    Concat::new(nodes.to_vec())
+}
+
+pub fn stack(nodes: &[ANode]) -> ANode {
+    Concat::stack(nodes.to_vec())
+}
+
+pub fn einsum(subscripts: &str, operands: &[ANode]) -> ANode {
+    Einsum::new(subscripts, operands.to_vec())
+}
 
 pub trait BulkOps {
     fn sum_all(self) -> ANode;
     fn concat(self) -> ANode;
+    fn stack(self) -> ANode;
 }
 
 impl BulkOps for Vec<ANode> {
@@ -334,6 +808,10 @@ impl BulkOps for Vec<ANode> {
         Concat::new(self)
     }
 
+    fn stack(self) -> ANode {
+        Concat::stack(self)
+    }
+
 }
 
 impl BulkOps for Vec<&ANode> {
@@ -342,10 +820,66 @@ impl BulkOps for Vec<&ANode> {
     }
 
     fn concat(self) -> ANode {
-        let n = self.into_iter().map(|n| n.clone()).collect();
+        let n = self.into_iter().cloned().collect();
         Concat::new(n)
     }
 
+    fn stack(self) -> ANode {
+        let n = self.into_iter().cloned().collect();
+        Concat::stack(n)
+    }
+
+}
+
+// Collects terms with `acc += node` and sums them in a single BulkSum node,
+// rather than the chain of binary adds folding with `+` would build.
+#[derive(Clone,Default)]
+pub struct Accumulator {
+    terms: Vec<ANode>
+}
+
+impl Accumulator {
+    pub fn new() -> Self {
+        Accumulator { terms: Vec::new() }
+    }
+
+    pub fn push<N: Into<ANode>>(&mut self, node: N) {
+        let node = node.into();
+        if let Some(first) = self.terms.first() {
+            if first.shape() != node.shape() {
+                panic!("Cannot accumulate shape {:?} into {:?}!", node.shape(), first.shape());
+            }
+        }
+        self.terms.push(node);
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn finish(self) -> ANode {
+        match self.terms.len() {
+            0 => panic!("Cannot finish an empty Accumulator!"),
+            1 => self.terms.into_iter().next().unwrap(),
+            _ => BulkSum::new(self.terms.into_iter())
+        }
+    }
+}
+
+impl <N: Into<ANode>> AddAssign<N> for Accumulator {
+    fn add_assign(&mut self, rhs: N) {
+        self.push(rhs);
+    }
+}
+
+impl <N: Into<ANode>> SubAssign<N> for Accumulator {
+    fn sub_assign(&mut self, rhs: N) {
+        self.push(-rhs.into());
+    }
 }
 
 pub trait MaximumOps<Rhs=Self> {
@@ -0,0 +1,262 @@
+use crate::{ANode,DType};
+use crate::ops::{Loss,LossKind};
+
+// Mean squared error between `pred` and `target`, which must have the same
+// number of values
+pub fn mse(pred: &ANode, target: &ANode) -> ANode {
+    Loss::new(vec![pred.clone(), target.clone()], LossKind::Mse)
+}
+
+// Mean absolute error. Its gradient is taken as 0 where pred equals target.
+pub fn mae(pred: &ANode, target: &ANode) -> ANode {
+    Loss::new(vec![pred.clone(), target.clone()], LossKind::Mae)
+}
+
+// Binary cross entropy between sigmoid(logits) and targets in [0, 1],
+// averaged over elements. Computed from the logits directly so it stays
+// finite however large they get.
+pub fn bce_with_logits(logits: &ANode, targets: &ANode) -> ANode {
+    Loss::new(vec![logits.clone(), targets.clone()], LossKind::BceWithLogits)
+}
+
+// KL(q || p) = sum(q * (ln(q) - ln(p))), taking `log_p` as log probabilities
+// and `q` as probabilities, like torch's kl_div. Entries where q is 0
+// contribute nothing.
+pub fn kl_div(log_p: &ANode, q: &ANode) -> ANode {
+    Loss::new(vec![log_p.clone(), q.clone()], LossKind::KlDiv)
+}
+
+// -sum(p * ln(p)), with 0 ln(0) taken as 0
+pub fn entropy(p: &ANode) -> ANode {
+    Loss::new(vec![p.clone()], LossKind::Entropy)
+}
+
+// mean(max(0, 1 - target * pred)) for targets of -1 or 1
+pub fn hinge_loss(pred: &ANode, target: &ANode) -> ANode {
+    let n = pred.value().len() as DType;
+    (1. - target * pred).relu().sum() / n
+}
+
+// mean(max(0, margin - target * (x1 - x2))): with a target of 1, x1 should
+// rank above x2 by at least `margin`, and below it with -1
+pub fn margin_ranking_loss(x1: &ANode, x2: &ANode, target: &ANode, margin: DType) -> ANode {
+    let n = x1.value().len() as DType;
+    (margin - target * (x1 - x2)).relu().sum() / n
+}
+
+// Softmax cross entropy between `logits`, one row of class scores per
+// target, and the target class indices, averaged over rows. The log-softmax
+// is computed with the max shifted out, so large logits don't overflow.
+pub fn cross_entropy(logits: &ANode, targets: &[usize]) -> ANode {
+    Loss::new(vec![logits.clone()], LossKind::CrossEntropy(targets.to_vec()))
+}
+
+// Negative log likelihood of the target classes given rows of log
+// probabilities, averaged over rows
+pub fn nll_loss(log_probs: &ANode, targets: &[usize]) -> ANode {
+    Loss::new(vec![log_probs.clone()], LossKind::Nll(targets.to_vec()))
+}
+
+#[cfg(test)]
+mod losses_tests {
+    use super::*;
+    use crate::{Graph,Variable,Constant};
+
+    fn assert_close(a: &[DType], b: &[DType]) {
+        assert_eq!(a.len(), b.len());
+        for (ai, bi) in a.iter().zip(b.iter()) {
+            assert!((ai - bi).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_mse_mae() {
+        let pred = Variable::new(vec![1., 2., 4.]);
+        let target = Constant::new(vec![1., 3., 2.]);
+
+        let loss = mse(&pred, &target);
+        assert_close(&loss.value(), &[5. / 3.]);
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&pred).unwrap(), &[0., -2. / 3., 4. / 3.]);
+
+        // Matches the same loss built from primitive ops
+        let diff = &pred - &target;
+        let naive = (&diff * &diff).sum() / 3.;
+        let mut expected = Graph::new();
+        expected.backward(&naive);
+        assert_close(graph.get_grad(&pred).unwrap(), expected.get_grad(&pred).unwrap());
+
+        let loss = mae(&pred, &target);
+        assert_close(&loss.value(), &[1.]);
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&pred).unwrap(), &[0., -1. / 3., 1. / 3.]);
+
+        // Forward mode agrees with the gradient
+        let tangent = [1., 1., 1.];
+        let jvp = Graph::new().jvp(&mse(&pred, &target), &[(&pred, &tangent[..])]);
+        assert_close(&jvp, &[2. / 3.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_length_mismatch() {
+        mse(&Variable::new(vec![1., 2.]), &Constant::new(vec![1.]));
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let logits = Variable::with_shape(vec![1., 2., 3., 1000., 0., -1000.], &[2, 3]);
+        let loss = cross_entropy(&logits, &[2, 0]);
+
+        // The second row would overflow a naive softmax
+        let first = (1. as DType).exp() + (2. as DType).exp() + (3. as DType).exp();
+        let expected = (first.ln() - 3.) / 2.;
+        assert_close(&loss.value(), &[expected]);
+
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        let grad = graph.get_grad(&logits).unwrap();
+        let e = |x: DType| x.exp() / first / 2.;
+        assert_close(grad, &[e(1.), e(2.), e(3.) - 0.5, 0., 0., 0.]);
+        assert!(grad.iter().all(|g| g.is_finite()));
+
+        // NLL over log probabilities picks out and negates the targets
+        let log_probs = Variable::with_shape(vec![-0.1, -2.5, -3., -0.2], &[2, 2]);
+        let loss = nll_loss(&log_probs, &[0, 1]);
+        assert_close(&loss.value(), &[0.15]);
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&log_probs).unwrap(), &[-0.5, 0., 0., -0.5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_target_out_of_range() {
+        cross_entropy(&Variable::new(vec![1., 2.]), &[2]);
+    }
+
+    #[test]
+    fn test_bce_with_logits() {
+        let logits = Variable::new(vec![0., 2., -1., 500., -500.]);
+        let targets = Constant::new(vec![1., 0., 0.5, 0., 0.]);
+        let loss = bce_with_logits(&logits, &targets);
+
+        let sigmoid = |x: DType| 1. / (1. + (-x).exp());
+        let bce = |x: DType, y: DType| -(y * sigmoid(x).ln() + (1. - y) * (1. - sigmoid(x)).ln());
+        // A saturated, wrong logit costs its own magnitude
+        let expected = (bce(0., 1.) + bce(2., 0.) + bce(-1., 0.5) + 500. + 0.) / 5.;
+        assert!((loss.value()[0] - expected).abs() < 1e-3);
+
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        let grad = graph.get_grad(&logits).unwrap();
+        let expected: Vec<DType> = [(0., 1.), (2., 0.), (-1., 0.5), (500., 0.), (-500., 0.)].iter()
+            .map(|(x, y)| (sigmoid(*x) - y) / 5.)
+            .collect();
+        assert_close(grad, &expected);
+        assert!(grad.iter().all(|g| g.is_finite()));
+    }
+
+    #[test]
+    fn test_kl_div_entropy() {
+        let log_p = Variable::new(vec![(0.5 as DType).ln(), (0.25 as DType).ln(), (0.25 as DType).ln()]);
+        let q = Variable::new(vec![0.5, 0.5, 0.]);
+        let loss = kl_div(&log_p, &q);
+        assert_close(&loss.value(), &[0.5 * (2. as DType).ln()]);
+
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&log_p).unwrap(), &[-0.5, -0.5, 0.]);
+        let ln2 = (2. as DType).ln();
+        assert_close(graph.get_grad(&q).unwrap(), &[1., ln2 + 1., 0.]);
+
+        let p = Variable::new(vec![0.5, 0.5, 0.]);
+        let h = entropy(&p);
+        assert_close(&h.value(), &[ln2]);
+        let mut graph = Graph::new();
+        graph.backward(&h);
+        assert_close(graph.get_grad(&p).unwrap(), &[ln2 - 1., ln2 - 1., 0.]);
+    }
+
+    #[test]
+    fn test_hinge_margin_ranking() {
+        let pred = Variable::new(vec![2., 0.5, -1., 1.]);
+        let target = Constant::new(vec![1., 1., 1., -1.]);
+        let loss = hinge_loss(&pred, &target);
+        // Margins of 2, 0.5, -1 and -1; the first is past the hinge
+        assert_close(&loss.value(), &[(0. + 0.5 + 2. + 2.) / 4.]);
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&pred).unwrap(), &[0., -0.25, -0.25, 0.25]);
+
+        let x1 = Variable::new(vec![1., 0., 3.]);
+        let x2 = Variable::new(vec![0., 1., 2.]);
+        let target = Constant::new(vec![1., 1., -1.]);
+        let loss = margin_ranking_loss(&x1, &x2, &target, 0.5);
+        assert_close(&loss.value(), &[(0. + 1.5 + 1.5) / 3.]);
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        let third = 1. / 3.;
+        assert_close(graph.get_grad(&x1).unwrap(), &[0., -third, third]);
+        assert_close(graph.get_grad(&x2).unwrap(), &[0., third, -third]);
+
+        // Exactly at the margin the loss is flat
+        let x = Variable::new(vec![1.]);
+        let loss = hinge_loss(&x, &Constant::new(vec![1.]));
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        assert_close(graph.get_grad(&x).unwrap(), &[0.]);
+    }
+
+    #[test]
+    fn test_grad_graphs() {
+        // Gradients built from nodes match the direct ones
+        let x = Variable::with_shape(vec![0.5, -1., 2., 0.], &[2, 2]);
+        let y = Variable::new(vec![0.25, 0.5, 0., 0.25]);
+        let losses = [
+            mse(&x, &y),
+            mae(&x, &y),
+            bce_with_logits(&x, &y),
+            kl_div(&x, &y),
+            entropy(&y),
+            cross_entropy(&x, &[1, 0]),
+            nll_loss(&x, &[0, 1])
+        ];
+        for loss in losses.iter() {
+            let (mut g1, mut g2) = (Graph::new(), Graph::new());
+            g1.backward(loss);
+            g2.backward_with_graph(loss).unwrap();
+            for n in [&x, &y] {
+                match (g1.get_grad(n), g2.get_grad(n)) {
+                    (Some(a), Some(b)) => assert_close(a, b),
+                    (a, b) => assert_eq!(a.is_some(), b.is_some())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hvp() {
+        // The Hessian of mse is 2/n I
+        let pred = Variable::new(vec![1., 2., 4.]);
+        let target = Constant::new(vec![1., 3., 2.]);
+        let hv = Graph::new().hvp(&mse(&pred, &target), &pred, &[1., -1., 3.]).unwrap();
+        assert_close(&hv, &[2. / 3., -2. / 3., 2.]);
+
+        // For a single row of cross entropy, diag(s) - s s^T
+        let logits = Variable::new(vec![1., 2., 3.]);
+        let s = logits.softmax().value().to_vec();
+        let v = [1., 0., -1.];
+        let sv: DType = s.iter().zip(v.iter()).map(|(si, vi)| si * vi).sum();
+        let expected: Vec<DType> = s.iter().zip(v.iter()).map(|(si, vi)| si * (vi - sv)).collect();
+        let hv = Graph::new().hvp(&cross_entropy(&logits.reshape(&[1, 3]), &[2]), &logits, &v).unwrap();
+        assert_close(&hv, &expected);
+
+        // Piecewise linear losses are flat
+        let target = Constant::new(vec![1., 1., -1.]);
+        let hv = Graph::new().hvp(&hinge_loss(&pred, &target), &pred, &[1., 1., 1.]).unwrap();
+        assert_close(&hv, &[0., 0., 0.]);
+    }
+}
@@ -0,0 +1,862 @@
+use std::cell::{Cell,RefCell};
+
+use crate::{ANode,Constant,DType,Pow};
+use crate::rand::Rng;
+
+// A layer: builds its part of the graph for an input and lists the variables
+// it owns, for handing to an optimizer.
+pub trait Module {
+    fn forward(&self, x: &ANode) -> ANode;
+
+    fn parameters(&self) -> Vec<ANode>;
+
+    // Switches between training and eval behavior, for layers like Dropout
+    // and BatchNorm that have any; a no-op for the rest.
+    fn set_training(&self, _training: bool) {}
+
+    fn train(&self) {
+        self.set_training(true);
+    }
+
+    fn eval(&self) {
+        self.set_training(false);
+    }
+}
+
+// y = W x + b, with `weight` of shape [out, in] and `bias` of shape [out].
+// Inputs may be a single vector of shape [in] or a batch of shape [n, in].
+pub struct Linear {
+    pub weight: ANode,
+    pub bias: ANode
+}
+
+impl Linear {
+    pub fn new(weight: ANode, bias: ANode) -> Self {
+        let dims = weight.shape().dims().to_vec();
+        if dims.len() != 2 || bias.shape().dims() != [dims[0]] {
+            panic!("Linear needs a [out, in] weight and [out] bias, got {:?} and {:?}!",
+                dims, bias.shape().dims());
+        }
+        Linear { weight, bias }
+    }
+
+    pub fn in_features(&self) -> usize {
+        self.weight.shape().dims()[1]
+    }
+
+    pub fn out_features(&self) -> usize {
+        self.weight.shape().dims()[0]
+    }
+}
+
+impl Module for Linear {
+    fn forward(&self, x: &ANode) -> ANode {
+        match x.shape().dims() {
+            [_] => self.weight.matmul(x) + &self.bias,
+            [_, _] => x.matmul(self.weight.transpose()) + &self.bias,
+            dims => panic!("Linear takes inputs of shape [in] or [n, in], got {:?}!", dims)
+        }
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        vec![self.weight.clone(), self.bias.clone()]
+    }
+}
+
+// Row lookups into a [vocab, dim] table. Only the rows looked up receive
+// gradients, which the optimizers apply sparsely.
+pub struct Embedding {
+    pub weight: ANode
+}
+
+impl Embedding {
+    pub fn new(weight: ANode) -> Self {
+        if weight.shape().dims().len() != 2 {
+            panic!("Embedding needs a [vocab, dim] weight, got {:?}!", weight.shape().dims());
+        }
+        Embedding { weight }
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.weight.shape().dims()[0]
+    }
+
+    pub fn dim(&self) -> usize {
+        self.weight.shape().dims()[1]
+    }
+
+    // Returns a [rows.len(), dim] node
+    pub fn lookup(&self, rows: &[usize]) -> ANode {
+        self.weight.embedding(rows)
+    }
+}
+
+impl Module for Embedding {
+    // Reads the row indices from the input's values
+    fn forward(&self, x: &ANode) -> ANode {
+        let rows: Vec<usize> = x.value().iter().map(|r| *r as usize).collect();
+        self.lookup(&rows)
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        vec![self.weight.clone()]
+    }
+}
+
+// Normalizes `x` over everything broadcast onto `dims`, returning the
+// normalized values along with the mean and (biased) variance
+fn normalize(x: &ANode, dims: &[usize], eps: DType) -> (ANode, ANode, ANode) {
+    let count = (x.value().len() / dims.iter().product::<usize>()) as DType;
+    let mean = x.sum_to(dims) / count;
+    let centered = x - &mean;
+    let var = (&centered * &centered).sum_to(dims) / count;
+    let x_hat = centered / (&var + eps).pow(0.5);
+    (x_hat, mean, var)
+}
+
+// Normalizes each input over its last axis, then scales and shifts it by
+// `weight` and `bias`, both of shape [d].
+pub struct LayerNorm {
+    pub weight: ANode,
+    pub bias: ANode,
+    pub eps: DType
+}
+
+impl LayerNorm {
+    pub fn new(weight: ANode, bias: ANode) -> Self {
+        let dims = weight.shape().dims().to_vec();
+        if dims.len() != 1 || bias.shape().dims() != dims {
+            panic!("LayerNorm needs [d] weight and bias, got {:?} and {:?}!",
+                dims, bias.shape().dims());
+        }
+        LayerNorm { weight, bias, eps: 1e-5 }
+    }
+}
+
+impl Module for LayerNorm {
+    fn forward(&self, x: &ANode) -> ANode {
+        let mut dims = x.shape().dims().to_vec();
+        if dims.last() != Some(&self.weight.value().len()) {
+            panic!("LayerNorm over {:?} got input of shape {:?}!",
+                self.weight.shape().dims(), dims);
+        }
+        *dims.last_mut().unwrap() = 1;
+        let (x_hat, _, _) = normalize(x, &dims, self.eps);
+        x_hat * &self.weight + &self.bias
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        vec![self.weight.clone(), self.bias.clone()]
+    }
+}
+
+// Normalizes each channel over the batch, for inputs of shape [n, c] or
+// [n, c, h, w]. While training, batch statistics are used and folded into
+// the running mean and variance; in eval mode the running statistics are.
+pub struct BatchNorm {
+    pub weight: ANode,
+    pub bias: ANode,
+    pub eps: DType,
+    pub momentum: DType,
+    running_mean: RefCell<Vec<DType>>,
+    running_var: RefCell<Vec<DType>>,
+    training: Cell<bool>
+}
+
+impl BatchNorm {
+    pub fn new(weight: ANode, bias: ANode) -> Self {
+        let dims = weight.shape().dims().to_vec();
+        if dims.len() != 1 || bias.shape().dims() != dims {
+            panic!("BatchNorm needs [c] weight and bias, got {:?} and {:?}!",
+                dims, bias.shape().dims());
+        }
+        BatchNorm {
+            weight, bias, eps: 1e-5, momentum: 0.1,
+            running_mean: RefCell::new(vec![0.; dims[0]]),
+            running_var: RefCell::new(vec![1.; dims[0]]),
+            training: Cell::new(true)
+        }
+    }
+
+    pub fn is_training(&self) -> bool {
+        self.training.get()
+    }
+
+    pub fn running_mean(&self) -> Vec<DType> {
+        self.running_mean.borrow().clone()
+    }
+
+    pub fn running_var(&self) -> Vec<DType> {
+        self.running_var.borrow().clone()
+    }
+}
+
+impl Module for BatchNorm {
+    fn forward(&self, x: &ANode) -> ANode {
+        let c = self.weight.value().len();
+        let dims = match x.shape().dims() {
+            [_, xc] if *xc == c => vec![c],
+            [_, xc, _, _] if *xc == c => vec![c, 1, 1],
+            dims => panic!("BatchNorm over {} channels got input of shape {:?}!", c, dims)
+        };
+        let x_hat = if self.is_training() {
+            let (x_hat, mean, var) = normalize(x, &dims, self.eps);
+            // Running variance is kept unbiased
+            let count = (x.value().len() / c) as DType;
+            let unbias = if count > 1. { count / (count - 1.) } else { 1. };
+            let m = self.momentum;
+            let mut running_mean = self.running_mean.borrow_mut();
+            running_mean.iter_mut().zip(mean.value().iter())
+                .for_each(|(r, v)| *r = (1. - m) * *r + m * v);
+            let mut running_var = self.running_var.borrow_mut();
+            running_var.iter_mut().zip(var.value().iter())
+                .for_each(|(r, v)| *r = (1. - m) * *r + m * v * unbias);
+            x_hat
+        } else {
+            let mean = Constant::with_shape(self.running_mean(), &dims);
+            let var = Constant::with_shape(self.running_var(), &dims);
+            (x - mean) / (var + self.eps).pow(0.5)
+        };
+        x_hat * self.weight.reshape(&dims) + self.bias.reshape(&dims)
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        vec![self.weight.clone(), self.bias.clone()]
+    }
+
+    fn set_training(&self, training: bool) {
+        self.training.set(training);
+    }
+}
+
+// Zeroes each input with probability `p` while training, scaling the rest by
+// 1 / (1 - p) so eval mode, where it is the identity, sees the same
+// expectation. The mask is a constant factor, so it gates the gradient too.
+pub struct Dropout {
+    p: DType,
+    rng: RefCell<Rng>,
+    training: Cell<bool>
+}
+
+impl Dropout {
+    pub fn new(p: DType) -> Self {
+        Dropout::with_seed(p, 0x5EED)
+    }
+
+    pub fn with_seed(p: DType, seed: u64) -> Self {
+        if !(0. ..1.).contains(&p) {
+            panic!("Dropout probability must be in [0, 1), got {}!", p);
+        }
+        Dropout { p, rng: RefCell::new(Rng::new(seed)), training: Cell::new(true) }
+    }
+
+    pub fn p(&self) -> DType {
+        self.p
+    }
+
+    pub fn is_training(&self) -> bool {
+        self.training.get()
+    }
+}
+
+impl Module for Dropout {
+    fn forward(&self, x: &ANode) -> ANode {
+        if !self.is_training() || self.p == 0. {
+            return x.clone()
+        }
+        let scale = 1. / (1. - self.p);
+        let mut rng = self.rng.borrow_mut();
+        let mask = (0..x.value().len())
+            .map(|_| if (rng.uniform() as DType) < self.p { 0. } else { scale })
+            .collect();
+        x * Constant::with_shape(mask, x.shape().dims())
+    }
+
+    fn parameters(&self) -> Vec<ANode> { Vec::new() }
+
+    fn set_training(&self, training: bool) {
+        self.training.set(training);
+    }
+}
+
+// 1 / (1 + e^-x), written through tanh so large inputs don't overflow
+fn sigmoid(x: &ANode) -> ANode {
+    (x * 0.5).tanh() * 0.5 + 0.5
+}
+
+// Splits the last axis of [g] or [n, g] gates into `k` equal parts
+fn split_gates(gates: &ANode, k: usize) -> Vec<ANode> {
+    match gates.shape().dims() {
+        [g] => gates.split(&vec![g / k; k]),
+        [n, g] => {
+            let (n, h) = (*n, g / k);
+            let t = gates.transpose();
+            (0..k).map(|i| t.slice(i * h * n, h * n).reshape(&[h, n]).transpose()).collect()
+        },
+        dims => panic!("Expected gates of shape [g] or [n, g], got {:?}!", dims)
+    }
+}
+
+fn check_cell(name: &str, input: &Linear, hidden: &Linear, gates: usize) -> usize {
+    let size = hidden.in_features();
+    if input.out_features() != gates * size || hidden.out_features() != gates * size {
+        panic!("{} needs input and hidden projections to {} x {} gates, got {} and {}!",
+            name, gates, size, input.out_features(), hidden.out_features());
+    }
+    size
+}
+
+// h' = tanh(W_ih x + b_ih + W_hh h + b_hh). Inputs are [in] or [n, in] and
+// hidden states [hidden] or [n, hidden].
+pub struct RNNCell {
+    pub input: Linear,
+    pub hidden: Linear
+}
+
+impl RNNCell {
+    pub fn new(input: Linear, hidden: Linear) -> Self {
+        check_cell("RNNCell", &input, &hidden, 1);
+        RNNCell { input, hidden }
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden.in_features()
+    }
+
+    pub fn forward(&self, x: &ANode, h: &ANode) -> ANode {
+        (self.input.forward(x) + self.hidden.forward(h)).tanh()
+    }
+
+    pub fn parameters(&self) -> Vec<ANode> {
+        let mut params = self.input.parameters();
+        params.extend(self.hidden.parameters());
+        params
+    }
+}
+
+// Projections stack the input, forget, cell and output gates, in that order.
+// The state is (h, c).
+pub struct LSTMCell {
+    pub input: Linear,
+    pub hidden: Linear
+}
+
+impl LSTMCell {
+    pub fn new(input: Linear, hidden: Linear) -> Self {
+        check_cell("LSTMCell", &input, &hidden, 4);
+        LSTMCell { input, hidden }
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden.in_features()
+    }
+
+    pub fn forward(&self, x: &ANode, state: &(ANode, ANode)) -> (ANode, ANode) {
+        let (h, c) = state;
+        let gates = self.input.forward(x) + self.hidden.forward(h);
+        let gates = split_gates(&gates, 4);
+        let (i, f) = (sigmoid(&gates[0]), sigmoid(&gates[1]));
+        let (g, o) = (gates[2].tanh(), sigmoid(&gates[3]));
+        let c = f * c + i * g;
+        (o * c.tanh(), c)
+    }
+
+    pub fn parameters(&self) -> Vec<ANode> {
+        let mut params = self.input.parameters();
+        params.extend(self.hidden.parameters());
+        params
+    }
+}
+
+// Projections stack the reset, update and new gates, in that order. The reset
+// gate scales the hidden projection of the new gate, bias included.
+pub struct GRUCell {
+    pub input: Linear,
+    pub hidden: Linear
+}
+
+impl GRUCell {
+    pub fn new(input: Linear, hidden: Linear) -> Self {
+        check_cell("GRUCell", &input, &hidden, 3);
+        GRUCell { input, hidden }
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden.in_features()
+    }
+
+    pub fn forward(&self, x: &ANode, h: &ANode) -> ANode {
+        let xg = split_gates(&self.input.forward(x), 3);
+        let hg = split_gates(&self.hidden.forward(h), 3);
+        let r = sigmoid(&(&xg[0] + &hg[0]));
+        let z = sigmoid(&(&xg[1] + &hg[1]));
+        let n = (&xg[2] + r * &hg[2]).tanh();
+        (1. - &z) * n + z * h
+    }
+
+    pub fn parameters(&self) -> Vec<ANode> {
+        let mut params = self.input.parameters();
+        params.extend(self.hidden.parameters());
+        params
+    }
+}
+
+// Runs `step` over a sequence from `init`, returning the state after each
+// input. Cells reuse their weights at every step, so gradients from the whole
+// sequence accumulate into the same variables.
+pub fn unroll<S, F>(xs: &[ANode], init: S, step: F) -> Vec<S>
+where
+    S: Clone,
+    F: Fn(&ANode, &S) -> S
+{
+    let mut state = init;
+    xs.iter().map(|x| {
+        state = step(x, &state);
+        state.clone()
+    }).collect()
+}
+
+// softmax(q k^T / sqrt(d)) v, for q of shape [lq, d], k of [lk, d] and v of
+// [lk, dv], or the same with a leading batch axis. `mask`, of shape
+// [lq, lk] and shared across the batch, marks the keys each query may
+// attend to; fully masked rows attend to nothing and come out as zeros.
+pub fn attention(q: &ANode, k: &ANode, v: &ANode, mask: Option<&[bool]>) -> ANode {
+    let (scores, lk) = match (q.shape().dims(), k.shape().dims(), v.shape().dims()) {
+        ([_, d], [lk, kd], [lv, _]) if d == kd && lk == lv => (q.matmul(k.transpose()), *lk),
+        ([b, _, d], [kb, lk, kd], [vb, lv, _]) if b == kb && b == vb && d == kd && lk == lv =>
+            (q.batch_matmul(k.permute(&[0, 2, 1])), *lk),
+        (qd, kd, vd) => panic!("Cannot attend with q {:?}, k {:?} and v {:?}!", qd, kd, vd)
+    };
+    let d = *q.shape().dims().last().unwrap() as DType;
+    let mut scores = scores / d.sqrt();
+    if let Some(mask) = mask {
+        let lq = q.shape().dims()[q.shape().dims().len() - 2];
+        if mask.len() != lq * lk {
+            panic!("Mask of length {} does not match [{}, {}] scores!", mask.len(), lq, lk);
+        }
+        let bias = mask.iter().map(|m| if *m { 0. } else { DType::NEG_INFINITY }).collect();
+        scores += Constant::with_shape(bias, &[lq, lk]);
+    }
+    let weights = scores.softmax();
+    if v.shape().dims().len() == 2 {
+        weights.matmul(v)
+    } else {
+        weights.batch_matmul(v)
+    }
+}
+
+// Attention over `heads` slices of the projected queries, keys and values,
+// each of width d_model / heads, recombined by the output projection. Inputs
+// are sequences of shape [l, d_model]; as a Module it attends over itself.
+pub struct MultiHeadAttention {
+    pub query: Linear,
+    pub key: Linear,
+    pub value: Linear,
+    pub output: Linear,
+    heads: usize
+}
+
+impl MultiHeadAttention {
+    pub fn new(query: Linear, key: Linear, value: Linear, output: Linear, heads: usize) -> Self {
+        let d = query.out_features();
+        if heads == 0 || !d.is_multiple_of(heads) {
+            panic!("Cannot split width {} into {} heads!", d, heads);
+        }
+        if key.out_features() != d || value.out_features() != d || output.in_features() != d {
+            panic!("MultiHeadAttention projections must all have width {}!", d);
+        }
+        MultiHeadAttention { query, key, value, output, heads }
+    }
+
+    pub fn heads(&self) -> usize {
+        self.heads
+    }
+
+    // [l, d] -> [heads, l, d / heads]
+    fn split_heads(&self, x: &ANode) -> ANode {
+        let dims = x.shape().dims().to_vec();
+        x.reshape(&[dims[0], self.heads, dims[1] / self.heads]).permute(&[1, 0, 2])
+    }
+
+    pub fn attend(&self, q: &ANode, k: &ANode, v: &ANode, mask: Option<&[bool]>) -> ANode {
+        let q = self.split_heads(&self.query.forward(q));
+        let k = self.split_heads(&self.key.forward(k));
+        let v = self.split_heads(&self.value.forward(v));
+        let out = attention(&q, &k, &v, mask).permute(&[1, 0, 2]);
+        let dims = out.shape().dims().to_vec();
+        self.output.forward(&out.reshape(&[dims[0], dims[1] * dims[2]]))
+    }
+}
+
+impl Module for MultiHeadAttention {
+    fn forward(&self, x: &ANode) -> ANode {
+        self.attend(x, x, x, None)
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        [&self.query, &self.key, &self.value, &self.output].iter()
+            .flat_map(|l| l.parameters())
+            .collect()
+    }
+}
+
+// Activations as modules, for use in Sequential
+pub struct Relu;
+
+impl Module for Relu {
+    fn forward(&self, x: &ANode) -> ANode { x.relu() }
+
+    fn parameters(&self) -> Vec<ANode> { Vec::new() }
+}
+
+pub struct Tanh;
+
+impl Module for Tanh {
+    fn forward(&self, x: &ANode) -> ANode { x.tanh() }
+
+    fn parameters(&self) -> Vec<ANode> { Vec::new() }
+}
+
+// Feeds each module's output into the next
+pub struct Sequential {
+    modules: Vec<Box<dyn Module>>
+}
+
+impl Sequential {
+    pub fn new(modules: Vec<Box<dyn Module>>) -> Self {
+        Sequential { modules }
+    }
+
+    pub fn push<M: Module + 'static>(&mut self, module: M) {
+        self.modules.push(Box::new(module));
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl Module for Sequential {
+    fn forward(&self, x: &ANode) -> ANode {
+        self.modules.iter().fold(x.clone(), |h, m| m.forward(&h))
+    }
+
+    fn parameters(&self) -> Vec<ANode> {
+        self.modules.iter().flat_map(|m| m.parameters()).collect()
+    }
+
+    fn set_training(&self, training: bool) {
+        self.modules.iter().for_each(|m| m.set_training(training));
+    }
+}
+
+#[cfg(test)]
+mod nn_tests {
+    use super::*;
+    use crate::{Graph,Variable,Constant};
+    use crate::optim::{Optimizer,SGD,Adam};
+
+    #[test]
+    fn test_linear() {
+        let layer = Linear::new(
+            Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]),
+            Variable::new(vec![0.5, -0.5]));
+        assert_eq!((layer.in_features(), layer.out_features()), (3, 2));
+
+        let x = Constant::new(vec![1., 0., -1.]);
+        let y = layer.forward(&x);
+        assert_eq!(y.shape().dims(), &[2]);
+        assert_eq!(y.value(), &[-1.5, -2.5]);
+
+        let batch = Constant::with_shape(vec![1., 0., -1., 0., 1., 0.], &[2, 3]);
+        let y = layer.forward(&batch);
+        assert_eq!(y.shape().dims(), &[2, 2]);
+        assert_eq!(y.value(), &[-1.5, -2.5, 2.5, 4.5]);
+
+        let mut graph = Graph::new();
+        graph.backward(&y.sum());
+        let params = layer.parameters();
+        assert_eq!(graph.get_grad(&params[0]).unwrap(), &[1., 1., -1., 1., 1., -1.]);
+        assert_eq!(graph.get_grad(&params[1]).unwrap(), &[2., 2.]);
+    }
+
+    #[test]
+    fn test_sequential() {
+        let mut mlp = Sequential::new(vec![
+            Box::new(Linear::new(Variable::with_shape(vec![1., -1., 2., 0.5], &[2, 2]), Variable::new(vec![0., 0.]))),
+            Box::new(Relu)
+        ]);
+        mlp.push(Linear::new(Variable::with_shape(vec![1., 1.], &[1, 2]), Variable::new(vec![0.5])));
+        assert_eq!(mlp.len(), 3);
+        assert_eq!(mlp.parameters().len(), 4);
+
+        // The hidden layer gives [-1, 3], which the relu cuts to [0, 3]
+        let x = Constant::new(vec![1., 2.]);
+        let y = mlp.forward(&x);
+        assert_eq!(y.value(), &[3.5]);
+
+        let mut graph = Graph::new();
+        graph.backward(&y);
+        let params = mlp.parameters();
+        assert_eq!(graph.get_grad(&params[0]).unwrap(), &[0., 0., 1., 2.]);
+        assert_eq!(graph.get_grad(&params[2]).unwrap(), &[0., 3.]);
+    }
+
+    #[test]
+    fn test_embedding() {
+        let emb = Embedding::new(Variable::with_shape(vec![0., 1., 2., 3., 4., 5.], &[3, 2]));
+        assert_eq!((emb.vocab_size(), emb.dim()), (3, 2));
+
+        let y = emb.forward(&Constant::new(vec![2., 0., 2.]));
+        assert_eq!(y.shape().dims(), &[3, 2]);
+        assert_eq!(y.value(), &[4., 5., 0., 1., 4., 5.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&y.sum());
+        assert!(graph.get_grad(&emb.weight).is_none());
+        let rows = graph.get_sparse_grad(&emb.weight).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[&2].as_slice(), &[2., 2.]);
+    }
+
+    #[test]
+    fn test_embedding_sparse_updates() {
+        let emb = Embedding::new(Variable::with_shape(vec![1.; 6], &[3, 2]));
+        let mut sgd = SGD::new(emb.parameters(), 0.5);
+        let mut graph = Graph::new();
+        graph.backward(&emb.lookup(&[1]).sum());
+        sgd.step(&graph);
+        assert_eq!(emb.weight.value(), &[1., 1., 0.5, 0.5, 1., 1.]);
+
+        // Adam keeps per-row moments, so untouched rows stay put
+        let mut adam = Adam::new(emb.parameters(), 0.1);
+        graph.zero_grads();
+        graph.backward(&emb.lookup(&[2, 2]).sum());
+        adam.step(&graph);
+        let w = emb.weight.value();
+        assert_eq!(&w[..4], &[1., 1., 0.5, 0.5]);
+        assert!((w[4] - 0.9).abs() < 1e-5 && (w[5] - 0.9).abs() < 1e-5);
+    }
+
+    fn close(a: &[DType], b: &[DType]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-3)
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let ln = LayerNorm::new(Variable::new(vec![1., 2.]), Variable::new(vec![0., 1.]));
+        let x = Variable::with_shape(vec![1., 3., 5., 5.], &[2, 2]);
+        let y = ln.forward(&x);
+        assert_eq!(y.shape().dims(), &[2, 2]);
+        // The second row has no variance, so normalizes to zero
+        assert!(close(&y.value(), &[-1., 3., 0., 1.]));
+
+        // Picking out one output, the gradient flows back through the mean
+        // and variance to every input in its row
+        let ln = LayerNorm::new(Variable::new(vec![1., 1., 1.]), Variable::new(vec![0., 0., 0.]));
+        let x = Variable::new(vec![-1., 0., 1.]);
+        let mut graph = Graph::new();
+        graph.backward(&ln.forward(&x).slice(0, 1));
+        assert!(close(graph.get_grad(&x).unwrap(), &[0.2041, -0.4082, 0.2041]));
+        assert!(close(graph.get_grad(&ln.weight).unwrap(), &[-1.2247, 0., 0.]));
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let bn = BatchNorm::new(Variable::new(vec![1., 2.]), Variable::new(vec![0., 0.5]));
+        let x = Variable::with_shape(vec![1., 0., 3., 4.], &[2, 2]);
+        let y = bn.forward(&x);
+        assert!(close(&y.value(), &[-1., -1.5, 1., 2.5]));
+        assert!(close(&bn.running_mean(), &[0.2, 0.2]));
+        assert!(close(&bn.running_var(), &[0.9 + 0.1 * 2., 0.9 + 0.1 * 8.]));
+
+        let mut graph = Graph::new();
+        graph.backward(&(&y * Constant::with_shape(vec![1., 0., 0., 0.], &[2, 2])).sum());
+        // The batch mean and variance both depend on the input
+        assert!(close(graph.get_grad(&x).unwrap(), &[0., 0., 0., 0.]));
+        assert!(close(graph.get_grad(&bn.weight).unwrap(), &[-1., 0.]));
+
+        bn.eval();
+        let y = bn.forward(&Constant::with_shape(vec![0.2, 0.2], &[1, 2]));
+        assert!(close(&y.value(), &[0., 0.5]));
+        assert!(close(&bn.running_mean(), &[0.2, 0.2]));
+
+        // Images normalize per channel
+        let bn = BatchNorm::new(Variable::new(vec![1., 1.]), Variable::new(vec![0., 0.]));
+        let x = Constant::with_shape(vec![1., 3., 10., 10., 5., 7., 10., 10.], &[2, 2, 1, 2]);
+        let y = bn.forward(&x);
+        assert_eq!(y.shape().dims(), &[2, 2, 1, 2]);
+        assert!(close(&y.value()[..4], &[-1.3416, -0.4472, 0., 0.]));
+    }
+
+    #[test]
+    fn test_dropout() {
+        let mut net = Sequential::new(vec![Box::new(Dropout::with_seed(0.5, 7))]);
+        net.push(Tanh);
+        let x = Variable::new(vec![1.; 1000]);
+        let y = net.forward(&x);
+        let zeros = y.value().iter().filter(|v| **v == 0.).count();
+        assert!(zeros > 400 && zeros < 600);
+
+        // Kept inputs are scaled by 2, and dropped ones get no gradient
+        let mut graph = Graph::new();
+        let dropped = Dropout::with_seed(0.5, 7).forward(&x);
+        graph.backward(&dropped.sum());
+        for (g, v) in graph.get_grad(&x).unwrap().iter().zip(dropped.value().iter()) {
+            assert_eq!(*g, *v);
+            assert!(*g == 0. || *g == 2.);
+        }
+
+        net.eval();
+        assert_eq!(net.forward(&x).value(), x.tanh().value());
+        net.train();
+        assert_ne!(net.forward(&x).value(), x.tanh().value());
+    }
+
+    fn linear(weights: Vec<DType>, bias: Vec<DType>) -> Linear {
+        let (out, inp) = (bias.len(), weights.len() / bias.len());
+        Linear::new(Variable::with_shape(weights, &[out, inp]), Variable::new(bias))
+    }
+
+    #[test]
+    fn test_rnn_cell() {
+        let cell = RNNCell::new(linear(vec![0.5], vec![0.1]), linear(vec![-1.], vec![0.]));
+        assert_eq!(cell.hidden_size(), 1);
+        let xs: Vec<ANode> = [1., 2., 3.].iter().map(|x| Constant::new(vec![*x])).collect();
+        let loss = |cell: &RNNCell| {
+            let hs = unroll(&xs, Constant::new(vec![0.]), |x, h| cell.forward(x, h));
+            hs.last().unwrap().clone()
+        };
+
+        let mut expected: DType = 0.;
+        for x in [1., 2., 3.] {
+            expected = (0.5 * x + 0.1 - expected).tanh();
+        }
+        let out = loss(&cell);
+        assert!(close(&out.value(), &[expected]));
+
+        // The shared input weight collects gradient from every step
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        let grad = graph.get_grad(&cell.input.weight).unwrap()[0];
+        let eps = 1e-2;
+        cell.input.weight.update(|w| w[0] += eps);
+        let up = loss(&cell).value()[0];
+        cell.input.weight.update(|w| w[0] -= 2. * eps);
+        let down = loss(&cell).value()[0];
+        assert!((grad - (up - down) / (2. * eps)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_lstm_cell() {
+        let cell = LSTMCell::new(
+            linear(vec![0.1, 0.2, 0.3, 0.4, -0.1, -0.2, -0.3, -0.4], vec![0., 0.1, 0., -0.1]),
+            linear(vec![0.5, -0.5, 0.25, 1.], vec![0.; 4]));
+        let state = (Constant::new(vec![0.5]), Constant::new(vec![-1.]));
+        let (h, c) = cell.forward(&Constant::new(vec![1., 2.]), &state);
+
+        let s = |x: DType| 1. / (1. + (-x).exp());
+        let (i, f) = (s(0.5 + 0.25), s(1.2 - 0.25));
+        let (g, o) = (((-0.5 + 0.125) as DType).tanh(), s(-1.2 + 0.5));
+        let c_exp = -f + i * g;
+        assert!(close(&c.value(), &[c_exp]));
+        assert!(close(&h.value(), &[o * c_exp.tanh()]));
+
+        // Batches give the same result row by row
+        let xb = Constant::with_shape(vec![1., 2., 1., 2.], &[2, 2]);
+        let state = (Constant::with_shape(vec![0.5, 0.5], &[2, 1]), Constant::with_shape(vec![-1., -1.], &[2, 1]));
+        let (hb, cb) = cell.forward(&xb, &state);
+        assert_eq!(hb.shape().dims(), &[2, 1]);
+        assert!(close(&hb.value(), &[h.value()[0], h.value()[0]]));
+        assert!(close(&cb.value(), &[c.value()[0], c.value()[0]]));
+    }
+
+    #[test]
+    fn test_gru_cell() {
+        let cell = GRUCell::new(
+            linear(vec![1., 0., 0., 1., 1., 1., -1., 0., 0., 1., 0.5, 0.5], vec![0.; 6]),
+            linear(vec![0.5, 0., 0., 0.5, -0.5, 0., 0., 0.5, 1., 0., 0., 1.], vec![0.1; 6]));
+        assert_eq!(cell.hidden_size(), 2);
+        assert_eq!(cell.parameters().len(), 4);
+
+        let x = Constant::new(vec![1., -1.]);
+        let h = Constant::new(vec![0.5, 0.25]);
+        let out = cell.forward(&x, &h);
+
+        let s = |x: DType| 1. / (1. + (-x).exp());
+        let r = [s(1. + 0.35), s(-1. + 0.225)];
+        let z = [s(0. - 0.15), s(-1. + 0.225)];
+        let n = [(-1. + r[0] * 0.6 as DType).tanh(), (0. + r[1] * 0.35 as DType).tanh()];
+        let expected: Vec<DType> = (0..2).map(|i| (1. - z[i]) * n[i] + z[i] * h.value()[i]).collect();
+        assert!(close(&out.value(), &expected));
+
+        let xb = Constant::with_shape(vec![1., -1., 0., 0.], &[2, 2]);
+        let hb = Constant::with_shape(vec![0.5, 0.25, 0.5, 0.25], &[2, 2]);
+        let outb = cell.forward(&xb, &hb);
+        assert!(close(&outb.value()[..2], &expected));
+    }
+
+    #[test]
+    fn test_attention() {
+        let q = Variable::with_shape(vec![1., 0., 0., 1.], &[2, 2]);
+        let k = Constant::with_shape(vec![1., 0., 0., 1., 1., 1.], &[3, 2]);
+        let v = Constant::with_shape(vec![1., 2., 3.], &[3, 1]);
+
+        let softmax = |xs: &[DType]| -> Vec<DType> {
+            let total: DType = xs.iter().map(|x| x.exp()).sum();
+            xs.iter().map(|x| x.exp() / total).collect()
+        };
+        let scale = (2. as DType).sqrt();
+        let w = softmax(&[1. / scale, 0., 1. / scale]);
+        let out = attention(&q, &k, &v, None);
+        assert_eq!(out.shape().dims(), &[2, 1]);
+        assert!(close(&out.value()[..1], &[w[0] + 2. * w[1] + 3. * w[2]]));
+
+        // Causal: the first query only sees the first key
+        let mask = [true, false, false, true, true, false];
+        let out = attention(&q, &k, &v, Some(&mask));
+        let w = softmax(&[0., 1. / scale]);
+        assert!(close(&out.value(), &[1., w[0] + 2. * w[1]]));
+
+        let mut graph = Graph::new();
+        graph.backward(&out.sum());
+        assert!(close(&graph.get_grad(&q).unwrap()[..2], &[0., 0.]));
+
+        // Batches attend independently
+        let qb = Constant::with_shape([&*q.value(), &*q.value()].concat(), &[2, 2, 2]);
+        let kb = Constant::with_shape([&*k.value(), &*k.value()].concat(), &[2, 3, 2]);
+        let vb = Constant::with_shape(vec![1., 2., 3., 0., 0., 0.], &[2, 3, 1]);
+        let out = attention(&qb, &kb, &vb, Some(&mask));
+        assert_eq!(out.shape().dims(), &[2, 2, 1]);
+        assert!(close(&out.value(), &[1., w[0] + 2. * w[1], 0., 0.]));
+    }
+
+    #[test]
+    fn test_multi_head_attention() {
+        let eye = || linear(vec![1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1.], vec![0.; 4]);
+        let mha = MultiHeadAttention::new(eye(), eye(), eye(), eye(), 2);
+        assert_eq!(mha.heads(), 2);
+        assert_eq!(mha.parameters().len(), 8);
+
+        // With identity projections each head attends over its own half
+        let x = Variable::with_shape(vec![1., 0., 2., 0., 0., 1., 0., 3.], &[2, 4]);
+        let out = mha.forward(&x);
+        assert_eq!(out.shape().dims(), &[2, 4]);
+        let halves = |a: usize| {
+            let xs: Vec<DType> = x.value().chunks(4).flat_map(|r| r[a..a + 2].to_vec()).collect();
+            let h = Constant::with_shape(xs, &[2, 2]);
+            attention(&h, &h, &h, None).value().to_vec()
+        };
+        let (first, second) = (halves(0), halves(2));
+        let expected = [first[0], first[1], second[0], second[1], first[2], first[3], second[2], second[3]];
+        assert!(close(&out.value(), &expected));
+
+        let mut graph = Graph::new();
+        graph.backward(&out.sum());
+        assert!(mha.parameters().iter().all(|p| graph.get_grad(p).is_some()));
+    }
+}
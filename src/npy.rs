@@ -0,0 +1,320 @@
+use std::fs::File;
+use std::io::{self,Read,Write,BufReader,BufWriter};
+use std::path::Path;
+
+use crate::{ANode,DType,Graph,Variable,Constant,StateDict};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// A single array in numpy's .npy format, version 1.0, C order
+fn encode_npy(dims: &[usize], values: &[DType]) -> Vec<u8> {
+    let descr = if std::mem::size_of::<DType>() == 4 { "<f4" } else { "<f8" };
+    let shape = match dims {
+        [d] => format!("({},)", d),
+        _ => format!("({})", dims.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "))
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    // The data has to start on a 64 byte boundary, the header ending in a newline
+    while !(MAGIC.len() + 4 + header.len() + 1).is_multiple_of(64) {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[1, 0]);
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    values.iter().for_each(|v| out.extend_from_slice(&v.to_le_bytes()));
+    out
+}
+
+// Pulls `'key': value` out of the header dict, up to the next top level comma
+fn header_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') { rest.find(')')? + 1 } else { rest.find([',', '}'])? };
+    Some(&rest[..end])
+}
+
+// Reads float32 and float64 arrays, little or big endian, converting them to
+// DType. Zero dimensional arrays come back with dims [1].
+fn decode_npy(bytes: &[u8]) -> io::Result<(Vec<usize>, Vec<DType>)> {
+    if bytes.len() < 10 || &bytes[..6] != MAGIC {
+        return Err(invalid("not a npy file"))
+    }
+    let (header_len, offset) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12),
+        _ => return Err(invalid("unsupported npy version"))
+    };
+    let header = bytes.get(offset..offset + header_len)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or_else(|| invalid("malformed npy header"))?;
+
+    if header_field(header, "fortran_order").map(|f| f.trim()) != Some("False") {
+        return Err(invalid("only C ordered arrays are supported"))
+    }
+    let descr = header_field(header, "descr").map(|d| d.trim().trim_matches(|c| c == '\'' || c == '"'));
+    let (width, little) = match descr {
+        Some("<f4") | Some("|f4") => (4, true),
+        Some(">f4") => (4, false),
+        Some("<f8") | Some("|f8") => (8, true),
+        Some(">f8") => (8, false),
+        _ => return Err(invalid("only float32 and float64 arrays are supported"))
+    };
+    let shape = header_field(header, "shape").ok_or_else(|| invalid("malformed npy header"))?;
+    let mut dims = shape.trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<usize>().map_err(|_| invalid("malformed npy shape")))
+        .collect::<io::Result<Vec<_>>>()?;
+    if dims.is_empty() {
+        dims.push(1);
+    }
+
+    let len: usize = dims.iter().product();
+    let data = &bytes[offset + header_len..];
+    if data.len() < len * width {
+        return Err(invalid("npy data is truncated"))
+    }
+    let values = data.chunks_exact(width).take(len).map(|c| match (width, little) {
+        (4, true) => f32::from_le_bytes(c.try_into().unwrap()) as DType,
+        (4, false) => f32::from_be_bytes(c.try_into().unwrap()) as DType,
+        (_, true) => f64::from_le_bytes(c.try_into().unwrap()) as DType,
+        (_, false) => f64::from_be_bytes(c.try_into().unwrap()) as DType
+    }).collect();
+    Ok((dims, values))
+}
+
+fn read_npy<P: AsRef<Path>>(path: P) -> io::Result<(Vec<usize>, Vec<DType>)> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+    decode_npy(&bytes)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes.iter() {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// An .npz is a zip of .npy files. Entries are written uncompressed, as
+// numpy.savez does.
+fn write_zip<W: Write>(w: &mut W, files: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+    for (name, data) in files.iter() {
+        let crc = crc32(data);
+        let mut local = Vec::new();
+        local.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        // version needed, flags, method (stored), mod time, mod date
+        for v in [20u16, 0, 0, 0, 0x21] {
+            local.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [crc, data.len() as u32, data.len() as u32] {
+            local.extend_from_slice(&v.to_le_bytes());
+        }
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(name.as_bytes());
+        w.write_all(&local)?;
+        w.write_all(data)?;
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        // version made by, version needed, flags, method, mod time, mod date
+        for v in [20u16, 20, 0, 0, 0, 0x21] {
+            central.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [crc, data.len() as u32, data.len() as u32] {
+            central.extend_from_slice(&v.to_le_bytes());
+        }
+        // name length, extra, comment, disk, internal attrs
+        for v in [name.len() as u16, 0, 0, 0, 0] {
+            central.extend_from_slice(&v.to_le_bytes());
+        }
+        central.extend_from_slice(&0u32.to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        offset += (local.len() + data.len()) as u32;
+    }
+    w.write_all(&central)?;
+
+    let mut end = Vec::new();
+    end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    for v in [0u16, 0, files.len() as u16, files.len() as u16] {
+        end.extend_from_slice(&v.to_le_bytes());
+    }
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&offset.to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    w.write_all(&end)
+}
+
+fn u16_at(b: &[u8], i: usize) -> usize {
+    u16::from_le_bytes([b[i], b[i + 1]]) as usize
+}
+
+fn u32_at(b: &[u8], i: usize) -> usize {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]) as usize
+}
+
+// Walks the central directory; only stored (uncompressed) entries can be read,
+// so archives from numpy.savez_compressed are rejected.
+fn read_zip(bytes: &[u8]) -> io::Result<Vec<(String, &[u8])>> {
+    let eocd = (0..bytes.len().saturating_sub(21)).rev()
+        .find(|i| bytes[*i..].starts_with(&0x0605_4b50u32.to_le_bytes()))
+        .ok_or_else(|| invalid("not a zip archive"))?;
+    let count = u16_at(bytes, eocd + 10);
+    let mut pos = u32_at(bytes, eocd + 16);
+
+    let mut files = Vec::with_capacity(count);
+    for _ in 0..count {
+        if bytes.len() < pos + 46 || u32_at(bytes, pos) != 0x0201_4b50 {
+            return Err(invalid("malformed zip directory"))
+        }
+        if u16_at(bytes, pos + 10) != 0 {
+            return Err(invalid("compressed npz entries are not supported"))
+        }
+        let size = u32_at(bytes, pos + 20);
+        let name_len = u16_at(bytes, pos + 28);
+        let skip = name_len + u16_at(bytes, pos + 30) + u16_at(bytes, pos + 32);
+        let name = bytes.get(pos + 46..pos + 46 + name_len)
+            .and_then(|n| String::from_utf8(n.to_vec()).ok())
+            .ok_or_else(|| invalid("malformed zip entry name"))?;
+
+        let local = u32_at(bytes, pos + 42);
+        if bytes.len() < local + 30 {
+            return Err(invalid("malformed zip entry"))
+        }
+        let start = local + 30 + u16_at(bytes, local + 26) + u16_at(bytes, local + 28);
+        let data = bytes.get(start..start + size).ok_or_else(|| invalid("zip entry is truncated"))?;
+        files.push((name, data));
+        pos += 46 + skip;
+    }
+    Ok(files)
+}
+
+impl Variable {
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> io::Result<ANode> {
+        let (dims, values) = read_npy(path)?;
+        Ok(Variable::with_shape(values, &dims))
+    }
+}
+
+impl Constant {
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> io::Result<ANode> {
+        let (dims, values) = read_npy(path)?;
+        Ok(Constant::with_shape(values, &dims))
+    }
+}
+
+impl ANode {
+    pub fn save_npy<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(&encode_npy(self.shape().dims(), &self.value()))?;
+        w.flush()
+    }
+}
+
+impl StateDict {
+    // Each entry becomes `<name>.npy` in the archive, so np.load gives back
+    // the same names.
+    pub fn save_npz<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let files: Vec<_> = self.names()
+            .map(|name| {
+                let npy = encode_npy(self.dims(name).unwrap(), self.get(name).unwrap());
+                (format!("{}.npy", name), npy)
+            })
+            .collect();
+        let mut w = BufWriter::new(File::create(path)?);
+        write_zip(&mut w, &files)?;
+        w.flush()
+    }
+
+    pub fn load_npz<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        let mut sd = StateDict::new();
+        for (name, data) in read_zip(&bytes)? {
+            let (dims, values) = decode_npy(data)?;
+            let name = name.strip_suffix(".npy").unwrap_or(&name);
+            sd.insert_values(name, dims, values);
+        }
+        Ok(sd)
+    }
+
+    // The gradients `graph` holds for the given nodes, under their names.
+    // Nodes without one are left out.
+    pub fn from_grads(graph: &Graph, nodes: &[(&str, &ANode)]) -> Self {
+        let mut sd = StateDict::new();
+        for (name, node) in nodes.iter() {
+            if let Some(g) = graph.get_grad(node) {
+                sd.insert_values(name, node.shape().dims().to_vec(), g.clone());
+            }
+        }
+        sd
+    }
+}
+
+#[cfg(test)]
+mod npy_tests {
+    use super::*;
+
+    #[test]
+    fn test_npy() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let bytes = encode_npy(x.shape().dims(), &x.value());
+        assert_eq!((bytes.len() - 6 * std::mem::size_of::<DType>()) % 64, 0);
+        assert!(String::from_utf8_lossy(&bytes).contains("'shape': (2, 3)"));
+
+        let path = std::env::temp_dir().join(format!("simple_grad_{}.npy", std::process::id()));
+        x.save_npy(&path).unwrap();
+        let c = Constant::from_npy(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(c.shape().dims(), &[2, 3]);
+        assert_eq!(c.value(), x.value());
+
+        // As written by numpy for np.array([1.5, -2.0], dtype='>f8')
+        let mut header = String::from("{'descr': '>f8', 'fortran_order': False, 'shape': (2,), }");
+        while !(10 + header.len() + 1).is_multiple_of(64) {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&1.5f64.to_be_bytes());
+        bytes.extend_from_slice(&(-2f64).to_be_bytes());
+        assert_eq!(decode_npy(&bytes).unwrap(), (vec![2], vec![1.5, -2.]));
+    }
+
+    #[test]
+    fn test_npz() {
+        let w = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let b = Variable::new(vec![0.5, -0.5]);
+        let out = (w.matmul(&b) + &b).sum();
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        let nodes = [("w", &w), ("b", &b)];
+        let path = std::env::temp_dir().join(format!("simple_grad_{}.npz", std::process::id()));
+        for sd in [StateDict::from_nodes(&nodes), StateDict::from_grads(&graph, &nodes)] {
+            sd.save_npz(&path).unwrap();
+            let loaded = StateDict::load_npz(&path).unwrap();
+            assert_eq!(loaded, sd);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
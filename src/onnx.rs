@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self,Write,BufWriter};
+use std::path::Path;
+
+use hashbrown::HashMap;
+
+use crate::{ANode,NodeIdx,DType};
+use crate::introspect::walk;
+
+const OPSET: u64 = 13;
+
+// ONNX TensorProto data types
+const FLOAT: u64 = 1;
+const DOUBLE: u64 = 11;
+const INT64: u64 = 7;
+
+fn elem_type() -> u64 {
+    if std::mem::size_of::<DType>() == 4 { FLOAT } else { DOUBLE }
+}
+
+// Just enough of the protobuf wire format to write an ONNX model
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn int(&mut self, field: u64, v: u64) -> &mut Self {
+        self.varint(field << 3);
+        self.varint(v);
+        self
+    }
+
+    fn bytes(&mut self, field: u64, v: &[u8]) -> &mut Self {
+        self.varint((field << 3) | 2);
+        self.varint(v.len() as u64);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn string(&mut self, field: u64, v: &str) -> &mut Self {
+        self.bytes(field, v.as_bytes())
+    }
+
+    fn message(&mut self, field: u64, v: &Message) -> &mut Self {
+        self.bytes(field, &v.0)
+    }
+}
+
+fn tensor(name: &str, dims: &[usize], data_type: u64, raw: Vec<u8>) -> Message {
+    let mut t = Message::default();
+    dims.iter().for_each(|d| { t.int(1, *d as u64); });
+    t.int(2, data_type).string(8, name).bytes(9, &raw);
+    t
+}
+
+fn value_tensor(name: &str, node: &ANode) -> Message {
+    let raw = node.value().iter().flat_map(|v| v.to_le_bytes()).collect();
+    tensor(name, node.shape().dims(), elem_type(), raw)
+}
+
+fn value_info(name: &str, dims: &[usize]) -> Message {
+    let mut shape = Message::default();
+    for d in dims.iter() {
+        shape.message(1, Message::default().int(1, *d as u64));
+    }
+    let mut tensor_type = Message::default();
+    tensor_type.int(1, elem_type()).message(2, &shape);
+    let mut info = Message::default();
+    info.string(1, name).message(2, Message::default().message(1, &tensor_type));
+    info
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str) -> Message {
+    let mut n = Message::default();
+    inputs.iter().for_each(|i| { n.string(1, i); });
+    n.string(2, output).string(3, output).string(4, op_type);
+    n
+}
+
+// Initializer holding the target shape of a Reshape
+fn shape_tensor(name: &str, dims: &[i64]) -> Message {
+    let raw = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+    tensor(name, &[dims.len()], INT64, raw)
+}
+
+// Encodes the graph under `output` as an ONNX model. Variables become graph
+// inputs defaulting to their current values, constants initializers. Ops
+// without an ONNX counterpart give an InvalidInput error.
+pub(crate) fn to_onnx(output: &ANode) -> io::Result<Vec<u8>> {
+    let mut names: HashMap<NodeIdx, String> = HashMap::new();
+    let mut graph = Message::default();
+    let mut initializers = Vec::new();
+    let mut inputs = Vec::new();
+    let mut unsupported = None;
+    walk(output, &mut |n: &ANode| {
+        let name = format!("n{}", n.get_id().0);
+        let children: Vec<&str> = n.get_children().unwrap_or(&[]).iter()
+            .map(|c| names[&c.get_id()].as_str())
+            .collect();
+
+        let op_type = match (n.op_name(), children.len()) {
+            ("Variable", 0) => {
+                inputs.push(value_info(&name, n.shape().dims()));
+                initializers.push(value_tensor(&name, n));
+                None
+            },
+            (_, 0) => {
+                initializers.push(value_tensor(&name, n));
+                None
+            },
+            ("AddN", 2) => Some("Add"),
+            ("Subtract", 2) => Some("Sub"),
+            ("Multiply", 2) => Some("Mul"),
+            ("Divide", 2) => Some("Div"),
+            ("Power", 2) => Some("Pow"),
+            ("Exp", 1) => Some("Exp"),
+            ("Ln", 1) => Some("Log"),
+            ("Sin", 1) => Some("Sin"),
+            ("Cos", 1) => Some("Cos"),
+            ("Tanh", 1) => Some("Tanh"),
+            ("Negate", 1) => Some("Neg"),
+            ("Maximum", 2) => Some("Max"),
+            ("Transpose", 1) => Some("Transpose"),
+            // Softmax is over the last axis, ONNX's default since opset 13
+            ("Softmax", 1) => Some("Softmax"),
+            ("MatMul", 2) if n.get_children().unwrap().iter().all(|c| c.shape().dims().len() == 1) => {
+                // Two vectors give a scalar in ONNX but a [1] vector here
+                let dot = format!("{}_dot", name);
+                let shape = format!("{}_shape", name);
+                initializers.push(shape_tensor(&shape, &[1]));
+                graph.message(1, &node("MatMul", &children, &dot));
+                graph.message(1, &node("Reshape", &[&dot, &shape], &name));
+                None
+            },
+            ("MatMul", 2) => Some("MatMul"),
+            ("Reshape", 1) => {
+                let shape = format!("{}_shape", name);
+                let dims: Vec<_> = n.shape().dims().iter().map(|d| *d as i64).collect();
+                initializers.push(shape_tensor(&shape, &dims));
+                graph.message(1, &node("Reshape", &[children[0], &shape], &name));
+                None
+            },
+            ("PowScalar", 1) => {
+                // The exponent becomes a scalar initializer
+                let exp = format!("{}_exp", name);
+                let raw = n.op_args().scalars[0].to_le_bytes().to_vec();
+                initializers.push(tensor(&exp, &[], elem_type(), raw));
+                graph.message(1, &node("Pow", &[children[0], &exp], &name));
+                None
+            },
+            ("SumVec", 1) => {
+                // Flattened first so the sum keeps SimpleGrad's [1] shape
+                let flat_shape = format!("{}_shape", name);
+                let flat = format!("{}_flat", name);
+                initializers.push(shape_tensor(&flat_shape, &[-1]));
+                graph.message(1, &node("Reshape", &[children[0], &flat_shape], &flat));
+                graph.message(1, &node("ReduceSum", &[&flat], &name));
+                None
+            },
+            (op, _) => {
+                unsupported.get_or_insert(op);
+                None
+            }
+        };
+        if let Some(op_type) = op_type {
+            graph.message(1, &node(op_type, &children, &name));
+        }
+        names.insert(n.get_id(), name);
+    });
+    if let Some(op) = unsupported {
+        let msg = format!("{} nodes cannot be exported to ONNX", op);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    }
+
+    graph.string(2, "simple_grad");
+    initializers.iter().for_each(|t| { graph.message(5, t); });
+    inputs.iter().for_each(|i| { graph.message(11, i); });
+    graph.message(12, &value_info(&names[&output.get_id()], output.shape().dims()));
+
+    let mut model = Message::default();
+    model.int(1, 7)
+        .string(2, "simple_grad")
+        .message(7, &graph)
+        .message(8, Message::default().string(1, "").int(2, OPSET));
+    Ok(model.0)
+}
+
+pub(crate) fn export_onnx<P: AsRef<Path>>(output: &ANode, path: P) -> io::Result<()> {
+    let model = to_onnx(output)?;
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(&model)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod onnx_tests {
+    use super::*;
+    use crate::{Variable,Constant,Pow};
+    use crate::nn::{Module,Linear,Relu,Sequential};
+
+    // Reads back the top level fields of a protobuf message
+    fn fields(mut buf: &[u8]) -> Vec<(u64, &[u8])> {
+        fn varint(buf: &mut &[u8]) -> u64 {
+            let mut v = 0;
+            let mut shift = 0;
+            loop {
+                let b = buf[0];
+                *buf = &buf[1..];
+                v |= ((b & 0x7f) as u64) << shift;
+                shift += 7;
+                if b < 0x80 { return v }
+            }
+        }
+        let mut out = Vec::new();
+        while !buf.is_empty() {
+            let key = varint(&mut buf);
+            if key & 7 == 2 {
+                let len = varint(&mut buf) as usize;
+                out.push((key >> 3, &buf[..len]));
+                buf = &buf[len..];
+            } else {
+                varint(&mut buf);
+                out.push((key >> 3, &[][..]));
+            }
+        }
+        out
+    }
+
+    fn graph_of(model: &[u8]) -> Vec<(u64, &[u8])> {
+        fields(fields(model).into_iter().find(|(f, _)| *f == 7).unwrap().1)
+    }
+
+    fn op_types(graph: &[(u64, &[u8])]) -> Vec<String> {
+        graph.iter()
+            .filter(|(f, _)| *f == 1)
+            .map(|(_, n)| {
+                let op = fields(n).into_iter().find(|(f, _)| *f == 4).unwrap().1;
+                std::str::from_utf8(op).unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::new(vec![3., 4.]);
+        let out = ((&x * &c).exp() - x.ln().pow(2.) + x.pow_scalar(3.)).sum();
+
+        let model = to_onnx(&out).unwrap();
+        let graph = graph_of(&model);
+        assert_eq!(op_types(&graph), vec!["Mul", "Exp", "Log", "Pow", "Sub", "Pow", "Add", "Reshape", "ReduceSum"]);
+
+        let count = |field| graph.iter().filter(|(f, _)| *f == field).count();
+        // x, c, both exponents and the reshape target
+        assert_eq!(count(5), 5);
+        assert_eq!(count(11), 1);
+        assert_eq!(count(12), 1);
+    }
+
+    #[test]
+    fn test_export_layers() {
+        let model = Sequential::new(vec![
+            Box::new(Linear::new(Variable::with_shape(vec![1., -1., 0.5, 2.], &[2, 2]), Variable::new(vec![0., 1.]))),
+            Box::new(Relu),
+            Box::new(Linear::new(Variable::with_shape(vec![1., 1.], &[1, 2]), Variable::new(vec![0.])))
+        ]);
+        let x = Constant::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let out = model.forward(&x).reshape(&[2]).softmax();
+
+        assert_eq!(op_types(&graph_of(&to_onnx(&out).unwrap())), vec!["Transpose", "MatMul", "Add", "Max", "Transpose", "MatMul", "Add",
+            "Reshape", "Softmax"]);
+
+        // A single example goes through a matrix-vector product
+        let x = Variable::new(vec![1., 2.]);
+        assert_eq!(op_types(&graph_of(&to_onnx(&model.forward(&x)).unwrap())), vec!["MatMul", "Add", "Max", "MatMul", "Add"]);
+
+        let dot = x.matmul(&x);
+        assert_eq!(op_types(&graph_of(&to_onnx(&dot).unwrap())), vec!["MatMul", "Reshape"]);
+    }
+
+    #[test]
+    fn test_export_unsupported() {
+        let x = Variable::new(vec![1., 2.]);
+        let err = to_onnx(&x.flip()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Nothing is written when the graph can't be exported
+        let path = std::env::temp_dir().join("simple_grad_unsupported.onnx");
+        let _ = std::fs::remove_file(&path);
+        assert!(export_onnx(&x.flip(), &path).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -1,8 +1,29 @@
 use std::rc::Rc;
+use std::cell::{Cell,Ref,RefCell,RefMut};
+use std::ops::Deref;
+use hashbrown::HashSet;
 
 use crate::*;
-use crate::vecops::{add, iadd, sub, isub, mul, imul, div};
+use crate::vecops;
+use crate::vecops::{add, iadd, sub, mul, div, matmul, matmul_at, matmul_bt, transpose};
 use crate::pool::{MPVec,allocate_vec};
+use crate::parallel::{for_each_mut, map, update, zip_map, zip_update};
+use crate::shape::{Shape,ShapeError,BroadcastIndex,broadcast_shapes};
+
+// Settings of an op beyond its children and output shape; enough to build it
+// again, as when loading a saved graph.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct OpArgs {
+    pub(crate) sizes: Vec<usize>,
+    pub(crate) scalars: Vec<DType>,
+    pub(crate) text: Option<String>
+}
+
+impl OpArgs {
+    fn sizes(sizes: Vec<usize>) -> Self {
+        OpArgs { sizes, ..Default::default() }
+    }
+}
 
 enum Data {
     Owned(Vec<DType>),
@@ -10,35 +31,409 @@ enum Data {
     Pooled(MPVec)
 }
 
+impl Data {
+    #[inline]
+    fn as_slice(&self) -> &[DType] {
+        match self {
+            Data::Owned(v) => v,
+            Data::Shared(v) => v,
+            Data::Pooled(v) => v.as_slice()
+        }
+    }
+}
+
+// A borrow of a node's values. Values are replaced when a graph is re-run or
+// a leaf is set, which panics while any of these are held rather than
+// leaving them dangling.
+pub struct Value<'a>(ValueRef<'a>);
+
+enum ValueRef<'a> {
+    Slice(&'a [DType]),
+    Cell(Ref<'a, [DType]>)
+}
+
+impl <'a> Value<'a> {
+    pub(crate) fn slice(v: &'a [DType]) -> Self {
+        Value(ValueRef::Slice(v))
+    }
+
+    // Narrows the borrow to part of the values
+    pub(crate) fn map<F: FnOnce(&[DType]) -> &[DType]>(self, f: F) -> Self {
+        match self.0 {
+            ValueRef::Slice(v) => Value(ValueRef::Slice(f(v))),
+            ValueRef::Cell(v) => Value(ValueRef::Cell(Ref::map(v, f)))
+        }
+    }
+}
+
+impl Deref for Value<'_> {
+    type Target = [DType];
+
+    #[inline]
+    fn deref(&self) -> &[DType] {
+        match &self.0 {
+            ValueRef::Slice(v) => v,
+            ValueRef::Cell(v) => v
+        }
+    }
+}
+
+impl AsRef<[DType]> for Value<'_> {
+    fn as_ref(&self) -> &[DType] {
+        self
+    }
+}
+
+impl std::fmt::Debug for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Value<'_>) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<[DType]> for Value<'_> {
+    fn eq(&self, other: &[DType]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<&[DType]> for Value<'_> {
+    fn eq(&self, other: &&[DType]) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<Vec<DType>> for Value<'_> {
+    fn eq(&self, other: &Vec<DType>) -> bool {
+        **self == **other
+    }
+}
+
+impl <const N: usize> PartialEq<[DType; N]> for Value<'_> {
+    fn eq(&self, other: &[DType; N]) -> bool {
+        **self == other[..]
+    }
+}
+
+impl <const N: usize> PartialEq<&[DType; N]> for Value<'_> {
+    fn eq(&self, other: &&[DType; N]) -> bool {
+        **self == other[..]
+    }
+}
+
+// Values are replaced in place when a graph is re-run; the RefCell makes sure
+// nobody is still reading the old ones.
 struct Computation {
-    value: Data
+    value: RefCell<Data>,
+    shape: Shape,
+    // Set once a leaf under the node is known to collect gradients
+    leads_to_grad: Cell<bool>
 }
 
 impl Computation {
     fn new(value: Vec<DType>) -> Self {
-        Computation { value: Data::Owned(value) }
+        let shape = Shape::vector(value.len());
+        Computation { value: RefCell::new(Data::Owned(value)), shape, leads_to_grad: Cell::new(false) }
     }
 
     fn shared(value: Rc<Vec<DType>>) -> Self {
-       Computation { value: Data::Shared(value) }
+       let shape = Shape::vector(value.len());
+       Computation { value: RefCell::new(Data::Shared(value)), shape, leads_to_grad: Cell::new(false) }
     }
 
     fn pooled(value: MPVec) -> Self {
-        Computation { value: Data::Pooled(value) }
+        let shape = Shape::vector(value.len());
+        Computation { value: RefCell::new(Data::Pooled(value)), shape, leads_to_grad: Cell::new(false) }
+    }
+
+    fn with_shape(mut self, shape: Shape) -> Self {
+        let len = self.value.get_mut().as_slice().len();
+        if shape.size() != len {
+            panic!("Shape {:?} does not match {} values!", shape, len);
+        }
+        self.shape = shape;
+        self
+    }
+
+    #[inline]
+    fn get(&self) -> Value<'_> {
+        Value(ValueRef::Cell(Ref::map(self.value.borrow(), Data::as_slice)))
+    }
+
+    fn into_pooled(self) -> MPVec {
+        match self.value.into_inner() {
+            Data::Pooled(v) => v,
+            other => {
+                let mut v = allocate_vec(other.as_slice().len());
+                v.copy_from_slice(other.as_slice());
+                v
+            }
+        }
+    }
+
+    fn borrow_mut(&self) -> RefMut<'_, Data> {
+        match self.value.try_borrow_mut() {
+            Ok(data) => data,
+            Err(_) => panic!("Cannot change the value of a node of shape {:?} while it is borrowed!", self.shape)
+        }
+    }
+
+    // Overwrites the values without reallocating, where possible
+    fn copy_from(&self, values: &[DType]) {
+        if values.len() != self.shape.size() {
+            panic!("Cannot set {} values on a node of shape {:?}!", values.len(), self.shape);
+        }
+        let mut data = self.borrow_mut();
+        match &mut *data {
+            Data::Owned(v) => v.copy_from_slice(values),
+            Data::Pooled(v) => v.copy_from_slice(values),
+            shared => *shared = Data::Owned(values.to_vec())
+        }
+    }
+
+    // Hands the values to `f` for editing in place, copying shared ones first
+    fn update(&self, f: &mut dyn FnMut(&mut [DType])) {
+        let mut data = self.borrow_mut();
+        if let Data::Shared(v) = &*data {
+            *data = Data::Owned(v.to_vec());
+        }
+        match &mut *data {
+            Data::Owned(v) => f(v),
+            Data::Pooled(v) => f(v),
+            Data::Shared(_) => unreachable!()
+        }
+    }
+
+    // Swaps in a freshly computed value of the same shape
+    fn set(&self, other: Computation) {
+        if other.shape != self.shape {
+            panic!("Cannot replace a value of shape {:?} with {:?}!", self.shape, other.shape);
+        }
+        *self.borrow_mut() = other.value.into_inner();
+    }
+
+    #[inline]
+    fn set_pooled(&self, value: MPVec) {
+        self.set(Computation::pooled(value).with_shape(self.shape));
+    }
+
+    // Only a positive answer is cached: a frozen leaf may be unfrozen later,
+    // but one collecting gradients can't make stealing its parents unsafe
+    // by being frozen.
+    fn leads_to_grad(&self, children: &[ANode]) -> bool {
+        if self.leads_to_grad.get() {
+            return true
+        }
+        let leads = children.iter().any(|c| c.leads_to_grad());
+        self.leads_to_grad.set(leads);
+        leads
+    }
+
+    // Hands over a pooled buffer, leaving the computation empty
+    fn take(&mut self) -> Option<MPVec> {
+        let value = self.value.get_mut();
+        match std::mem::replace(value, Data::Owned(Vec::new())) {
+            Data::Pooled(v) => Some(v),
+            other => {
+                *value = other;
+                None
+            }
+        }
     }
+}
+
+thread_local! {
+    static INPLACE: Cell<bool> = const { Cell::new(false) };
+}
+
+// In-place forward mode: elementwise ops overwrite the buffer of a child that
+// nothing else references instead of allocating their own. The child's value
+// is gone afterwards, so results are detached constants; only children with
+// no leaf collecting gradients beneath them are reused, so backward still
+// reaches every variable. It's meant for inference with frozen parameters.
+// Like graphs themselves, the setting is per thread.
+pub fn use_inplace_forward(inplace: bool) {
+    INPLACE.with(|i| i.set(inplace));
+}
+
+fn steal(node: &mut ANode) -> Option<MPVec> {
+    if !INPLACE.with(|i| i.get()) {
+        return None
+    }
+    match Rc::get_mut(&mut node.0) {
+        Some(n) if !n.is_leaf() && !n.leads_to_grad() => n.take_value(),
+        _ => None
+    }
+}
+
+fn detached(value: MPVec, shape: Shape) -> ANode {
+    let c = Computation::pooled(value).with_shape(shape);
+    ANode::new(Rc::new(Detached(NodeIdx::new(), c)))
+}
+
+// Result of an in-place op. Unlike a constant it isn't a leaf, so the next op
+// in a chain can keep reusing its buffer.
+struct Detached(NodeIdx, Computation);
 
+impl Node for Detached {
     #[inline]
-    fn get(&self) -> &[DType] {
-        match &self.value {
-            Data::Owned(v) => &v,
-            Data::Shared(v) => &v,
-            Data::Pooled(v) => v.as_ref().as_slice()
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { None }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        self.1.get()
+    }
+
+    fn shape(&self) -> Shape { self.1.shape }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.1.take() }
+}
+
+fn unary_inplace<F: FnOnce(&mut [DType])>(vec: &mut ANode, f: F) -> Option<ANode> {
+    let shape = vec.shape();
+    steal(vec).map(|mut v| {
+        f(&mut v);
+        detached(v, shape)
+    })
+}
+
+// Checked up front so a mismatch names the op and its operands rather than
+// failing somewhere inside the kernels
+pub(crate) fn check_broadcast(op: &'static str, left: &ANode, right: &ANode) -> Result<Shape, ShapeError> {
+    let (l_shape, r_shape) = (left.shape(), right.shape());
+    l_shape.broadcast(&r_shape).ok_or(ShapeError {
+        op,
+        left: (left.op_name(), l_shape.dims().to_vec()),
+        right: (right.op_name(), r_shape.dims().to_vec())
+    })
+}
+
+pub(crate) fn check_matmul(left: &ANode, right: &ANode) -> Result<Shape, String> {
+    MatMul::try_dims(&left.shape(), &right.shape()).map(|(_, shape)| shape)
+}
+
+fn expect_broadcast(op: &'static str, left: &ANode, right: &ANode) {
+    if let Err(e) = check_broadcast(op, left, right) {
+        panic!("{}!", e);
+    }
+}
+
+fn binary_inplace<F>(left: &mut ANode, right: &mut ANode, f: F) -> Option<ANode>
+where
+    F: Fn(DType, DType) -> DType + Sync + Send
+{
+    // The result has no children, so neither side may lead to a gradient
+    if !INPLACE.with(|i| i.get()) || left.leads_to_grad() || right.leads_to_grad() {
+        return None
+    }
+    let (l_shape, r_shape) = (left.shape(), right.shape());
+    let out = broadcast_shapes(&l_shape, &r_shape);
+    if l_shape == out {
+        if let Some(mut v) = steal(left) {
+            if r_shape == out {
+                zip_update(&mut v, &right.value(), |li, ri| *li = f(*li, ri));
+            } else {
+                let r = right.value();
+                let rv = Broadcast::new(&r, &r_shape, &out);
+                v.iter_mut().zip(rv).for_each(|(li, ri)| *li = f(*li, *ri));
+            }
+            return Some(detached(v, out))
         }
     }
+    if r_shape == out {
+        if let Some(mut v) = steal(right) {
+            let l = left.value();
+            let lv = Broadcast::new(&l, &l_shape, &out);
+            v.iter_mut().zip(lv).for_each(|(ri, li)| *ri = f(*li, *ri));
+            return Some(detached(v, out))
+        }
+    }
+    None
+}
+
+// Forward mode helpers. Tangents arrive in the shapes of the children.
+fn tangent(t: &[DType], like: &ANode) -> ANode {
+    Constant::with_shape(t.to_vec(), like.shape().dims())
+}
+
+fn unary_jvp<F>(x: &[DType], t: &[DType], f: F) -> Option<MPVec>
+where
+    F: Fn(DType, DType) -> DType + Sync + Send
+{
+    let mut out = allocate_vec(x.len());
+    zip_map(x, t, &mut out, f);
+    Some(out)
+}
+
+// f(x, y, tx, ty) evaluated over the broadcast of both children
+fn binary_jvp<F>(x: &ANode, y: &ANode, t: &[&[DType]], out: &Shape, f: F) -> Option<MPVec>
+where
+    F: Fn(DType, DType, DType, DType) -> DType
+{
+    let (xs, ys) = (x.shape(), y.shape());
+    let (xv, yv) = (x.value(), y.value());
+    let values = Broadcast::new(&xv, &xs, out).zip(Broadcast::new(&yv, &ys, out));
+    let tangents = Broadcast::new(t[0], &xs, out).zip(Broadcast::new(t[1], &ys, out));
+    let mut res = allocate_vec(out.size());
+    res.iter_mut().zip(values.zip(tangents)).for_each(|(o, ((x, y), (tx, ty)))| {
+        *o = f(*x, *y, *tx, *ty)
+    });
+    Some(res)
+}
+
+// Linear ops are their own derivative
+fn linear_jvp<F: Fn(&ANode) -> MPVec>(child: &ANode, t: &[DType], f: F) -> Option<MPVec> {
+    Some(f(&tangent(t, child)))
+}
+
+// Product rule for ops linear in each argument separately
+fn bilinear_jvp<F>(x: &ANode, y: &ANode, t: &[&[DType]], f: F) -> Option<MPVec>
+where
+    F: Fn(&ANode, &ANode) -> MPVec
+{
+    let mut out = f(&tangent(t[0], x), y);
+    iadd(&mut out, &f(x, &tangent(t[1], y)));
+    Some(out)
 }
 
+// Routes the gradient of a two way selection to whichever child was picked
+// at each position of the broadcast output
+fn select_grad_graph<F>(x: &ANode, y: &ANode, grad: &ANode, pick_left: F) -> Option<Vec<ANode>>
+where
+    F: Fn(DType, DType) -> bool
+{
+    let (l, r) = (x.value(), y.value());
+    let (lv, rv) = Broadcast::from_pair(x, &l, y, &r);
+    let shape = lv.shape;
+    let mask: Vec<DType> = lv.zip(rv).map(|(xi, yi)| if pick_left(*xi, *yi) { 1. } else { 0. }).collect();
+    let mask = Constant::with_shape(mask, shape.dims());
+    let dx = (grad * &mask).sum_to(x.shape().dims());
+    let dy = (grad * (1. - mask)).sum_to(y.shape().dims());
+    Some(vec![dx, dy])
+}
+
+// 1 where the value is nonzero, 0 elsewhere, for guarding gradients built
+// from nodes against 0 * inf
+fn nonzero_mask(x: &ANode) -> ANode {
+    let mask: Vec<DType> = x.value().iter().map(|xi| if *xi == 0. { 0. } else { 1. }).collect();
+    Constant::with_shape(mask, x.shape().dims())
+}
+
+// Only built when loading saved graphs
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
 pub struct RequiresGrad(Rc<dyn Node>);
 
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
 impl RequiresGrad {
     pub fn new(n: Rc<dyn Node>) -> Self {
         RequiresGrad(n)
@@ -52,47 +447,95 @@ impl Node for RequiresGrad {
     #[inline]
     fn is_leaf(&self) -> bool { self.0.is_leaf() }
 
+    fn op_name(&self) -> &'static str { self.0.op_name() }
+
+    fn op_args(&self) -> OpArgs {
+        self.0.op_args()
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        self.0.rebuild(children).map(|n| ANode::new(Rc::new(RequiresGrad::new(n.0))))
+    }
+
     #[inline]
-    fn value(&self) -> &[DType] {
-        &self.0.value()
+    fn value(&self) -> Value<'_> {
+        self.0.value()
     }
 
+    #[inline]
+    fn shape(&self) -> Shape { self.0.shape() }
+
     #[inline]
     fn get_children(&self) -> Option<&[ANode]> { self.0.get_children() }
 
     #[inline]
     fn requires_grad(&self) -> bool { true }
 
+    fn recompute(&self) { self.0.recompute() }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        self.0.compute_grad_graph(grad)
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        self.0.compute_jvp(tangents)
+    }
+
     #[inline]
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         self.0.compute_grad(grad, child_grads)
     }
 }
 
-pub struct Variable(NodeIdx, Computation);
+pub struct Variable(NodeIdx, Computation, Cell<bool>);
 
 impl Variable {
     pub fn new(value: Vec<DType>) -> ANode {
-        let v = Variable(NodeIdx::new(), Computation::new(value));
+        let v = Variable(NodeIdx::new(), Computation::new(value), Cell::new(true));
+        ANode::new(Rc::new(v))
+    }
+
+    // A frozen variable (requires_grad false) collects no gradients until
+    // unfrozen
+    pub fn new_with_grad(value: Vec<DType>, requires_grad: bool) -> ANode {
+        let v = Variable(NodeIdx::new(), Computation::new(value), Cell::new(requires_grad));
         ANode::new(Rc::new(v))
     }
 
     pub fn scalar(value: DType) -> ANode {
         Variable::new(vec![value])
     }
+
+    pub fn with_shape(value: Vec<DType>, shape: &[usize]) -> ANode {
+        let c = Computation::new(value).with_shape(Shape::new(shape));
+        let v = Variable(NodeIdx::new(), c, Cell::new(true));
+        ANode::new(Rc::new(v))
+    }
     
     pub fn shared(value: Rc<Vec<DType>>) -> ANode {
-        let v = Variable(NodeIdx::new(), Computation::shared(value));
+        let v = Variable(NodeIdx::new(), Computation::shared(value), Cell::new(true));
         ANode::new(Rc::new(v))
     }
 
     pub fn pooled(value: &[DType]) -> ANode {
         let mut mpv = allocate_vec(value.len());
         mpv.clone_from_slice(value);
-        let v = Variable(NodeIdx::new(), Computation::pooled(mpv));
+        let v = Variable(NodeIdx::new(), Computation::pooled(mpv), Cell::new(true));
         ANode::new(Rc::new(v))
     }
 
+    pub fn zeros(n: usize) -> ANode {
+        Variable::full(n, 0.)
+    }
+
+    pub fn ones(n: usize) -> ANode {
+        Variable::full(n, 1.)
+    }
+
+    pub fn full(n: usize, value: DType) -> ANode {
+        Variable::new(vec![value; n])
+    }
+
 }
 
 impl Node for Variable {
@@ -103,15 +546,30 @@ impl Node for Variable {
     fn is_leaf(&self) -> bool { true }
 
     #[inline]
-    fn value(&self) -> &[DType] {
-        &self.1.get()
+    fn value(&self) -> Value<'_> {
+        self.1.get()
     }
 
+    #[inline]
+    fn shape(&self) -> Shape { self.1.shape }
+
     #[inline]
     fn get_children(&self) -> Option<&[ANode]> { None }
 
+    fn set_value(&self, value: &[DType]) {
+        self.1.copy_from(value);
+    }
+
+    fn update_value(&self, f: &mut dyn FnMut(&mut [DType])) {
+        self.1.update(f);
+    }
+
     #[inline]
-    fn requires_grad(&self) -> bool { true }
+    fn requires_grad(&self) -> bool { self.2.get() }
+
+    fn set_requires_grad(&self, requires_grad: bool) {
+        self.2.set(requires_grad);
+    }
 
     #[inline]
     fn compute_grad(&self, _grad: &[DType], _child_grads: &mut [&mut [DType]]) {
@@ -134,6 +592,36 @@ impl Constant {
         ANode::new(Rc::new(c))
     }
 
+    pub fn with_shape(value: Vec<DType>, shape: &[usize]) -> ANode {
+        let c = Computation::new(value).with_shape(Shape::new(shape));
+        ANode::new(Rc::new(Constant(NodeIdx::new(), c)))
+    }
+
+    pub fn eye(n: usize) -> ANode {
+        let mut v = vec![0.; n * n];
+        (0..n).for_each(|i| v[i * n + i] = 1.);
+        Constant::with_shape(v, &[n, n])
+    }
+
+    // start, start + step, ... up to but excluding end
+    pub fn arange(start: DType, end: DType, step: DType) -> ANode {
+        if step == 0. || !step.is_finite() {
+            panic!("Cannot arange with a step of {}!", step);
+        }
+        let n = ((end - start) / step).ceil().max(0.) as usize;
+        Constant::new((0..n).map(|i| start + i as DType * step).collect())
+    }
+
+    // n evenly spaced values from start to end, both included
+    pub fn linspace(start: DType, end: DType, n: usize) -> ANode {
+        let step = if n > 1 { (end - start) / (n - 1) as DType } else { 0. };
+        let mut v: Vec<DType> = (0..n).map(|i| start + i as DType * step).collect();
+        if n > 1 {
+            v[n - 1] = end;
+        }
+        Constant::new(v)
+    }
+
 }
 
 impl Node for Constant {
@@ -148,87 +636,113 @@ impl Node for Constant {
     fn is_leaf(&self) -> bool { true }
 
     #[inline]
-    fn value(&self) -> &[DType] {
-        &self.1.get()
+    fn is_constant(&self) -> bool { true }
+
+    #[inline]
+    fn value(&self) -> Value<'_> {
+        self.1.get()
+    }
+
+    #[inline]
+    fn shape(&self) -> Shape { self.1.shape }
+
+    fn set_value(&self, value: &[DType]) {
+        self.1.copy_from(value);
+    }
+
+    #[inline]
+    fn requires_grad(&self) -> bool { false }
+}
+
+// Shares the value of another node without linking to it, so gradients stop
+// here.
+pub(crate) struct Detach(NodeIdx, [ANode; 1]);
+
+impl Detach {
+    pub fn new(node: ANode) -> ANode {
+        ANode::new(Rc::new(Detach(NodeIdx::new(), [node])))
+    }
+}
+
+impl Node for Detach {
+
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    #[inline]
+    fn get_children(&self) -> Option<&[ANode]> { None }
+
+    #[inline]
+    fn is_leaf(&self) -> bool { true }
+
+    fn value_sources(&self) -> Option<&[ANode]> {
+        Some(self.1.as_slice())
+    }
+
+    #[inline]
+    fn value(&self) -> Value<'_> {
+        self.1[0].value()
     }
 
+    #[inline]
+    fn shape(&self) -> Shape { self.1[0].shape() }
+
     #[inline]
     fn requires_grad(&self) -> bool { false }
 }
 
 struct Broadcast<'a> {
     vec: &'a [DType],
-    remaining: usize,
-    len: usize
+    idx: BroadcastIndex,
+    len: usize,
+    shape: Shape
 }
 
 impl <'a> Broadcast<'a> {
-    fn new<'b>(vec: &'a [DType], other: &'b [DType]) -> Self {
-        Broadcast::sized(vec, other.len())
-    }
-
-    fn sized(vec: &'a [DType], other: usize) -> Self {
-        if vec.len() == 1 || vec.len() == other {
-            Broadcast { vec, remaining: other, len: other }
-        } else if other == 1 {
-            Broadcast { vec, remaining: vec.len(), len: vec.len() }
-        } else {
-            panic!("Cannot broadcast values!");
-        }
+    fn new(vec: &'a [DType], shape: &Shape, out: &Shape) -> Self {
+        Broadcast { vec, idx: BroadcastIndex::new(shape, out), len: out.size(), shape: *out }
     }
 
-    fn from_pair<'b>(left: &'a [DType], right: &'b [DType]) -> (Self, Broadcast<'b>) {
-        (Broadcast::new(left, right), Broadcast::new(right, left))
+    // Both children broadcast against each other; `lv` and `rv` are their
+    // borrowed values
+    fn from_pair<'b>(left: &ANode, lv: &'a [DType], right: &ANode, rv: &'b [DType]) -> (Self, Broadcast<'b>) {
+        let (l_shape, r_shape) = (left.shape(), right.shape());
+        let out = broadcast_shapes(&l_shape, &r_shape);
+        (Broadcast::new(lv, &l_shape, &out), Broadcast::new(rv, &r_shape, &out))
     }
 }
 
 impl <'a> Iterator for Broadcast<'a> {
     type Item = &'a DType;
+
+    #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.remaining == 0 {
-            None
-        } else {
-            let v_len = self.vec.len();
-            if v_len > 1 {
-                let idx = v_len - self.remaining;
-                self.remaining -= 1;
-                Some(&self.vec[idx])
-            } else {
-                self.remaining -= 1;
-                Some(&self.vec[0])
-            }
-        }
+        let vec = self.vec;
+        self.idx.next().map(|i| &vec[i])
     }
 }
 
+// Accumulates gradients for an output of shape `out` back into a child of
+// shape `shape`, summing along any broadcast dimensions.
 struct Updater<'a> {
     data: &'a mut [DType],
-    cur_idx: usize,
-    max_size: usize
+    idx: BroadcastIndex
 }
 
 impl <'a> Updater<'a> {
-    fn new(data: &'a mut [DType], max_size: usize) -> Self {
-        let v_len = data.len();
-        if v_len == max_size || v_len == 1 {
-            Updater { data, cur_idx: 0, max_size }
-        } else {
-            panic!("Cannot broadcast values!");
+    fn new(data: &'a mut [DType], shape: &Shape, out: &Shape) -> Self {
+        if data.len() != shape.size() {
+            panic!("Gradient of length {} does not match shape {:?}!", data.len(), shape);
         }
+        Updater { data, idx: BroadcastIndex::new(shape, out) }
     }
 
     #[inline]
     fn add(&mut self, v: DType) {
-        if self.data.len() == 1 {
-            unsafe {
-                *self.data.get_unchecked_mut(0) += v;
-            }
-        } else {
-
+        if let Some(i) = self.idx.next() {
             unsafe {
-                *self.data.get_unchecked_mut(self.cur_idx) += v;
+                *self.data.get_unchecked_mut(i) += v;
             }
-            self.cur_idx += 1;
         }
     }
 }
@@ -236,20 +750,32 @@ impl <'a> Updater<'a> {
 pub(crate) struct AddN(NodeIdx, [ANode; 2], Computation);
 
 impl AddN {
-    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+    pub(crate) fn new(mut left: ANode, mut right: ANode) -> ANode {
+        expect_broadcast("AddN", &left, &right);
+        if let Some(n) = binary_inplace(&mut left, &mut right, |li, ri| li + ri) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = AddN::compute(&left, &right);
-        let node = AddN(idx, [left, right], Computation::pooled(value));
+        let node = AddN(idx, [left, right], value);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        if left.shape() == right.shape() {
+            let mut out = allocate_vec(left.value().len());
+            add(&left.value(), &right.value(), &mut out);
+            return Computation::pooled(out).with_shape(left.shape())
+        }
+
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
         let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
         out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
             *oi = lvi + rvi
         });
-        out
+        Computation::pooled(out).with_shape(shape)
     }
 }
 
@@ -263,20 +789,42 @@ impl Node for AddN {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(AddN::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(AddN::compute(&self.1[0], &self.1[1]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |_, _, tx, ty| tx + ty)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        Some(vec![grad.sum_to(x.shape().dims()), grad.sum_to(y.shape().dims())])
     }
 
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         // f(x,y) = x + y
         // df(x,y)/dx = 1
         // df(x,y)/dy = 1
-        for out in child_grads.iter_mut() {
-            let it = Broadcast::sized(grad, out.len());
-            let mut agg = Updater::new(out, grad.len());
-            it.for_each(|gi| agg.add(*gi));
+        for (out, child) in child_grads.iter_mut().zip(self.1.iter()) {
+            let mut agg = Updater::new(out, &child.shape(), &self.2.shape);
+            grad.iter().for_each(|gi| agg.add(*gi));
         }
     }
 
@@ -285,20 +833,32 @@ impl Node for AddN {
 pub(crate) struct Subtract(NodeIdx, [ANode;2], Computation);
 
 impl Subtract {
-    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+    pub(crate) fn new(mut left: ANode, mut right: ANode) -> ANode {
+        expect_broadcast("Subtract", &left, &right);
+        if let Some(n) = binary_inplace(&mut left, &mut right, |li, ri| li - ri) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Subtract::compute(&left, &right);
-        let node = Subtract(idx, [left, right], Computation::pooled(value));
+        let node = Subtract(idx, [left, right], value);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        if left.shape() == right.shape() {
+            let mut out = allocate_vec(left.value().len());
+            sub(&left.value(), &right.value(), &mut out);
+            return Computation::pooled(out).with_shape(left.shape())
+        }
+
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
         let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
         out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
             *oi = lvi - rvi
         });
-        out
+        Computation::pooled(out).with_shape(shape)
     }
 }
 
@@ -312,42 +872,77 @@ impl Node for Subtract {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Subtract::new(children[0].clone(), children[1].clone()))
     }
 
-    fn requires_grad(&self) -> bool { false }
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
 
-    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        // f(x,y) = x - y
-        // df(x,y)/dx = 1
-        // df(x,y)/dy = -1
-        let mut out = Updater::new(&mut child_grads[0], grad.len());
-        grad.iter().for_each(|gi| out.add(*gi));
+    fn shape(&self) -> Shape { self.2.shape }
 
-        let mut out = Updater::new(&mut child_grads[1], grad.len());
-        grad.iter().for_each(|gi| out.add(-*gi));
+    fn recompute(&self) {
+        self.2.set(Subtract::compute(&self.1[0], &self.1[1]));
     }
 
-}
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |_, _, tx, ty| tx - ty)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        Some(vec![grad.sum_to(x.shape().dims()), (-grad).sum_to(y.shape().dims())])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // f(x,y) = x - y
+        // df(x,y)/dx = 1
+        // df(x,y)/dy = -1
+        let mut out = Updater::new(child_grads[0], &self.1[0].shape(), &self.2.shape);
+        grad.iter().for_each(|gi| out.add(*gi));
+
+        let mut out = Updater::new(child_grads[1], &self.1[1].shape(), &self.2.shape);
+        grad.iter().for_each(|gi| out.add(-*gi));
+    }
+
+}
 
 pub(crate) struct Multiply(NodeIdx, [ANode; 2], Computation);
 
 impl Multiply {
-    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+    pub(crate) fn new(mut left: ANode, mut right: ANode) -> ANode {
+        expect_broadcast("Multiply", &left, &right);
+        if let Some(n) = binary_inplace(&mut left, &mut right, |li, ri| li * ri) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Multiply::compute(&left, &right);
-        let node = Multiply(idx, [left, right], Computation::pooled(value));
+        let node = Multiply(idx, [left, right], value);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        if left.shape() == right.shape() {
+            let mut out = allocate_vec(left.value().len());
+            mul(&left.value(), &right.value(), &mut out);
+            return Computation::pooled(out).with_shape(left.shape())
+        }
+
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
         let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
         out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
             *oi = lvi * rvi
         });
-        out
+        Computation::pooled(out).with_shape(shape)
     }
 }
 
@@ -361,26 +956,52 @@ impl Node for Multiply {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Multiply::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Multiply::compute(&self.1[0], &self.1[1]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |x, y, tx, ty| tx * y + x * ty)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        Some(vec![(grad * y).sum_to(x.shape().dims()), (grad * x).sum_to(y.shape().dims())])
     }
 
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         // f(x,y) = x * y
         // df(x,y)/dx = y
         // df(x,y)/dy = x
-        let x = self.1[0].value();
-        let y = self.1[1].value();
+        if self.1[0].shape() == self.1[1].shape() {
+            mul(grad, &self.1[1].value(), child_grads[0]);
+            mul(grad, &self.1[0].value(), child_grads[1]);
+            return
+        }
 
-        // x.len() = 1, x_grad.len() = 1, y.len() == 3, y_grad.len() = 3, grad.len() = 3
-        let mut ly  = Broadcast::sized(y, child_grads[0].len());
-        let mut out = Updater::new(&mut child_grads[0], grad.len());
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lx, ly) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+
+        let mut out = Updater::new(child_grads[0], &self.1[0].shape(), &self.2.shape);
         grad.iter().zip(ly).for_each(|(gi, yi)| out.add(*gi * *yi));
 
-        let mut lx  = Broadcast::sized(x, child_grads[1].len());
-        let mut out = Updater::new(&mut child_grads[1], grad.len());
+        let mut out = Updater::new(child_grads[1], &self.1[1].shape(), &self.2.shape);
         grad.iter().zip(lx).for_each(|(gi, xi)| out.add(*gi * *xi));
 
     }
@@ -390,20 +1011,33 @@ impl Node for Multiply {
 pub(crate) struct Divide(NodeIdx, [ANode; 2], Computation);
 
 impl Divide {
-    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+    pub(crate) fn new(mut left: ANode, right: ANode) -> ANode {
+        expect_broadcast("Divide", &left, &right);
+        let mut right = domain::guard_div(right);
+        if let Some(n) = binary_inplace(&mut left, &mut right, |li, ri| li / ri) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Divide::compute(&left, &right);
-        let node = Divide(idx, [left, right], Computation::pooled(value));
+        let node = Divide(idx, [left, right], value);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        if left.shape() == right.shape() {
+            let mut out = allocate_vec(left.value().len());
+            div(&left.value(), &right.value(), &mut out);
+            return Computation::pooled(out).with_shape(left.shape())
+        }
+
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
         let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
         out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
             *oi = lvi / rvi
         });
-        out
+        Computation::pooled(out).with_shape(shape)
     }
 }
 
@@ -417,26 +1051,49 @@ impl Node for Divide {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Divide::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Divide::compute(&self.1[0], &self.1[1]));
     }
 
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |x, y, tx, ty| (tx * y - x * ty) / (y * y))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        let dy = -(grad * x) / (y * y);
+        Some(vec![(grad / y).sum_to(x.shape().dims()), dy.sum_to(y.shape().dims())])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         // f(x,y) = x / y
-        let x = self.1[0].value();
-        let y = self.1[1].value();
-        
         // df(x,y)/dx = 1 / y
-        let mut ly  = Broadcast::sized(y, child_grads[0].len());
-        let mut out = Updater::new(&mut child_grads[0], grad.len());
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (_, ly) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let mut out = Updater::new(child_grads[0], &self.1[0].shape(), &self.2.shape);
         grad.iter().zip(ly).for_each(|(gi, yi)| out.add(*gi / *yi));
 
         // df(x,y)/dy = -x / y ^ 2
-        let (lx, ly) = Broadcast::from_pair(x, y);
-        let mut out = Updater::new(&mut child_grads[1], lx.len);
-        grad.iter().zip(lx.zip(ly)).for_each(|(gi, (xi, yi))| out.add(*gi * -*xi / yi.powf(2f32)));
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lx, ly) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let mut out = Updater::new(child_grads[1], &self.1[1].shape(), &self.2.shape);
+        grad.iter().zip(lx.zip(ly)).for_each(|(gi, (xi, yi))| out.add(*gi * -*xi / yi.powf(2.)));
     }
 
 }
@@ -444,20 +1101,27 @@ impl Node for Divide {
 pub(crate) struct Power(NodeIdx, [ANode;2], Computation);
 
 impl Power {
-    pub(crate) fn new(base: ANode, exp: ANode) -> ANode {
+    pub(crate) fn new(base: ANode, mut exp: ANode) -> ANode {
+        expect_broadcast("Power", &base, &exp);
+        let mut base = domain::guard_pow(base, &exp);
+        if let Some(n) = binary_inplace(&mut base, &mut exp, |li, ri| li.powf(ri)) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Power::compute(&base, &exp);
-        let node = Power(idx, [base, exp], Computation::pooled(value));
+        let node = Power(idx, [base, exp], value);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
         let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
         out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
             *oi = lvi.powf(*rvi)
         });
-        out
+        Computation::pooled(out).with_shape(shape)
     }
 }
 
@@ -471,31 +1135,78 @@ impl Node for Power {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Power::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Power::compute(&self.1[0], &self.1[1]));
     }
 
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |x, y, tx, ty| {
+            // Guarded like compute_grad
+            let dx = if tx == 0. || y == 0. { 0. } else { tx * y * x.powf(y - 1.) };
+            let dy = if ty == 0. || x == 0. { 0. } else { ty * x.ln() * x.powf(y) };
+            dx + dy
+        })
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        // Guarded like compute_grad: zero exponents are raised by 0 instead of
+        // -1 so 0 ^ 0 stays flat, and zero bases take the ln of 1
+        let y_zero = 1. - nonzero_mask(y);
+        let dx = grad * y * x.pow(y - 1. + y_zero);
+        // Constant exponents skip the ln, which is NaN for negative bases
+        let dy = if y.is_constant() {
+            Constant::with_shape(vec![0.; y.value().len()], y.shape().dims())
+        } else {
+            let x_nonzero = nonzero_mask(x);
+            let safe = x + (1. - &x_nonzero);
+            (grad * safe.ln() * x.pow(y) * x_nonzero).sum_to(y.shape().dims())
+        };
+        Some(vec![dx.sum_to(x.shape().dims()), dy])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         // f(x,y) = x ^ y
         // df(x,y)/dx = y * x ^ (y - 1)
-        // df(x,y)/dy = ln(y) * x ^ y
-        let x = self.1[0].value();
-        let y = self.1[1].value();
+        // df(x,y)/dy = ln(x) * x ^ y
 
-        // df(x,y)/dx = y * x ^ (y - 1)
-        let (lx, ly) = Broadcast::from_pair(x, y);
-        let mut out = Updater::new(&mut child_grads[0], lx.len);
+        // df(x,y)/dx = y * x ^ (y - 1), which is 0 rather than 0 * inf for
+        // a zero exponent
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lx, ly) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let mut out = Updater::new(child_grads[0], &self.1[0].shape(), &self.2.shape);
         grad.iter().zip(lx.zip(ly)).for_each(|(gi, (xi, yi))| {
-            out.add(*gi * *yi * xi.powf(*yi - 1f32));
+            out.add(if *yi == 0. { 0. } else { *gi * *yi * xi.powf(*yi - 1.) });
         });
-        
-        // df(x,y)/dy = ln(y) * x ^ y
-        let (lx, ly) = Broadcast::from_pair(x, y);
-        let mut out = Updater::new(&mut child_grads[1], lx.len);
+
+        // Constant exponents need no gradient; skipping them keeps negative
+        // bases from filling it with NaNs
+        if self.1[1].is_constant() {
+            return
+        }
+
+        // df(x,y)/dy = ln(x) * x ^ y, with 0 ^ y flat in y
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lx, ly) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let mut out = Updater::new(child_grads[1], &self.1[1].shape(), &self.2.shape);
         grad.iter().zip(lx.zip(ly)).for_each(|(gi, (xi, yi))| {
-            out.add(*gi * yi.ln() * xi.powf(*yi));
+            out.add(if *xi == 0. { 0. } else { *gi * xi.ln() * xi.powf(*yi) });
         });
     }
 
@@ -514,7 +1225,7 @@ impl SumVec {
     fn compute(left: &ANode) -> MPVec {
         let lv = left.value();
         let mut out = allocate_vec(1);
-        out[0] = lv.iter().sum::<f32>();
+        out[0] = vecops::sum(&lv);
         out
     }
 }
@@ -529,8 +1240,28 @@ impl Node for SumVec {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(SumVec::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(SumVec::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(1);
+        out[0] = vecops::sum(tangents[0]);
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.broadcast_to(self.1[0].shape().dims())])
     }
 
     fn requires_grad(&self) -> bool { false }
@@ -547,17 +1278,21 @@ impl Node for SumVec {
 pub(crate) struct Cos(NodeIdx, [ANode;1], Computation);
 
 impl Cos {
-    pub(crate) fn new(vec: ANode) -> ANode {
+    pub(crate) fn new(mut vec: ANode) -> ANode {
+        if let Some(n) = unary_inplace(&mut vec, |v| update(v, |x| x.cos())) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Cos::compute(&vec);
-        let node = Cos(idx, [vec], Computation::pooled(value));
+        let shape = vec.shape();
+        let node = Cos(idx, [vec], Computation::pooled(value).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
     fn compute(left: &ANode) -> MPVec {
         let lv = left.value();
         let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter()).for_each(|(oi, lvi)| *oi = lvi.cos());
+        map(&lv, &mut out, |lvi| lvi.cos());
         out
     }
 }
@@ -572,35 +1307,59 @@ impl Node for Cos {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Cos::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Cos::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.1[0].value(), tangents[0], |x, t| -x.sin() * t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![-(grad * self.1[0].sin())])
     }
 
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         let x = self.1[0].value();
         let out = &mut child_grads[0];
-        out.iter_mut().zip(grad.iter().zip(x.iter())).for_each(|(oi, (gi, xi))| {
-            *oi = *gi * -xi.sin()
-        });
+        zip_map(grad, &x, out, |gi, xi| gi * -xi.sin());
     }
 }
 
 pub(crate) struct Sin(NodeIdx, [ANode;1], Computation);
 
 impl Sin {
-    pub(crate) fn new(vec: ANode) -> ANode {
+    pub(crate) fn new(mut vec: ANode) -> ANode {
+        if let Some(n) = unary_inplace(&mut vec, |v| update(v, |x| x.sin())) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Sin::compute(&vec);
-        let node = Sin(idx, [vec], Computation::pooled(value));
+        let shape = vec.shape();
+        let node = Sin(idx, [vec], Computation::pooled(value).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
     fn compute(left: &ANode) -> MPVec {
         let lv = left.value();
         let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter()).for_each(|(oi, lvi)| *oi = lvi.sin());
+        map(&lv, &mut out, |lvi| lvi.sin());
         out
     }
 
@@ -616,36 +1375,59 @@ impl Node for Sin {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Sin::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Sin::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.1[0].value(), tangents[0], |x, t| x.cos() * t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad * self.1[0].cos()])
     }
 
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         let x = self.1[0].value();
         let out = &mut child_grads[0];
-        out.iter_mut().zip(grad.iter().zip(x.iter())).for_each(|(oi, (gi, xi))| {
-            *oi = *gi * xi.cos()
-        });
+        zip_map(grad, &x, out, |gi, xi| gi * xi.cos());
     }
 }
 
 pub(crate) struct Tanh(NodeIdx, [ANode;1], Computation);
 
 impl Tanh {
-    pub(crate) fn new(vec: ANode) -> ANode {
+    pub(crate) fn new(mut vec: ANode) -> ANode {
+        if let Some(n) = unary_inplace(&mut vec, vecops::itanh) {
+            return n
+        }
         let idx = NodeIdx::new();
         let value = Tanh::compute(&vec);
-        let node = Tanh(idx, [vec], Computation::pooled(value));
+        let shape = vec.shape();
+        let node = Tanh(idx, [vec], Computation::pooled(value).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
     fn compute(left: &ANode) -> MPVec {
         let lv = left.value();
         let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter())
-            .for_each(|(oi, lvi)| *oi = lvi.tanh());
+        vecops::tanh(&lv, &mut out);
         out
     }
 
@@ -661,127 +1443,251 @@ impl Node for Tanh {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Tanh::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
     }
 
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Tanh::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.value(), tangents[0], |y, t| (1. - y * y) * t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let t = self.1[0].tanh();
+        Some(vec![grad * (1. - &t * &t)])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
         let x = self.2.get();
         let out = &mut child_grads[0];
-        out.iter_mut().zip(grad.iter().zip(x.iter())).for_each(|(oi, (gi, xi))| {
-            *oi = *gi * (1f32 - xi.powf(2.))
-        });
+        zip_map(grad, &x, out, |gi, xi| gi * (1. - xi.powf(2.)));
     }
 }
 
-pub(crate) struct Ln(NodeIdx, [ANode;1], Computation);
+// x ^ c for a constant c. Integer exponents use repeated multiplication, so
+// negative bases work, and there's no ln(x) term in the gradient.
+pub(crate) struct PowScalar(NodeIdx, [ANode;1], Computation, DType);
+
+// Squares and cubes, common in losses, are spelled out
+fn powc(x: DType, c: DType) -> DType {
+    if c == 2. {
+        x * x
+    } else if c == 3. {
+        x * x * x
+    } else if c.fract() == 0. && c.abs() <= i32::MAX as DType {
+        x.powi(c as i32)
+    } else {
+        x.powf(c)
+    }
+}
 
-impl Ln {
-    pub(crate) fn new(vec: ANode) -> ANode {
+// d/dx x ^ c, 0 everywhere for c = 0
+fn dpowc(x: DType, c: DType) -> DType {
+    match c {
+        0. => 0.,
+        1. => 1.,
+        2. => 2. * x,
+        3. => 3. * x * x,
+        _ => c * powc(x, c - 1.)
+    }
+}
+
+impl PowScalar {
+    pub(crate) fn new(vec: ANode, c: DType) -> ANode {
+        let mut vec = domain::guard_pow(vec, &Constant::scalar(c));
+        if let Some(n) = unary_inplace(&mut vec, |v| v.iter_mut().for_each(|x| *x = powc(*x, c))) {
+            return n
+        }
         let idx = NodeIdx::new();
-        let value = Ln::compute(&vec);
-        let node = Ln(idx, [vec], Computation::pooled(value));
+        let value = PowScalar::compute(&vec, c);
+        let shape = vec.shape();
+        let node = PowScalar(idx, [vec], Computation::pooled(value).with_shape(shape), c);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode) -> MPVec {
-        let lv = left.value();
-        let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter()).for_each(|(oi, lvi)| *oi = lvi.ln());
+    fn compute(vec: &ANode, c: DType) -> MPVec {
+        let mut out = allocate_vec(vec.value().len());
+        map(&vec.value(), &mut out, |x| powc(x, c));
         out
     }
 }
 
-impl Node for Ln {
+impl Node for PowScalar {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
-    fn get_children(&self) -> Option<&[ANode]> { 
+    fn get_children(&self) -> Option<&[ANode]> {
         Some(self.1.as_slice())
     }
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn op_args(&self) -> OpArgs {
+        OpArgs { scalars: vec![self.3], ..Default::default() }
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(PowScalar::new(children[0].clone(), self.3))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(PowScalar::compute(&self.1[0], self.3));
     }
 
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let c = self.3;
+        unary_jvp(&self.1[0].value(), tangents[0], move |x, t| t * dpowc(x, c))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, c) = (&self.1[0], self.3);
+        if c == 0. {
+            return Some(vec![grad * 0.])
+        }
+        Some(vec![grad * c * x.pow_scalar(c - 1.)])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        let x = self.1[0].value();
-        let out = &mut child_grads[0];
-        out.iter_mut().zip(grad.iter().zip(x.iter())).for_each(|(oi, (gi, xi))| {
-            *oi = *gi / *xi
-        });
+        let c = self.3;
+        zip_map(grad, &self.1[0].value(), child_grads[0], move |gi, xi| gi * dpowc(xi, c));
     }
 }
 
-pub(crate) struct Exp(NodeIdx, [ANode;1], Computation);
+// Replaces NaNs and infinities with the given values. Replaced elements get
+// no gradient, the rest pass it through.
+pub(crate) struct NanToNum(NodeIdx, [ANode;1], Computation, [DType; 3]);
 
-impl Exp {
-    pub(crate) fn new(vec: ANode) -> ANode {
+impl NanToNum {
+    pub(crate) fn new(vec: ANode, nan: DType, posinf: DType, neginf: DType) -> ANode {
         let idx = NodeIdx::new();
-        let value = Exp::compute(&vec);
-        let node = Exp(idx, [vec], Computation::pooled(value));
+        let fill = [nan, posinf, neginf];
+        let value = NanToNum::compute(&vec, &fill);
+        let shape = vec.shape();
+        let node = NanToNum(idx, [vec], Computation::pooled(value).with_shape(shape), fill);
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode) -> MPVec {
-        let lv = left.value();
-        let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter()).for_each(|(oi, lvi)| *oi = lvi.exp());
+    fn compute(vec: &ANode, fill: &[DType; 3]) -> MPVec {
+        let [nan, posinf, neginf] = *fill;
+        let mut out = allocate_vec(vec.value().len());
+        map(&vec.value(), &mut out, move |x| {
+            if x.is_nan() {
+                nan
+            } else if x == DType::INFINITY {
+                posinf
+            } else if x == DType::NEG_INFINITY {
+                neginf
+            } else {
+                x
+            }
+        });
         out
     }
 
+    fn mask(&self) -> Vec<DType> {
+        self.1[0].value().iter().map(|x| if x.is_finite() { 1. } else { 0. }).collect()
+    }
 }
 
-impl Node for Exp {
+impl Node for NanToNum {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
-    fn get_children(&self) -> Option<&[ANode]> { 
+    fn get_children(&self) -> Option<&[ANode]> {
         Some(self.1.as_slice())
     }
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn op_args(&self) -> OpArgs {
+        OpArgs { scalars: self.3.to_vec(), ..Default::default() }
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        let [nan, posinf, neginf] = self.3;
+        Some(NanToNum::new(children[0].clone(), nan, posinf, neginf))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(NanToNum::compute(&self.1[0], &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.1[0].value(), tangents[0], |x, t| if x.is_finite() { t } else { 0. })
     }
 
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad * Constant::with_shape(self.mask(), self.1[0].shape().dims())])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        let x = self.value();
-        let mut out = &mut child_grads[0];
-        out.clone_from_slice(x);
-        imul(&mut out, grad);
+        zip_map(grad, &self.1[0].value(), child_grads[0], |gi, xi| if xi.is_finite() { gi } else { 0. });
     }
 }
 
-pub(crate) struct Negate(NodeIdx, [ANode;1], Computation);
+pub(crate) struct Ln(NodeIdx, [ANode;1], Computation);
 
-impl Negate {
+impl Ln {
     pub(crate) fn new(vec: ANode) -> ANode {
+        let mut vec = domain::guard_ln(vec);
+        if let Some(n) = unary_inplace(&mut vec, vecops::iln) {
+            return n
+        }
         let idx = NodeIdx::new();
-        let value = Negate::compute(&vec);
-        let node = Negate(idx, [vec], Computation::pooled(value));
+        let value = Ln::compute(&vec);
+        let shape = vec.shape();
+        let node = Ln(idx, [vec], Computation::pooled(value).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
     fn compute(left: &ANode) -> MPVec {
         let lv = left.value();
         let mut out = allocate_vec(lv.len());
-        out.iter_mut().zip(lv.iter()).for_each(|(oi, lvi)| *oi = -lvi);
+        vecops::ln(&lv, &mut out);
         out
     }
-
 }
 
-impl Node for Negate {
+impl Node for Ln {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
@@ -791,87 +1697,65 @@ impl Node for Negate {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Ln::new(children[0].clone()))
     }
 
-    fn requires_grad(&self) -> bool { false }
-
-    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        child_grads[0].iter_mut().zip(grad.iter()).for_each(|(oi, gi)| {
-            *oi = -*gi;
-        });
+    fn value(&self) -> Value<'_> {
+        self.2.get()
     }
-}
 
-pub(crate) struct BulkSum(NodeIdx, Vec<ANode>, Computation);
+    fn shape(&self) -> Shape { self.2.shape }
 
-impl BulkSum {
-    pub(crate) fn new(vecs: impl Iterator<Item=ANode>) -> ANode {
-        let idx = NodeIdx::new();
-        let children: Vec<_> = vecs.collect();
-        let value = BulkSum::compute(&children);
-        let node  = BulkSum(idx, children, Computation::pooled(value));
-        ANode::new(Rc::new(node))
+    fn recompute(&self) {
+        self.2.set_pooled(Ln::compute(&self.1[0]));
     }
 
-    fn compute(xs: &[ANode]) -> MPVec {
-        let mut agg = allocate_vec(xs[0].value().len());
-        for x in xs {
-            iadd(&mut agg, x.value());
-        }
-        agg
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.1[0].value(), tangents[0], |x, t| t / x)
     }
-}
-
-impl Node for BulkSum {
-    #[inline]
-    fn get_id(&self) -> NodeIdx { self.0 }
 
-    fn get_children(&self) -> Option<&[ANode]> { 
-        Some(self.1.as_slice())
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad / &self.1[0]])
     }
 
-    fn is_leaf(&self) -> bool { false }
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
 
-    #[inline]
-    fn value(&self) -> &[DType] {
-        &self.2.get()
-    }
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
 
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        // Just the gradient for each, easy peasy
-        let x = self.value();
-        for out in child_grads.iter_mut() {
-            out.clone_from_slice(grad);
-        }
+        let x = self.1[0].value();
+        let out = &mut child_grads[0];
+        zip_map(grad, &x, out, |gi, xi| gi / xi);
     }
 }
 
+pub(crate) struct Exp(NodeIdx, [ANode;1], Computation);
 
-pub(crate) struct Maximum(NodeIdx, [ANode;2], Computation);
-
-impl Maximum {
-    pub(crate) fn new(left: ANode, right:ANode) -> ANode {
+impl Exp {
+    pub(crate) fn new(mut vec: ANode) -> ANode {
+        if let Some(n) = unary_inplace(&mut vec, vecops::iexp) {
+            return n
+        }
         let idx = NodeIdx::new();
-        let value = Maximum::compute(&left, &right);
-        let node  = Maximum(idx, [left, right], Computation::pooled(value));
+        let value = Exp::compute(&vec);
+        let shape = vec.shape();
+        let node = Exp(idx, [vec], Computation::pooled(value).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
-        let mut out = allocate_vec(lv.len);
-        out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
-            *oi = lvi.max(*rvi)
-        });
+    fn compute(left: &ANode) -> MPVec {
+        let lv = left.value();
+        let mut out = allocate_vec(lv.len());
+        vecops::exp(&lv, &mut out);
         out
     }
+
 }
 
-impl Node for Maximum {
+impl Node for Exp {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
@@ -881,53 +1765,2612 @@ impl Node for Maximum {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Exp::new(children[0].clone()))
     }
 
-    fn requires_grad(&self) -> bool { false }
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
 
-    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        // f(x,y) = x.max(y)
-        let left = self.1[0].value();
-        let right = self.1[1].value();
-        let (lv, rv) = Broadcast::from_pair(left, right);
-        let (left_grad, right_grad) = child_grads.split_at_mut(1);
-        let mut left_out = Updater::new(&mut left_grad[0], grad.len());
-        let mut right_out = Updater::new(&mut right_grad[0], grad.len());
-        grad.iter().zip(lv.zip(rv)).for_each(|(gi, (xi, yi))| {
-            if xi >= yi {
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Exp::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(&self.value(), tangents[0], |y, t| y * t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad * self.1[0].exp()])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        mul(&self.value(), grad, child_grads[0]);
+    }
+}
+
+pub(crate) struct Negate(NodeIdx, [ANode;1], Computation);
+
+impl Negate {
+    pub(crate) fn new(mut vec: ANode) -> ANode {
+        if let Some(n) = unary_inplace(&mut vec, |v| update(v, |x| -x)) {
+            return n
+        }
+        let idx = NodeIdx::new();
+        let value = Negate::compute(&vec);
+        let shape = vec.shape();
+        let node = Negate(idx, [vec], Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode) -> MPVec {
+        let lv = left.value();
+        let mut out = allocate_vec(lv.len());
+        map(&lv, &mut out, |lvi| -lvi);
+        out
+    }
+
+}
+
+impl Node for Negate {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Negate::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Negate::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(tangents[0], tangents[0], |_, t| -t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![-grad])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        map(grad, child_grads[0], |gi| -gi);
+    }
+}
+
+// Applies an arbitrary elementwise function going forward, but treats it as
+// the identity going backward.
+pub(crate) struct StraightThrough(NodeIdx, [ANode;1], Computation, ScalarFn);
+
+impl StraightThrough {
+    pub(crate) fn new<F>(vec: ANode, f: F) -> ANode
+    where
+        F: Fn(DType) -> DType + 'static
+    {
+        let idx = NodeIdx::new();
+        let value = StraightThrough::compute(&vec, &f);
+        let shape = vec.shape();
+        let node = StraightThrough(idx, [vec], Computation::pooled(value).with_shape(shape), Box::new(f));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute<F: Fn(DType) -> DType + ?Sized>(left: &ANode, f: &F) -> MPVec {
+        map_serial(&left.value(), f)
+    }
+
+}
+
+impl Node for StraightThrough {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(StraightThrough::compute(&self.1[0], &*self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        unary_jvp(tangents[0], tangents[0], |_, t| t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.clone()])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        child_grads[0].copy_from_slice(grad);
+    }
+}
+
+type ScalarFn = Box<dyn Fn(DType) -> DType>;
+
+// User functions aren't required to be Sync, so they always run on the calling
+// thread rather than through the parallel helpers
+fn map_serial<F: Fn(DType) -> DType + ?Sized>(x: &[DType], f: &F) -> MPVec {
+    let mut out = allocate_vec(x.len());
+    out.iter_mut().zip(x.iter()).for_each(|(o, xi)| *o = f(*xi));
+    out
+}
+
+// Arbitrary elementwise function. Without a derivative the gradient is
+// taken by central differences.
+pub(crate) struct Map(NodeIdx, [ANode;1], Computation, ScalarFn, Option<ScalarFn>);
+
+impl Map {
+    pub(crate) fn new(vec: ANode, f: ScalarFn, df: Option<ScalarFn>) -> ANode {
+        let idx = NodeIdx::new();
+        let value = Map::compute(&vec, &f);
+        let shape = vec.shape();
+        let node = Map(idx, [vec], Computation::pooled(value).with_shape(shape), f, df);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, f: &ScalarFn) -> MPVec {
+        map_serial(&left.value(), f)
+    }
+
+    fn derivative(&self, x: DType) -> DType {
+        match &self.4 {
+            Some(df) => df(x),
+            None => {
+                let h = DType::EPSILON.cbrt() * x.abs().max(1.);
+                ((self.3)(x + h) - (self.3)(x - h)) / (2. * h)
+            }
+        }
+    }
+}
+
+impl Node for Map {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Map::compute(&self.1[0], &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(tangents[0].len());
+        out.iter_mut().zip(self.1[0].value().iter().zip(tangents[0].iter())).for_each(|(o, (x, t))| {
+            *o = self.derivative(*x) * t;
+        });
+        Some(out)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        child_grads[0].iter_mut().zip(self.1[0].value().iter().zip(grad.iter())).for_each(|(g, (x, gi))| {
+            *g = self.derivative(*x) * gi;
+        });
+    }
+}
+
+type CustomGradFn = Box<dyn Fn(&[&[DType]], &[DType], &[DType], &mut [&mut [DType]])>;
+
+// Stands in for the subgraph between `inputs` and `output`, whose gradient
+// comes from a user function of (inputs, output, grad) rather than autodiff.
+// The subgraph itself is kept so it can still be recomputed.
+pub(crate) struct CustomGrad(NodeIdx, Vec<ANode>, ANode, Vec<ANode>, CustomGradFn);
+
+impl CustomGrad {
+    pub(crate) fn new<F>(output: ANode, inputs: &[ANode], f: F) -> ANode
+    where
+        F: Fn(&[&[DType]], &[DType], &[DType], &mut [&mut [DType]]) + 'static
+    {
+        // Nodes strictly between the inputs and the output, children first
+        let mut steps = Vec::new();
+        let mut seen: HashSet<NodeIdx> = inputs.iter().map(|i| i.get_id()).collect();
+        let mut stack = vec![(output.clone(), false)];
+        while let Some((node, expanded)) = stack.pop() {
+            if expanded {
+                steps.push(node);
+            } else if !node.is_leaf() && seen.insert(node.get_id()) {
+                let children = node.get_children().map(|c| c.to_vec()).unwrap_or_default();
+                stack.push((node, true));
+                stack.extend(children.into_iter().map(|c| (c, false)));
+            }
+        }
+        let node = CustomGrad(NodeIdx::new(), inputs.to_vec(), output, steps, Box::new(f));
+        ANode::new(Rc::new(node))
+    }
+}
+
+impl Node for CustomGrad {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        self.2.value()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape() }
+
+    fn recompute(&self) {
+        self.3.iter().for_each(|n| n.recompute());
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let values: Vec<_> = self.1.iter().map(|i| i.value()).collect();
+        let inputs: Vec<&[DType]> = values.iter().map(|v| &**v).collect();
+        (self.4)(&inputs, &self.2.value(), grad, child_grads);
+    }
+}
+
+// Identity going forward, scales the gradient by -lambda going backward
+pub(crate) struct GradReverse(NodeIdx, [ANode;1], DType);
+
+impl GradReverse {
+    pub(crate) fn new(vec: ANode, lambda: DType) -> ANode {
+        ANode::new(Rc::new(GradReverse(NodeIdx::new(), [vec], lambda)))
+    }
+}
+
+impl Node for GradReverse {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs { scalars: vec![self.2], ..Default::default() }
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(GradReverse::new(children[0].clone(), self.2))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.1[0].value()
+    }
+
+    fn shape(&self) -> Shape { self.1[0].shape() }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let lambda = self.2;
+        unary_jvp(tangents[0], tangents[0], move |_, t| -lambda * t)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad * -self.2])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let lambda = self.2;
+        map(grad, child_grads[0], move |gi| -lambda * gi);
+    }
+}
+
+// One step of a fused elementwise program. Operands refer to earlier steps.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub(crate) enum FusedOp {
+    Input(usize),
+    Scalar(DType),
+    Neg(usize),
+    Exp(usize),
+    Ln(usize),
+    Sin(usize),
+    Cos(usize),
+    Tanh(usize),
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Div(usize, usize)
+}
+
+// A chain of elementwise ops evaluated in a single loop, without buffers for
+// the intermediate results. Backward re-runs the program per element and
+// walks it in reverse.
+pub(crate) struct Fused(NodeIdx, Vec<ANode>, Computation, Vec<FusedOp>);
+
+impl Fused {
+    pub(crate) fn new(inputs: Vec<ANode>, program: Vec<FusedOp>, shape: Shape) -> ANode {
+        let idx = NodeIdx::new();
+        let value = Fused::compute(&inputs, &program, shape.size());
+        let node = Fused(idx, inputs, Computation::pooled(value).with_shape(shape), program);
+        ANode::new(Rc::new(node))
+    }
+
+    #[inline]
+    fn eval(program: &[FusedOp], inputs: &[&[DType]], i: usize, regs: &mut [DType]) {
+        for (j, op) in program.iter().enumerate() {
+            regs[j] = match *op {
+                FusedOp::Input(k) => inputs[k][i],
+                FusedOp::Scalar(v) => v,
+                FusedOp::Neg(a) => -regs[a],
+                FusedOp::Exp(a) => vecops::exp_scalar(regs[a]),
+                FusedOp::Ln(a) => vecops::ln_scalar(regs[a]),
+                FusedOp::Sin(a) => regs[a].sin(),
+                FusedOp::Cos(a) => regs[a].cos(),
+                FusedOp::Tanh(a) => vecops::tanh_scalar(regs[a]),
+                FusedOp::Add(a, b) => regs[a] + regs[b],
+                FusedOp::Sub(a, b) => regs[a] - regs[b],
+                FusedOp::Mul(a, b) => regs[a] * regs[b],
+                FusedOp::Div(a, b) => regs[a] / regs[b]
+            };
+        }
+    }
+
+    fn compute(inputs: &[ANode], program: &[FusedOp], len: usize) -> MPVec {
+        let guards: Vec<_> = inputs.iter().map(|i| i.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        let mut regs = vec![0.; program.len()];
+        let mut out = allocate_vec(len);
+        out.iter_mut().enumerate().for_each(|(i, o)| {
+            Fused::eval(program, &values, i, &mut regs);
+            *o = regs[program.len() - 1];
+        });
+        out
+    }
+}
+
+impl Node for Fused {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Fused::new(children.to_vec(), self.3.clone(), self.2.shape))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Fused::compute(&self.1, &self.3, self.2.shape.size()));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let program = &self.3;
+        let guards: Vec<_> = self.1.iter().map(|i| i.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        let mut regs = vec![0.; program.len()];
+        let mut dots = vec![0.; program.len()];
+        let mut out = allocate_vec(self.2.shape.size());
+        for (i, o) in out.iter_mut().enumerate() {
+            Fused::eval(program, &values, i, &mut regs);
+            for (j, op) in program.iter().enumerate() {
+                dots[j] = match *op {
+                    FusedOp::Input(k) => tangents[k][i],
+                    FusedOp::Scalar(_) => 0.,
+                    FusedOp::Neg(a) => -dots[a],
+                    FusedOp::Exp(a) => dots[a] * regs[j],
+                    FusedOp::Ln(a) => dots[a] / regs[a],
+                    FusedOp::Sin(a) => dots[a] * regs[a].cos(),
+                    FusedOp::Cos(a) => -dots[a] * regs[a].sin(),
+                    FusedOp::Tanh(a) => dots[a] * (1. - regs[j] * regs[j]),
+                    FusedOp::Add(a, b) => dots[a] + dots[b],
+                    FusedOp::Sub(a, b) => dots[a] - dots[b],
+                    FusedOp::Mul(a, b) => dots[a] * regs[b] + regs[a] * dots[b],
+                    FusedOp::Div(a, b) => (dots[a] - regs[j] * dots[b]) / regs[b]
+                };
+            }
+            *o = dots[program.len() - 1];
+        }
+        Some(out)
+    }
+
+    // Unfuses the program into ordinary nodes and walks it in reverse, as
+    // compute_grad does per element
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let program = &self.3;
+        let mut nodes: Vec<ANode> = Vec::with_capacity(program.len());
+        for op in program.iter() {
+            let node = match *op {
+                FusedOp::Input(k) => self.1[k].clone(),
+                FusedOp::Scalar(v) => Constant::scalar(v),
+                FusedOp::Neg(a) => -&nodes[a],
+                FusedOp::Exp(a) => nodes[a].exp(),
+                FusedOp::Ln(a) => nodes[a].ln(),
+                FusedOp::Sin(a) => nodes[a].sin(),
+                FusedOp::Cos(a) => nodes[a].cos(),
+                FusedOp::Tanh(a) => nodes[a].tanh(),
+                FusedOp::Add(a, b) => &nodes[a] + &nodes[b],
+                FusedOp::Sub(a, b) => &nodes[a] - &nodes[b],
+                FusedOp::Mul(a, b) => &nodes[a] * &nodes[b],
+                FusedOp::Div(a, b) => &nodes[a] / &nodes[b]
+            };
+            nodes.push(node);
+        }
+
+        fn accumulate(adj: &mut [Option<ANode>], a: usize, g: ANode) {
+            adj[a] = Some(match adj[a].take() {
+                Some(prev) => prev + g,
+                None => g
+            });
+        }
+        let mut adj: Vec<Option<ANode>> = vec![None; program.len()];
+        let mut child_grads: Vec<Option<ANode>> = vec![None; self.1.len()];
+        adj[program.len() - 1] = Some(grad.clone());
+        for (j, op) in program.iter().enumerate().rev() {
+            let g = match adj[j].take() {
+                Some(g) => g,
+                None => continue
+            };
+            match *op {
+                FusedOp::Input(k) => accumulate(&mut child_grads, k, g),
+                FusedOp::Scalar(_) => {},
+                FusedOp::Neg(a) => accumulate(&mut adj, a, -g),
+                FusedOp::Exp(a) => accumulate(&mut adj, a, g * &nodes[j]),
+                FusedOp::Ln(a) => accumulate(&mut adj, a, g / &nodes[a]),
+                FusedOp::Sin(a) => accumulate(&mut adj, a, g * nodes[a].cos()),
+                FusedOp::Cos(a) => accumulate(&mut adj, a, -(g * nodes[a].sin())),
+                FusedOp::Tanh(a) => accumulate(&mut adj, a, g * (1. - &nodes[j] * &nodes[j])),
+                FusedOp::Add(a, b) => {
+                    accumulate(&mut adj, a, g.clone());
+                    accumulate(&mut adj, b, g);
+                },
+                FusedOp::Sub(a, b) => {
+                    accumulate(&mut adj, a, g.clone());
+                    accumulate(&mut adj, b, -g);
+                },
+                FusedOp::Mul(a, b) => {
+                    accumulate(&mut adj, a, &g * &nodes[b]);
+                    accumulate(&mut adj, b, g * &nodes[a]);
+                },
+                FusedOp::Div(a, b) => {
+                    accumulate(&mut adj, a, &g / &nodes[b]);
+                    accumulate(&mut adj, b, -(g * &nodes[j] / &nodes[b]));
+                }
+            }
+        }
+
+        // Inputs only need to match the output in length
+        Some(self.1.iter().zip(child_grads).map(|(input, g)| {
+            let dims = input.shape();
+            match g {
+                Some(g) if g.shape() == dims => g,
+                Some(g) => g.reshape(dims.dims()),
+                None => Constant::with_shape(vec![0.; dims.size()], dims.dims())
+            }
+        }).collect())
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let program = &self.3;
+        let guards: Vec<_> = self.1.iter().map(|i| i.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        let mut regs = vec![0.; program.len()];
+        let mut adj = vec![0.; program.len()];
+        for (i, gi) in grad.iter().enumerate() {
+            Fused::eval(program, &values, i, &mut regs);
+            adj.fill(0.);
+            adj[program.len() - 1] = *gi;
+            for (j, op) in program.iter().enumerate().rev() {
+                let g = adj[j];
+                match *op {
+                    FusedOp::Input(k) => child_grads[k][i] += g,
+                    FusedOp::Scalar(_) => {},
+                    FusedOp::Neg(a) => adj[a] -= g,
+                    FusedOp::Exp(a) => adj[a] += g * regs[j],
+                    FusedOp::Ln(a) => adj[a] += g / regs[a],
+                    FusedOp::Sin(a) => adj[a] += g * regs[a].cos(),
+                    FusedOp::Cos(a) => adj[a] -= g * regs[a].sin(),
+                    FusedOp::Tanh(a) => adj[a] += g * (1. - regs[j] * regs[j]),
+                    FusedOp::Add(a, b) => { adj[a] += g; adj[b] += g; },
+                    FusedOp::Sub(a, b) => { adj[a] += g; adj[b] -= g; },
+                    FusedOp::Mul(a, b) => { adj[a] += g * regs[b]; adj[b] += g * regs[a]; },
+                    FusedOp::Div(a, b) => {
+                        adj[a] += g / regs[b];
+                        adj[b] -= g * regs[a] / (regs[b] * regs[b]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub(crate) enum LossKind {
+    Mse,
+    Mae,
+    BceWithLogits,
+    KlDiv,
+    Entropy,
+    // Class indices, one per row of the input
+    CrossEntropy(Vec<usize>),
+    Nll(Vec<usize>)
+}
+
+impl LossKind {
+    fn name(&self) -> &'static str {
+        match self {
+            LossKind::Mse => "Mse",
+            LossKind::Mae => "Mae",
+            LossKind::BceWithLogits => "BceWithLogits",
+            LossKind::KlDiv => "KlDiv",
+            LossKind::Entropy => "Entropy",
+            LossKind::CrossEntropy(_) => "CrossEntropy",
+            LossKind::Nll(_) => "Nll"
+        }
+    }
+
+    fn targets(&self) -> Option<&[usize]> {
+        match self {
+            LossKind::CrossEntropy(t) | LossKind::Nll(t) => Some(t),
+            _ => None
+        }
+    }
+
+    // Elementwise losses are averaged, except those over distributions
+    fn divisor(&self, len: usize) -> DType {
+        match self {
+            LossKind::KlDiv | LossKind::Entropy => 1.,
+            _ => len as DType
+        }
+    }
+
+    // Loss of a single element, given its value in each input
+    fn elem(&self, xs: &[DType]) -> DType {
+        match (self, xs) {
+            (LossKind::Mse, [p, t]) => (p - t) * (p - t),
+            (LossKind::Mae, [p, t]) => (p - t).abs(),
+            // -(y ln(sigmoid(x)) + (1 - y) ln(1 - sigmoid(x))), rearranged so
+            // exp only sees non-positive arguments
+            (LossKind::BceWithLogits, [x, y]) => x.max(0.) - x * y + (-x.abs()).exp().ln_1p(),
+            // Zero probabilities contribute nothing, as in the limit
+            (LossKind::KlDiv, [_, q]) if *q == 0. => 0.,
+            (LossKind::KlDiv, [log_p, q]) => q * (q.ln() - log_p),
+            (LossKind::Entropy, [p]) if *p == 0. => 0.,
+            (LossKind::Entropy, [p]) => -p * p.ln(),
+            _ => unreachable!()
+        }
+    }
+
+    // Derivatives of `elem` with respect to each input
+    fn elem_grad(&self, xs: &[DType], out: &mut [DType]) {
+        match (self, xs) {
+            (LossKind::Mse, [p, t]) => {
+                out[0] = 2. * (p - t);
+                out[1] = -out[0];
+            },
+            // sign(p - t), taken as 0 where they're equal
+            (LossKind::Mae, [p, t]) => {
+                out[0] = if p > t { 1. } else if p < t { -1. } else { 0. };
+                out[1] = -out[0];
+            },
+            (LossKind::BceWithLogits, [x, y]) => {
+                let e = (-x.abs()).exp();
+                let sigmoid = if *x >= 0. { 1. / (1. + e) } else { e / (1. + e) };
+                out[0] = sigmoid - y;
+                out[1] = -x;
+            },
+            // The derivatives in q and p diverge at zero; those elements are
+            // treated as constant there rather than producing infinities
+            (LossKind::KlDiv, [log_p, q]) => {
+                out[0] = -q;
+                out[1] = if *q == 0. { 0. } else { q.ln() - log_p + 1. };
+            },
+            (LossKind::Entropy, [p]) => {
+                out[0] = if *p == 0. { 0. } else { -(p.ln() + 1.) };
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+// Losses reducing their inputs to a scalar in a single pass, with the
+// gradient written out directly rather than through a chain of ops.
+// Elementwise losses average or sum over the elements, index based ones
+// average over rows.
+pub(crate) struct Loss(NodeIdx, Vec<ANode>, Computation, LossKind);
+
+impl Loss {
+    pub(crate) fn new(inputs: Vec<ANode>, kind: LossKind) -> ANode {
+        let len = inputs[0].value().len();
+        if let Some(targets) = kind.targets() {
+            let rows = targets.len();
+            if rows == 0 || !len.is_multiple_of(rows) {
+                panic!("{} got {} targets for {} values!", kind.name(), rows, len);
+            }
+            if let Some(t) = targets.iter().find(|t| **t >= len / rows) {
+                panic!("{} target {} is out of range for {} classes!", kind.name(), t, len / rows);
+            }
+        } else if let Some(i) = inputs.iter().find(|i| i.value().len() != len) {
+            panic!("{} inputs must have the same length, got {} and {}!",
+                kind.name(), len, i.value().len());
+        }
+        let idx = NodeIdx::new();
+        let value = Loss::compute(&inputs, &kind);
+        ANode::new(Rc::new(Loss(idx, inputs, Computation::pooled(value), kind)))
+    }
+
+    fn compute(inputs: &[ANode], kind: &LossKind) -> MPVec {
+        let mut out = allocate_vec(1);
+        out[0] = match kind {
+            LossKind::CrossEntropy(targets) => {
+                let classes = inputs[0].value().len() / targets.len();
+                let x = inputs[0].value();
+                let rows = x.chunks(classes);
+                rows.zip(targets.iter())
+                    .map(|(row, t)| log_sum_exp(row) - row[*t])
+                    .sum::<DType>() / targets.len() as DType
+            },
+            LossKind::Nll(targets) => {
+                let classes = inputs[0].value().len() / targets.len();
+                let x = inputs[0].value();
+                let rows = x.chunks(classes);
+                -rows.zip(targets.iter()).map(|(row, t)| row[*t]).sum::<DType>() / targets.len() as DType
+            },
+            _ => {
+                let values: Vec<_> = inputs.iter().map(|i| i.value()).collect();
+                let len = values[0].len();
+                let mut xs = vec![0.; values.len()];
+                (0..len).map(|i| {
+                    xs.iter_mut().zip(values.iter()).for_each(|(x, v)| *x = v[i]);
+                    kind.elem(&xs)
+                }).sum::<DType>() / kind.divisor(len)
+            }
+        };
+        out
+    }
+}
+
+// ln(sum(exp(x))), shifted by the max so large inputs don't overflow
+fn log_sum_exp(xs: &[DType]) -> DType {
+    let max = xs.iter().cloned().fold(DType::NEG_INFINITY, DType::max);
+    max + xs.iter().map(|x| (x - max).exp()).sum::<DType>().ln()
+}
+
+impl Node for Loss {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> {
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_name(&self) -> &'static str { self.3.name() }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Loss::new(children.to_vec(), self.3.clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Loss::compute(&self.1, &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        // The output is a scalar, so its tangent is the gradient dotted with
+        // the inputs' tangents
+        let mut grads: Vec<Vec<DType>> = self.1.iter().map(|c| vec![0.; c.value().len()]).collect();
+        let mut slices: Vec<&mut [DType]> = grads.iter_mut().map(|g| g.as_mut_slice()).collect();
+        self.compute_grad(&[1.], &mut slices);
+        let mut out = allocate_vec(1);
+        out[0] = grads.iter().zip(tangents.iter())
+            .map(|(g, t)| g.iter().zip(t.iter()).map(|(gi, ti)| gi * ti).sum::<DType>())
+            .sum();
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        // The same derivatives as compute_grad, built from ops so they can
+        // be differentiated again
+        let len = self.1[0].value().len();
+        let scale = grad / self.3.divisor(len);
+        let onehot = |targets: &[usize]| {
+            let classes = len / targets.len();
+            let mut v = vec![0.; len];
+            targets.iter().enumerate().for_each(|(r, t)| v[r * classes + t] = 1.);
+            Constant::with_shape(v, self.1[0].shape().dims())
+        };
+        // Zero probabilities are held constant, as in compute_grad
+        let nonzero = |x: &ANode| {
+            let mask = nonzero_mask(x);
+            let safe = x + (1. - &mask);
+            (mask, safe)
+        };
+        let grads = match (&self.3, self.1.as_slice()) {
+            (LossKind::CrossEntropy(targets), [x]) => {
+                let classes = len / targets.len();
+                let probs = x.reshape(&[targets.len(), classes]).softmax().reshape(x.shape().dims());
+                vec![(probs - onehot(targets)) * (grad / targets.len() as DType)]
+            },
+            (LossKind::Nll(targets), [_]) => {
+                vec![onehot(targets) * -(grad / targets.len() as DType)]
+            },
+            (LossKind::Mse, [p, t]) => {
+                let d = (p - t.reshape(p.shape().dims())) * (scale * 2.);
+                vec![d.clone(), -d.reshape(t.shape().dims())]
+            },
+            (LossKind::Mae, [p, t]) => {
+                let (pv, tv) = (p.value(), t.value());
+                let sign: Vec<DType> = pv.iter().zip(tv.iter())
+                    .map(|(pi, ti)| if pi > ti { 1. } else if pi < ti { -1. } else { 0. })
+                    .collect();
+                let d = Constant::with_shape(sign, p.shape().dims()) * scale;
+                vec![d.clone(), -d.reshape(t.shape().dims())]
+            },
+            (LossKind::BceWithLogits, [x, y]) => {
+                // sigmoid written through tanh so large logits don't overflow
+                let sigmoid = (x * 0.5).tanh() * 0.5 + 0.5;
+                let dx = (sigmoid - y.reshape(x.shape().dims())) * &scale;
+                vec![dx, -(x.reshape(y.shape().dims()) * scale)]
+            },
+            (LossKind::KlDiv, [log_p, q]) => {
+                let (mask, safe) = nonzero(q);
+                let dq = mask * (safe.ln() - log_p.reshape(q.shape().dims()) + 1.) * &scale;
+                vec![-(q.reshape(log_p.shape().dims()) * scale), dq]
+            },
+            (LossKind::Entropy, [p]) => {
+                let (mask, safe) = nonzero(p);
+                vec![-(mask * (safe.ln() + 1.) * scale)]
+            },
+            _ => unreachable!()
+        };
+        Some(grads)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        match &self.3 {
+            LossKind::CrossEntropy(targets) => {
+                // softmax(x) - onehot(t) per row
+                let scale = grad[0] / targets.len() as DType;
+                let classes = self.1[0].value().len() / targets.len();
+                let x = self.1[0].value();
+                let rows = x.chunks(classes).zip(child_grads[0].chunks_mut(classes));
+                for ((row, g), t) in rows.zip(targets.iter()) {
+                    let lse = log_sum_exp(row);
+                    g.iter_mut().zip(row.iter()).for_each(|(gi, x)| *gi = scale * (x - lse).exp());
+                    g[*t] -= scale;
+                }
+            },
+            LossKind::Nll(targets) => {
+                let scale = grad[0] / targets.len() as DType;
+                let classes = self.1[0].value().len() / targets.len();
+                child_grads[0].fill(0.);
+                for (r, t) in targets.iter().enumerate() {
+                    child_grads[0][r * classes + t] = -scale;
+                }
+            },
+            kind => {
+                let values: Vec<_> = self.1.iter().map(|i| i.value()).collect();
+                let scale = grad[0] / kind.divisor(values[0].len());
+                let mut xs = vec![0.; values.len()];
+                let mut ds = vec![0.; values.len()];
+                for i in 0..values[0].len() {
+                    xs.iter_mut().zip(values.iter()).for_each(|(x, v)| *x = v[i]);
+                    kind.elem_grad(&xs, &mut ds);
+                    for (cg, d) in child_grads.iter_mut().zip(ds.iter()) {
+                        cg[i] = scale * d;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// exp(x) / sum(exp(x)) over the last axis. Rows that are entirely -inf, as
+// from a fully masked attention row, come out as zeros rather than NaNs.
+pub(crate) struct Softmax(NodeIdx, [ANode;1], Computation);
+
+impl Softmax {
+    pub(crate) fn new(vec: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let value = Softmax::compute(&vec);
+        let shape = vec.shape();
+        let node = Softmax(idx, [vec], Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn width(node: &ANode) -> usize {
+        node.shape().dims().last().cloned().unwrap_or(1).max(1)
+    }
+
+    fn compute(vec: &ANode) -> MPVec {
+        let width = Softmax::width(vec);
+        let mut out = allocate_vec(vec.value().len());
+        for (row, o) in vec.value().chunks(width).zip(out.chunks_mut(width)) {
+            let max = row.iter().cloned().fold(DType::NEG_INFINITY, DType::max);
+            if max == DType::NEG_INFINITY {
+                o.fill(0.);
+                continue
+            }
+            o.iter_mut().zip(row.iter()).for_each(|(oi, x)| *oi = (x - max).exp());
+            let total = o.iter().sum::<DType>();
+            o.iter_mut().for_each(|oi| *oi /= total);
+        }
+        out
+    }
+}
+
+impl Node for Softmax {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> {
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Softmax::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Softmax::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        // The Jacobian is symmetric, so the JVP is the VJP of the tangent
+        let mut out = allocate_vec(self.value().len());
+        self.compute_grad(tangents[0], &mut [&mut out]);
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let y = self.1[0].softmax();
+        let mut dims = y.shape().dims().to_vec();
+        if let Some(last) = dims.last_mut() {
+            *last = 1;
+        }
+        let dot = (grad * &y).sum_to(&dims);
+        Some(vec![y * (grad - dot)])
+    }
+
+    fn take_value(&mut self) -> Option<MPVec> { self.2.take() }
+
+    fn leads_to_grad(&self) -> bool { self.2.leads_to_grad(&self.1) }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // y * (g - sum(g * y)) per row
+        let width = Softmax::width(&self.1[0]);
+        let y = self.value();
+        let rows = y.chunks(width).zip(grad.chunks(width));
+        for ((y, g), out) in rows.zip(child_grads[0].chunks_mut(width)) {
+            let dot = y.iter().zip(g.iter()).map(|(yi, gi)| yi * gi).sum::<DType>();
+            out.iter_mut().zip(y.iter().zip(g.iter())).for_each(|(o, (yi, gi))| *o = yi * (gi - dot));
+        }
+    }
+}
+
+pub(crate) struct BulkSum(NodeIdx, Vec<ANode>, Computation);
+
+impl BulkSum {
+    pub(crate) fn new(vecs: impl Iterator<Item=ANode>) -> ANode {
+        let idx = NodeIdx::new();
+        let children: Vec<_> = vecs.collect();
+        let value = BulkSum::compute(&children);
+        let shape = children[0].shape();
+        let node  = BulkSum(idx, children, Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(xs: &[ANode]) -> MPVec {
+        let mut agg = allocate_vec(xs[0].value().len());
+        let guards: Vec<_> = xs.iter().map(|x| x.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        vecops::sum_slices(&values, &mut agg);
+        agg
+    }
+}
+
+impl Node for BulkSum {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(BulkSum::new(children.iter().cloned()))
+    }
+
+    #[inline]
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(BulkSum::compute(&self.1));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(self.2.get().len());
+        vecops::sum_slices(tangents, &mut out);
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(self.1.iter().map(|_| grad.clone()).collect())
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Just the gradient for each, easy peasy
+        for_each_mut(child_grads, |_, out| out.clone_from_slice(grad));
+    }
+}
+
+
+pub(crate) struct Maximum(NodeIdx, [ANode;2], Computation);
+
+impl Maximum {
+    pub(crate) fn new(left: ANode, right:ANode) -> ANode {
+        expect_broadcast("Maximum", &left, &right);
+        let idx = NodeIdx::new();
+        let value = Maximum::compute(&left, &right);
+        let node  = Maximum(idx, [left, right], value);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
+        let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
+        out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
+            *oi = lvi.max(*rvi)
+        });
+        Computation::pooled(out).with_shape(shape)
+    }
+}
+
+impl Node for Maximum {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Maximum::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Maximum::compute(&self.1[0], &self.1[1]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |x, y, tx, ty| if x >= y { tx } else { ty })
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        select_grad_graph(&self.1[0], &self.1[1], grad, |x, y| x >= y)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // f(x,y) = x.max(y)
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lv, rv) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let (left_grad, right_grad) = child_grads.split_at_mut(1);
+        let mut left_out = Updater::new(left_grad[0], &self.1[0].shape(), &self.2.shape);
+        let mut right_out = Updater::new(right_grad[0], &self.1[1].shape(), &self.2.shape);
+        grad.iter().zip(lv.zip(rv)).for_each(|(gi, (xi, yi))| {
+            if xi >= yi {
                 left_out.add(*gi);
-                right_out.add(0f32);
+                right_out.add(0.);
             } else {
                 right_out.add(*gi);
-                left_out.add(0f32);
+                left_out.add(0.);
+            }
+        });
+    }
+}
+
+pub(crate) struct Minimum(NodeIdx, [ANode;2], Computation);
+
+impl Minimum {
+    pub(crate) fn new(left: ANode, right:ANode) -> ANode {
+        expect_broadcast("Minimum", &left, &right);
+        let idx = NodeIdx::new();
+        let value = Minimum::compute(&left, &right);
+        let node  = Minimum(idx, [left, right], value);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, right: &ANode) -> Computation {
+        let (l, r) = (left.value(), right.value());
+        let (lv, rv) = Broadcast::from_pair(left, &l, right, &r);
+        let mut out = allocate_vec(lv.len);
+        let shape = lv.shape;
+        out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
+            *oi = lvi.min(*rvi)
+        });
+        Computation::pooled(out).with_shape(shape)
+    }
+}
+
+impl Node for Minimum {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Minimum::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Minimum::compute(&self.1[0], &self.1[1]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        binary_jvp(&self.1[0], &self.1[1], tangents, &self.2.shape, |x, y, tx, ty| if x >= y { ty } else { tx })
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        select_grad_graph(&self.1[0], &self.1[1], grad, |x, y| x < y)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // f(x,y) = x.max(y)
+        let (l, r) = (self.1[0].value(), self.1[1].value());
+        let (lv, rv) = Broadcast::from_pair(&self.1[0], &l, &self.1[1], &r);
+        let (left_grad, right_grad) = child_grads.split_at_mut(1);
+        let mut left_out = Updater::new(left_grad[0], &self.1[0].shape(), &self.2.shape);
+        let mut right_out = Updater::new(right_grad[0], &self.1[1].shape(), &self.2.shape);
+        grad.iter().zip(lv.zip(rv)).for_each(|(gi, (xi, yi))| {
+            if xi >= yi {
+                right_out.add(*gi);
+                left_out.add(0.);
+            } else {
+                left_out.add(*gi);
+                right_out.add(0.);
+            }
+        });
+    }
+}
+
+pub(crate) struct SumAxis(NodeIdx, [ANode; 1], Computation, usize);
+
+impl SumAxis {
+    pub(crate) fn new(vec: ANode, axis: usize) -> ANode {
+        let idx = NodeIdx::new();
+        let value = SumAxis::compute(&vec, axis);
+        let node = SumAxis(idx, [vec], value, axis);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, axis: usize) -> Computation {
+        let shape = left.shape();
+        let (outer, dim, inner) = shape.around_axis(axis);
+        let lv = left.value();
+        let mut out = allocate_vec(outer * inner);
+        for o in 0..outer {
+            let agg = &mut out[o * inner..(o + 1) * inner];
+            for k in 0..dim {
+                let start = (o * dim + k) * inner;
+                iadd(agg, &lv[start..start + inner]);
+            }
+        }
+        Computation::pooled(out).with_shape(shape.remove_axis(axis))
+    }
+}
+
+impl Node for SumAxis {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.3])
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(SumAxis::new(children[0].clone(), self.3))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(SumAxis::compute(&self.1[0], self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| SumAxis::compute(t, self.3).into_pooled())
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let mut dims = self.1[0].shape().dims().to_vec();
+        dims[self.3] = 1;
+        Some(vec![grad.reshape(&dims).broadcast_to(self.1[0].shape().dims())])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Every element along the summed axis receives the gradient of its
+        // output position
+        let (outer, dim, inner) = self.1[0].shape().around_axis(self.3);
+        let out = &mut child_grads[0];
+        for o in 0..outer {
+            let g = &grad[o * inner..(o + 1) * inner];
+            for k in 0..dim {
+                let start = (o * dim + k) * inner;
+                iadd(&mut out[start..start + inner], g);
+            }
+        }
+    }
+}
+
+pub(crate) struct MatMul(NodeIdx, [ANode; 2], Computation, (usize, usize, usize));
+
+impl MatMul {
+    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let (dims, shape) = MatMul::dims(&left.shape(), &right.shape());
+        let value = MatMul::compute(&left, &right, dims);
+        let node = MatMul(idx, [left, right], Computation::pooled(value).with_shape(shape), dims);
+        ANode::new(Rc::new(node))
+    }
+
+    // 1-D operands are treated as a row vector on the left and a column
+    // vector on the right, with that dimension dropped from the output.
+    fn dims(left: &Shape, right: &Shape) -> ((usize, usize, usize), Shape) {
+        match MatMul::try_dims(left, right) {
+            Ok(dims) => dims,
+            Err(e) => panic!("{}!", e)
+        }
+    }
+
+    fn try_dims(left: &Shape, right: &Shape) -> Result<((usize, usize, usize), Shape), String> {
+        let (m, k, l_vec) = match left.dims() {
+            [k] => (1, *k, true),
+            [m, k] => (*m, *k, false),
+            _ => return Err(format!("MatMul expects 1-D or 2-D operands, got {:?}", left))
+        };
+        let (k2, n, r_vec) = match right.dims() {
+            [k] => (*k, 1, true),
+            [k, n] => (*k, *n, false),
+            _ => return Err(format!("MatMul expects 1-D or 2-D operands, got {:?}", right))
+        };
+        if k != k2 {
+            return Err(format!("Cannot multiply matrices of shapes {:?} and {:?}", left, right))
+        }
+        let shape = match (l_vec, r_vec) {
+            (true, true)   => Shape::vector(1),
+            (true, false)  => Shape::vector(n),
+            (false, true)  => Shape::vector(m),
+            (false, false) => Shape::new(&[m, n])
+        };
+        Ok(((m, k, n), shape))
+    }
+
+    fn compute(left: &ANode, right: &ANode, (m, k, n): (usize, usize, usize)) -> MPVec {
+        let mut out = allocate_vec(m * n);
+        matmul(&left.value(), &right.value(), &mut out, m, k, n);
+        out
+    }
+}
+
+impl Node for MatMul {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(MatMul::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(MatMul::compute(&self.1[0], &self.1[1], self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        bilinear_jvp(&self.1[0], &self.1[1], tangents, |x, y| MatMul::compute(x, y, self.3))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (m, k, n) = self.3;
+        let (x, y) = (&self.1[0], &self.1[1]);
+        let g = grad.reshape(&[m, n]);
+        let dx = g.matmul(y.reshape(&[k, n]).transpose());
+        let dy = x.reshape(&[m, k]).transpose().matmul(&g);
+        Some(vec![dx.reshape(x.shape().dims()), dy.reshape(y.shape().dims())])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // f(A,B) = AB
+        // df/dA = G * B^T
+        // df/dB = A^T * G
+        let (m, k, n) = self.3;
+        let (a, b) = (self.1[0].value(), self.1[1].value());
+        matmul_bt(grad, &b, child_grads[0], m, n, k);
+        matmul_at(&a, grad, child_grads[1], m, k, n);
+    }
+}
+
+pub(crate) struct BatchMatMul(NodeIdx, [ANode; 2], Computation, (usize, usize, usize, usize));
+
+impl BatchMatMul {
+    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let (l_shape, r_shape) = (left.shape(), right.shape());
+        let dims = match (l_shape.dims(), r_shape.dims()) {
+            ([b, m, k], [b2, k2, n]) if b == b2 && k == k2 => (*b, *m, *k, *n),
+            _ => panic!("Cannot batch multiply shapes {:?} and {:?}!", l_shape, r_shape)
+        };
+        let value = BatchMatMul::compute(&left, &right, dims);
+        let shape = Shape::new(&[dims.0, dims.1, dims.3]);
+        let node = BatchMatMul(idx, [left, right], Computation::pooled(value).with_shape(shape), dims);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, right: &ANode, (b, m, k, n): (usize, usize, usize, usize)) -> MPVec {
+        let mut out = allocate_vec(b * m * n);
+        let (lv, rv) = (left.value(), right.value());
+        for bi in 0..b {
+            matmul(&lv[bi*m*k..(bi+1)*m*k], &rv[bi*k*n..(bi+1)*k*n], 
+                   &mut out[bi*m*n..(bi+1)*m*n], m, k, n);
+        }
+        out
+    }
+}
+
+impl Node for BatchMatMul {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(BatchMatMul::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(BatchMatMul::compute(&self.1[0], &self.1[1], self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        bilinear_jvp(&self.1[0], &self.1[1], tangents, |x, y| BatchMatMul::compute(x, y, self.3))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (a, b) = (&self.1[0], &self.1[1]);
+        Some(vec![grad.batch_matmul(b.permute(&[0, 2, 1])), a.permute(&[0, 2, 1]).batch_matmul(grad)])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Same as MatMul, independently for each batch element
+        let (b, m, k, n) = self.3;
+        let (a, bv) = (self.1[0].value(), self.1[1].value());
+        let (a_grad, b_grad) = child_grads.split_at_mut(1);
+        for bi in 0..b {
+            let g = &grad[bi*m*n..(bi+1)*m*n];
+            matmul_bt(g, &bv[bi*k*n..(bi+1)*k*n], &mut a_grad[0][bi*m*k..(bi+1)*m*k], m, n, k);
+            matmul_at(&a[bi*m*k..(bi+1)*m*k], g, &mut b_grad[0][bi*k*n..(bi+1)*k*n], m, k, n);
+        }
+    }
+}
+
+pub(crate) struct Outer(NodeIdx, [ANode; 2], Computation);
+
+impl Outer {
+    pub(crate) fn new(left: ANode, right: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let (m, n) = (left.value().len(), right.value().len());
+        let value = Outer::compute(&left, &right);
+        let c = Computation::pooled(value).with_shape(Shape::new(&[m, n]));
+        let node = Outer(idx, [left, right], c);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(left: &ANode, right: &ANode) -> MPVec {
+        let (m, n) = (left.value().len(), right.value().len());
+        let mut value = allocate_vec(m * n);
+        matmul(&left.value(), &right.value(), &mut value, m, 1, n);
+        value
+    }
+}
+
+impl Node for Outer {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Outer::new(children[0].clone(), children[1].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Outer::compute(&self.1[0], &self.1[1]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        bilinear_jvp(&self.1[0], &self.1[1], tangents, Outer::compute)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (x, y) = (&self.1[0], &self.1[1]);
+        let (m, n) = (x.value().len(), y.value().len());
+        let dx = grad.matmul(y.reshape(&[n, 1])).reshape(x.shape().dims());
+        let dy = x.reshape(&[1, m]).matmul(grad).reshape(y.shape().dims());
+        Some(vec![dx, dy])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // f(x,y) = x y^T
+        // df/dx = G * y
+        // df/dy = G^T * x
+        let (x, y) = (self.1[0].value(), self.1[1].value());
+        let (m, n) = (x.len(), y.len());
+        matmul(grad, &y, child_grads[0], m, n, 1);
+        matmul_at(&x, grad, child_grads[1], m, 1, n);
+    }
+}
+
+pub(crate) struct Transpose(NodeIdx, [ANode; 1], Computation);
+
+impl Transpose {
+    pub(crate) fn new(node: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let shape = node.shape();
+        let (rows, cols) = match shape.dims() {
+            [r, c] => (*r, *c),
+            _ => panic!("Transpose expects a 2-D node, got {:?}!", shape)
+        };
+        let value = Transpose::compute(&node, rows, cols);
+        let shape = Shape::new(&[cols, rows]);
+        let node = Transpose(idx, [node], Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode, rows: usize, cols: usize) -> MPVec {
+        let mut value = allocate_vec(rows * cols);
+        transpose(&node.value(), &mut value, rows, cols);
+        value
+    }
+}
+
+impl Node for Transpose {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Transpose::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        let dims = self.2.shape.dims();
+        self.2.set_pooled(Transpose::compute(&self.1[0], dims[1], dims[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let dims = self.2.shape.dims();
+        linear_jvp(&self.1[0], tangents[0], |t| Transpose::compute(t, dims[1], dims[0]))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.transpose()])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let dims = self.2.shape.dims();
+        transpose(grad, child_grads[0], dims[0], dims[1]);
+    }
+}
+
+pub(crate) struct Permute(NodeIdx, [ANode; 1], Computation, Vec<usize>);
+
+impl Permute {
+    pub(crate) fn new(node: ANode, axes: &[usize]) -> ANode {
+        let idx = NodeIdx::new();
+        let shape = node.shape().permute(axes);
+        let value = Permute::compute(&node, axes);
+        let c = Computation::pooled(value).with_shape(shape);
+        let node = Permute(idx, [node], c, axes.to_vec());
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode, axes: &[usize]) -> MPVec {
+        let v = node.value();
+        let mut value = allocate_vec(v.len());
+        value.iter_mut().zip(BroadcastIndex::permuted(&node.shape(), axes)).for_each(|(oi, i)| {
+            *oi = v[i];
+        });
+        value
+    }
+}
+
+impl Node for Permute {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(self.3.clone())
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Permute::new(children[0].clone(), &self.3))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Permute::compute(&self.1[0], &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| Permute::compute(t, &self.3))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let mut inverse = vec![0; self.3.len()];
+        self.3.iter().enumerate().for_each(|(i, a)| inverse[*a] = i);
+        Some(vec![grad.permute(&inverse)])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Scatter each gradient back to where its value was gathered from
+        let out = &mut child_grads[0];
+        let it = BroadcastIndex::permuted(&self.1[0].shape(), &self.3);
+        grad.iter().zip(it).for_each(|(gi, i)| out[i] += gi);
+    }
+}
+
+// Dimensions of a 2-D sliding window over an [N, C, H, W] input.
+#[derive(Clone,Copy)]
+struct Window {
+    n: usize, c: usize, h: usize, w: usize,
+    kh: usize, kw: usize, oh: usize, ow: usize,
+    stride: usize
+}
+
+impl Window {
+    fn new(input: &Shape, kh: usize, kw: usize, stride: usize) -> Self {
+        match Window::try_new(input, kh, kw, stride) {
+            Ok(win) => win,
+            Err(e) => panic!("{}!", e)
+        }
+    }
+
+    fn try_new(input: &Shape, kh: usize, kw: usize, stride: usize) -> Result<Self, String> {
+        let (n, c, h, w) = match input.dims() {
+            [n, c, h, w] => (*n, *c, *h, *w),
+            _ => return Err(format!("Expected an [N, C, H, W] input, got {:?}", input))
+        };
+        if kh > h || kw > w || stride == 0 {
+            return Err(format!("Window {}x{} with stride {} does not fit input {:?}", kh, kw, stride, input))
+        }
+        let (oh, ow) = ((h - kh) / stride + 1, (w - kw) / stride + 1);
+        Ok(Window { n, c, h, w, kh, kw, oh, ow, stride })
+    }
+
+    // Calls `f(out_idx, in_idx, k_idx)` for every (output, window element) pair
+    // within a single [H, W] plane.
+    #[inline]
+    fn for_each(&self, mut f: impl FnMut(usize, usize, usize)) {
+        for y in 0..self.oh {
+            for x in 0..self.ow {
+                for ky in 0..self.kh {
+                    for kx in 0..self.kw {
+                        let in_idx = (y * self.stride + ky) * self.w + x * self.stride + kx;
+                        f(y * self.ow + x, in_idx, ky * self.kw + kx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct Conv2d(NodeIdx, [ANode; 2], Computation, (Window, usize));
+
+// Whether the shapes of a convolution or pooling fit together, without
+// building it
+#[cfg(feature = "serde")]
+pub(crate) fn check_conv2d(input: &Shape, filters: &Shape, stride: usize) -> Result<(), String> {
+    Conv2d::try_window(input, filters, stride).map(|_| ())
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn check_pool2d(input: &Shape, kernel: usize, stride: usize) -> Result<(), String> {
+    Window::try_new(input, kernel, kernel, stride).map(|_| ())
+}
+
+impl Conv2d {
+    pub(crate) fn new(input: ANode, filters: ANode, stride: usize) -> ANode {
+        let idx = NodeIdx::new();
+        let (win, o) = match Conv2d::try_window(&input.shape(), &filters.shape(), stride) {
+            Ok(w) => w,
+            Err(e) => panic!("{}!", e)
+        };
+        let value = Conv2d::compute(&input, &filters, &win, o);
+        let shape = Shape::new(&[win.n, o, win.oh, win.ow]);
+        let node = Conv2d(idx, [input, filters], Computation::pooled(value).with_shape(shape), (win, o));
+        ANode::new(Rc::new(node))
+    }
+
+    fn try_window(input: &Shape, f_shape: &Shape, stride: usize) -> Result<(Window, usize), String> {
+        let (o, kh, kw) = match f_shape.dims() {
+            [o, _, kh, kw] => (*o, *kh, *kw),
+            _ => return Err(format!("Expected [O, C, KH, KW] filters, got {:?}", f_shape))
+        };
+        let win = Window::try_new(input, kh, kw, stride)?;
+        if f_shape.dims()[1] != win.c {
+            return Err(format!("Filters {:?} do not match input channels {}", f_shape, win.c))
+        }
+        Ok((win, o))
+    }
+
+    fn compute(input: &ANode, filters: &ANode, win: &Window, o: usize) -> MPVec {
+        let (iv, fv) = (input.value(), filters.value());
+        let (plane, k_size, o_plane) = (win.h * win.w, win.kh * win.kw, win.oh * win.ow);
+        let mut out = allocate_vec(win.n * o * o_plane);
+        for n in 0..win.n {
+            for oc in 0..o {
+                let out_p = &mut out[(n * o + oc) * o_plane..(n * o + oc + 1) * o_plane];
+                for c in 0..win.c {
+                    let in_p = &iv[(n * win.c + c) * plane..];
+                    let k = &fv[(oc * win.c + c) * k_size..];
+                    win.for_each(|oi, ii, ki| out_p[oi] += in_p[ii] * k[ki]);
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Node for Conv2d {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.3.0.stride])
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Conv2d::new(children[0].clone(), children[1].clone(), self.3.0.stride))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        let (win, o) = &self.3;
+        self.2.set_pooled(Conv2d::compute(&self.1[0], &self.1[1], win, *o));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let (win, o) = &self.3;
+        bilinear_jvp(&self.1[0], &self.1[1], tangents, |x, f| Conv2d::compute(x, f, win, *o))
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // d/dinput: scatter each output gradient through the filter
+        // d/dfilter: correlate the output gradient with the input
+        let (win, o) = self.3;
+        let (iv, fv) = (self.1[0].value(), self.1[1].value());
+        let (plane, k_size, o_plane) = (win.h * win.w, win.kh * win.kw, win.oh * win.ow);
+        let (in_grad, f_grad) = child_grads.split_at_mut(1);
+        for n in 0..win.n {
+            for oc in 0..o {
+                let g = &grad[(n * o + oc) * o_plane..(n * o + oc + 1) * o_plane];
+                for c in 0..win.c {
+                    let in_off = (n * win.c + c) * plane;
+                    let k_off = (oc * win.c + c) * k_size;
+                    win.for_each(|oi, ii, ki| {
+                        in_grad[0][in_off + ii] += g[oi] * fv[k_off + ki];
+                        f_grad[0][k_off + ki] += g[oi] * iv[in_off + ii];
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct MaxPool2d(NodeIdx, [ANode; 1], Computation, RefCell<Vec<usize>>, Window);
+
+impl MaxPool2d {
+    pub(crate) fn new(input: ANode, kernel: usize, stride: usize) -> ANode {
+        let idx = NodeIdx::new();
+        let win = Window::new(&input.shape(), kernel, kernel, stride);
+        let (out, argmax) = MaxPool2d::compute(&input, &win);
+        let shape = Shape::new(&[win.n, win.c, win.oh, win.ow]);
+        let c = Computation::pooled(out).with_shape(shape);
+        let node = MaxPool2d(idx, [input], c, RefCell::new(argmax), win);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(input: &ANode, win: &Window) -> (MPVec, Vec<usize>) {
+        let (plane, o_plane) = (win.h * win.w, win.oh * win.ow);
+        let iv = input.value();
+        let mut out = allocate_vec(win.n * win.c * o_plane);
+        // Each window starts from its first element, so one of all -inf or
+        // NaN still sends its gradient somewhere inside it
+        let mut argmax = vec![usize::MAX; out.len()];
+        for p in 0..(win.n * win.c) {
+            let in_p = &iv[p * plane..(p + 1) * plane];
+            let out_p = &mut out[p * o_plane..(p + 1) * o_plane];
+            let arg_p = &mut argmax[p * o_plane..(p + 1) * o_plane];
+            win.for_each(|oi, ii, _| {
+                if arg_p[oi] == usize::MAX || in_p[ii] > out_p[oi] {
+                    out_p[oi] = in_p[ii];
+                    arg_p[oi] = p * plane + ii;
+                }
+            });
+        }
+        (out, argmax)
+    }
+}
+
+impl Node for MaxPool2d {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.4.kh, self.4.stride])
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(MaxPool2d::new(children[0].clone(), self.4.kh, self.4.stride))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        let (out, argmax) = MaxPool2d::compute(&self.1[0], &self.4);
+        self.2.set_pooled(out);
+        *self.3.borrow_mut() = argmax;
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(self.2.get().len());
+        out.iter_mut().zip(self.3.borrow().iter()).for_each(|(o, i)| *o = tangents[0][*i]);
+        Some(out)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Only the winning element of each window receives the gradient
+        let out = &mut child_grads[0];
+        grad.iter().zip(self.3.borrow().iter()).for_each(|(gi, i)| out[*i] += gi);
+    }
+}
+
+pub(crate) struct AvgPool2d(NodeIdx, [ANode; 1], Computation, Window);
+
+impl AvgPool2d {
+    pub(crate) fn new(input: ANode, kernel: usize, stride: usize) -> ANode {
+        let idx = NodeIdx::new();
+        let win = Window::new(&input.shape(), kernel, kernel, stride);
+        let out = AvgPool2d::compute(&input, &win);
+        let shape = Shape::new(&[win.n, win.c, win.oh, win.ow]);
+        let node = AvgPool2d(idx, [input], Computation::pooled(out).with_shape(shape), win);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(input: &ANode, win: &Window) -> MPVec {
+        let (plane, o_plane) = (win.h * win.w, win.oh * win.ow);
+        let scale = 1. / (win.kh * win.kw) as DType;
+        let iv = input.value();
+        let mut out = allocate_vec(win.n * win.c * o_plane);
+        for p in 0..(win.n * win.c) {
+            let in_p = &iv[p * plane..(p + 1) * plane];
+            let out_p = &mut out[p * o_plane..(p + 1) * o_plane];
+            win.for_each(|oi, ii, _| out_p[oi] += in_p[ii] * scale);
+        }
+        out
+    }
+}
+
+impl Node for AvgPool2d {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.3.kh, self.3.stride])
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(AvgPool2d::new(children[0].clone(), self.3.kh, self.3.stride))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(AvgPool2d::compute(&self.1[0], &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| AvgPool2d::compute(t, &self.3))
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Each window element receives an equal share of the gradient
+        let win = self.3;
+        let (plane, o_plane) = (win.h * win.w, win.oh * win.ow);
+        let scale = 1. / (win.kh * win.kw) as DType;
+        let out = &mut child_grads[0];
+        for p in 0..(win.n * win.c) {
+            let g = &grad[p * o_plane..(p + 1) * o_plane];
+            let out_p = &mut out[p * plane..(p + 1) * plane];
+            win.for_each(|oi, ii, _| out_p[ii] += g[oi] * scale);
+        }
+    }
+}
+
+// A parsed einsum expression. Every distinct subscript letter becomes a label;
+// each operand (and the output) addresses its buffer through per-label strides,
+// so repeated letters within an operand (e.g. "ii") walk the diagonal.
+struct EinsumSpec {
+    subscripts: String,
+    sizes: Vec<usize>,
+    operand_strides: Vec<Vec<usize>>,
+    output_strides: Vec<usize>,
+    output_shape: Shape
+}
+
+impl EinsumSpec {
+    fn parse(subscripts: &str, operands: &[ANode]) -> Self {
+        match EinsumSpec::try_parse(subscripts, operands) {
+            Ok(spec) => spec,
+            Err(e) => panic!("{}!", e)
+        }
+    }
+
+    fn try_parse(subscripts: &str, operands: &[ANode]) -> Result<Self, String> {
+        let subscripts: String = subscripts.chars().filter(|c| !c.is_whitespace()).collect();
+        let (inputs, output) = match subscripts.split_once("->") {
+            Some((i, o)) => (i.to_string(), Some(o.to_string())),
+            None => (subscripts.clone(), None)
+        };
+        let inputs: Vec<&str> = inputs.split(',').collect();
+        if inputs.len() != operands.len() {
+            return Err(format!("Einsum '{}' expects {} operands, got {}", subscripts, inputs.len(), operands.len()))
+        }
+
+        let mut labels: Vec<char> = Vec::new();
+        let mut sizes = Vec::new();
+        for (sub, op) in inputs.iter().zip(operands.iter()) {
+            let shape = op.shape();
+            if sub.chars().count() != shape.ndim() {
+                return Err(format!("Einsum subscript '{}' does not match shape {:?}", sub, shape))
+            }
+            for (c, d) in sub.chars().zip(shape.dims().iter()) {
+                match labels.iter().position(|l| *l == c) {
+                    Some(i) if sizes[i] != *d => {
+                        return Err(format!("Einsum label '{}' has conflicting sizes {} and {}", c, sizes[i], d))
+                    },
+                    Some(_) => {},
+                    None => {
+                        labels.push(c);
+                        sizes.push(*d);
+                    }
+                }
+            }
+        }
+
+        // Implicit mode: labels that appear exactly once, in alphabetical order
+        let output = output.unwrap_or_else(|| {
+            let mut once: Vec<char> = labels.iter().cloned()
+                .filter(|l| inputs.iter().map(|s| s.matches(*l).count()).sum::<usize>() == 1)
+                .collect();
+            once.sort();
+            once.into_iter().collect()
+        });
+
+        let strides_for = |sub: &str, dims: &[usize]| {
+            let mut strides = vec![0; labels.len()];
+            let mut stride = 1;
+            for (c, d) in sub.chars().rev().zip(dims.iter().rev()) {
+                let l = match labels.iter().position(|l| *l == c) {
+                    Some(l) => l,
+                    None => return Err(format!("Einsum output label '{}' not found in inputs", c))
+                };
+                strides[l] += stride;
+                stride *= d;
+            }
+            Ok(strides)
+        };
+
+        let operand_strides = inputs.iter().zip(operands.iter())
+            .map(|(sub, op)| strides_for(sub, op.shape().dims()))
+            .collect::<Result<_, _>>()?;
+
+        let out_dims: Vec<usize> = output.chars()
+            .map(|c| labels.iter().position(|l| *l == c).map(|l| sizes[l]).unwrap_or(0))
+            .collect();
+        let output_strides = strides_for(&output, &out_dims)?;
+        let output_shape = if out_dims.is_empty() { Shape::vector(1) } else { Shape::new(&out_dims) };
+
+        Ok(EinsumSpec { subscripts, sizes, operand_strides, output_strides, output_shape })
+    }
+
+    // Visits every point of the full label space with the flat offset into
+    // each operand and into the output.
+    fn for_each(&self, mut f: impl FnMut(&[usize], usize)) {
+        let total: usize = self.sizes.iter().product();
+        let mut counter = vec![0; self.sizes.len()];
+        let mut offsets = vec![0; self.operand_strides.len()];
+        for _ in 0..total {
+            for (off, strides) in offsets.iter_mut().zip(self.operand_strides.iter()) {
+                *off = counter.iter().zip(strides.iter()).map(|(c, s)| c * s).sum();
+            }
+            let out = counter.iter().zip(self.output_strides.iter()).map(|(c, s)| c * s).sum();
+            f(&offsets, out);
+
+            for l in (0..counter.len()).rev() {
+                counter[l] += 1;
+                if counter[l] < self.sizes[l] {
+                    break
+                }
+                counter[l] = 0;
+            }
+        }
+    }
+}
+
+// Whether the subscripts fit the operands, without building the op
+#[cfg(feature = "serde")]
+pub(crate) fn check_einsum(subscripts: &str, operands: &[ANode]) -> Result<(), String> {
+    EinsumSpec::try_parse(subscripts, operands).map(|_| ())
+}
+
+pub(crate) struct Einsum(NodeIdx, Vec<ANode>, Computation, EinsumSpec);
+
+impl Einsum {
+    pub(crate) fn new(subscripts: &str, operands: Vec<ANode>) -> ANode {
+        let idx = NodeIdx::new();
+        let spec = EinsumSpec::parse(subscripts, &operands);
+        let out = Einsum::compute(&operands, &spec);
+        let c = Computation::pooled(out).with_shape(spec.output_shape);
+        let node = Einsum(idx, operands, c, spec);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(operands: &[ANode], spec: &EinsumSpec) -> MPVec {
+        let mut out = allocate_vec(spec.output_shape.size());
+        let guards: Vec<_> = operands.iter().map(|o| o.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        spec.for_each(|offsets, oi| {
+            out[oi] += values.iter().zip(offsets.iter()).map(|(v, i)| v[*i]).product::<DType>();
+        });
+        out
+    }
+}
+
+impl Node for Einsum {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs { text: Some(self.3.subscripts.clone()), ..Default::default() }
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Einsum::new(&self.3.subscripts, children.to_vec()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Einsum::compute(&self.1, &self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(self.2.get().len());
+        for (i, t) in tangents.iter().enumerate() {
+            let mut operands = self.1.clone();
+            operands[i] = tangent(t, &self.1[i]);
+            iadd(&mut out, &Einsum::compute(&operands, &self.3));
+        }
+        Some(out)
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        // Product rule: each operand receives the gradient times every other
+        // operand at the same point of the label space
+        let guards: Vec<_> = self.1.iter().map(|o| o.value()).collect();
+        let values: Vec<&[DType]> = guards.iter().map(|v| &**v).collect();
+        self.3.for_each(|offsets, oi| {
+            for (i, cg) in child_grads.iter_mut().enumerate() {
+                let rest = values.iter().zip(offsets.iter()).enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, (v, o))| v[*o])
+                    .product::<DType>();
+                cg[offsets[i]] += grad[oi] * rest;
+            }
+        });
+    }
+}
+
+// Gathers rows of a 2-D table. The table is deliberately not a child: its
+// gradient is reported per row through `sparse_rows` so large vocabularies
+// never need a dense gradient buffer.
+pub(crate) struct Embedding(NodeIdx, ANode, Computation, Vec<usize>);
+
+impl Embedding {
+    pub(crate) fn new(table: ANode, rows: &[usize]) -> ANode {
+        let idx = NodeIdx::new();
+        if !table.is_leaf() {
+            panic!("Embedding tables must be leaf nodes!");
+        }
+        let out = Embedding::compute(&table, rows);
+        let width = table.shape().dims()[1];
+        let c = Computation::pooled(out).with_shape(Shape::new(&[rows.len(), width]));
+        let node = Embedding(idx, table, c, rows.to_vec());
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(table: &ANode, rows: &[usize]) -> MPVec {
+        let shape = table.shape();
+        let (n_rows, width) = match shape.dims() {
+            [r, w] => (*r, *w),
+            _ => panic!("Embedding expects a 2-D table, got {:?}!", shape)
+        };
+        let tv = table.value();
+        let mut out = allocate_vec(rows.len() * width);
+        out.chunks_mut(width).zip(rows.iter()).for_each(|(o, r)| {
+            if *r >= n_rows {
+                panic!("Row {} out of range for table {:?}!", r, shape);
+            }
+            o.clone_from_slice(&tv[r * width..(r + 1) * width]);
+        });
+        out
+    }
+}
+
+impl Node for Embedding {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { None }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Embedding::compute(&self.1, &self.3));
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn sparse_rows(&self) -> Option<(NodeIdx, &[usize])> {
+        Some((self.1.get_id(), &self.3))
+    }
+}
+
+// Extracts the diagonal of a matrix, or builds a diagonal matrix from a vector.
+pub(crate) struct Diag(NodeIdx, [ANode; 1], Computation);
+
+impl Diag {
+    pub(crate) fn new(node: ANode) -> ANode {
+        let idx = NodeIdx::new();
+        let c = Diag::compute(&node);
+        let node = Diag(idx, [node], c);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode) -> Computation {
+        let v = node.value();
+        match node.shape().dims() {
+            [n] => {
+                let mut out = allocate_vec(n * n);
+                v.iter().enumerate().for_each(|(i, vi)| out[i * n + i] = *vi);
+                Computation::pooled(out).with_shape(Shape::new(&[*n, *n]))
+            },
+            [r, c] => {
+                let mut out = allocate_vec(*r.min(c));
+                out.iter_mut().enumerate().for_each(|(i, oi)| *oi = v[i * c + i]);
+                Computation::pooled(out)
+            },
+            _ => panic!("Diag expects a 1-D or 2-D node, got {:?}!", node.shape())
+        }
+    }
+}
+
+impl Node for Diag {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Diag::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set(Diag::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| Diag::compute(t).into_pooled())
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (r, c) = match self.1[0].shape().dims() {
+            [_] => return Some(vec![grad.diag()]),
+            [r, c] => (*r, *c),
+            _ => unreachable!()
+        };
+        // Pad the square diagonal matrix out to the child's shape
+        let k = r.min(c);
+        let select = |rows: usize, cols: usize| {
+            let mut v = vec![0.; rows * cols];
+            (0..k).for_each(|i| v[i * cols + i] = 1.);
+            Constant::with_shape(v, &[rows, cols])
+        };
+        let mut dx = grad.diag();
+        if r > k { dx = select(r, k).matmul(dx); }
+        if c > k { dx = dx.matmul(select(k, c)); }
+        Some(vec![dx])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let out = &mut child_grads[0];
+        match self.1[0].shape().dims() {
+            [n] => (0..*n).for_each(|i| out[i] += grad[i * n + i]),
+            [_, c] => grad.iter().enumerate().for_each(|(i, gi)| out[i * c + i] += gi),
+            _ => unreachable!()
+        }
+    }
+}
+
+pub(crate) struct Concat(NodeIdx, Vec<ANode>, Computation);
+
+impl Concat {
+    pub(crate) fn new(nodes: Vec<ANode>) -> ANode {
+        let idx = NodeIdx::new();
+        let value = Concat::compute(&nodes);
+        let node  = Concat(idx, nodes, Computation::pooled(value));
+        ANode::new(Rc::new(node))
+    }
+
+    pub(crate) fn stack(nodes: Vec<ANode>) -> ANode {
+        let child_shape = match nodes.first() {
+            Some(n) => n.shape(),
+            None => panic!("Cannot stack an empty list of nodes!")
+        };
+        if nodes.iter().any(|n| n.shape() != child_shape) {
+            panic!("Cannot stack nodes of differing shapes!");
+        }
+        let mut dims = vec![nodes.len()];
+        dims.extend_from_slice(child_shape.dims());
+
+        let idx = NodeIdx::new();
+        let value = Concat::compute(&nodes);
+        let shape = Shape::new(&dims);
+        let node  = Concat(idx, nodes, Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(nodes: &[ANode]) -> MPVec {
+        let size = nodes.iter().map(|n| n.value().len()).sum::<usize>();
+        let mut out = allocate_vec(size);
+        let mut i = 0;
+        for node in nodes {
+            for vi in node.value().iter() {
+                out[i] = *vi;
+                i += 1;
             }
+        }
+        out
+    }
+}
+
+impl Node for Concat {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Concat::new(children.to_vec()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Concat::compute(&self.1));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(self.2.get().len());
+        let mut start = 0;
+        for t in tangents.iter() {
+            out[start..start + t.len()].copy_from_slice(t);
+            start += t.len();
+        }
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let mut start = 0;
+        Some(self.1.iter().map(|c| {
+            let len = c.value().len();
+            let g = grad.slice(start, len).reshape(c.shape().dims());
+            start += len;
+            g
+        }).collect())
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let mut offsets = Vec::with_capacity(child_grads.len());
+        child_grads.iter().fold(0, |offset, cg| {
+            offsets.push(offset);
+            offset + cg.len()
         });
+        for_each_mut(child_grads, |i, cg| {
+            let start = offsets[i];
+            iadd(cg, &grad[start..start + cg.len()]);
+        });
+    }
+}
+
+// Repeats a node along broadcast dimensions until it has `shape`.
+pub(crate) struct BroadcastTo(NodeIdx, [ANode; 1], Computation);
+
+impl BroadcastTo {
+    pub(crate) fn new(node: ANode, dims: &[usize]) -> ANode {
+        let shape = Shape::new(dims);
+        if node.shape() == shape {
+            return node
+        }
+        let idx = NodeIdx::new();
+        let value = BroadcastTo::compute(&node, &shape);
+        let node = BroadcastTo(idx, [node], Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode, shape: &Shape) -> MPVec {
+        let mut out = allocate_vec(shape.size());
+        let value = node.value();
+        let v = Broadcast::new(&value, &node.shape(), shape);
+        out.iter_mut().zip(v).for_each(|(oi, vi)| *oi = *vi);
+        out
+    }
+}
+
+impl Node for BroadcastTo {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(BroadcastTo::new(children[0].clone(), self.2.shape.dims()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(BroadcastTo::compute(&self.1[0], &self.2.shape));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| BroadcastTo::compute(t, &self.2.shape))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.sum_to(self.1[0].shape().dims())])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let mut out = Updater::new(child_grads[0], &self.1[0].shape(), &self.2.shape);
+        grad.iter().for_each(|gi| out.add(*gi));
+    }
+}
+
+// Sums a node down to a shape it was broadcast from; the adjoint of
+// BroadcastTo.
+pub(crate) struct SumTo(NodeIdx, [ANode; 1], Computation);
+
+impl SumTo {
+    pub(crate) fn new(node: ANode, dims: &[usize]) -> ANode {
+        let shape = Shape::new(dims);
+        let n_shape = node.shape();
+        if n_shape == shape {
+            return node
+        }
+        if shape.broadcast(&n_shape) != Some(n_shape) {
+            panic!("Cannot sum shape {:?} down to {:?}!", n_shape, shape);
+        }
+        let idx = NodeIdx::new();
+        let value = SumTo::compute(&node, &shape);
+        let node = SumTo(idx, [node], Computation::pooled(value).with_shape(shape));
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode, shape: &Shape) -> MPVec {
+        let mut out = allocate_vec(shape.size());
+        {
+            let mut agg = Updater::new(&mut out, shape, &node.shape());
+            node.value().iter().for_each(|vi| agg.add(*vi));
+        }
+        out
+    }
+}
+
+impl Node for SumTo {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(SumTo::new(children[0].clone(), self.2.shape.dims()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(SumTo::compute(&self.1[0], &self.2.shape));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], |t| SumTo::compute(t, &self.2.shape))
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.broadcast_to(self.1[0].shape().dims())])
+    }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let g = Broadcast::new(grad, &self.2.shape, &self.1[0].shape());
+        child_grads[0].iter_mut().zip(g).for_each(|(oi, gi)| *oi += gi);
+    }
+}
+
+pub(crate) struct Slice(NodeIdx, [ANode; 1], (usize, usize));
+
+impl Slice {
+    pub(crate) fn new(node: ANode, start: usize, len: usize) -> ANode {
+        let idx = NodeIdx::new();
+        let slice  = Slice(idx, [node], (start, len));
+        ANode::new(Rc::new(slice))
+    }
+}
+
+impl Node for Slice {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { 
+        Some(self.1.as_slice())
+    }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.2.0, self.2.1])
     }
-}
 
-pub(crate) struct Minimum(NodeIdx, [ANode;2], Computation);
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Slice::new(children[0].clone(), self.2.0, self.2.1))
+    }
 
-impl Minimum {
-    pub(crate) fn new(left: ANode, right:ANode) -> ANode {
-        let idx = NodeIdx::new();
-        let value = Minimum::compute(&left, &right);
-        let node  = Minimum(idx, [left, right], Computation::pooled(value));
-        ANode::new(Rc::new(node))
+    fn value(&self) -> Value<'_> {
+        let (start, len) = self.2;
+        self.1[0].value().map(|v| &v[start..(start+len)])
     }
 
-    fn compute(left: &ANode, right: &ANode) -> MPVec {
-        let (lv, rv) = Broadcast::from_pair(left.value(), right.value());
-        let mut out = allocate_vec(lv.len);
-        out.iter_mut().zip(lv.zip(rv)).for_each(|(oi, (lvi, rvi))| {
-            *oi = lvi.min(*rvi)
+    fn requires_grad(&self) -> bool { false }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let (start, len) = self.2;
+        let mut out = allocate_vec(len);
+        out.copy_from_slice(&tangents[0][start..start + len]);
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let (start, len) = self.2;
+        let total = self.1[0].value().len();
+        let mut parts = Vec::with_capacity(3);
+        if start > 0 {
+            parts.push(Constant::new(vec![0.; start]));
+        }
+        parts.push(grad.reshape(&[len]));
+        if start + len < total {
+            parts.push(Constant::new(vec![0.; total - start - len]));
+        }
+        let g = Concat::new(parts);
+        Some(vec![g.reshape(self.1[0].shape().dims())])
+    }
+
+    fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
+        let (start, len) = self.2;
+        let child = &mut child_grads[0][start..(start+len)];
+        child.iter_mut().zip(grad.iter()).for_each(|(ci, gi)| {
+            *ci += gi;
         });
-        out
     }
 }
 
-impl Node for Minimum {
+pub(crate) struct Reshape(NodeIdx, [ANode; 1], Shape);
+
+impl Reshape {
+    pub(crate) fn new(node: ANode, dims: &[usize]) -> ANode {
+        let idx = NodeIdx::new();
+        let shape = Shape::new(dims);
+        if shape.size() != node.value().len() {
+            panic!("Cannot reshape {:?} into {:?}!", node.shape(), shape);
+        }
+        let reshape = Reshape(idx, [node], shape);
+        ANode::new(Rc::new(reshape))
+    }
+}
+
+impl Node for Reshape {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
@@ -937,57 +4380,53 @@ impl Node for Minimum {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Reshape::new(children[0].clone(), self.2.dims()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.1[0].value()
     }
 
+    fn shape(&self) -> Shape { self.2 }
+
     fn requires_grad(&self) -> bool { false }
 
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(tangents[0].len());
+        out.copy_from_slice(tangents[0]);
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.reshape(self.1[0].shape().dims())])
+    }
+
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        // f(x,y) = x.max(y)
-        let left = self.1[0].value();
-        let right = self.1[1].value();
-        let (lv, rv) = Broadcast::from_pair(left, right);
-        let (left_grad, right_grad) = child_grads.split_at_mut(1);
-        let mut left_out = Updater::new(&mut left_grad[0], grad.len());
-        let mut right_out = Updater::new(&mut right_grad[0], grad.len());
-        grad.iter().zip(lv.zip(rv)).for_each(|(gi, (xi, yi))| {
-            if xi >= yi {
-                right_out.add(*gi);
-                left_out.add(0f32);
-            } else {
-                left_out.add(*gi);
-                right_out.add(0f32);
-            }
-        });
+        iadd(child_grads[0], grad);
     }
 }
 
-pub(crate) struct Concat(NodeIdx, Vec<ANode>, Computation);
+pub(crate) struct Flip(NodeIdx, [ANode; 1], Computation);
 
-impl Concat {
-    pub(crate) fn new(nodes: Vec<ANode>) -> ANode {
+impl Flip {
+    pub(crate) fn new(node: ANode) -> ANode {
         let idx = NodeIdx::new();
-        let value = Concat::compute(&nodes);
-        let node  = Concat(idx, nodes, Computation::pooled(value));
+        let out = Flip::compute(&node);
+        let shape = node.shape();
+        let node = Flip(idx, [node], Computation::pooled(out).with_shape(shape));
         ANode::new(Rc::new(node))
     }
 
-    fn compute(nodes: &[ANode]) -> MPVec {
-        let size = nodes.iter().map(|n| n.value().len()).sum::<usize>();
-        let mut out = allocate_vec(size);
-        let mut i = 0;
-        for node in nodes {
-            for vi in node.value() {
-                out[i] = *vi;
-                i += 1;
-            }
-        }
+    fn compute(node: &ANode) -> MPVec {
+        let v = node.value();
+        let mut out = allocate_vec(v.len());
+        out.iter_mut().zip(v.iter().rev()).for_each(|(oi, vi)| *oi = *vi);
         out
     }
 }
 
-impl Node for Concat {
+impl Node for Flip {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
@@ -997,34 +4436,56 @@ impl Node for Concat {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        &self.2.get()
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Flip::new(children[0].clone()))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Flip::compute(&self.1[0]));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        linear_jvp(&self.1[0], tangents[0], Flip::compute)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        Some(vec![grad.flip()])
     }
 
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        let mut i = 0;
-        for cg in child_grads.iter_mut() {
-            cg.iter_mut().for_each(|cgi| {
-                *cgi += grad[i];
-                i += 1;
-            });
-        }
+        child_grads[0].iter_mut().zip(grad.iter().rev()).for_each(|(ci, gi)| *ci += gi);
     }
 }
 
-pub(crate) struct Slice(NodeIdx, [ANode; 1], (usize, usize));
+pub(crate) struct Repeat(NodeIdx, [ANode; 1], Computation, usize);
 
-impl Slice {
-    pub(crate) fn new(node: ANode, start: usize, len: usize) -> ANode {
+impl Repeat {
+    pub(crate) fn new(node: ANode, n: usize) -> ANode {
         let idx = NodeIdx::new();
-        let slice  = Slice(idx, [node], (start, len));
-        ANode::new(Rc::new(slice))
+        let value = Repeat::compute(&node, n);
+        let node  = Repeat(idx, [node], Computation::pooled(value), n);
+        ANode::new(Rc::new(node))
+    }
+
+    fn compute(node: &ANode, n: usize) -> MPVec {
+        let v = node.value();
+        let mut out = allocate_vec(v.len() * n);
+        if !v.is_empty() {
+            out.chunks_mut(v.len()).for_each(|chunk| chunk.clone_from_slice(&v));
+        }
+        out
     }
 }
 
-impl Node for Slice {
+impl Node for Repeat {
     #[inline]
     fn get_id(&self) -> NodeIdx { self.0 }
 
@@ -1034,19 +4495,49 @@ impl Node for Slice {
 
     fn is_leaf(&self) -> bool { false }
 
-    fn value(&self) -> &[DType] {
-        let (start, len) = self.2;
-        &self.1[0].value()[start..(start+len)]
+    fn op_args(&self) -> OpArgs {
+        OpArgs::sizes(vec![self.3])
+    }
+
+    fn rebuild(&self, children: &[ANode]) -> Option<ANode> {
+        Some(Repeat::new(children[0].clone(), self.3))
+    }
+
+    fn value(&self) -> Value<'_> {
+        self.2.get()
+    }
+
+    fn shape(&self) -> Shape { self.2.shape }
+
+    fn recompute(&self) {
+        self.2.set_pooled(Repeat::compute(&self.1[0], self.3));
+    }
+
+    fn compute_jvp(&self, tangents: &[&[DType]]) -> Option<MPVec> {
+        let mut out = allocate_vec(self.2.shape.size());
+        if !tangents[0].is_empty() {
+            out.chunks_mut(tangents[0].len()).for_each(|c| c.copy_from_slice(tangents[0]));
+        }
+        Some(out)
+    }
+
+    fn compute_grad_graph(&self, grad: &ANode) -> Option<Vec<ANode>> {
+        let child = self.1[0].shape();
+        let len = child.size();
+        if len == 0 || self.3 == 0 {
+            return Some(vec![Constant::with_shape(vec![0.; len], child.dims())])
+        }
+        Some(vec![grad.reshape(&[self.3, len]).sum_axis(0).reshape(child.dims())])
     }
 
     fn requires_grad(&self) -> bool { false }
 
     fn compute_grad(&self, grad: &[DType], child_grads: &mut [&mut [DType]]) {
-        let (start, len) = self.2;
-        let child = &mut child_grads[0][start..(start+len)];
-        child.iter_mut().zip(grad.iter()).for_each(|(ci, gi)| {
-            *ci += gi;
-        });
+        // Each tile contributes its segment of the gradient
+        let out = &mut child_grads[0];
+        if !out.is_empty() {
+            grad.chunks(out.len()).for_each(|gi| iadd(out, gi));
+        }
     }
 }
 
@@ -1103,20 +4594,353 @@ mod tests {
     }
 
     #[test]
-    fn test_sub_scalar() {
-        let x = Variable::new(vec![0., 1.]);
-        let y = Variable::scalar(2f32);
-        let res = &x - &y;
-        assert_eq!(res.value(), &[-2., -1.]);
-
-        let mut graph = Graph::new();
-        graph.backward(&res);
+    fn test_sub_scalar() {
+        let x = Variable::new(vec![0., 1.]);
+        let y = Variable::scalar(2.);
+        let res = &x - &y;
+        assert_eq!(res.value(), &[-2., -1.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&res);
+
+        let x_grad = graph.get_grad(&x).unwrap();
+        let y_grad = graph.get_grad(&y).unwrap();
+        assert_eq!(x_grad, &[1., 1.]);
+        assert_eq!(y_grad, &[-2.]);
+
+    }
+
+    #[test]
+    fn test_scalar_left() {
+        let s = Variable::scalar(2.);
+        let x = Variable::new(vec![1., 2., 4.]);
+
+        let out = &s - &x;
+        assert_eq!(out.value(), &[1., 0., -2.]);
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&s).unwrap(), &[3.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[-1., -1., -1.]);
+
+        let out = &s * &x;
+        assert_eq!(out.value(), &[2., 4., 8.]);
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&s).unwrap(), &[7.]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[2., 2., 2.]);
+
+        let out = &s / &x;
+        assert_eq!(out.value(), &[2., 1., 0.5]);
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&s).unwrap(), &[1.75]);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[-2., -0.5, -0.125]);
+    }
+
+    #[test]
+    fn test_broadcast_rows() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let b = Variable::new(vec![10., 20., 30.]);
+        let out = &x + &b;
+        assert_eq!(out.shape(), Shape::new(&[2, 3]));
+        assert_eq!(out.value(), &[11., 22., 33., 14., 25., 36.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * &x));
+
+        assert_eq!(graph.get_grad(&b).unwrap(), &[5., 7., 9.]);
+    }
+
+    #[test]
+    fn test_broadcast_outer() {
+        let x = Variable::with_shape(vec![1., 2.], &[2, 1]);
+        let y = Variable::with_shape(vec![1., 10., 100.], &[1, 3]);
+        let out = &x * &y;
+        assert_eq!(out.shape(), Shape::new(&[2, 3]));
+        assert_eq!(out.value(), &[1., 10., 100., 2., 20., 200.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        assert_eq!(graph.get_grad(&x).unwrap(), &[111., 111.]);
+        assert_eq!(graph.get_grad(&y).unwrap(), &[3., 3., 3.]);
+    }
+
+    #[test]
+    fn test_sum_axis() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let rows = x.sum_axis(1);
+        assert_eq!(rows.shape(), Shape::vector(2));
+        assert_eq!(rows.value(), &[6., 15.]);
+
+        let square = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        assert_eq!(square.sum_axis(0).value(), &[4., 6.]);
+
+        let cols = x.sum_axis(0);
+        assert_eq!(cols.shape(), Shape::vector(3));
+        assert_eq!(cols.value(), &[5., 7., 9.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(cols * vec![1., 2., 3.]));
+        assert_eq!(graph.get_grad(&x).unwrap(), &[1., 2., 3., 1., 2., 3.]);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let b = Variable::with_shape(vec![1., 0., 0., 1., 1., 1.], &[3, 2]);
+        let out = a.matmul(&b);
+        assert_eq!(out.shape(), Shape::new(&[2, 2]));
+        assert_eq!(out.value(), &[4., 5., 10., 11.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        // G is all ones: dA = 1 * B^T, dB = A^T * 1
+        assert_eq!(graph.get_grad(&a).unwrap(), &[1., 1., 2., 1., 1., 2.]);
+        assert_eq!(graph.get_grad(&b).unwrap(), &[5., 5., 7., 7., 9., 9.]);
+    }
+
+    #[test]
+    fn test_matvec() {
+        let a = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let x = Variable::new(vec![1., -1.]);
+        let out = a.matmul(&x);
+        assert_eq!(out.shape(), Shape::vector(2));
+        assert_eq!(out.value(), &[-1., -1.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[4., 6.]);
+    }
+
+    #[test]
+    fn test_batch_matmul() {
+        let a = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 1, 2]);
+        let b = Variable::with_shape(vec![1., 1., 2., 0.], &[2, 2, 1]);
+        let out = a.batch_matmul(&b);
+        assert_eq!(out.shape(), Shape::new(&[2, 1, 1]));
+        assert_eq!(out.value(), &[3., 6.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * Constant::with_shape(vec![1., 2.], &[2, 1, 1])));
+        assert_eq!(graph.get_grad(&a).unwrap(), &[1., 1., 4., 0.]);
+        assert_eq!(graph.get_grad(&b).unwrap(), &[1., 2., 6., 8.]);
+    }
+
+    #[test]
+    fn test_outer() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![1., 10., 100.]);
+        let out = x.outer(&y);
+        assert_eq!(out.shape(), Shape::new(&[2, 3]));
+        assert_eq!(out.value(), &[1., 10., 100., 2., 20., 200.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * Constant::new(vec![1., 2., 3.])));
+        assert_eq!(graph.get_grad(&x).unwrap(), &[321., 321.]);
+        assert_eq!(graph.get_grad(&y).unwrap(), &[3., 6., 9.]);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let a = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let t = a.transpose();
+        assert_eq!(t.shape(), Shape::new(&[3, 2]));
+        assert_eq!(t.value(), &[1., 4., 2., 5., 3., 6.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(t * Constant::new(vec![1., 2.])));
+        assert_eq!(graph.get_grad(&a).unwrap(), &[1., 1., 1., 2., 2., 2.]);
+    }
+
+    #[test]
+    fn test_permute() {
+        let a = Variable::with_shape((0..24).map(|i| i as DType).collect(), &[2, 3, 4]);
+        let p = a.permute(&[2, 0, 1]);
+        assert_eq!(p.shape(), Shape::new(&[4, 2, 3]));
+        assert_eq!(&p.value()[..4], &[0., 4., 8., 12.]);
+
+        let w: Vec<DType> = (0..24).map(|i| i as DType).collect();
+        let mut graph = Graph::new();
+        graph.backward(&(p * Constant::with_shape(w, &[4, 2, 3])));
+
+        // Gradient is the weight permuted back into the original layout
+        let back = Constant::with_shape((0..24).map(|i| i as DType).collect(), &[4, 2, 3]).permute(&[1, 2, 0]);
+        assert_eq!(graph.get_grad(&a).unwrap().as_slice(), &*back.value());
+    }
+
+    #[test]
+    fn test_reshape() {
+        let x = Variable::new(vec![1., 2., 3., 4., 5., 6.]);
+        let m = x.reshape(&[3, 2]);
+        assert_eq!(m.shape(), Shape::new(&[3, 2]));
+        assert_eq!(m.value().as_ptr(), x.value().as_ptr());
+
+        let out = m.sum_axis(1);
+        assert_eq!(out.value(), &[3., 7., 11.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * vec![1., 2., 3.]));
+        assert_eq!(graph.get_grad(&x).unwrap(), &[1., 1., 2., 2., 3., 3.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_reshape_mismatch() {
+        Variable::new(vec![1., 2., 3.]).reshape(&[2, 2]);
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let x = Variable::with_shape((1..=9).map(|i| i as DType).collect(), &[1, 1, 3, 3]);
+        let w = Variable::with_shape(vec![1., 0., 0., -1.], &[1, 1, 2, 2]);
+        let out = x.conv2d(&w, 1);
+        assert_eq!(out.shape(), Shape::new(&[1, 1, 2, 2]));
+        assert_eq!(out.value(), &[-4., -4., -4., -4.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[1., 1., 0., 1., 0., -1., 0., -1., -1.]);
+        assert_eq!(graph.get_grad(&w).unwrap(), &[12., 16., 24., 28.]);
+    }
+
+    #[test]
+    fn test_pool2d() {
+        let x = Variable::with_shape(vec![
+            1., 2., 5., 0.,
+            3., 4., 1., 1.,
+            0., 0., 2., 2.,
+            0., 8., 2., 2.], &[1, 1, 4, 4]);
+
+        let max = x.max_pool2d(2, 2);
+        assert_eq!(max.shape(), Shape::new(&[1, 1, 2, 2]));
+        assert_eq!(max.value(), &[4., 5., 8., 2.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&max);
+        let grad = graph.get_grad(&x).unwrap();
+        assert_eq!(grad.iter().sum::<DType>(), 4.);
+        assert_eq!(grad[5], 1.);
+        assert_eq!(grad[13], 1.);
+
+        let avg = x.avg_pool2d(2, 2);
+        assert_eq!(avg.value(), &[2.5, 1.75, 2., 2.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&avg);
+        assert!(graph.get_grad(&x).unwrap().iter().all(|g| *g == 0.25));
+    }
+
+    #[test]
+    fn test_max_pool2d_degenerate_windows() {
+        // Gradients of windows with no usable maximum stay in their own
+        // plane, at the window's first element
+        let inf = DType::NEG_INFINITY;
+        let x = Variable::with_shape(vec![1., 2., inf, inf, inf, inf, inf, inf], &[1, 2, 2, 2]);
+        let max = x.max_pool2d(2, 2);
+        assert_eq!(max.value(), &[2., inf]);
+        let mut graph = Graph::new();
+        graph.backward(&max);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., 1., 0., 0., 1., 0., 0., 0.]);
+
+        let x = Variable::with_shape(vec![1., 2., 3., 4., DType::NAN, DType::NAN, DType::NAN, DType::NAN], &[1, 2, 2, 2]);
+        let mut graph = Graph::new();
+        graph.backward(&x.max_pool2d(2, 2));
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., 0., 0., 1., 1., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_einsum_matmul() {
+        let a = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let b = Variable::with_shape(vec![1., 0., 0., 1., 1., 1.], &[3, 2]);
+        let out = einsum("ij,jk->ik", &[a.clone(), b.clone()]);
+        assert_eq!(out.shape(), Shape::new(&[2, 2]));
+        assert_eq!(out.value(), a.matmul(&b).value());
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&a).unwrap(), &[1., 1., 2., 1., 1., 2.]);
+        assert_eq!(graph.get_grad(&b).unwrap(), &[5., 5., 7., 7., 9., 9.]);
+    }
+
+    #[test]
+    fn test_einsum_reductions() {
+        let m = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let trace = einsum("ii", std::slice::from_ref(&m));
+        assert_eq!(trace.value(), &[5.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&trace);
+        assert_eq!(graph.get_grad(&m).unwrap(), &[1., 0., 0., 1.]);
+
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3., 4.]);
+        assert_eq!(einsum("i,j->ij", &[x.clone(), y.clone()]).value(), x.outer(&y).value());
+        assert_eq!(einsum("i,i->", &[x.clone(), y.clone()]).value(), &[11.]);
+        assert_eq!(einsum("ij->ji", std::slice::from_ref(&m)).value(), m.transpose().value());
+    }
+
+    #[test]
+    fn test_diag_trace() {
+        let m = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        assert_eq!(m.diag().value(), &[1., 5.]);
+
+        let sq = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let tr = sq.trace();
+        assert_eq!(tr.value(), &[5.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&tr);
+        assert_eq!(graph.get_grad(&sq).unwrap(), &[1., 0., 0., 1.]);
+
+        let v = Variable::new(vec![2., 3.]);
+        let d = v.diag();
+        assert_eq!(d.shape(), Shape::new(&[2, 2]));
+        assert_eq!(d.value(), &[2., 0., 0., 3.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(d * Constant::with_shape(vec![1., 2., 3., 4.], &[2, 2])));
+        assert_eq!(graph.get_grad(&v).unwrap(), &[1., 4.]);
+    }
+
+    #[test]
+    fn test_eye() {
+        let i = Constant::eye(3);
+        assert_eq!(i.shape(), Shape::new(&[3, 3]));
+        let m = Variable::with_shape((0..9).map(|i| i as DType).collect(), &[3, 3]);
+        assert_eq!(m.matmul(&i).value(), m.value());
+    }
+
+    #[test]
+    fn test_stack_shape() {
+        let x = Variable::new(vec![1., 2., 3.]);
+        let y = Variable::new(vec![4., 5., 6.]);
+        let out = stack(&[x, y]);
+        assert_eq!(out.shape(), Shape::new(&[2, 3]));
+        assert_eq!(out.sum_axis(0).value(), &[5., 7., 9.]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot stack an empty list")]
+    fn test_stack_empty() {
+        stack(&[]);
+    }
 
-        let x_grad = graph.get_grad(&x).unwrap();
-        let y_grad = graph.get_grad(&y).unwrap();
-        assert_eq!(x_grad, &[1., 1.]);
-        assert_eq!(y_grad, &[-2.]);
+    #[test]
+    fn test_unary_keeps_shape() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        assert_eq!(x.exp().shape(), Shape::new(&[2, 2]));
+        assert_eq!((-&x).shape(), Shape::new(&[2, 2]));
+        assert_eq!(x.sum().shape(), Shape::vector(1));
+    }
 
+    #[test]
+    #[should_panic]
+    fn test_mismatched_lengths() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![1., 2., 3.]);
+        let _ = x + y;
     }
 
     #[test]
@@ -1130,7 +4954,7 @@ mod tests {
     #[test]
     fn test_mul_scalar() {
         let x = Variable::new(vec![1., 2.]);
-        let y = Variable::scalar(3f32);
+        let y = Variable::scalar(3.);
         let res = &x * &y;
         assert_eq!(res.value(), &[3., 6.]);
 
@@ -1154,7 +4978,7 @@ mod tests {
     #[test]
     fn test_div_scalar() {
         let x = Variable::new(vec![1., 2.]);
-        let y = Variable::scalar(3f32);
+        let y = Variable::scalar(3.);
         let res = &x / &y;
         assert_eq!(res.value(), &[1./3., 2./3.]);
 
@@ -1178,7 +5002,7 @@ mod tests {
     #[test]
     fn test_pow_scalar() {
         let x = Variable::new(vec![1., 2.]);
-        let y = Variable::scalar(3f32);
+        let y = Variable::scalar(3.);
         let res = (&x).pow(&y);
         assert_eq!(res.value(), &[1., 8.]);
 
@@ -1189,43 +5013,545 @@ mod tests {
         let y_grad = graph.get_grad(&y).unwrap();
         assert_eq!(x_grad, &[3., 12.]);
         
-        // df(x,y)/dy = ln(y) * x ^ y
-        let e_y_grad = 3f32.ln() * (1f32.powf(3.) + 2f32.powf(3.));
+        // df(x,y)/dy = ln(x) * x ^ y
+        let e_y_grad = (1 as DType).ln() * (1 as DType).powf(3.) + (2 as DType).ln() * (2 as DType).powf(3.);
         assert_eq!(y_grad, &[e_y_grad]);
     }
 
+    #[test]
+    fn test_detach() {
+        let x = Variable::new(vec![1., 2.]);
+        let d = (&x * 2.).detach();
+        assert_eq!(d.value(), &[2., 4.]);
+        assert!(d.is_leaf());
+
+        let out = (&x * &d).sum();
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[2., 4.]);
+    }
+
+    #[test]
+    fn test_straight_through() {
+        let x = Variable::new(vec![0.2, -1.7, 2.5]);
+        let q = (&x * 2.).straight_through(|v| v.round());
+        assert_eq!(q.value(), &[0., -3., 5.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(&q * &q).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., -12., 20.]);
+    }
+
+    #[test]
+    fn test_grad_reverse() {
+        let x = Variable::new(vec![1., 2.]);
+        let r = (&x * 3.).grad_reverse(0.5);
+        assert_eq!(r.value(), &[3., 6.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&r.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[-1.5, -1.5]);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new_with_grad(vec![3., 4.], false);
+        let out = (&x * &y).sum();
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[3., 4.]);
+        assert_eq!(graph.get_grad(&y), None);
+
+        x.freeze();
+        y.unfreeze();
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x), None);
+        assert_eq!(graph.get_grad(&y).unwrap(), &[1., 2.]);
+    }
+
+    #[test]
+    fn test_update() {
+        let shared = Rc::new(vec![1., 2.]);
+        let x = Variable::shared(shared.clone());
+        let out = (&x * 2.).sum();
+        x.update(|v| v.iter_mut().for_each(|vi| *vi += 1.));
+        assert_eq!(x.value(), &[2., 3.]);
+        assert_eq!(shared.as_slice(), &[1., 2.]);
+
+        // Dependents see the new values once recomputed
+        assert_eq!(out.value(), &[6.]);
+        let plan = Graph::new().compile(&out);
+        plan.forward();
+        assert_eq!(out.value(), &[10.]);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let x = Variable::new(vec![1., 2.]);
+        assert_eq!((&x + vec![1., 2.]).value(), &[2., 4.]);
+        assert_eq!((&x * &[3., 4.][..]).value(), &[3., 8.]);
+        assert_eq!((&x).pow(2.).value(), &[1., 4.]);
+        assert_eq!(x.dot(vec![1., 1.]).value(), &[3.]);
+        assert_eq!(x.dot(&x).value(), &[5.]);
+        assert_eq!(x.outer([1., -1.].as_slice()).value(), &[1., -1., 2., -2.]);
+
+        let c = ANode::from(2.);
+        assert!(c.is_leaf() && !c.requires_grad());
+        assert_eq!(ANode::from(vec![1., 2.]).value(), &[1., 2.]);
+        let n: ANode = (&x).into();
+        assert_eq!(n.get_id(), x.get_id());
+    }
+
+    #[test]
+    fn test_operator_matrix() {
+        let x = Variable::new(vec![2., 4.]);
+        let y = Constant::new(vec![1., 2.]);
+        let owned = || x.clone();
+        for out in [owned() + y.clone(), &x + y.clone(), owned() + &y, &x + &y, &x + 0., 1. + &x] {
+            assert_eq!(out.value().len(), 2);
+        }
+        assert_eq!((owned() - 1.).value(), &[1., 3.]);
+        assert_eq!((1. - owned()).value(), &[-1., -3.]);
+        assert_eq!((&x * 2.).value(), &[4., 8.]);
+        assert_eq!((2. * owned()).value(), &[4., 8.]);
+        assert_eq!((owned() / &y).value(), &[2., 2.]);
+        assert_eq!((8. / &x).value(), &[4., 2.]);
+        assert_eq!((-owned()).value(), &[-2., -4.]);
+        assert_eq!((-&x).value(), &[-2., -4.]);
+    }
+
+    #[test]
+    fn test_assign_ops() {
+        let x = Variable::new(vec![2., 4.]);
+        let mut acc = x.clone();
+        acc += &x;
+        acc -= 1.;
+        acc *= vec![1., 0.5];
+        acc /= Constant::scalar(3.);
+        assert_eq!(acc.value(), &[1., 7. / 6.]);
+        assert_eq!(x.value(), &[2., 4.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&acc.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[2. / 3., 1. / 3.]);
+    }
+
+    #[test]
+    fn test_accumulator() {
+        let x = Variable::new(vec![1., 2.]);
+        let mut acc = Accumulator::new();
+        assert!(acc.is_empty());
+        for i in 0..100 {
+            acc += &x * (i as DType);
+        }
+        acc -= &x;
+        assert_eq!(acc.len(), 101);
+
+        let total = acc.finish();
+        assert_eq!(total.depth(), 2);
+        assert_eq!(total.value(), &[4949., 9898.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&total.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[4949., 4949.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_accumulator_shapes() {
+        let mut acc = Accumulator::new();
+        acc += vec![1., 2.];
+        acc += 1.;
+    }
+
+    #[test]
+    fn test_try_ops() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Constant::new(vec![1., 2., 3.]);
+        assert_eq!(x.try_add(vec![1., 1.]).unwrap().value(), &[2., 3.]);
+        assert_eq!(x.try_div(2.).unwrap().value(), &[0.5, 1.]);
+
+        let err = match x.try_mul(&y) {
+            Err(e) => e,
+            Ok(_) => panic!("Expected a shape error")
+        };
+        assert_eq!(err.op, "Multiply");
+        assert_eq!(err.left, ("Variable", vec![2]));
+        assert_eq!(err.right, ("Constant", vec![3]));
+        assert_eq!(err.to_string(), "Multiply cannot broadcast Variable of shape [2] with Constant of shape [3]");
+        assert!(x.try_sub(&y).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "AddN cannot broadcast Variable of shape [2] with Constant of shape [3]!")]
+    fn test_add_mismatch() {
+        let _ = Variable::new(vec![1., 2.]) + Constant::new(vec![1., 2., 3.]);
+    }
+
+    #[test]
+    fn test_power_grad() {
+        // d/dy x^y = ln(x) x^y
+        let x = Variable::new(vec![2., 3.]);
+        let y = Variable::new(vec![3., 0.5]);
+        let mut graph = Graph::new();
+        graph.backward(&(&x).pow(&y).sum());
+        let dy = graph.get_grad(&y).unwrap();
+        assert!((dy[0] - (2. as DType).ln() * 8.).abs() < 1e-4);
+        assert!((dy[1] - (3. as DType).ln() * (3. as DType).sqrt()).abs() < 1e-4);
+
+        // Zero and negative bases under constant exponents stay finite
+        let x = Variable::new(vec![0., -2.]);
+        let mut graph = Graph::new();
+        graph.debug_nan(true);
+        graph.backward(&(&x).pow(2.).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., -4.]);
+
+        let y = Variable::new(vec![2., 0.]);
+        let mut graph = Graph::new();
+        graph.backward(&Constant::new(vec![0., 0.]).pow(&y).sum());
+        assert_eq!(graph.get_grad(&y).unwrap(), &[0., 0.]);
+
+        // 0 ^ 0 is flat in both arguments, whichever way it's differentiated
+        let x = Variable::new(vec![0., 2.]);
+        let y = Variable::new(vec![0., 0.]);
+        let out = (&x).pow(&y).sum();
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&out).unwrap();
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., 0.]);
+        assert_eq!(graph.get_grad(&y).unwrap()[0], 0.);
+        assert!((graph.get_grad(&y).unwrap()[1] - (2. as DType).ln()).abs() < 1e-6);
+
+        let jvp = Graph::new().jvp(&out, &[(&x, &[1., 1.][..]), (&y, &[1., 1.][..])]);
+        assert!((jvp[0] - (2. as DType).ln()).abs() < 1e-6);
+        let jvp = Graph::new().jvp(&out, &[(&x, &[1., 0.][..])]);
+        assert_eq!(jvp, vec![0.]);
+    }
+
+    #[test]
+    fn test_pow_scalar_node() {
+        let x = Variable::new(vec![-2., 0., 3.]);
+        let cube = x.pow_scalar(3.);
+        assert_eq!(cube.value(), &[-8., 0., 27.]);
+        let mut graph = Graph::new();
+        graph.backward(&cube.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[12., 0., 27.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&x.pow_scalar(0.).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., 0., 0.]);
+
+        let x = Variable::new(vec![4.]);
+        assert_eq!(x.pow_scalar(-0.5).value(), &[0.5]);
+        assert_eq!(Graph::new().jacobian(&x.pow_scalar(0.5), &x), vec![vec![0.25]]);
+
+        // Second derivative of x^3 is 6x
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&x.pow_scalar(3.).sum()).unwrap();
+        let dx = graph.get_grad_node(&x).unwrap().clone();
+        let mut graph = Graph::new();
+        graph.backward(&dx.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[24.]);
+    }
+
+    #[test]
+    fn test_square_cube() {
+        let x = Variable::new(vec![-1.5, 0., 2.]);
+        let (sq, cube) = (x.square(), x.cube());
+        assert_eq!(sq.op_name(), "PowScalar");
+        assert_eq!(sq.value(), &[2.25, 0., 4.]);
+        assert_eq!(cube.value(), &[-3.375, 0., 8.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(sq + cube).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[3.75, 0., 16.]);
+    }
+
+    #[test]
+    fn test_nan_to_num() {
+        let x = Variable::new(vec![1., 2., 3., 4.]);
+        let y = &x + Constant::new(vec![0., DType::NAN, DType::INFINITY, DType::NEG_INFINITY]);
+        let clean = y.nan_to_num(0., 100., -100.);
+        assert_eq!(clean.value(), &[1., 0., 100., -100.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&clean.sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[1., 0., 0., 0.]);
+
+        // A bad sample no longer poisons the gradient
+        let x = Variable::new(vec![-1., 2.]);
+        let mut graph = Graph::new();
+        graph.backward(&x.ln().nan_to_num(0., 0., 0.).sum());
+        assert_eq!(graph.get_grad(&x).unwrap(), &[0., 0.5]);
+    }
+
+    #[test]
+    fn test_filled() {
+        let z = Variable::zeros(3);
+        assert_eq!(z.value(), &[0., 0., 0.]);
+        assert!(z.requires_grad());
+        assert_eq!(Variable::ones(2).value(), &[1., 1.]);
+        assert_eq!(Variable::full(2, 0.5).value(), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(Constant::arange(0., 5., 2.).value(), &[0., 2., 4.]);
+        assert_eq!(Constant::arange(1., 0., -0.25).value(), &[1., 0.75, 0.5, 0.25]);
+        assert!(Constant::arange(1., 0., 1.).value().is_empty());
+
+        assert_eq!(Constant::linspace(0., 1., 5).value(), &[0., 0.25, 0.5, 0.75, 1.]);
+        assert_eq!(Constant::linspace(2., 3., 1).value(), &[2.]);
+        assert!(Constant::linspace(0., 1., 0).value().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_arange_zero_step() {
+        Constant::arange(0., 1., 0.);
+    }
+
+    #[test]
+    #[should_panic(expected = "while it is borrowed")]
+    fn test_update_while_borrowed() {
+        let x = Variable::new(vec![1., 2.]);
+        let held = x.value();
+        x.update(|v| v[0] = 3.);
+        assert_eq!(held[0], 1.);
+    }
+
+    #[test]
+    #[should_panic(expected = "while it is borrowed")]
+    fn test_set_value_while_borrowed() {
+        let x = Variable::new(vec![1., 2.]);
+        let held = x.value();
+        x.set_value(&[3., 4.]);
+        assert_eq!(held[0], 1.);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_update_constant() {
+        Constant::new(vec![1.]).update(|v| v[0] = 2.);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let x = Variable::with_shape(vec![0., 0., 1., 2., DType::NEG_INFINITY, DType::NEG_INFINITY], &[3, 2]);
+        let y = x.softmax();
+        let e = (1. as DType).exp();
+        let expected = [0.5, 0.5, 1. / (1. + e), e / (1. + e), 0., 0.];
+        y.value().iter().zip(expected.iter()).for_each(|(a, b)| assert!((a - b).abs() < 1e-6));
+
+        // Picking out the first column, the gradient is y0 (1 - y0) on it and
+        // -y0 y1 on the second
+        let pick = Constant::with_shape(vec![1., 0., 1., 0., 1., 0.], &[3, 2]);
+        let out = (&y * &pick).sum();
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        let grad = graph.get_grad(&x).unwrap().clone();
+        let (y0, y1) = (expected[2], expected[3]);
+        let expected = [0.25, -0.25, y0 * (1. - y0), -y0 * y1, 0., 0.];
+        grad.iter().zip(expected.iter()).for_each(|(a, b)| assert!((a - b).abs() < 1e-6));
+
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&out).unwrap();
+        graph.get_grad(&x).unwrap().iter().zip(expected.iter())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-6));
+
+        let x = Variable::new(vec![0.5, -1., 2.]);
+        let jac = Graph::new().jacobian(&x.softmax(), &x);
+        for (i, row) in jac.iter().enumerate() {
+            let mut graph = Graph::new();
+            graph.backward(&x.softmax().slice(i, 1));
+            row.iter().zip(graph.get_grad(&x).unwrap().iter())
+                .for_each(|(a, b)| assert!((a - b).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_more_grad_graphs() {
+        // Gradients built from nodes match the ones computed directly
+        let x = Variable::with_shape(vec![1., -2., 3., 0.5, 4., -1.], &[2, 3]);
+        let y = Variable::with_shape(vec![2., 0., -1., 1., 5., 1., 3., -2., 0.], &[3, 3]);
+        let v = Variable::new(vec![1., 3., -2.]);
+        let b = Variable::with_shape((0..12).map(|i| i as DType - 4.).collect(), &[2, 3, 2]);
+        let outs = [
+            (&x).maximum(&v),
+            (&x).minimum(&v),
+            x.permute(&[1, 0]),
+            x.outer(&v),
+            b.batch_matmul(b.permute(&[0, 2, 1])),
+            x.flip(),
+            x.repeat(3),
+            x.diag(),
+            x.transpose().diag(),
+            y.diag(),
+            v.diag()
+        ];
+        for out in outs.iter() {
+            let w: Vec<DType> = (0..out.value().len()).map(|i| (i % 5) as DType - 1.5).collect();
+            let out = (out * Constant::with_shape(w, out.shape().dims())).sum();
+            let (mut g1, mut g2) = (Graph::new(), Graph::new());
+            g1.backward(&out);
+            g2.backward_with_graph(&out).unwrap();
+            for n in [&x, &y, &v, &b] {
+                assert_eq!(g1.get_grad(n).map(|g| g.to_vec()), g2.get_grad(n).map(|g| g.to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_grad() {
+        // softplus computed naively, with the stable gradient sigmoid(x)
+        let x = Variable::new(vec![0., 2.]);
+        let sp = (x.exp() + 1.).ln().with_custom_grad(std::slice::from_ref(&x), |inputs, _out, grad, child_grads| {
+            for ((g, xi), gi) in child_grads[0].iter_mut().zip(inputs[0]).zip(grad) {
+                *g = gi / (1. + (-xi).exp());
+            }
+        });
+        assert!((sp.value()[0] - (2 as DType).ln()).abs() < 1e-6);
+
+        let mut graph = Graph::new();
+        graph.backward(&(&sp * 2.).sum());
+        let grad = graph.get_grad(&x).unwrap();
+        assert!((grad[0] - 1.).abs() < 1e-6);
+        assert!((grad[1] - 2. / (1. + (-2 as DType).exp())).abs() < 1e-6);
+
+        // The inner subgraph is recomputed with the new inputs
+        let out = sp.sum();
+        let plan = Graph::new().compile(&out);
+        plan.set(&x, &[0., 0.]);
+        plan.forward();
+        assert!((out.value()[0] - 2. * (2 as DType).ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_map() {
+        let x = Variable::new(vec![0.5, 2.]);
+        let sq = x.map(|v| v * v);
+        let cube = x.map_with_grad(|v| v * v * v, |v| 3. * v * v);
+        assert_eq!(sq.value(), &[0.25, 4.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(sq + cube).sum());
+        let grad = graph.get_grad(&x).unwrap();
+        assert!((grad[0] - (1. + 0.75)).abs() < 1e-3);
+        assert!((grad[1] - (4. + 12.)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_map_captures_rc() {
+        // Closures only need to be 'static, so they can hold nodes and other Rc state
+        let scale = Variable::new(vec![3.]);
+        let calls = Rc::new(Cell::new(0));
+        let (s, c) = (scale.clone(), calls.clone());
+        let x = Variable::new(vec![1., 2.]);
+        let y = x.map(move |v| { c.set(c.get() + 1); v * s.value()[0] });
+        let z = x.straight_through(move |v| v * scale.value()[0]);
+        assert_eq!(y.value(), &[3., 6.]);
+        assert_eq!(z.value(), &[3., 6.]);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_inplace_forward() {
+        let x = Variable::new_with_grad(vec![0., 1., 2.], false);
+        let expected = (((&x * 2.) + 1.) * 3.).value().to_vec();
+
+        use_inplace_forward(true);
+        let scaled = &x * 2.;
+        let ptr = scaled.value().as_ptr();
+        let out = (scaled + 1.) * 3.;
+        let held = &out * 2.;
+        let last = -held.clone();
+        use_inplace_forward(false);
+
+        assert_eq!(out.value(), expected.as_slice());
+        assert_eq!(out.value().as_ptr(), ptr);
+        assert_eq!(x.value(), &[0., 1., 2.]);
+        // Still referenced, so not overwritten
+        assert_eq!(held.value(), &[6., 18., 30.]);
+        assert_eq!(last.value(), &[-6., -18., -30.]);
+    }
+
+    #[test]
+    fn test_inplace_forward_keeps_grads() {
+        let w = Variable::new(vec![1., 2.]);
+        use_inplace_forward(true);
+        let affine = ((&w * 2.) + 1.).sum();
+        let negated = (-(&w * 3.)).sum();
+        let mixed = (Constant::new(vec![1., 2.]).exp() + 1.) * &w;
+        use_inplace_forward(false);
+
+        let mut graph = Graph::new();
+        graph.backward(&affine);
+        assert_eq!(graph.get_grad(&w).unwrap(), &[2., 2.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&negated);
+        assert_eq!(graph.get_grad(&w).unwrap(), &[-3., -3.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&mixed.sum());
+        let e = (1 as DType).exp();
+        assert!((graph.get_grad(&w).unwrap()[0] - (e + 1.)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_summation() {
+        let mut v = vec![1.];
+        v.extend(std::iter::repeat_n(1e-8, 1_000_000));
+        let x = Constant::new(v);
+        let mut parts = vec![Constant::new(vec![1.])];
+        parts.extend((0..1000).map(|_| Constant::new(vec![1e-8])));
+
+        for mode in [Summation::Kahan, Summation::Pairwise] {
+            vecops::set_summation(mode);
+            let total = x.sum().value()[0];
+            let bulk = parts.clone().sum_all().value()[0];
+            vecops::set_summation(Summation::Naive);
+
+            assert!((total - 1.01).abs() < 1e-5, "{:?}: {}", mode, total);
+            assert!((bulk - 1.00001).abs() < 1e-5, "{:?}: {}", mode, bulk);
+        }
+    }
+
     #[test]
     fn test_tanh() {
         let x = Variable::new(vec![0., 1., 2.]);
-        let out = (&x).tanh();
-        assert_eq!(out.value(), &[0., 1f32.tanh(), 2f32.tanh()]);
+        let out = x.tanh();
+        assert_eq!(out.value(), &[0., (1 as DType).tanh(), (2 as DType).tanh()]);
         let mut graph = Graph::new();
         graph.backward(&out);
         let grad = graph.get_grad(&x).unwrap();
-        assert_eq!(grad, &[1., (1f32 - 1f32.tanh().powf(2f32)), (1f32 - 2f32.tanh().powf(2f32))]);
+        assert_eq!(grad, &[1., (1. - (1 as DType).tanh().powf(2.)), (1. - (2 as DType).tanh().powf(2.))]);
     }
 
     #[test]
     fn test_exp() {
         let x = Variable::new(vec![0., 1., 2.]);
-        let out = (&x).exp();
+        let out = x.exp();
         let mut graph = Graph::new();
         graph.backward(&out);
         let grad = graph.get_grad(&x).unwrap();
-        assert_eq!(out.value(), &[1., 1f32.exp(), 2f32.exp()]);
+        assert_eq!(out.value(), &[1., (1 as DType).exp(), (2 as DType).exp()]);
+        assert_eq!(grad.as_slice(), &*out.value());
     }
 
     #[test]
     fn test_sum() {
         let x = Variable::new(vec![0., 1., 2.]);
         let out = x.sum();
-        assert_eq!(out.value(), vec![3f32]);
+        assert_eq!(out.value(), vec![3.]);
         let mut graph = Graph::new();
 
         graph.backward(&out);
 
         let grad = graph.get_grad(&x).unwrap();
-        assert_eq!(grad, &[1f32, 1f32, 1f32]);
+        assert_eq!(grad, &[1., 1., 1.]);
     }
 
     #[test]
@@ -1238,7 +5564,7 @@ mod tests {
         graph.backward(&out);
 
         let grad = graph.get_grad(&x).unwrap();
-        assert_eq!(grad, &[-1., -(-1f32).exp(), -(-2f32).exp()]);
+        assert_eq!(grad, &[-1., -(-1 as DType).exp(), -(-2 as DType).exp()]);
     }
 
     #[test]
@@ -1246,15 +5572,15 @@ mod tests {
         let x = Variable::new(vec![1., 2.]);
         let y = Variable::new(vec![3., 5.]);
 
-        let out = (&x).pow(4f32).maximum(2f32 * &y);
+        let out = (&x).pow(4.).maximum(2. * &y);
 
         let mut graph = Graph::new();
         graph.backward(&out);
 
         let x_grad = graph.get_grad(&x).unwrap();
         let y_grad = graph.get_grad(&y).unwrap();
-        assert_eq!(x_grad, &[0f32, 32f32]);
-        assert_eq!(y_grad, &[2f32, 0f32]);
+        assert_eq!(x_grad, &[0., 32.]);
+        assert_eq!(y_grad, &[2., 0.]);
     }
 
     #[test]
@@ -1262,15 +5588,15 @@ mod tests {
         let x = Variable::new(vec![1., 2.]);
         let y = Variable::new(vec![3., 5.]);
 
-        let out = (&x).pow(4f32).minimum(2f32 * &y);
+        let out = (&x).pow(4.).minimum(2. * &y);
 
         let mut graph = Graph::new();
         graph.backward(&out);
 
         let x_grad = graph.get_grad(&x).unwrap();
         let y_grad = graph.get_grad(&y).unwrap();
-        assert_eq!(x_grad, &[4f32, 0f32]);
-        assert_eq!(y_grad, &[0f32, 2f32]);
+        assert_eq!(x_grad, &[4., 0.]);
+        assert_eq!(y_grad, &[0., 2.]);
     }
 
     #[test]
@@ -1279,7 +5605,7 @@ mod tests {
         let y = Variable::new(vec![3., 5.]);
 
         let mut out = vec![&x, &y].concat();
-        out = out + 10f32;
+        out += 10.;
 
         let mut graph = Graph::new();
         graph.backward(&out);
@@ -1290,12 +5616,53 @@ mod tests {
         assert_eq!(y_grad, &[1., 1.]);
     }
 
+    #[test]
+    fn test_concat_grad() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3.]);
+
+        let out = concat(&[x.clone(), y.clone()]);
+        assert_eq!(out.value(), &[1., 2., 3.]);
+        let out = out * vec![1., 2., 3.];
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        let x_grad = graph.get_grad(&x).unwrap();
+        let y_grad = graph.get_grad(&y).unwrap();
+        assert_eq!(x_grad, &[1., 2.]);
+        assert_eq!(y_grad, &[3.]);
+    }
+
+    #[test]
+    fn test_stack() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3., 5.]);
+
+        let out = stack(&[x.clone(), y.clone()]);
+        assert_eq!(out.value(), &[1., 2., 3., 5.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * 2.));
+
+        let y_grad = graph.get_grad(&y).unwrap();
+        assert_eq!(y_grad, &[2., 2.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_stack_mismatched() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3.]);
+        stack(&[x, y]);
+    }
+
     #[test]
     fn test_slice() {
         let x = Variable::new(vec![1., 2., 3.]);
 
         let x_slice = x.slice(1, 2);
-        let mut out = x_slice * 2.;
+        let out = x_slice * 2.;
 
         let mut graph = Graph::new();
         graph.backward(&out);
@@ -1304,39 +5671,116 @@ mod tests {
         assert_eq!(x_grad, &[0., 2., 2.]);
     }
 
+    #[test]
+    fn test_split() {
+        let x = Variable::new(vec![1., 2., 3., 4., 5.]);
+
+        let parts = x.split(&[2, 3]);
+        assert_eq!(parts[0].value(), &[1., 2.]);
+        assert_eq!(parts[1].value(), &[3., 4., 5.]);
+
+        let out = vec![parts[0].sum() * 2., parts[1].sum() * 3.].concat();
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        let x_grad = graph.get_grad(&x).unwrap();
+        assert_eq!(x_grad, &[2., 2., 3., 3., 3.]);
+    }
+
+    #[test]
+    fn test_repeat() {
+        let x = Variable::new(vec![1., 2.]);
+        let out = x.repeat(3);
+        assert_eq!(out.value(), &[1., 2., 1., 2., 1., 2.]);
+
+        let out = out * vec![1., 2., 3., 4., 5., 6.];
+        let mut graph = Graph::new();
+        graph.backward(&out);
+
+        let x_grad = graph.get_grad(&x).unwrap();
+        assert_eq!(x_grad, &[9., 12.]);
+    }
+
+    #[test]
+    fn test_repeat_empty() {
+        let x = Variable::new(vec![]);
+        let out = x.repeat(3);
+        assert!(out.value().is_empty());
+        assert_eq!(out.op_args().sizes, vec![3]);
+
+        let mut graph = Graph::new();
+        graph.backward(&out.sum());
+        assert!(graph.get_grad(&x).unwrap().is_empty());
+
+        // The count survives rebuilding over a non-empty child
+        let rebuilt = out.rebuild(&[Constant::new(vec![1.])]).unwrap();
+        assert_eq!(rebuilt.value(), &[1., 1., 1.]);
+    }
+
+    #[test]
+    fn test_flip() {
+        let x = Variable::new(vec![1., 2., 3.]);
+        let out = x.flip();
+        assert_eq!(out.value(), &[3., 2., 1.]);
+
+        let mut graph = Graph::new();
+        graph.backward(&(out * vec![1., 2., 3.]));
+        assert_eq!(graph.get_grad(&x).unwrap(), &[3., 2., 1.]);
+    }
+
+    #[test]
+    fn test_chunk() {
+        let x = Variable::new(vec![1., 2., 3., 4., 5.]);
+        let parts = x.chunk(2);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].value(), &[1., 2., 3.]);
+        assert_eq!(parts[1].value(), &[4., 5.]);
+
+        assert_eq!(x.chunk(5).len(), 5);
+        assert_eq!(x.chunk(8).len(), 5);
+        assert!(Variable::new(vec![]).chunk(3).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "into 0 chunks")]
+    fn test_chunk_zero() {
+        Variable::new(vec![1.]).chunk(0);
+    }
+
 
     #[test]
     fn test_backward_pass_simple1() {
         // 2x
         // df/dx = 2
-        let x = Variable::new(vec![0f32]);
-        let x2 = Multiply::new(x.clone(), Constant::scalar(2f32));
+        let x = Variable::new(vec![0.]);
+        let x2 = Multiply::new(x.clone(), Constant::scalar(2.));
 
         let mut graph = Graph::new();
         graph.backward(&x2);
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![2f32]), x_grad);
+        assert_eq!(Some(&vec![2.]), x_grad);
     }
 
     #[test]
     fn test_backward_pass_simple2() {
         // 2 + x
         // df/dx = 1
-        let x = Variable::new(vec![0f32]);
-        let x2 = AddN::new(x.clone(), Constant::scalar(2f32));
+        let x = Variable::new(vec![0.]);
+        let x2 = AddN::new(x.clone(), Constant::scalar(2.));
 
         let mut graph = Graph::new();
         graph.backward(&x2);
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![1f32]), x_grad);
+        assert_eq!(Some(&vec![1.]), x_grad);
     }
 
     #[test]
     fn test_backward_pass_simple3() {
         // x - y
         // df/dx = 1
-        let x = Variable::new(vec![1f32]);
-        let y = Variable::new(vec![2f32]);
+        let x = Variable::new(vec![1.]);
+        let y = Variable::new(vec![2.]);
         let x2 = Subtract::new(x.clone(), y.clone());
 
         let mut graph = Graph::new();
@@ -1344,40 +5788,40 @@ mod tests {
         let x_grad = graph.get_grad(&x);
         let y_grad = graph.get_grad(&y);
 
-        assert_eq!(Some(&vec![1f32]), x_grad);
-        assert_eq!(Some(&vec![-1f32]), y_grad);
+        assert_eq!(Some(&vec![1.]), x_grad);
+        assert_eq!(Some(&vec![-1.]), y_grad);
     }
 
     #[test]
     fn test_backward_pass_simple4() {
         // x ^ 2
         // df/dx = 2x
-        let x = Variable::new(vec![1f32]);
-        let x2 = Power::new(x.clone(), Constant::scalar(2f32));
+        let x = Variable::new(vec![1.]);
+        let x2 = Power::new(x.clone(), Constant::scalar(2.));
 
         let mut graph = Graph::new();
         graph.backward(&x2);
 
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![2f32]), x_grad);
+        assert_eq!(Some(&vec![2.]), x_grad);
     }
 
     #[test]
     fn test_backward_pass_simple5() {
         // x ^ 2 + 3x
         // df/dx = 2x + 3
-        let x = Variable::new(vec![1f32]);
-        let x2 = Power::new(x.clone(), Constant::scalar(2f32));
-        let x3 = Multiply::new(x.clone(), Constant::scalar(3f32));
+        let x = Variable::new(vec![1.]);
+        let x2 = Power::new(x.clone(), Constant::scalar(2.));
+        let x3 = Multiply::new(x.clone(), Constant::scalar(3.));
         let x4 = AddN::new(x2, x3);
 
-        assert_eq!(x4.value(), vec![4f32]);
+        assert_eq!(x4.value(), vec![4.]);
 
         let mut graph = Graph::new();
         graph.backward(&x4);
 
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![5f32]), x_grad);
+        assert_eq!(Some(&vec![5.]), x_grad);
     }
 
 
@@ -1385,37 +5829,29 @@ mod tests {
     fn test_backward_pass_simple6() {
         // 2x + 3
         // df/dx = 2
-        let x = Variable::new(vec![0f32]);
-        let x2 = Multiply::new(x.clone(), Constant::scalar(2f32));
-        let x2_3 = AddN::new(x2, Constant::scalar(3f32));
+        let x = Variable::new(vec![0.]);
+        let x2 = Multiply::new(x.clone(), Constant::scalar(2.));
+        let x2_3 = AddN::new(x2, Constant::scalar(3.));
 
         let mut graph = Graph::new();
         graph.backward(&x2_3);
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![2f32]), x_grad);
+        assert_eq!(Some(&vec![2.]), x_grad);
     }
 
     #[test]
     fn test_backward_pass_simple7() {
         // dot(x, y)
-        let x = Variable::new(vec![1f32, 2f32, 3f32]);
-        let y = Variable::new(vec![0f32, 2f32, 4f32]);
+        let x = Variable::new(vec![1., 2., 3.]);
+        let y = Variable::new(vec![0., 2., 4.]);
         let x2 = Multiply::new(x.clone(), y.clone());
         let ret = SumVec::new(x2);
 
-        assert_eq!(ret.value(), vec![16f32]);
+        assert_eq!(ret.value(), vec![16.]);
         let mut graph = Graph::new();
         graph.backward(&ret);
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![0f32, 2f32, 4f32]), x_grad);
-    }
-
-    fn euclidean_distance(x: &ANode, y: &ANode) -> ANode {
-        let minus = x - y;
-        let pow = minus.pow(2f32);
-        let sum = pow.sum();
-        let sqrt = sum.pow(0.5);
-        sqrt
+        assert_eq!(Some(&vec![0., 2., 4.]), x_grad);
     }
 
     #[test]
@@ -1423,58 +5859,58 @@ mod tests {
         // (x+2) ^ 2 
         // x^2 + 4x + 4
         // 2x + 4
-        let x      = Variable::new(vec![0f32]);
-        let x2     = AddN::new(x.clone(), Constant::scalar(2f32));
-        let x2_2   = Power::new(x2.clone(), Constant::scalar(2f32));
+        let x      = Variable::new(vec![0.]);
+        let x2     = AddN::new(x.clone(), Constant::scalar(2.));
+        let x2_2   = Power::new(x2.clone(), Constant::scalar(2.));
 
-        assert_eq!(x2_2.value(), vec![4f32]);
+        assert_eq!(x2_2.value(), vec![4.]);
 
         let mut graph = Graph::new();
         graph.backward(&x2_2);
 
-        let x2_grad = graph.get_grad(&x2);
+        // Only leaf gradients are kept
+        assert_eq!(None, graph.get_grad(&x2));
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![4f32]), x_grad);
+        assert_eq!(Some(&vec![4.]), x_grad);
     }
 
     #[test]
     fn test_composition() {
         // (x+2) ^ 2 
-        let x      = Variable::new(vec![0f32]);
-        let res = (&x + 2f32).pow(2f32);
-        assert_eq!(res.value(), vec![4f32]);
+        let x      = Variable::new(vec![0.]);
+        let res = (&x + 2.).pow(2.);
+        assert_eq!(res.value(), vec![4.]);
 
         let mut graph = Graph::new();
         graph.backward(&res);
 
         let x_grad = graph.get_grad(&x);
-        assert_eq!(Some(&vec![4f32]), x_grad);
+        assert_eq!(Some(&vec![4.]), x_grad);
     }
 
     #[test]
     fn test_sigmoid_denom() {
         // e ^ -x
-        let x      = Variable::new(vec![1f32]);
-        let res = &(-&x).exp();
-        assert_eq!(res.value(), vec![(-1f32).exp()]);
+        let x      = Variable::new(vec![1.]);
+        let res = (-&x).exp();
+        assert_eq!(res.value(), vec![(-1 as DType).exp()]);
 
         let mut graph = Graph::new();
         graph.backward(&res);
 
         let x_grad = graph.get_grad(&x);
-        let x_0 = res.value()[0];
-        let expected = -(-1f32).exp();
+        let expected = -(-1 as DType).exp();
         assert_eq!(Some(&vec![expected]), x_grad);
     }
 
     fn sigmoid(x: &ANode) -> ANode {
-        1f32 / ((-x).exp() + 1f32)
+        1. / ((-x).exp() + 1.)
     }
 
     #[test]
     fn test_logistic() {
         // 1 / (1 + e ^ -x)
-        let x = Variable::new(vec![0f32]);
+        let x = Variable::new(vec![0.]);
         let res = sigmoid(&x);
         assert_eq!(res.value(), vec![0.5]);
 
@@ -1482,22 +5918,22 @@ mod tests {
         graph.backward(&res);
 
         let x_grad = graph.get_grad(&x);
-        let sigma_trick = res.value()[0] * (1f32 - res.value()[0]);
+        let sigma_trick = res.value()[0] * (1. - res.value()[0]);
         assert_eq!(Some(&vec![sigma_trick]), x_grad);
     }
 
     #[test]
     fn test_simple_sgd() {
-        let y = Constant::new(vec![3f32,-4f32]);
-        let mut v = vec![0f32, 0f32]; 
+        let y = Constant::new(vec![3.,-4.]);
+        let mut v = vec![0., 0.]; 
         let mut graph = Graph::new();
         let alpha = 3e-1;
         for _ in 0..20 {
             let x = Variable::new(v.clone());
-            let c = Constant::scalar(2f32);
+            let c = Constant::scalar(2.);
             let y1 = &x - &y;
-            let y2 = (&y1).pow(&c);
-            let err = (&y2).sum();
+            let y2 = y1.pow(&c);
+            let err = y2.sum();
             graph.zero_grads();
             graph.backward(&err);
             let x_grad = graph.get_grad(&x).unwrap();
@@ -1514,16 +5950,40 @@ mod tests {
 
     #[test]
     fn test_updateable() {
-        let mut v = Rc::new(vec![0f32, 0f32]);
+        let mut v = Rc::new(vec![0., 0.]);
         let mut graph = Graph::new();
         let grad = {
             let x = Variable::shared(v.clone());
-            let res = (&x + 3f32).pow(2f32) + 3f32;
+            let res = (&x + 3.).pow(2.) + 3.;
             graph.backward(&res);
             graph.get_grad(&x)
         };
+        assert_eq!(grad, Some(&vec![6., 6.]));
         let v = Rc::get_mut(&mut v).unwrap();
-        assert_eq!(v, &mut [0f32, 0f32]);
+        assert_eq!(v, &mut [0., 0.]);
     }
 
+
+    #[test]
+    fn test_threaded_construction() {
+        // Each thread builds and differentiates its own graph
+        let handles: Vec<_> = (0..4).map(|t| std::thread::spawn(move || {
+            let x = Variable::new(vec![t as DType, 1.]);
+            let out = (&x * &x).sum();
+            let mut graph = Graph::new();
+            graph.backward(&out);
+            let mut ids = vec![x.get_id(), out.get_id()];
+            ids.extend((0..2000).map(|_| Constant::scalar(0.).get_id()));
+            (graph.get_grad(&x).unwrap().to_vec(), ids)
+        })).collect();
+
+        let mut seen = HashSet::new();
+        for (t, h) in handles.into_iter().enumerate() {
+            let (grad, ids) = h.join().unwrap();
+            assert_eq!(grad, vec![2. * t as DType, 2.]);
+            // Ids stay unique across threads and increasing within one
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            assert!(ids.into_iter().all(|id| seen.insert(id)));
+        }
+    }
 }
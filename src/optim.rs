@@ -0,0 +1,962 @@
+use std::collections::VecDeque;
+
+use hashbrown::HashMap;
+
+use crate::{ANode,NodeIdx,DType,Graph,Variable};
+
+// Parameters sharing a learning rate and weight decay. Weight decay is added
+// to the gradient as an L2 penalty, except by AdamW which decays the values
+// directly.
+pub struct ParamGroup {
+    pub params: Vec<ANode>,
+    pub lr: DType,
+    pub weight_decay: DType
+}
+
+impl ParamGroup {
+    pub fn new(params: Vec<ANode>, lr: DType, weight_decay: DType) -> Self {
+        ParamGroup { params, lr, weight_decay }
+    }
+}
+
+// Updates its parameters from the gradients held in `graph`. Optimizers start
+// out with a single group built from the parameters they're constructed with;
+// more can be added with their own hyperparameters.
+pub trait Optimizer {
+    fn step(&mut self, graph: &Graph);
+
+    fn param_groups(&self) -> &[ParamGroup];
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup>;
+
+    fn add_param_group(&mut self, group: ParamGroup) {
+        self.param_groups_mut().push(group);
+    }
+
+    // Sets the learning rate of every group
+    fn set_lr(&mut self, lr: DType) {
+        self.param_groups_mut().iter_mut().for_each(|g| g.lr = lr);
+    }
+}
+
+// Calls `update` on the values of each parameter that has a gradient, in
+// place, along with its group and the gradient with the group's weight decay
+// folded in. The gradient comes as (offset, values) segments: a single one
+// for dense gradients, one per touched row for sparse ones, so embedding
+// tables only have the rows looked up updated.
+fn for_each_param<F>(groups: &[ParamGroup], graph: &Graph, l2: bool, mut update: F)
+where
+    F: FnMut(NodeIdx, &ParamGroup, &[(usize, &[DType])], &mut [DType])
+{
+    for group in groups.iter() {
+        for p in group.params.iter() {
+            let mut segments: Vec<(usize, &[DType])> = if let Some(g) = graph.get_grad(p) {
+                vec![(0, g.as_slice())]
+            } else if let Some(rows) = graph.get_sparse_grad(p) {
+                let mut rows: Vec<_> = rows.iter().map(|(r, g)| (r * g.len(), g.as_slice())).collect();
+                rows.sort_by_key(|(offset, _)| *offset);
+                rows
+            } else {
+                continue
+            };
+
+            let decayed: Vec<(usize, Vec<DType>)>;
+            if l2 && group.weight_decay != 0. {
+                let x = p.value();
+                decayed = segments.iter().map(|(offset, g)| {
+                    let g = g.iter().zip(x[*offset..].iter())
+                        .map(|(g, x)| g + group.weight_decay * x)
+                        .collect();
+                    (*offset, g)
+                }).collect();
+                segments = decayed.iter().map(|(offset, g)| (*offset, g.as_slice())).collect();
+            }
+            p.update(|value| update(p.get_id(), group, &segments, value));
+        }
+    }
+}
+
+// Optimizer state matching a parameter's values, zeroed on first use
+fn state_for(state: &mut HashMap<NodeIdx, Vec<DType>>, id: NodeIdx, len: usize) -> &mut [DType] {
+    state.entry(id).or_insert_with(|| vec![0.; len])
+}
+
+// Gradient descent, optionally with momentum. Parameters without a gradient in
+// the graph, such as frozen ones, are left alone.
+pub struct SGD {
+    groups: Vec<ParamGroup>,
+    momentum: DType,
+    nesterov: bool,
+    velocity: HashMap<NodeIdx, Vec<DType>>
+}
+
+impl SGD {
+    pub fn new(params: Vec<ANode>, lr: DType) -> Self {
+        SGD::with_momentum(params, lr, 0., false)
+    }
+
+    // Keeps a running velocity v = momentum * v + grad per parameter and steps
+    // along it, or with `nesterov` along grad + momentum * v.
+    pub fn with_momentum(params: Vec<ANode>, lr: DType, momentum: DType, nesterov: bool) -> Self {
+        let groups = vec![ParamGroup::new(params, lr, 0.)];
+        SGD { groups, momentum, nesterov, velocity: HashMap::new() }
+    }
+}
+
+impl Optimizer for SGD {
+    fn step(&mut self, graph: &Graph) {
+        let (momentum, nesterov, velocity) = (self.momentum, self.nesterov, &mut self.velocity);
+        for_each_param(&self.groups, graph, true, |id, group, segments, value| {
+            if momentum == 0. {
+                for (offset, grad) in segments.iter() {
+                    value[*offset..].iter_mut().zip(grad.iter()).for_each(|(v, g)| *v -= group.lr * g);
+                }
+                return
+            }
+            let velocity = state_for(velocity, id, value.len());
+            for (offset, grad) in segments.iter() {
+                let velocity = velocity[*offset..].iter_mut();
+                for ((v, g), vel) in value[*offset..].iter_mut().zip(grad.iter()).zip(velocity) {
+                    *vel = momentum * *vel + g;
+                    let update = if nesterov { g + momentum * *vel } else { *vel };
+                    *v -= group.lr * update;
+                }
+            }
+        });
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] { &self.groups }
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup> { &mut self.groups }
+}
+
+struct Moments {
+    t: i32,
+    m: Vec<DType>,
+    v: Vec<DType>
+}
+
+// Adam with bias corrected moment estimates. A nonzero weight decay is added
+// to the gradient as an L2 penalty; see AdamW for the decoupled form.
+pub struct Adam {
+    groups: Vec<ParamGroup>,
+    beta1: DType,
+    beta2: DType,
+    eps: DType,
+    decoupled: bool,
+    moments: HashMap<NodeIdx, Moments>
+}
+
+impl Adam {
+    pub fn new(params: Vec<ANode>, lr: DType) -> Self {
+        Adam::with_params(params, lr, 0.9, 0.999, 1e-8, 0.)
+    }
+
+    pub fn with_params(
+        params: Vec<ANode>,
+        lr: DType,
+        beta1: DType,
+        beta2: DType,
+        eps: DType,
+        weight_decay: DType
+    ) -> Self {
+        let groups = vec![ParamGroup::new(params, lr, weight_decay)];
+        Adam { groups, beta1, beta2, eps, decoupled: false, moments: HashMap::new() }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, graph: &Graph) {
+        let (beta1, beta2, eps, decoupled) = (self.beta1, self.beta2, self.eps, self.decoupled);
+        let moments = &mut self.moments;
+        for_each_param(&self.groups, graph, !decoupled, |id, group, segments, value| {
+            let state = moments.entry(id).or_insert_with(|| Moments {
+                t: 0, m: vec![0.; value.len()], v: vec![0.; value.len()]
+            });
+            state.t += 1;
+            let c1 = 1. - beta1.powi(state.t);
+            let c2 = 1. - beta2.powi(state.t);
+            let decay = if decoupled { group.lr * group.weight_decay } else { 0. };
+
+            for (offset, grad) in segments.iter() {
+                let moments = state.m[*offset..].iter_mut().zip(state.v[*offset..].iter_mut());
+                for ((x, g), (m, v)) in value[*offset..].iter_mut().zip(grad.iter()).zip(moments) {
+                    *m = beta1 * *m + (1. - beta1) * g;
+                    *v = beta2 * *v + (1. - beta2) * g * g;
+                    *x -= decay * *x + group.lr * (*m / c1) / ((*v / c2).sqrt() + eps);
+                }
+            }
+        });
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] { &self.groups }
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup> { &mut self.groups }
+}
+
+// Adam with decoupled weight decay: parameters shrink by lr * weight_decay
+// each step rather than the decay going through the moment estimates.
+pub struct AdamW(Adam);
+
+impl AdamW {
+    pub fn new(params: Vec<ANode>, lr: DType, weight_decay: DType) -> Self {
+        AdamW::with_params(params, lr, 0.9, 0.999, 1e-8, weight_decay)
+    }
+
+    pub fn with_params(
+        params: Vec<ANode>,
+        lr: DType,
+        beta1: DType,
+        beta2: DType,
+        eps: DType,
+        weight_decay: DType
+    ) -> Self {
+        let mut adam = Adam::with_params(params, lr, beta1, beta2, eps, weight_decay);
+        adam.decoupled = true;
+        AdamW(adam)
+    }
+}
+
+impl Optimizer for AdamW {
+    fn step(&mut self, graph: &Graph) {
+        self.0.step(graph)
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] { self.0.param_groups() }
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup> { self.0.param_groups_mut() }
+}
+
+// Scales each step by a running RMS of the gradient,
+// v = alpha * v + (1 - alpha) * grad^2.
+pub struct RMSProp {
+    groups: Vec<ParamGroup>,
+    alpha: DType,
+    eps: DType,
+    square_avg: HashMap<NodeIdx, Vec<DType>>
+}
+
+impl RMSProp {
+    pub fn new(params: Vec<ANode>, lr: DType) -> Self {
+        RMSProp::with_params(params, lr, 0.99, 1e-8)
+    }
+
+    pub fn with_params(params: Vec<ANode>, lr: DType, alpha: DType, eps: DType) -> Self {
+        let groups = vec![ParamGroup::new(params, lr, 0.)];
+        RMSProp { groups, alpha, eps, square_avg: HashMap::new() }
+    }
+}
+
+impl Optimizer for RMSProp {
+    fn step(&mut self, graph: &Graph) {
+        let (alpha, eps, square_avg) = (self.alpha, self.eps, &mut self.square_avg);
+        for_each_param(&self.groups, graph, true, |id, group, segments, value| {
+            let square_avg = state_for(square_avg, id, value.len());
+            for (offset, grad) in segments.iter() {
+                let square_avg = square_avg[*offset..].iter_mut();
+                for ((x, g), v) in value[*offset..].iter_mut().zip(grad.iter()).zip(square_avg) {
+                    *v = alpha * *v + (1. - alpha) * g * g;
+                    *x -= group.lr * g / (v.sqrt() + eps);
+                }
+            }
+        });
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] { &self.groups }
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup> { &mut self.groups }
+}
+
+// Scales each step by the root of the summed squared gradients seen so far,
+// so often updated coordinates slow down.
+pub struct Adagrad {
+    groups: Vec<ParamGroup>,
+    eps: DType,
+    sum: HashMap<NodeIdx, Vec<DType>>
+}
+
+impl Adagrad {
+    pub fn new(params: Vec<ANode>, lr: DType) -> Self {
+        Adagrad::with_params(params, lr, 1e-10)
+    }
+
+    pub fn with_params(params: Vec<ANode>, lr: DType, eps: DType) -> Self {
+        let groups = vec![ParamGroup::new(params, lr, 0.)];
+        Adagrad { groups, eps, sum: HashMap::new() }
+    }
+}
+
+impl Optimizer for Adagrad {
+    fn step(&mut self, graph: &Graph) {
+        let (eps, sum) = (self.eps, &mut self.sum);
+        for_each_param(&self.groups, graph, true, |id, group, segments, value| {
+            let sum = state_for(sum, id, value.len());
+            for (offset, grad) in segments.iter() {
+                for ((x, g), s) in value[*offset..].iter_mut().zip(grad.iter()).zip(sum[*offset..].iter_mut()) {
+                    *s += g * g;
+                    *x -= group.lr * g / (s.sqrt() + eps);
+                }
+            }
+        });
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] { &self.groups }
+
+    fn param_groups_mut(&mut self) -> &mut Vec<ParamGroup> { &mut self.groups }
+}
+
+fn dot(a: &[DType], b: &[DType]) -> DType {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+// Minimizer of the cubic matching f and f' at both ends, kept inside the
+// inner 80% of the bracket; falls back to bisecting it.
+fn cubic_min(a: (DType, DType, DType), b: (DType, DType, DType)) -> DType {
+    let ((x1, f1, g1), (x2, f2, g2)) = (a, b);
+    let (lo, hi) = if x1 <= x2 { (x1, x2) } else { (x2, x1) };
+    let d1 = g1 + g2 - 3. * (f1 - f2) / (x1 - x2);
+    let d2_sq = d1 * d1 - g1 * g2;
+    let t = if d2_sq >= 0. {
+        let d2 = d2_sq.sqrt() * (x2 - x1).signum();
+        x2 - (x2 - x1) * (g2 + d2 - d1) / (g2 - g1 + 2. * d2)
+    } else {
+        DType::NAN
+    };
+    let margin = 0.1 * (hi - lo);
+    if t.is_finite() && t >= lo + margin && t <= hi - margin { t } else { (lo + hi) / 2. }
+}
+
+// Limited memory BFGS. Each step evaluates `closure`, which rebuilds the loss
+// from the parameters' current values, several times while searching for a
+// step length satisfying the strong Wolfe conditions.
+pub struct LBFGS {
+    params: Vec<ANode>,
+    lr: DType,
+    history_size: usize,
+    max_evals: usize,
+    // (s, y) pairs, oldest first
+    history: VecDeque<(Vec<DType>, Vec<DType>)>
+}
+
+impl LBFGS {
+    pub fn new(params: Vec<ANode>, lr: DType) -> Self {
+        LBFGS::with_params(params, lr, 10, 25)
+    }
+
+    // `max_evals` caps the loss evaluations spent in each line search
+    pub fn with_params(params: Vec<ANode>, lr: DType, history_size: usize, max_evals: usize) -> Self {
+        LBFGS { params, lr, history_size, max_evals, history: VecDeque::new() }
+    }
+
+    fn flat_values(&self) -> Vec<DType> {
+        self.params.iter().flat_map(|p| p.value().to_vec()).collect()
+    }
+
+    // Loss and flattened gradient with the parameters set to `x`
+    fn evaluate<F: FnMut() -> ANode>(&self, closure: &mut F, x: &[DType]) -> (DType, Vec<DType>) {
+        let mut offset = 0;
+        for p in self.params.iter() {
+            let len = p.value().len();
+            p.set_value(&x[offset..offset + len]);
+            offset += len;
+        }
+        let loss = closure();
+        let mut graph = Graph::new();
+        graph.backward(&loss);
+        let grad = self.params.iter()
+            .flat_map(|p| match graph.get_grad(p) {
+                Some(g) => g.clone(),
+                None => vec![0.; p.value().len()]
+            })
+            .collect();
+        let loss = loss.value()[0];
+        (loss, grad)
+    }
+
+    // The two loop recursion: approximates -H * g from the stored pairs
+    fn direction(&self, g: &[DType]) -> Vec<DType> {
+        let mut q = g.to_vec();
+        let mut alphas = Vec::with_capacity(self.history.len());
+        for (s, y) in self.history.iter().rev() {
+            let alpha = dot(s, &q) / dot(y, s);
+            q.iter_mut().zip(y.iter()).for_each(|(qi, yi)| *qi -= alpha * yi);
+            alphas.push(alpha);
+        }
+        let gamma = match self.history.back() {
+            Some((s, y)) => dot(s, y) / dot(y, y),
+            None => 1.
+        };
+        q.iter_mut().for_each(|qi| *qi *= gamma);
+        for ((s, y), alpha) in self.history.iter().zip(alphas.into_iter().rev()) {
+            let beta = dot(y, &q) / dot(y, s);
+            q.iter_mut().zip(s.iter()).for_each(|(qi, si)| *qi += (alpha - beta) * si);
+        }
+        q.iter_mut().for_each(|qi| *qi = -*qi);
+        q
+    }
+
+    // Takes one quasi-Newton step and returns the loss at the new point
+    pub fn step<F: FnMut() -> ANode>(&mut self, mut closure: F) -> DType {
+        const C1: DType = 1e-4;
+        const C2: DType = 0.9;
+
+        let x0 = self.flat_values();
+        let (f0, g0) = self.evaluate(&mut closure, &x0);
+        if g0.iter().all(|g| *g == 0.) {
+            return f0
+        }
+
+        let mut d = self.direction(&g0);
+        let mut dphi0 = dot(&g0, &d);
+        if dphi0 >= 0. {
+            // Curvature information went stale; start over from steepest descent
+            self.history.clear();
+            d = g0.iter().map(|g| -g).collect();
+            dphi0 = dot(&g0, &d);
+        }
+        let mut t = if self.history.is_empty() {
+            self.lr * (1. / g0.iter().map(|g| g.abs()).sum::<DType>()).min(1.)
+        } else {
+            self.lr
+        };
+
+        let mut evals = 0;
+        let mut phi = |t: DType, evals: &mut usize| {
+            *evals += 1;
+            let x: Vec<DType> = x0.iter().zip(d.iter()).map(|(x, d)| x + t * d).collect();
+            let (f, g) = self.evaluate(&mut closure, &x);
+            let dphi = dot(&g, &d);
+            (t, f, dphi, g)
+        };
+
+        // Bracket a step satisfying the Wolfe conditions, then zoom in on it
+        let mut prev = (0., f0, dphi0, g0.clone());
+        let mut bracket = None;
+        let mut accepted = None;
+        while evals < self.max_evals {
+            let cur = phi(t, &mut evals);
+            if cur.1 > f0 + C1 * cur.0 * dphi0 || (evals > 1 && cur.1 >= prev.1) {
+                bracket = Some((prev.clone(), cur));
+                break
+            }
+            if cur.2.abs() <= -C2 * dphi0 {
+                accepted = Some(cur);
+                break
+            }
+            if cur.2 >= 0. {
+                bracket = Some((cur, prev.clone()));
+                break
+            }
+            t = cur.0 * 2.;
+            prev = cur;
+        }
+        if accepted.is_none() {
+            if let Some((mut lo, mut hi)) = bracket {
+                while evals < self.max_evals && (hi.0 - lo.0).abs() > DType::EPSILON * lo.0.abs().max(1.) {
+                    let t = cubic_min((lo.0, lo.1, lo.2), (hi.0, hi.1, hi.2));
+                    let cur = phi(t, &mut evals);
+                    if cur.1 > f0 + C1 * cur.0 * dphi0 || cur.1 >= lo.1 {
+                        hi = cur;
+                    } else {
+                        if cur.2.abs() <= -C2 * dphi0 {
+                            lo = cur;
+                            break
+                        }
+                        if cur.2 * (hi.0 - lo.0) >= 0. {
+                            hi = lo;
+                        }
+                        lo = cur;
+                    }
+                }
+                accepted = Some(lo);
+            } else {
+                accepted = Some(prev);
+            }
+        }
+
+        let (t, f, _, g) = accepted.unwrap();
+        let x: Vec<DType> = x0.iter().zip(d.iter()).map(|(x, d)| x + t * d).collect();
+        self.evaluate(&mut closure, &x);
+
+        let s: Vec<DType> = d.iter().map(|d| t * d).collect();
+        let y: Vec<DType> = g.iter().zip(g0.iter()).map(|(a, b)| a - b).collect();
+        if dot(&s, &y) > 1e-10 {
+            if self.history.len() == self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back((s, y));
+        }
+        f
+    }
+}
+
+// Learning rate as a function of a group's initial rate and the number of
+// scheduler steps taken
+pub trait Schedule {
+    fn lr(&self, base_lr: DType, step: usize) -> DType;
+}
+
+// Multiplies the rate by `gamma` every `step_size` steps
+pub struct StepLR {
+    step_size: usize,
+    gamma: DType
+}
+
+impl StepLR {
+    pub fn new(step_size: usize, gamma: DType) -> Self {
+        if step_size == 0 {
+            panic!("StepLR needs a step size of at least one!");
+        }
+        StepLR { step_size, gamma }
+    }
+}
+
+impl Schedule for StepLR {
+    fn lr(&self, base_lr: DType, step: usize) -> DType {
+        base_lr * self.gamma.powi((step / self.step_size) as i32)
+    }
+}
+
+// Anneals from the initial rate down to `min_lr` along half a cosine over
+// `t_max` steps, staying at `min_lr` afterwards
+pub struct CosineAnnealing {
+    t_max: usize,
+    min_lr: DType
+}
+
+impl CosineAnnealing {
+    pub fn new(t_max: usize, min_lr: DType) -> Self {
+        if t_max == 0 {
+            panic!("CosineAnnealing needs a t_max of at least one!");
+        }
+        CosineAnnealing { t_max, min_lr }
+    }
+}
+
+impl Schedule for CosineAnnealing {
+    fn lr(&self, base_lr: DType, step: usize) -> DType {
+        let progress = step.min(self.t_max) as DType / self.t_max as DType;
+        let pi = std::f64::consts::PI as DType;
+        self.min_lr + (base_lr - self.min_lr) * (1. + (pi * progress).cos()) / 2.
+    }
+}
+
+// Ramps the rate up linearly over the first `steps` steps, then hands over to
+// `after` if given, counting its steps from the end of the warmup
+pub struct LinearWarmup {
+    steps: usize,
+    after: Option<Box<dyn Schedule>>
+}
+
+impl LinearWarmup {
+    pub fn new(steps: usize) -> Self {
+        LinearWarmup { steps, after: None }
+    }
+
+    pub fn then<S: Schedule + 'static>(steps: usize, after: S) -> Self {
+        LinearWarmup { steps, after: Some(Box::new(after)) }
+    }
+}
+
+impl Schedule for LinearWarmup {
+    fn lr(&self, base_lr: DType, step: usize) -> DType {
+        match &self.after {
+            _ if step < self.steps => base_lr * (step + 1) as DType / self.steps as DType,
+            Some(after) => after.lr(base_lr, step - self.steps),
+            None => base_lr
+        }
+    }
+}
+
+// Drives an optimizer's learning rates from a schedule. The rates the groups
+// had when first seen are the base rates; call `step` once per optimizer step
+// or epoch, whichever the schedule is counted in.
+pub struct LRScheduler<S: Schedule> {
+    schedule: S,
+    base_lrs: Vec<DType>,
+    steps: usize
+}
+
+impl <S: Schedule> LRScheduler<S> {
+    pub fn new<O: Optimizer + ?Sized>(schedule: S, optimizer: &mut O) -> Self {
+        let mut scheduler = LRScheduler { schedule, base_lrs: Vec::new(), steps: 0 };
+        scheduler.apply(optimizer);
+        scheduler
+    }
+
+    pub fn step<O: Optimizer + ?Sized>(&mut self, optimizer: &mut O) {
+        self.steps += 1;
+        self.apply(optimizer);
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn apply<O: Optimizer + ?Sized>(&mut self, optimizer: &mut O) {
+        let groups = optimizer.param_groups_mut();
+        // Groups added since the last step use the rate they came with as base
+        let seen = self.base_lrs.len();
+        self.base_lrs.extend(groups[seen..].iter().map(|g| g.lr));
+        for (group, base_lr) in groups.iter_mut().zip(self.base_lrs.iter()) {
+            group.lr = self.schedule.lr(*base_lr, self.steps);
+        }
+    }
+}
+
+// Gradients summed over the batches one worker handled
+struct Shard {
+    loss: DType,
+    dense: Vec<Option<Vec<DType>>>,
+    sparse: Vec<HashMap<usize, Vec<DType>>>
+}
+
+impl Shard {
+    fn add(&mut self, params: &[ANode], graph: &Graph, loss: &ANode) {
+        self.loss += loss.value().iter().sum::<DType>();
+        for (i, p) in params.iter().enumerate() {
+            if let Some(g) = graph.get_grad(p) {
+                match self.dense[i].as_mut() {
+                    Some(d) => d.iter_mut().zip(g.iter()).for_each(|(di, gi)| *di += gi),
+                    None => self.dense[i] = Some(g.to_vec())
+                }
+            }
+            if let Some(rows) = graph.get_sparse_grad(p) {
+                for (row, g) in rows.iter() {
+                    let acc = self.sparse[i].entry(*row).or_insert_with(|| vec![0.; g.len()]);
+                    acc.iter_mut().zip(g.iter()).for_each(|(ai, gi)| *ai += gi);
+                }
+            }
+        }
+    }
+}
+
+// Synchronous data parallel training. Each of `n_threads` workers takes its
+// share of `batches`, builds each batch's loss with `model_fn` and
+// backpropagates it in a graph of its own. Nodes can't cross threads, so
+// workers build `model_fn` over copies of `params` made from their values.
+// Returns a graph holding the parameters' gradients averaged over the
+// batches, ready for an optimizer step, along with the mean loss.
+pub fn data_parallel<B, F>(params: &[ANode], model_fn: F, batches: &[B], n_threads: usize) -> (Graph, DType)
+where
+    B: Sync,
+    F: Fn(&[ANode], &B) -> ANode + Sync
+{
+    if batches.is_empty() {
+        panic!("data_parallel needs at least one batch!");
+    }
+    if n_threads == 0 {
+        panic!("data_parallel needs at least one thread!");
+    }
+    let n_threads = n_threads.min(batches.len());
+    let snapshot: Vec<(Vec<DType>, Vec<usize>, bool)> = params.iter()
+        .map(|p| (p.value().to_vec(), p.shape().dims().to_vec(), p.requires_grad()))
+        .collect();
+
+    let (model_fn, snapshot) = (&model_fn, &snapshot);
+    let shards: Vec<Shard> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..n_threads).map(|t| s.spawn(move || {
+            let local: Vec<ANode> = snapshot.iter().map(|(value, dims, requires_grad)| {
+                let p = Variable::with_shape(value.clone(), dims);
+                if !requires_grad {
+                    p.freeze();
+                }
+                p
+            }).collect();
+            let mut shard = Shard {
+                loss: 0.,
+                dense: vec![None; local.len()],
+                sparse: vec![HashMap::new(); local.len()]
+            };
+            for batch in batches.iter().skip(t).step_by(n_threads) {
+                let loss = model_fn(&local, batch);
+                let mut graph = Graph::new();
+                graph.backward(&loss);
+                shard.add(&local, &graph, &loss);
+            }
+            shard
+        })).collect();
+        handles.into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+
+    let scale = 1. / batches.len() as DType;
+    let mut graph = Graph::new();
+    let mut loss = 0.;
+    for shard in shards.into_iter() {
+        loss += shard.loss;
+        for (p, dense) in params.iter().zip(shard.dense) {
+            if let Some(mut g) = dense {
+                g.iter_mut().for_each(|gi| *gi *= scale);
+                graph.accumulate_grad(p, &g);
+            }
+        }
+        for (p, sparse) in params.iter().zip(shard.sparse) {
+            for (row, mut g) in sparse.into_iter() {
+                g.iter_mut().for_each(|gi| *gi *= scale);
+                graph.accumulate_sparse_grad(p, &[row], &g);
+            }
+        }
+    }
+    (graph, loss * scale)
+}
+
+#[cfg(test)]
+mod optim_tests {
+    use super::*;
+    use crate::{Variable,Constant};
+
+    #[test]
+    fn test_sgd() {
+        let x = Variable::new(vec![0., 10.]);
+        let frozen = Variable::new(vec![1.]);
+        frozen.freeze();
+        let target = Constant::new(vec![3., -1.]);
+        let mut opt = SGD::new(vec![x.clone(), frozen.clone()], 0.1);
+        for _ in 0..100 {
+            let diff = &x - &target;
+            let loss = (&diff * &diff).sum() * &frozen;
+            let mut graph = Graph::new();
+            graph.backward(&loss);
+            opt.step(&graph);
+        }
+        assert!((x.value()[0] - 3.).abs() < 1e-4);
+        assert!((x.value()[1] + 1.).abs() < 1e-4);
+        assert_eq!(frozen.value(), &[1.]);
+    }
+
+    #[test]
+    fn test_momentum() {
+        // With a constant gradient of 1 the velocity goes 1, 1.5, 1.75
+        let x = Variable::new(vec![0.]);
+        let mut opt = SGD::with_momentum(vec![x.clone()], 1., 0.5, false);
+        let y = Variable::new(vec![0.]);
+        let mut nesterov = SGD::with_momentum(vec![y.clone()], 1., 0.5, true);
+        for _ in 0..3 {
+            let mut graph = Graph::new();
+            graph.backward(&(x.sum() + y.sum()));
+            opt.step(&graph);
+            nesterov.step(&graph);
+        }
+        assert_eq!(x.value(), &[-4.25]);
+        // Nesterov steps along 1.5, 1.75, 1.875
+        assert_eq!(y.value(), &[-5.125]);
+
+        let x = Variable::new(vec![5.]);
+        let mut opt = SGD::with_momentum(vec![x.clone()], 0.05, 0.9, true);
+        for _ in 0..200 {
+            let mut graph = Graph::new();
+            graph.backward(&(&x * &x).sum());
+            opt.step(&graph);
+        }
+        assert!(x.value()[0].abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_adam() {
+        // The first step moves every coordinate by lr regardless of the
+        // gradient's scale
+        let x = Variable::new(vec![1., -2.]);
+        let mut opt = Adam::new(vec![x.clone()], 0.1);
+        let mut graph = Graph::new();
+        graph.backward(&(&x * &Constant::new(vec![100., -0.01])).sum());
+        opt.step(&graph);
+        assert!((x.value()[0] - 0.9).abs() < 1e-5);
+        assert!((x.value()[1] + 1.9).abs() < 1e-3);
+
+        let target = Constant::new(vec![3., -1.]);
+        let x = Variable::new(vec![0., 0.]);
+        let mut opt = Adam::new(vec![x.clone()], 0.05);
+        for _ in 0..500 {
+            let diff = &x - &target;
+            let mut graph = Graph::new();
+            graph.backward(&(&diff * &diff).sum());
+            opt.step(&graph);
+        }
+        assert!((x.value()[0] - 3.).abs() < 1e-2);
+        assert!((x.value()[1] + 1.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_adamw() {
+        // With a zero gradient only the decay acts
+        let x = Variable::new(vec![2.]);
+        let zero = Constant::new(vec![0.]);
+        let mut opt = AdamW::new(vec![x.clone()], 0.1, 0.5);
+        let mut graph = Graph::new();
+        graph.backward(&(&x * &zero).sum());
+        opt.step(&graph);
+        assert!((x.value()[0] - 1.9).abs() < 1e-6);
+
+        // Coupled decay is normalized like any other gradient, so a heavier
+        // decay still moves the first step by lr
+        let y = Variable::new(vec![2.]);
+        let mut opt = Adam::with_params(vec![y.clone()], 0.1, 0.9, 0.999, 1e-8, 5.);
+        let mut graph = Graph::new();
+        graph.backward(&(&y * &zero).sum());
+        opt.step(&graph);
+        assert!((y.value()[0] - 1.9).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rmsprop_adagrad() {
+        // Adagrad's steps shrink as lr / sqrt(n) under a constant gradient
+        let x = Variable::new(vec![0.]);
+        let mut opt = Adagrad::new(vec![x.clone()], 1.);
+        for _ in 0..4 {
+            let mut graph = Graph::new();
+            graph.backward(&(&x * 3.).sum());
+            opt.step(&graph);
+        }
+        let expected = -(1. + 1. / (2. as DType).sqrt() + 1. / (3. as DType).sqrt() + 0.5);
+        assert!((x.value()[0] - expected).abs() < 1e-5);
+
+        // Both are swappable behind the trait and converge
+        let target = Constant::new(vec![3., -1.]);
+        for make in [
+            |p| Box::new(RMSProp::new(p, 0.01)) as Box<dyn Optimizer>,
+            |p| Box::new(Adagrad::new(p, 0.5)) as Box<dyn Optimizer>
+        ] {
+            let x = Variable::new(vec![0., 0.]);
+            let mut opt = make(vec![x.clone()]);
+            for _ in 0..1000 {
+                let diff = &x - &target;
+                let mut graph = Graph::new();
+                graph.backward(&(&diff * &diff).sum());
+                opt.step(&graph);
+            }
+            assert!((x.value()[0] - 3.).abs() < 2e-2);
+            assert!((x.value()[1] + 1.).abs() < 2e-2);
+        }
+    }
+
+    #[test]
+    fn test_lbfgs() {
+        // The Rosenbrock function, minimized at (1, 1)
+        let x = Variable::new(vec![-1.2]);
+        let y = Variable::new(vec![1.]);
+        let mut opt = LBFGS::new(vec![x.clone(), y.clone()], 1.);
+        let rosenbrock = || {
+            let a = 1. - &x;
+            let b = &y - &x * &x;
+            (&a * &a + &b * &b * 100.).sum()
+        };
+        let mut loss = DType::INFINITY;
+        for _ in 0..40 {
+            loss = opt.step(rosenbrock);
+        }
+        assert!(loss < 1e-6, "{}", loss);
+        assert!((x.value()[0] - 1.).abs() < 1e-2);
+        assert!((y.value()[0] - 1.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_param_groups() {
+        let body = Variable::new(vec![1.]);
+        let head = Variable::new(vec![1.]);
+        let decayed = Variable::new(vec![1.]);
+        let mut opt = SGD::new(vec![head.clone()], 0.1);
+        opt.add_param_group(ParamGroup::new(vec![body.clone()], 0.01, 0.));
+        opt.add_param_group(ParamGroup::new(vec![decayed.clone()], 0.1, 1.));
+
+        let mut graph = Graph::new();
+        graph.backward(&(&body + &head + &decayed).sum());
+        opt.step(&graph);
+        assert_eq!(head.value(), &[0.9]);
+        assert_eq!(body.value(), &[0.99]);
+        // Its gradient is 1 plus the decay's 1
+        assert_eq!(decayed.value(), &[0.8]);
+
+        opt.set_lr(0.);
+        opt.step(&graph);
+        assert_eq!(head.value(), &[0.9]);
+        assert_eq!(opt.param_groups().len(), 3);
+    }
+
+    #[test]
+    fn test_schedulers() {
+        let lrs = |schedule: &dyn Schedule| (0..6).map(|t| schedule.lr(1., t)).collect::<Vec<_>>();
+        assert_eq!(lrs(&StepLR::new(2, 0.5)), vec![1., 1., 0.5, 0.5, 0.25, 0.25]);
+        assert_eq!(lrs(&LinearWarmup::new(4)), vec![0.25, 0.5, 0.75, 1., 1., 1.]);
+        assert_eq!(lrs(&LinearWarmup::then(2, StepLR::new(1, 0.5))), vec![0.5, 1., 1., 0.5, 0.25, 0.125]);
+
+        let cosine = lrs(&CosineAnnealing::new(4, 0.1));
+        for (a, b) in cosine.iter().zip([1., 0.868, 0.55, 0.232, 0.1, 0.1].iter()) {
+            assert!((a - b).abs() < 1e-3);
+        }
+
+        let x = Variable::new(vec![0.]);
+        let mut opt = SGD::new(vec![x.clone()], 0.4);
+        let mut scheduler = LRScheduler::new(StepLR::new(1, 0.5), &mut opt);
+        opt.add_param_group(ParamGroup::new(vec![], 1., 0.));
+        for _ in 0..2 {
+            let mut graph = Graph::new();
+            graph.backward(&x.sum());
+            opt.step(&graph);
+            scheduler.step(&mut opt);
+        }
+        // Steps of 0.4 then 0.2
+        assert!((x.value()[0] + 0.6).abs() < 1e-6);
+        assert_eq!(scheduler.steps(), 2);
+        let lrs: Vec<_> = opt.param_groups().iter().map(|g| g.lr).collect();
+        assert_eq!(lrs, vec![0.1, 0.25]);
+    }
+
+    #[test]
+    #[should_panic(expected = "step size of at least one")]
+    fn test_step_lr_zero_step_size() {
+        StepLR::new(0, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "t_max of at least one")]
+    fn test_cosine_annealing_zero_t_max() {
+        CosineAnnealing::new(0, 0.1);
+    }
+
+    #[test]
+    fn test_data_parallel() {
+        let w = Variable::new(vec![1., -2.]);
+        let frozen = Variable::new(vec![3.]);
+        frozen.freeze();
+        let table = Variable::with_shape(vec![0., 1., 2., 3., 4., 5.], &[3, 2]);
+        let params = vec![w.clone(), frozen.clone(), table.clone()];
+        let batches: Vec<(Vec<DType>, usize)> = (0..5)
+            .map(|i| (vec![i as DType, 1.], i % 3))
+            .collect();
+        let model = |p: &[ANode], (x, row): &(Vec<DType>, usize)| {
+            let x = Constant::new(x.clone());
+            ((&p[0] * &x).sum() * &p[1] + (p[2].embedding(&[*row]) * &p[0]).sum()).square()
+        };
+
+        // Same as backpropagating the mean loss over every batch in one graph
+        let mut total = model(&params, &batches[0]);
+        for b in batches[1..].iter() {
+            total += model(&params, b);
+        }
+        let mean = total / batches.len() as DType;
+        let mut expected = Graph::new();
+        expected.backward(&mean);
+
+        for n_threads in [1, 2, 8] {
+            let (graph, loss) = data_parallel(&params, model, &batches, n_threads);
+            assert!((loss - mean.value()[0]).abs() < 1e-3);
+            let (g, e) = (graph.get_grad(&w).unwrap(), expected.get_grad(&w).unwrap());
+            assert!(g.iter().zip(e.iter()).all(|(a, b)| (a - b).abs() < 1e-3));
+            assert!(graph.get_grad(&frozen).is_none());
+
+            let (g, e) = (graph.get_sparse_grad(&table).unwrap(), expected.get_sparse_grad(&table).unwrap());
+            assert_eq!(g.len(), 3);
+            for (row, grad) in g.iter() {
+                assert!(grad.iter().zip(e[row].iter()).all(|(a, b)| (a - b).abs() < 1e-3));
+            }
+        }
+
+        // The averaged gradients drive an optimizer as usual
+        let (graph, _) = data_parallel(&params, model, &batches, 2);
+        let before = w.value().to_vec();
+        SGD::new(vec![w.clone()], 0.1).step(&graph);
+        assert_ne!(w.value(), before.as_slice());
+    }
+}
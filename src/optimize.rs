@@ -0,0 +1,291 @@
+use hashbrown::{HashMap,HashSet};
+
+use crate::{ANode,NodeIdx,Constant};
+use crate::ops::{Fused,FusedOp};
+use crate::plan::evaluation_order;
+use crate::introspect::walk;
+
+// Rebuilds the graph under `output` bottom up, letting `f` replace each node
+// given its already rewritten children and everything rewritten so far.
+// Nodes whose children are unchanged are kept as is, as are those which
+// can't be rebuilt.
+pub(crate) fn rewrite<F>(output: &ANode, mut f: F) -> ANode
+where
+    F: FnMut(&ANode, &[ANode], &HashMap<NodeIdx, ANode>) -> Option<ANode>
+{
+    let mut rewritten: HashMap<NodeIdx, ANode> = HashMap::new();
+    for node in evaluation_order(output).iter() {
+        let children = node.get_children().unwrap_or(&[]);
+        let new_children: Vec<ANode> = children.iter()
+            .map(|c| rewritten.get(&c.get_id()).unwrap_or(c).clone())
+            .collect();
+
+        let changed = children.iter().zip(new_children.iter())
+            .any(|(c, n)| c.get_id() != n.get_id());
+
+        let new_node = match f(node, &new_children, &rewritten) {
+            Some(n) => n,
+            None if changed => node.rebuild(&new_children).unwrap_or_else(|| node.clone()),
+            None => continue
+        };
+        rewritten.insert(node.get_id(), new_node);
+    }
+    rewritten.remove(&output.get_id()).unwrap_or_else(|| output.clone())
+}
+
+// Collapses every subgraph fed only by constants into a single constant
+pub(crate) fn fold_constants(output: &ANode) -> ANode {
+    rewrite(output, |node, children, _| {
+        if !children.is_empty() && children.iter().all(|c| c.is_constant()) {
+            Some(Constant::with_shape(node.value().to_vec(), node.shape().dims()))
+        } else {
+            None
+        }
+    })
+}
+
+// Copies the graph under `output` with each `old` leaf swapped for its `new`
+// one, recomputing every node downstream of a swap.
+pub(crate) fn substitute(output: &ANode, pairs: &[(&ANode, &ANode)]) -> ANode {
+    let mut swaps: HashMap<NodeIdx, ANode> = HashMap::new();
+    for (old, new) in pairs.iter() {
+        if !old.is_leaf() {
+            panic!("Only leaves can be substituted, found {}!", old.op_name());
+        }
+        if old.shape() != new.shape() {
+            panic!("Substitute has shape {:?} but replaces a leaf of shape {:?}!",
+                   new.shape().dims(), old.shape().dims());
+        }
+        swaps.insert(old.get_id(), (*new).clone());
+    }
+
+    if let Some(new) = swaps.get(&output.get_id()) {
+        return new.clone()
+    }
+
+    // Leaves never reach `f`, so they're swapped as their parents' children
+    rewrite(output, |node, children, _| {
+        let children: Vec<ANode> = children.iter()
+            .map(|c| swaps.get(&c.get_id()).unwrap_or(c).clone())
+            .collect();
+        let changed = node.get_children().unwrap_or(&[]).iter().zip(children.iter())
+            .any(|(c, n)| c.get_id() != n.get_id());
+
+        // Keeping the old node would silently leave a stale value downstream
+        if !changed { return None }
+        node.rebuild(&children).or_else(|| {
+            panic!("{} nodes can't be rebuilt for substitution!", node.op_name())
+        })
+    })
+}
+
+fn is_scalar_constant(node: &ANode) -> bool {
+    node.is_constant() && node.value().len() == 1
+}
+
+// Elementwise ops whose operands need no broadcasting, scalar constants aside.
+// Nodes holding on to their gradient stay unfused.
+fn is_fusible(node: &ANode) -> bool {
+    let elementwise = !node.requires_grad() && matches!(node.op_name(),
+        "Negate" | "Exp" | "Ln" | "Sin" | "Cos" | "Tanh" |
+        "AddN" | "Subtract" | "Multiply" | "Divide");
+    let len = node.value().len();
+    elementwise && node.get_children().unwrap_or(&[]).iter()
+        .all(|c| c.value().len() == len || is_scalar_constant(c))
+}
+
+struct Program<'a> {
+    inlined: &'a HashSet<NodeIdx>,
+    rewritten: &'a HashMap<NodeIdx, ANode>,
+    inputs: Vec<ANode>,
+    ops: Vec<FusedOp>
+}
+
+impl <'a> Program<'a> {
+    fn emit(&mut self, node: &ANode, member: bool) -> usize {
+        let op = if member {
+            let children = node.get_children().unwrap_or(&[]);
+            let args: Vec<_> = children.iter()
+                .map(|c| self.emit(c, self.inlined.contains(&c.get_id())))
+                .collect();
+            match (node.op_name(), args.as_slice()) {
+                ("Negate", [a]) => FusedOp::Neg(*a),
+                ("Exp", [a]) => FusedOp::Exp(*a),
+                ("Ln", [a]) => FusedOp::Ln(*a),
+                ("Sin", [a]) => FusedOp::Sin(*a),
+                ("Cos", [a]) => FusedOp::Cos(*a),
+                ("Tanh", [a]) => FusedOp::Tanh(*a),
+                ("AddN", [a, b]) => FusedOp::Add(*a, *b),
+                ("Subtract", [a, b]) => FusedOp::Sub(*a, *b),
+                ("Multiply", [a, b]) => FusedOp::Mul(*a, *b),
+                ("Divide", [a, b]) => FusedOp::Div(*a, *b),
+                (name, _) => panic!("Cannot fuse {}!", name)
+            }
+        } else if is_scalar_constant(node) {
+            FusedOp::Scalar(node.value()[0])
+        } else {
+            let input = self.rewritten.get(&node.get_id()).unwrap_or(node);
+            let k = match self.inputs.iter().position(|i| i.get_id() == input.get_id()) {
+                Some(k) => k,
+                None => {
+                    self.inputs.push(input.clone());
+                    self.inputs.len() - 1
+                }
+            };
+            FusedOp::Input(k)
+        };
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+}
+
+// Merges chains of elementwise ops into single fused nodes. A node joins its
+// parent's chain when both are fusible and it has no other parents.
+pub(crate) fn fuse_elementwise(output: &ANode) -> ANode {
+    let mut parents: HashMap<NodeIdx, usize> = HashMap::new();
+    walk(output, &mut |node: &ANode| {
+        for c in node.get_children().unwrap_or(&[]).iter() {
+            *parents.entry(c.get_id()).or_insert(0) += 1;
+        }
+    });
+
+    let mut inlined = HashSet::new();
+    walk(output, &mut |node: &ANode| {
+        if is_fusible(node) {
+            for c in node.get_children().unwrap_or(&[]).iter() {
+                if is_fusible(c) && parents[&c.get_id()] == 1 {
+                    inlined.insert(c.get_id());
+                }
+            }
+        }
+    });
+
+    rewrite(output, |node, _, rewritten| {
+        if inlined.contains(&node.get_id()) {
+            // Absorbed by its parent's chain
+            return Some(node.clone())
+        }
+        let is_chain = is_fusible(node) && node.get_children().unwrap_or(&[]).iter()
+            .any(|c| inlined.contains(&c.get_id()));
+        if !is_chain {
+            return None
+        }
+        let mut program = Program { inlined: &inlined, rewritten, inputs: Vec::new(), ops: Vec::new() };
+        program.emit(node, true);
+        Some(Fused::new(program.inputs, program.ops, node.shape()))
+    })
+}
+
+#[cfg(test)]
+mod optimize_tests {
+    use super::*;
+    use crate::{Graph,Variable,Pow,DType};
+
+    #[test]
+    fn test_fold_constants() {
+        let x = Variable::new(vec![1., 2.]);
+        let c = Constant::new(vec![3., 4.]);
+        let folded_part = (c.exp() + 1.).ln().pow(2.);
+        let out = (&x * &folded_part).sum();
+
+        let optimized = Graph::new().optimize(&out);
+        assert_eq!(optimized.value(), out.value());
+        assert_eq!(optimized.node_count(), 4);
+
+        let mut graph = Graph::new();
+        graph.backward(&optimized);
+        assert_eq!(graph.get_grad(&x).unwrap().as_slice(), &*folded_part.value());
+
+        // Graphs without constant subexpressions come back untouched
+        let out = (&x * &x).sum();
+        assert_eq!(Graph::new().optimize(&out).get_id(), out.get_id());
+    }
+
+    #[test]
+    fn test_fuse_elementwise() {
+        let x = Variable::new(vec![0.5, -1., 2.]);
+        let y = Variable::new(vec![1., 2., 3.]);
+        let h = (-&x).exp();
+        let out = ((&h + 1.) / (&y * &x).tanh()).sum() + h.sum();
+
+        let fused = fuse_elementwise(&out);
+        let mut fused_ops = 0;
+        fused.walk(&mut |n: &ANode| if n.op_name() == "Fused" { fused_ops += 1 });
+        // h is shared so it starts a chain of its own
+        assert_eq!(fused_ops, 2);
+        for (a, b) in fused.value().iter().zip(out.value().iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        let mut expected = Graph::new();
+        expected.backward(&out);
+        let mut graph = Graph::new();
+        graph.backward(&fused);
+        for v in [&x, &y] {
+            let (a, b) = (graph.get_grad(v).unwrap(), expected.get_grad(v).unwrap());
+            for (ai, bi) in a.iter().zip(b.iter()) {
+                assert!((ai - bi).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fused_grad_graph_and_jvp() {
+        let x = Variable::new(vec![0.5, -1., 2.]);
+        let y = Variable::new(vec![1., 2., 3.]);
+        let out = (((&x * &y).exp() - x.sin() / &y + (-&y).tanh() * x.cos()) * y.ln()).sum();
+        let fused = fuse_elementwise(&out);
+        let mut fused_ops = 0;
+        fused.walk(&mut |n: &ANode| if n.op_name() == "Fused" { fused_ops += 1 });
+        assert_eq!(fused_ops, 1);
+        let close = |a: &[DType], b: &[DType]| a.iter().zip(b.iter()).all(|(ai, bi)| (ai - bi).abs() < 1e-3);
+
+        let mut expected = Graph::new();
+        expected.backward_with_graph(&out).unwrap();
+        let mut graph = Graph::new();
+        graph.backward_with_graph(&fused).unwrap();
+        for v in [&x, &y] {
+            let (a, b) = (graph.get_grad_node(v).unwrap(), expected.get_grad_node(v).unwrap());
+            assert!(close(&a.value(), &b.value()));
+        }
+
+        let (tx, ty): (&[DType], &[DType]) = (&[1., 0.5, -1.], &[0., 2., 1.]);
+        let a = Graph::new().jvp(&fused, &[(&x, tx), (&y, ty)]);
+        let b = Graph::new().jvp(&out, &[(&x, tx), (&y, ty)]);
+        assert!(close(&a, &b));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = Variable::new(vec![3., 4.]);
+        let shared = (&x * &y).exp();
+        let out = (&shared + &x).sum() + shared.sum();
+
+        let z = Variable::new(vec![0., -1.]);
+        let swapped = out.substitute(&[(&x, &z)]);
+        let expected: DType = 2. * (1. + (-4 as DType).exp()) - 1.;
+        assert!((swapped.value()[0] - expected).abs() < 1e-5);
+
+        // The original graph keeps its values and y is shared, not copied
+        let expected: DType = 2. * ((3 as DType).exp() + (8 as DType).exp()) + 3.;
+        assert!((out.value()[0] - expected).abs() / expected < 1e-5);
+        let mut ids = HashSet::new();
+        swapped.walk(&mut |n: &ANode| { ids.insert(n.get_id()); });
+        assert!(ids.contains(&y.get_id()));
+        assert!(!ids.contains(&x.get_id()));
+
+        let mut graph = Graph::new();
+        graph.backward(&swapped);
+        assert!(graph.get_grad(&z).is_some());
+        assert!(graph.get_grad(&x).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_substitute_shape_mismatch() {
+        let x = Variable::new(vec![1., 2.]);
+        let out = (&x * 2.).sum();
+        out.substitute(&[(&x, &Variable::new(vec![1.]))]);
+    }
+}
@@ -0,0 +1,180 @@
+// Helpers for splitting work across the rayon pool when the `rayon` feature is
+// enabled, falling back to plain loops otherwise. Only the work inside a
+// single node is split: the fan-out to many children in `compute_grad` and
+// long elementwise kernels. Independent branches of a graph are still walked
+// one after another on the calling thread, since nodes are reference counted
+// with `Rc` and can't be handed to other threads.
+
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::DType;
+
+#[cfg(feature = "rayon")]
+#[inline]
+pub(crate) fn for_each_mut<T, F>(items: &mut [T], f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut T) + Sync + Send
+{
+    items.par_iter_mut().enumerate().for_each(|(i, x)| f(i, x));
+}
+
+#[cfg(not(feature = "rayon"))]
+#[inline]
+pub(crate) fn for_each_mut<T, F>(items: &mut [T], f: F)
+where
+    F: Fn(usize, &mut T)
+{
+    items.iter_mut().enumerate().for_each(|(i, x)| f(i, x));
+}
+
+// Elementwise kernels only fan out to the pool once a buffer is at least this
+// long; below it the cost of splitting outweighs the work.
+static THRESHOLD: AtomicUsize = AtomicUsize::new(1 << 16);
+
+pub fn set_parallel_threshold(len: usize) {
+    THRESHOLD.store(len, Ordering::Relaxed);
+}
+
+pub fn parallel_threshold() -> usize {
+    THRESHOLD.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "rayon")]
+#[inline]
+fn chunk_size(len: usize) -> Option<usize> {
+    if len < parallel_threshold().max(1) {
+        None
+    } else {
+        let threads = rayon::current_num_threads();
+        Some(len.div_ceil(threads).max(1))
+    }
+}
+
+// out[i] = f(x[i])
+#[inline]
+pub(crate) fn map<F>(x: &[DType], out: &mut [DType], f: F)
+where
+    F: Fn(DType) -> DType + Sync + Send
+{
+    #[cfg(feature = "rayon")]
+    if let Some(chunk) = chunk_size(out.len()) {
+        out.par_chunks_mut(chunk).zip(x.par_chunks(chunk)).for_each(|(o, x)| map_serial(x, o, &f));
+        return
+    }
+    map_serial(x, out, f)
+}
+
+#[inline]
+fn map_serial<F: Fn(DType) -> DType>(x: &[DType], out: &mut [DType], f: F) {
+    out.iter_mut().zip(x.iter()).for_each(|(oi, xi)| *oi = f(*xi));
+}
+
+// x[i] = f(x[i])
+#[inline]
+pub(crate) fn update<F>(x: &mut [DType], f: F)
+where
+    F: Fn(DType) -> DType + Sync + Send
+{
+    #[cfg(feature = "rayon")]
+    if let Some(chunk) = chunk_size(x.len()) {
+        x.par_chunks_mut(chunk).for_each(|x| update_serial(x, &f));
+        return
+    }
+    update_serial(x, f)
+}
+
+#[inline]
+fn update_serial<F: Fn(DType) -> DType>(x: &mut [DType], f: F) {
+    x.iter_mut().for_each(|xi| *xi = f(*xi));
+}
+
+// out[i] = f(l[i], r[i])
+#[inline]
+pub(crate) fn zip_map<F>(l: &[DType], r: &[DType], out: &mut [DType], f: F)
+where
+    F: Fn(DType, DType) -> DType + Sync + Send
+{
+    #[cfg(feature = "rayon")]
+    if let Some(chunk) = chunk_size(out.len()) {
+        out.par_chunks_mut(chunk).zip(l.par_chunks(chunk).zip(r.par_chunks(chunk)))
+            .for_each(|(o, (l, r))| zip_map_serial(l, r, o, &f));
+        return
+    }
+    zip_map_serial(l, r, out, f)
+}
+
+#[inline]
+fn zip_map_serial<F: Fn(DType, DType) -> DType>(l: &[DType], r: &[DType], out: &mut [DType], f: F) {
+    out.iter_mut().zip(l.iter().zip(r.iter())).for_each(|(oi, (li, ri))| *oi = f(*li, *ri));
+}
+
+// f(&mut l[i], r[i])
+#[inline]
+pub(crate) fn zip_update<F>(l: &mut [DType], r: &[DType], f: F)
+where
+    F: Fn(&mut DType, DType) + Sync + Send
+{
+    #[cfg(feature = "rayon")]
+    if let Some(chunk) = chunk_size(l.len()) {
+        l.par_chunks_mut(chunk).zip(r.par_chunks(chunk)).for_each(|(l, r)| zip_update_serial(l, r, &f));
+        return
+    }
+    zip_update_serial(l, r, f)
+}
+
+#[inline]
+fn zip_update_serial<F: Fn(&mut DType, DType)>(l: &mut [DType], r: &[DType], f: F) {
+    l.iter_mut().zip(r.iter()).for_each(|(li, ri)| f(li, *ri));
+}
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::*;
+
+    #[test]
+    fn test_chunked_matches_serial() {
+        let x: Vec<DType> = (0..1000).map(|i| i as DType).collect();
+        let mut serial = vec![0.; 1000];
+        zip_map(&x, &x, &mut serial, |a, b| a * b + 1.);
+
+        let old = parallel_threshold();
+        set_parallel_threshold(10);
+        let mut chunked = vec![0.; 1000];
+        zip_map(&x, &x, &mut chunked, |a, b| a * b + 1.);
+        map(&x, &mut chunked[..500], |a| a * a + 1.);
+        zip_update(&mut chunked[500..], &x[500..], |o, a| *o -= a * a);
+        set_parallel_threshold(old);
+
+        assert_eq!(&chunked[..500], &serial[..500]);
+        assert!(chunked[500..].iter().all(|v| *v == 1.));
+    }
+
+    // Gradients of a wide graph, with a vector long enough to split the
+    // elementwise kernels, computed in whatever pool is current
+    #[cfg(feature = "rayon")]
+    fn wide_grads() -> Vec<Vec<DType>> {
+        use crate::{Graph,Variable,BulkOps,concat};
+        let mut xs: Vec<_> = (0..2000).map(|i| Variable::new(vec![i as DType, 1., -2.])).collect();
+        let squares: Vec<_> = xs.iter().map(|x| x * x).collect();
+        let cat = concat(&xs);
+        let big = Variable::new((0..1 << 17).map(|i| (i % 7) as DType).collect());
+        let out = squares.sum_all().sum() + (&cat * &cat.cos()).sum() + (&big * &big.sin()).sum();
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        xs.push(big);
+        xs.iter().map(|x| graph.get_grad(x).unwrap().to_vec()).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_parallel_backward_matches_serial() {
+        let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let expected = serial.install(wide_grads);
+        let parallel = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        assert_eq!(parallel.install(wide_grads), expected);
+    }
+}
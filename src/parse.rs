@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{ANode,DType,Constant,Pow,MaximumOps,MinimumOps};
+use crate::ops::{check_broadcast,check_matmul};
+
+// Where and why an expression couldn't be turned into a graph
+#[derive(Clone,Debug,PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone,Debug,PartialEq)]
+enum Token {
+    Number(DType),
+    Name(String),
+    Op(char)
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            // Exponents, as in 1e-3
+            if i < chars.len() && (chars[i].1 == 'e' || chars[i].1 == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j].1 == '+' || chars[j].1 == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].1.is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].1.is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let end = chars.get(i).map(|(p, _)| *p).unwrap_or(src.len());
+            let text = &src[pos..end];
+            match text.parse::<DType>() {
+                Ok(v) => tokens.push((pos, Token::Number(v))),
+                Err(_) => return Err(ParseError { position: chars[start].0, message: format!("Invalid number '{}'", text) })
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|(p, _)| *p).unwrap_or(src.len());
+            tokens.push((pos, Token::Name(src[pos..end].to_string())));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push((pos, Token::Op(c)));
+            i += 1;
+        } else {
+            return Err(ParseError { position: pos, message: format!("Unexpected character '{}'", c) })
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent over the usual precedence: sums, then products, then
+// negation, then right associative powers.
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+    bindings: &'a HashMap<&'a str, ANode>
+}
+
+impl <'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|(p, _)| *p).unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(ParseError { position: self.position(), message })
+    }
+
+    fn expect(&mut self, op: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(Token::Op(c)) if *c == op => {
+                self.pos += 1;
+                Ok(())
+            },
+            Some(t) => self.error(format!("Expected '{}' but found {:?}", op, t)),
+            None => self.error(format!("Expected '{}' but the expression ended", op))
+        }
+    }
+
+    fn binary(&self, at: usize, op: char, left: ANode, right: ANode) -> Result<ANode, ParseError> {
+        let res = match op {
+            '+' => left.try_add(right),
+            '-' => left.try_sub(right),
+            '*' => left.try_mul(right),
+            '/' => left.try_div(right),
+            _ => check_broadcast("Power", &left, &right).map(|_| left.pow(right))
+        };
+        res.map_err(|e| ParseError { position: at, message: e.to_string() })
+    }
+
+    fn expr(&mut self) -> Result<ANode, ParseError> {
+        let mut left = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            let at = self.position();
+            self.pos += 1;
+            let right = self.term()?;
+            left = self.binary(at, op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<ANode, ParseError> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            let at = self.position();
+            self.pos += 1;
+            let right = self.unary()?;
+            left = self.binary(at, op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<ANode, ParseError> {
+        if let Some(Token::Op('-')) = self.peek() {
+            self.pos += 1;
+            return Ok(-self.unary()?)
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<ANode, ParseError> {
+        let base = self.atom()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            let at = self.position();
+            self.pos += 1;
+            let exp = self.unary()?;
+            return self.binary(at, '^', base, exp)
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<ANode, ParseError> {
+        let at = self.position();
+        match self.peek().cloned() {
+            Some(Token::Number(v)) => {
+                self.pos += 1;
+                Ok(Constant::scalar(v))
+            },
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                if let Some(Token::Op('(')) = self.peek() {
+                    self.pos += 1;
+                    let args = self.args()?;
+                    return call(&name, args).map_err(|message| ParseError { position: at, message })
+                }
+                match self.bindings.get(name.as_str()) {
+                    Some(node) => Ok(node.clone()),
+                    None => Err(ParseError { position: at, message: format!("Unbound variable '{}'", name) })
+                }
+            },
+            Some(Token::Op('(')) => {
+                self.pos += 1;
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            },
+            Some(t) => self.error(format!("Unexpected {:?}", t)),
+            None => self.error("Unexpected end of expression".to_string())
+        }
+    }
+
+    // Arguments after an opening parenthesis, through the closing one
+    fn args(&mut self) -> Result<Vec<ANode>, ParseError> {
+        let mut args = Vec::new();
+        if let Some(Token::Op(')')) = self.peek() {
+            self.pos += 1;
+            return Ok(args)
+        }
+        loop {
+            args.push(self.expr()?);
+            match self.peek() {
+                Some(Token::Op(',')) => self.pos += 1,
+                _ => break
+            }
+        }
+        self.expect(')')?;
+        Ok(args)
+    }
+}
+
+// Checks both arguments of a function broadcast, handing back the first
+fn broadcast<'n>(op: &'static str, x: &'n ANode, y: &ANode) -> Result<&'n ANode, String> {
+    check_broadcast(op, x, y).map(|_| x).map_err(|e| e.to_string())
+}
+
+fn call(name: &str, args: Vec<ANode>) -> Result<ANode, String> {
+    let res = match (name, args.as_slice()) {
+        ("sin", [x]) => x.sin(),
+        ("cos", [x]) => x.cos(),
+        ("tanh", [x]) => x.tanh(),
+        ("exp", [x]) => x.exp(),
+        ("ln", [x]) | ("log", [x]) => x.ln(),
+        ("sqrt", [x]) => x.pow_scalar(0.5),
+        ("square", [x]) => x.square(),
+        ("cube", [x]) => x.cube(),
+        ("relu", [x]) => x.relu(),
+        ("softmax", [x]) => x.softmax(),
+        ("sum", [x]) => x.sum(),
+        ("pow", [x, y]) => broadcast("Power", x, y)?.pow(y),
+        ("max", [x, y]) => broadcast("Maximum", x, y)?.maximum(y),
+        ("min", [x, y]) => broadcast("Minimum", x, y)?.minimum(y),
+        ("dot", [x, y]) => broadcast("Multiply", x, y)?.dot(y),
+        ("matmul", [x, y]) => {
+            check_matmul(x, y)?;
+            x.matmul(y)
+        },
+        ("sin" | "cos" | "tanh" | "exp" | "ln" | "log" | "sqrt" | "square" | "cube" |
+         "relu" | "softmax" | "sum", _) => {
+            return Err(format!("{} takes 1 argument but got {}", name, args.len()))
+        },
+        ("pow" | "max" | "min" | "dot" | "matmul", _) => {
+            return Err(format!("{} takes 2 arguments but got {}", name, args.len()))
+        },
+        _ => return Err(format!("Unknown function '{}'", name))
+    };
+    Ok(res)
+}
+
+// Builds the graph for an expression such as "sin(x) * w + b", looking up
+// names in `bindings`. Supports + - * / ^, parentheses, numbers and the
+// functions sin, cos, tanh, exp, ln (or log), sqrt, square, cube, relu,
+// softmax, sum, pow, max, min, dot and matmul.
+pub fn parse_expr(src: &str, bindings: &HashMap<&str, ANode>) -> Result<ANode, ParseError> {
+    let mut parser = Parser { tokens: tokenize(src)?, pos: 0, end: src.len(), bindings };
+    let node = parser.expr()?;
+    match parser.peek() {
+        None => Ok(node),
+        Some(t) => parser.error(format!("Unexpected {:?}", t))
+    }
+}
+
+#[cfg(test)]
+mod parse_tests {
+    use super::*;
+    use crate::{Graph,Variable};
+
+    fn close(a: &[DType], b: &[DType]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        let x = Variable::new(vec![0.5, 1.]);
+        let w = Variable::new(vec![2., 3.]);
+        let b = Variable::scalar(1.);
+        let bindings = HashMap::from([("x", x.clone()), ("w", w.clone()), ("b", b.clone())]);
+
+        let out = parse_expr("sin(x)*w + b", &bindings).unwrap();
+        let expected = x.sin() * &w + &b;
+        assert!(close(&out.value(), &expected.value()));
+
+        // Gradients flow back to the bound variables
+        let mut graph = Graph::new();
+        graph.backward(&out.sum());
+        assert!(close(graph.get_grad(&w).unwrap(), &x.sin().value()));
+        assert_eq!(graph.get_grad(&b).unwrap(), &vec![2.]);
+
+        // Precedence, associativity and negation
+        let bindings = HashMap::new();
+        let eval = |s: &str| parse_expr(s, &bindings).unwrap().value()[0];
+        assert_eq!(eval("1 + 2 * 3"), 7.);
+        assert_eq!(eval("(1 + 2) * 3"), 9.);
+        assert_eq!(eval("8 - 4 - 2"), 2.);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.);
+        assert_eq!(eval("-2 ^ 2"), -4.);
+        assert_eq!(eval("2 ^ -1"), 0.5);
+        assert_eq!(eval("max(1.5e1, pow(2, 3)) / 3"), 5.);
+        assert_eq!(eval("sum(square(2) + 1)"), 5.);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let bindings = HashMap::from([("x", Variable::new(vec![1., 2.])), ("y", Variable::new(vec![1., 2., 3.]))]);
+        let err = |s: &str| parse_expr(s, &bindings).err().unwrap();
+
+        assert_eq!(err("x + z").position, 4);
+        assert_eq!(err("x + z").message, "Unbound variable 'z'");
+        assert_eq!(err("sin(x").position, 5);
+        assert_eq!(err("foo(x)").message, "Unknown function 'foo'");
+        assert_eq!(err("sin(x, x)").message, "sin takes 1 argument but got 2");
+        assert_eq!(err("x $ 2").position, 2);
+        assert_eq!(err("x 2").position, 2);
+        assert_eq!(err("").position, 0);
+
+        // Shapes which don't broadcast are reported at the operator
+        let e = err("x * y");
+        assert_eq!(e.position, 2);
+        assert!(e.message.starts_with("Multiply cannot broadcast"));
+
+        // As are arguments of functions, at the call
+        let e = err("1 + max(x, y)");
+        assert_eq!(e.position, 4);
+        assert!(e.message.starts_with("Maximum cannot broadcast"));
+        assert!(err("min(x, y)").message.starts_with("Minimum cannot broadcast"));
+        assert!(err("pow(x, y)").message.starts_with("Power cannot broadcast"));
+        assert!(err("dot(x, y)").message.starts_with("Multiply cannot broadcast"));
+        let e = err("matmul(x, y)");
+        assert_eq!(e.position, 0);
+        assert_eq!(e.message, "Cannot multiply matrices of shapes [2] and [3]");
+    }
+}
@@ -0,0 +1,160 @@
+use hashbrown::HashSet;
+
+use crate::{DType,ANode};
+
+// Non-leaf nodes under `output`, children before parents
+pub(crate) fn evaluation_order(output: &ANode) -> Vec<ANode> {
+    let mut steps = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![(output.clone(), false)];
+    while let Some((node, expanded)) = stack.pop() {
+        if expanded {
+            steps.push(node);
+        } else if !node.is_leaf() && seen.insert(node.get_id()) {
+            let children = node.get_children().map(|c| c.to_vec()).unwrap_or_default();
+            stack.push((node, true));
+            stack.extend(children.into_iter().rev().map(|c| (c, false)));
+        } else if let Some(sources) = node.value_sources() {
+            // Leaves sharing another node's value have nothing to recompute
+            // themselves, but their sources do
+            if seen.insert(node.get_id()) {
+                stack.extend(sources.iter().rev().map(|c| (c.clone(), false)));
+            }
+        }
+    }
+    steps
+}
+
+// A graph flattened into evaluation order. Re-running it recomputes every
+// node in place from the current leaf values, so a training loop can update
+// its inputs and parameters without rebuilding the nodes each step. Node
+// values are recycled through the memory pool, so steady state runs don't
+// allocate new output buffers, though some ops still use scratch space.
+pub struct Plan {
+    output: ANode,
+    steps: Vec<ANode>
+}
+
+impl Plan {
+    pub(crate) fn new(output: &ANode) -> Self {
+        Plan { output: output.clone(), steps: evaluation_order(output) }
+    }
+
+    #[inline]
+    pub fn output(&self) -> &ANode {
+        &self.output
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn set(&self, leaf: &ANode, value: &[DType]) {
+        leaf.set_value(value);
+    }
+
+    // Panics if a value under the plan is still borrowed, since recomputing
+    // would replace it underneath the borrow
+    pub fn forward(&self) -> &ANode {
+        self.steps.iter().for_each(|n| n.recompute());
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod plan_tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_rerun() {
+        let x = Variable::new(vec![1., 2.]);
+        let w = Variable::with_shape(vec![1., 0., 0., 1.], &[2, 2]);
+        let h = w.matmul(&x);
+        let out = ((&h * &h) + h.exp()).sum();
+
+        let graph = Graph::new();
+        let plan = graph.compile(&out);
+        assert_eq!(plan.len(), 5);
+
+        plan.set(&x, &[3., 4.]);
+        plan.set(&w, &[0., 1., 1., 0.]);
+        plan.forward();
+
+        let h2 = Constant::new(vec![4., 3.]);
+        let expected = ((&h2 * &h2) + h2.exp()).sum();
+        assert_eq!(h.value(), &[4., 3.]);
+        assert_eq!(out.value(), expected.value());
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        let (e4, e3) = ((4 as DType).exp(), (3 as DType).exp());
+        let grad = graph.get_grad(&x).unwrap();
+        assert!((grad[0] - (6. + e3)).abs() < 1e-4);
+        assert!((grad[1] - (8. + e4)).abs() < 1e-4);
+    }
+
+    #[test]
+    #[should_panic(expected = "while it is borrowed")]
+    fn test_rerun_while_borrowed() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = (&x * 2.).exp();
+        let held = y.value();
+        x.set_value(&[3., 4.]);
+        Graph::new().forward(&y);
+        assert_eq!(held.len(), 2);
+    }
+
+    #[test]
+    fn test_rerun_after_borrow() {
+        let x = Variable::new(vec![1., 2.]);
+        let y = &x * 2.;
+        let before = y.value().to_vec();
+        x.set_value(&[3., 4.]);
+        Graph::new().forward(&y);
+        assert_eq!(before, vec![2., 4.]);
+        assert_eq!(y.value(), &[6., 8.]);
+    }
+
+    #[test]
+    fn test_rerun_closures() {
+        let x = Variable::new(vec![1., 2.]);
+        let table = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[3, 2]);
+        let sq = x.map(|v| v * v);
+        let rounded = x.straight_through(|v| v.round());
+        let custom = (&x * 3.).with_custom_grad(std::slice::from_ref(&x), |_, _, g, out| out[0].copy_from_slice(g));
+        let rows = table.embedding(&[2, 0]).sum();
+        let out = (sq + rounded + custom + rows).sum();
+
+        let plan = Graph::new().compile(&out);
+        plan.set(&x, &[0.6, 3.]);
+        plan.set(&table, &[0., 0., 0., 0., 1., 1.]);
+        plan.forward();
+        // 0.36 + 1 + 1.8 and 9 + 3 + 9, each plus 2 from the rows
+        assert!((out.value()[0] - (3.16 + 21. + 4.)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rerun_detached() {
+        // Detached values follow their source, without passing gradients
+        let x = Variable::new(vec![1., 2.]);
+        let out = ((&x * 2.).detach() + &x).sum();
+        assert_eq!(out.value(), &[9.]);
+        x.set_value(&[10., 20.]);
+        Graph::new().forward(&out);
+        assert_eq!(out.value(), &[90.]);
+
+        x.set_value(&[1., 1.]);
+        Graph::new().compile(&out).forward();
+        assert_eq!(out.value(), &[6.]);
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        assert_eq!(graph.get_grad(&x).unwrap(), &[1., 1.]);
+    }
+}
@@ -1,12 +1,13 @@
 use hashbrown::HashMap;
 use std::convert::{AsRef, AsMut};
 use std::cell::RefCell;
-use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
 use std::ops::{Drop,Deref,DerefMut};
 
 use crate::DType;
 
 static USE_POOL: AtomicBool = AtomicBool::new(true);
+static MAX_PER_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
 thread_local! {
     static POOL: RefCell<MemoryPool> = {
         let m = MemoryPool::new();
@@ -15,27 +16,42 @@ thread_local! {
 }
 
 struct MemoryPool {
-    data: HashMap<usize, Vec<Vec<DType>>>
+    data: HashMap<usize, Vec<Vec<DType>>>,
+    hits: usize,
+    misses: usize
 }
 
 impl MemoryPool {
     fn new() -> Self {
-        MemoryPool { data: HashMap::new() }
+        MemoryPool { data: HashMap::new(), hits: 0, misses: 0 }
     }
 
     fn get(&mut self, size: usize) -> MPVec {
         if let Some(vs) = self.data.get_mut(&size) {
             if let Some(mut v) = vs.pop() {
-                v.fill(0f32);
+                self.hits += 1;
+                v.fill(0.);
                 return MPVec(v)
             }
         }
-        MPVec(vec![0f32; size])
+        self.misses += 1;
+        MPVec(vec![0.; size])
     }
 
     fn ret(&mut self, v: Vec<DType>) {
-        let e = self.data.entry(v.len()).or_insert_with(|| Vec::new());
-        e.push(v);
+        if v.is_empty() {
+            return
+        }
+        let e = self.data.entry(v.len()).or_default();
+        if e.len() < MAX_PER_SIZE.load(Ordering::Relaxed) {
+            e.push(v);
+        }
+    }
+
+    fn stats(&self) -> PoolStats {
+        let buffers = self.data.values().map(|vs| vs.len()).sum();
+        let elements = self.data.iter().map(|(size, vs)| size * vs.len()).sum();
+        PoolStats { hits: self.hits, misses: self.misses, buffers, elements }
     }
 
     fn clear(&mut self) {
@@ -64,6 +80,25 @@ pub fn allocate_vec(size: usize) -> MPVec {
     }
 }
 
+// Caps how many free buffers of any one length are kept around; anything
+// returned beyond that is dropped.
+pub fn set_pool_limit(max_per_size: usize) {
+    MAX_PER_SIZE.store(max_per_size, Ordering::SeqCst);
+}
+
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct PoolStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub buffers: usize,
+    pub elements: usize
+}
+
+// Stats for the calling thread's pool
+pub fn pool_stats() -> PoolStats {
+    POOL.with(|p| p.borrow().stats())
+}
+
 pub fn clear_pool() {
     POOL.with(|p| {
         let mut pool = p.borrow_mut();
@@ -83,6 +118,13 @@ fn return_vec(v: Vec<DType>) {
 #[derive(Debug,Clone)]
 pub struct MPVec(Vec<DType>);
 
+impl MPVec {
+    // Takes the buffer out of the pool's hands for good
+    pub fn into_vec(mut self) -> Vec<DType> {
+        std::mem::take(&mut self.0)
+    }
+}
+
 impl Drop for MPVec {
     fn drop(&mut self) {
         let mut m = Vec::with_capacity(0);
@@ -116,3 +158,23 @@ impl DerefMut for MPVec {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod pool_tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let start = pool_stats();
+        let mut v = allocate_vec(37);
+        v[0] = 3.;
+        drop(v);
+        assert_eq!(pool_stats().buffers, start.buffers + 1);
+
+        let v = allocate_vec(37);
+        assert_eq!(v[0], 0.);
+        let stats = pool_stats();
+        assert_eq!(stats.hits, start.hits + 1);
+        assert_eq!(stats.misses, start.misses + 1);
+    }
+}
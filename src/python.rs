@@ -0,0 +1,186 @@
+// Python bindings. Build the extension module with
+//
+//     cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib
+//
+// and copy the library to simple_grad.so (simple_grad.pyd on Windows) somewhere
+// on the python path. Arrays come in and go out as numpy arrays of float32, or
+// float64 with the f64 feature. Nodes hold Rcs, so they stay on the thread
+// which made them.
+use numpy::{IntoPyArray,PyArrayDyn,PyArrayMethods,PyReadonlyArrayDyn,PyUntypedArrayMethods};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::{ANode,Constant,DType,Graph,Pow,ShapeError,Variable};
+
+#[pyclass(name = "Node", module = "simple_grad", unsendable)]
+#[derive(Clone)]
+pub struct PyNode(ANode);
+
+#[pyclass(name = "Graph", module = "simple_grad", unsendable)]
+pub struct PyGraph(Graph);
+
+// Right hand sides of the operators: another node, an array or a number, the
+// latter two as constants
+#[derive(FromPyObject)]
+enum Operand<'py> {
+    Node(PyNode),
+    Array(PyReadonlyArrayDyn<'py, DType>),
+    Scalar(DType)
+}
+
+impl From<Operand<'_>> for ANode {
+    fn from(o: Operand<'_>) -> ANode {
+        match o {
+            Operand::Node(n)   => n.0,
+            Operand::Array(a)  => Constant::with_shape(array_values(&a), &array_dims(&a)),
+            Operand::Scalar(s) => Constant::scalar(s)
+        }
+    }
+}
+
+// Zero dimensional arrays become SimpleGrad's [1] scalars
+fn array_dims(a: &PyReadonlyArrayDyn<DType>) -> Vec<usize> {
+    if a.ndim() == 0 { vec![1] } else { a.shape().to_vec() }
+}
+
+fn array_values(a: &PyReadonlyArrayDyn<DType>) -> Vec<DType> {
+    a.as_array().iter().copied().collect()
+}
+
+fn to_numpy<'py>(py: Python<'py>, values: Vec<DType>, dims: &[usize]) -> PyResult<Bound<'py, PyArrayDyn<DType>>> {
+    values.into_pyarray(py).reshape(dims)
+}
+
+fn shape_error(e: ShapeError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+#[pymethods]
+impl PyNode {
+    #[getter]
+    fn shape(&self) -> Vec<usize> {
+        self.0.shape().dims().to_vec()
+    }
+
+    fn numpy<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArrayDyn<DType>>> {
+        to_numpy(py, self.0.value().to_vec(), self.0.shape().dims())
+    }
+
+    fn __add__(&self, other: Operand) -> PyResult<PyNode> {
+        self.0.try_add(ANode::from(other)).map(PyNode).map_err(shape_error)
+    }
+
+    fn __radd__(&self, other: Operand) -> PyResult<PyNode> {
+        ANode::from(other).try_add(&self.0).map(PyNode).map_err(shape_error)
+    }
+
+    fn __sub__(&self, other: Operand) -> PyResult<PyNode> {
+        self.0.try_sub(ANode::from(other)).map(PyNode).map_err(shape_error)
+    }
+
+    fn __rsub__(&self, other: Operand) -> PyResult<PyNode> {
+        ANode::from(other).try_sub(&self.0).map(PyNode).map_err(shape_error)
+    }
+
+    fn __mul__(&self, other: Operand) -> PyResult<PyNode> {
+        self.0.try_mul(ANode::from(other)).map(PyNode).map_err(shape_error)
+    }
+
+    fn __rmul__(&self, other: Operand) -> PyResult<PyNode> {
+        ANode::from(other).try_mul(&self.0).map(PyNode).map_err(shape_error)
+    }
+
+    fn __truediv__(&self, other: Operand) -> PyResult<PyNode> {
+        self.0.try_div(ANode::from(other)).map(PyNode).map_err(shape_error)
+    }
+
+    fn __rtruediv__(&self, other: Operand) -> PyResult<PyNode> {
+        ANode::from(other).try_div(&self.0).map(PyNode).map_err(shape_error)
+    }
+
+    fn __pow__(&self, other: Operand, _modulo: Option<PyObject>) -> PyNode {
+        PyNode((&self.0).pow(ANode::from(other)))
+    }
+
+    fn __neg__(&self) -> PyNode {
+        PyNode(-&self.0)
+    }
+
+    fn __matmul__(&self, other: Operand) -> PyNode {
+        PyNode(self.0.matmul(ANode::from(other)))
+    }
+
+    fn dot(&self, other: Operand) -> PyNode {
+        PyNode(self.0.dot(ANode::from(other)))
+    }
+
+    #[pyo3(signature = (axis=None))]
+    fn sum(&self, axis: Option<usize>) -> PyNode {
+        PyNode(match axis {
+            Some(axis) => self.0.sum_axis(axis),
+            None       => self.0.sum()
+        })
+    }
+
+    fn reshape(&self, dims: Vec<usize>) -> PyNode {
+        PyNode(self.0.reshape(&dims))
+    }
+
+    fn transpose(&self) -> PyNode {
+        PyNode(self.0.transpose())
+    }
+
+    fn ln(&self) -> PyNode { PyNode(self.0.ln()) }
+    fn exp(&self) -> PyNode { PyNode(self.0.exp()) }
+    fn sin(&self) -> PyNode { PyNode(self.0.sin()) }
+    fn cos(&self) -> PyNode { PyNode(self.0.cos()) }
+    fn tanh(&self) -> PyNode { PyNode(self.0.tanh()) }
+    fn relu(&self) -> PyNode { PyNode(self.0.relu()) }
+    fn softmax(&self) -> PyNode { PyNode(self.0.softmax()) }
+
+    fn __repr__(&self) -> String {
+        format!("Node(shape={:?})", self.0.shape().dims())
+    }
+}
+
+#[pymethods]
+impl PyGraph {
+    #[new]
+    fn new() -> Self {
+        PyGraph(Graph::new())
+    }
+
+    fn backward(&mut self, end_node: &PyNode) {
+        self.0.backward(&end_node.0);
+    }
+
+    fn zero_grads(&mut self) {
+        self.0.zero_grads();
+    }
+
+    // Gradients take the shape of their node; None if it didn't get one
+    fn grad<'py>(&self, py: Python<'py>, node: &PyNode) -> PyResult<Option<Bound<'py, PyArrayDyn<DType>>>> {
+        self.0.get_grad(&node.0)
+            .map(|g| to_numpy(py, g.clone(), node.0.shape().dims()))
+            .transpose()
+    }
+}
+
+#[pyfunction]
+fn variable(a: PyReadonlyArrayDyn<DType>) -> PyNode {
+    PyNode(Variable::with_shape(array_values(&a), &array_dims(&a)))
+}
+
+#[pyfunction]
+fn constant(a: PyReadonlyArrayDyn<DType>) -> PyNode {
+    PyNode(Constant::with_shape(array_values(&a), &array_dims(&a)))
+}
+
+#[pymodule]
+fn simple_grad(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyNode>()?;
+    m.add_class::<PyGraph>()?;
+    m.add_function(wrap_pyfunction!(variable, m)?)?;
+    m.add_function(wrap_pyfunction!(constant, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// SplitMix64: small, fast and good enough for masks and initialization.
+// Seeded explicitly so runs are reproducible.
+#[derive(Clone,Debug)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub(crate) fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Standard normal, by Box-Muller
+    pub(crate) fn normal(&mut self) -> f64 {
+        let u = 1. - self.uniform();
+        let v = self.uniform();
+        (-2. * u.ln()).sqrt() * (2. * std::f64::consts::PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod rand_tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform() {
+        let mut rng = Rng::new(42);
+        let xs: Vec<f64> = (0..1000).map(|_| rng.uniform()).collect();
+        assert!(xs.iter().all(|x| (0. ..1.).contains(x)));
+        let mean = xs.iter().sum::<f64>() / 1000.;
+        assert!((mean - 0.5).abs() < 0.05);
+
+        let mut again = Rng::new(42);
+        assert_eq!(again.uniform(), xs[0]);
+    }
+
+    #[test]
+    fn test_normal() {
+        let mut rng = Rng::new(7);
+        let xs: Vec<f64> = (0..10000).map(|_| rng.normal()).collect();
+        let mean = xs.iter().sum::<f64>() / 10000.;
+        let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 10000.;
+        assert!(mean.abs() < 0.05);
+        assert!((var - 1.).abs() < 0.05);
+    }
+}
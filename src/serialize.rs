@@ -0,0 +1,421 @@
+use std::fmt;
+
+use hashbrown::{HashMap,HashSet};
+use serde::{Serialize,Deserialize,Serializer,Deserializer};
+use serde::{de,ser};
+
+use crate::{ANode,NodeIdx,DType,Graph,Variable,Constant,Shape};
+use crate::ops::*;
+use crate::shape::MAX_DIMS;
+use crate::introspect::walk;
+
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+struct SavedNode {
+    op: String,
+    shape: Vec<usize>,
+    children: Vec<usize>,
+    // Only leaves keep their values; everything else is recomputed on load
+    value: Option<Vec<DType>>,
+    requires_grad: bool,
+    sizes: Vec<usize>,
+    scalars: Vec<DType>,
+    text: Option<String>
+}
+
+// A graph flattened into a list of nodes, children before parents, with the
+// output last. Ops holding closures, fused ops and embeddings can't be saved.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct SavedGraph {
+    nodes: Vec<SavedNode>,
+    grads: Vec<Option<Vec<DType>>>
+}
+
+// A graph rebuilt from a SavedGraph. Variables are listed in the order they
+// were saved in.
+pub struct LoadedGraph {
+    pub output: ANode,
+    pub variables: Vec<ANode>,
+    pub graph: Graph
+}
+
+// Why a graph couldn't be saved or loaded, and the position of the node at
+// fault in the saved list
+#[derive(Clone,Debug,PartialEq)]
+pub struct SavedGraphError {
+    pub node: usize,
+    pub message: String
+}
+
+impl fmt::Display for SavedGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at node {}", self.message, self.node)
+    }
+}
+
+impl std::error::Error for SavedGraphError {}
+
+impl SavedGraph {
+    pub fn new(output: &ANode) -> Self {
+        match SavedGraph::try_new(output) {
+            Ok(saved) => saved,
+            Err(e) => panic!("{}!", e.message)
+        }
+    }
+
+    // Fails, rather than panicking, on ops which can't be saved
+    pub fn try_new(output: &ANode) -> Result<Self, SavedGraphError> {
+        let mut index: HashMap<NodeIdx, usize> = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut error = None;
+        walk(output, &mut |node: &ANode| {
+            let children = node.get_children().unwrap_or(&[]);
+            let op = match (node.op_name(), children.is_empty()) {
+                ("Variable", _) => "Variable",
+                (_, true) => "Constant",
+                (op, false) if is_supported(op) => op,
+                (op, false) => {
+                    if error.is_none() {
+                        let message = format!("{} nodes cannot be saved", op);
+                        error = Some(SavedGraphError { node: nodes.len(), message });
+                    }
+                    op
+                }
+            };
+            let args = node.op_args();
+            nodes.push(SavedNode {
+                op: op.to_string(),
+                shape: node.shape().dims().to_vec(),
+                children: children.iter().map(|c| index[&c.get_id()]).collect(),
+                value: if children.is_empty() { Some(node.value().to_vec()) } else { None },
+                requires_grad: node.requires_grad(),
+                sizes: args.sizes,
+                scalars: args.scalars,
+                text: args.text
+            });
+            index.insert(node.get_id(), nodes.len() - 1);
+        });
+        if let Some(e) = error {
+            return Err(e)
+        }
+        let grads = vec![None; nodes.len()];
+        Ok(SavedGraph { nodes, grads })
+    }
+
+    // Also keeps the gradients `graph` holds for the nodes saved
+    pub fn with_grads(output: &ANode, graph: &Graph) -> Self {
+        let mut saved = SavedGraph::new(output);
+        let mut i = 0;
+        walk(output, &mut |node: &ANode| {
+            saved.grads[i] = graph.get_grad(node).cloned();
+            i += 1;
+        });
+        saved
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn load(&self) -> LoadedGraph {
+        match self.try_load() {
+            Ok(loaded) => loaded,
+            Err(e) => panic!("{}!", e)
+        }
+    }
+
+    // Checks every node against the ones before it as it goes, so a
+    // corrupted or hand edited graph fails rather than panicking
+    pub fn try_load(&self) -> Result<LoadedGraph, SavedGraphError> {
+        if self.grads.len() != self.nodes.len() {
+            let message = format!("Saved graph has {} gradients for {} nodes", self.grads.len(), self.nodes.len());
+            return Err(SavedGraphError { node: 0, message })
+        }
+        let mut built: Vec<ANode> = Vec::with_capacity(self.nodes.len());
+        let mut variables = Vec::new();
+        let mut graph = Graph::new();
+        for (i, (saved, grad)) in self.nodes.iter().zip(self.grads.iter()).enumerate() {
+            let err = |message: String| SavedGraphError { node: i, message };
+            let mut children = Vec::with_capacity(saved.children.len());
+            for c in saved.children.iter() {
+                match built.get(*c) {
+                    Some(n) => children.push(n.clone()),
+                    None => return Err(err(format!("Child {} is not an earlier node", c)))
+                }
+            }
+            let node = build(saved, children).map_err(err)?;
+            if node.shape().dims() != saved.shape.as_slice() {
+                return Err(err(format!("{} rebuilt with shape {:?} rather than {:?}",
+                    saved.op, node.shape(), saved.shape)))
+            }
+            if saved.op == "Variable" {
+                variables.push(node.clone());
+            }
+            if let Some(g) = grad {
+                if g.len() != node.value().len() {
+                    return Err(err(format!("Gradient of length {} does not match shape {:?}", g.len(), saved.shape)))
+                }
+                graph.accumulate_grad(&node, g);
+            }
+            built.push(node);
+        }
+        let output = match built.pop() {
+            Some(n) => n,
+            None => return Err(SavedGraphError { node: 0, message: "Saved graph has no nodes".to_string() })
+        };
+        Ok(LoadedGraph { output, variables, graph })
+    }
+}
+
+fn is_supported(op: &str) -> bool {
+    matches!(op,
+        "AddN" | "Subtract" | "Multiply" | "Divide" | "Power" | "Maximum" | "Minimum" |
+        "MatMul" | "BatchMatMul" | "Outer" | "Conv2d" |
+        "SumVec" | "Cos" | "Sin" | "Tanh" | "Softmax" | "Ln" | "Exp" | "Negate" | "Transpose" |
+        "Diag" | "Flip" | "SumAxis" | "Permute" | "MaxPool2d" | "AvgPool2d" | "Slice" |
+        "Repeat" | "BroadcastTo" | "SumTo" | "Reshape" | "GradReverse" | "PowScalar" | "NanToNum" |
+        "BulkSum" | "Concat" | "Einsum")
+}
+
+// Checks the arguments and shapes each op's constructor would otherwise panic on
+fn check(saved: &SavedNode, c: &[ANode]) -> Result<(), String> {
+    let op = saved.op.as_str();
+    let (children, sizes, scalars) = match op {
+        "AddN" | "Subtract" | "Multiply" | "Divide" | "Power" | "Maximum" | "Minimum" |
+        "MatMul" | "BatchMatMul" | "Outer" => (2, 0, 0),
+        "Conv2d" => (2, 1, 0),
+        "SumAxis" | "Repeat" => (1, 1, 0),
+        "MaxPool2d" | "AvgPool2d" | "Slice" => (1, 2, 0),
+        "Permute" => (1, saved.sizes.len(), 0),
+        "GradReverse" | "PowScalar" => (1, 0, 1),
+        "NanToNum" => (1, 0, 3),
+        "BulkSum" | "Concat" | "Einsum" => (c.len(), 0, 0),
+        _ if is_supported(op) => (1, 0, 0),
+        _ => return Err(format!("Unknown op {} in saved graph", op))
+    };
+    if c.len() != children {
+        return Err(format!("{} expects {} children, got {}", op, children, c.len()))
+    }
+    if saved.sizes.len() != sizes || saved.scalars.len() != scalars {
+        return Err(format!("{} expects {} sizes and {} scalars, got {} and {}",
+            op, sizes, scalars, saved.sizes.len(), saved.scalars.len()))
+    }
+
+    let shapes: Vec<Shape> = c.iter().map(|n| n.shape()).collect();
+    let target = Shape::new(&saved.shape);
+    match op {
+        "AddN" | "Subtract" | "Multiply" | "Divide" | "Power" | "Maximum" | "Minimum" => {
+            shapes[0].broadcast(&shapes[1])
+                .map(|_| ())
+                .ok_or_else(|| format!("{} cannot broadcast {:?} with {:?}", op, shapes[0], shapes[1]))
+        },
+        "MatMul" => check_matmul(&c[0], &c[1]).map(|_| ()),
+        "BatchMatMul" => match (shapes[0].dims(), shapes[1].dims()) {
+            ([b, _, k], [b2, k2, _]) if b == b2 && k == k2 => Ok(()),
+            _ => Err(format!("Cannot batch multiply shapes {:?} and {:?}", shapes[0], shapes[1]))
+        },
+        "Conv2d" => check_conv2d(&shapes[0], &shapes[1], saved.sizes[0]),
+        "MaxPool2d" | "AvgPool2d" => check_pool2d(&shapes[0], saved.sizes[0], saved.sizes[1]),
+        "Transpose" if shapes[0].ndim() != 2 => Err(format!("Transpose expects a 2-D node, got {:?}", shapes[0])),
+        "Diag" if !matches!(shapes[0].ndim(), 1 | 2) => {
+            Err(format!("Diag expects a 1-D or 2-D node, got {:?}", shapes[0]))
+        },
+        "SumAxis" if saved.sizes[0] >= shapes[0].ndim() => {
+            Err(format!("Axis {} out of range for shape {:?}", saved.sizes[0], shapes[0]))
+        },
+        "Permute" => {
+            let mut seen = HashSet::new();
+            if saved.sizes.len() == shapes[0].ndim() && saved.sizes.iter().all(|a| *a < shapes[0].ndim() && seen.insert(*a)) {
+                Ok(())
+            } else {
+                Err(format!("Axes {:?} are not a permutation of shape {:?}", saved.sizes, shapes[0]))
+            }
+        },
+        "Slice" => match saved.sizes[0].checked_add(saved.sizes[1]) {
+            Some(end) if end <= shapes[0].size() => Ok(()),
+            _ => Err(format!("Slice of {} from {} is out of range for shape {:?}", saved.sizes[1], saved.sizes[0], shapes[0]))
+        },
+        "BroadcastTo" if shapes[0].broadcast(&target) != Some(target) => {
+            Err(format!("Cannot broadcast shape {:?} into {:?}", shapes[0], target))
+        },
+        "SumTo" if target.broadcast(&shapes[0]) != Some(shapes[0]) => {
+            Err(format!("Cannot sum shape {:?} down to {:?}", shapes[0], target))
+        },
+        "Reshape" if target.size() != shapes[0].size() => {
+            Err(format!("Cannot reshape {:?} into {:?}", shapes[0], target))
+        },
+        "BulkSum" if shapes.iter().any(|s| s.size() != shapes[0].size()) => {
+            Err("BulkSum children must have the same length".to_string())
+        },
+        "Einsum" => match &saved.text {
+            Some(subscripts) => check_einsum(subscripts, c),
+            None => Err("Saved Einsum is missing its subscripts".to_string())
+        },
+        _ => Ok(())
+    }
+}
+
+fn build(saved: &SavedNode, children: Vec<ANode>) -> Result<ANode, String> {
+    let shape = &saved.shape;
+    if shape.len() > MAX_DIMS {
+        return Err(format!("Shapes support at most {} dimensions, got {}", MAX_DIMS, shape.len()))
+    }
+    if children.is_empty() {
+        let value = match &saved.value {
+            Some(v) if v.len() == Shape::new(shape).size() => v.clone(),
+            Some(v) => return Err(format!("Shape {:?} does not match {} values", shape, v.len())),
+            None => return Err(format!("Saved {} is missing its value", saved.op))
+        };
+        if saved.op == "Variable" {
+            let v = Variable::with_shape(value, shape);
+            if !saved.requires_grad {
+                v.freeze();
+            }
+            return Ok(v)
+        }
+        return Ok(Constant::with_shape(value, shape))
+    } else if saved.op == "Variable" {
+        return Err("Variables cannot have children".to_string())
+    }
+
+    check(saved, &children)?;
+    let c = &children;
+    let node = match saved.op.as_str() {
+        "AddN" => AddN::new(c[0].clone(), c[1].clone()),
+        "Subtract" => Subtract::new(c[0].clone(), c[1].clone()),
+        "Multiply" => Multiply::new(c[0].clone(), c[1].clone()),
+        "Divide" => Divide::new(c[0].clone(), c[1].clone()),
+        "Power" => Power::new(c[0].clone(), c[1].clone()),
+        "Maximum" => Maximum::new(c[0].clone(), c[1].clone()),
+        "Minimum" => Minimum::new(c[0].clone(), c[1].clone()),
+        "MatMul" => MatMul::new(c[0].clone(), c[1].clone()),
+        "BatchMatMul" => BatchMatMul::new(c[0].clone(), c[1].clone()),
+        "Outer" => Outer::new(c[0].clone(), c[1].clone()),
+        "Conv2d" => Conv2d::new(c[0].clone(), c[1].clone(), saved.sizes[0]),
+        "SumVec" => SumVec::new(c[0].clone()),
+        "Cos" => Cos::new(c[0].clone()),
+        "Sin" => Sin::new(c[0].clone()),
+        "Tanh" => Tanh::new(c[0].clone()),
+        "Softmax" => Softmax::new(c[0].clone()),
+        "Ln" => Ln::new(c[0].clone()),
+        "Exp" => Exp::new(c[0].clone()),
+        "Negate" => Negate::new(c[0].clone()),
+        "Transpose" => Transpose::new(c[0].clone()),
+        "Diag" => Diag::new(c[0].clone()),
+        "Flip" => Flip::new(c[0].clone()),
+        "SumAxis" => SumAxis::new(c[0].clone(), saved.sizes[0]),
+        "Permute" => Permute::new(c[0].clone(), &saved.sizes),
+        "MaxPool2d" => MaxPool2d::new(c[0].clone(), saved.sizes[0], saved.sizes[1]),
+        "AvgPool2d" => AvgPool2d::new(c[0].clone(), saved.sizes[0], saved.sizes[1]),
+        "Slice" => Slice::new(c[0].clone(), saved.sizes[0], saved.sizes[1]),
+        "Repeat" => Repeat::new(c[0].clone(), saved.sizes[0]),
+        "BroadcastTo" => BroadcastTo::new(c[0].clone(), shape),
+        "SumTo" => SumTo::new(c[0].clone(), shape),
+        "Reshape" => Reshape::new(c[0].clone(), shape),
+        "GradReverse" => GradReverse::new(c[0].clone(), saved.scalars[0]),
+        "PowScalar" => PowScalar::new(c[0].clone(), saved.scalars[0]),
+        "NanToNum" => NanToNum::new(c[0].clone(), saved.scalars[0], saved.scalars[1], saved.scalars[2]),
+        "BulkSum" => BulkSum::new(children.into_iter()),
+        "Concat" => Concat::new(children),
+        // Subscripts were checked above
+        "Einsum" => Einsum::new(saved.text.as_deref().unwrap_or_default(), children),
+        op => return Err(format!("Unknown op {} in saved graph", op))
+    };
+    if saved.requires_grad {
+        Ok(node.require_grad())
+    } else {
+        Ok(node)
+    }
+}
+
+impl Serialize for ANode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedGraph::try_new(self).map_err(ser::Error::custom)?.serialize(serializer)
+    }
+}
+
+impl <'de> Deserialize<'de> for ANode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedGraph::deserialize(deserializer)?;
+        saved.try_load().map(|loaded| loaded.output).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod serialize_tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let w = Variable::new_with_grad(vec![0.5, -1.], false);
+        let out = (x.matmul(&w).exp() * 2.).sum_axis(0).slice(0, 1).grad_reverse(0.5).sum();
+
+        let mut graph = Graph::new();
+        graph.backward(&out);
+        let saved = SavedGraph::with_grads(&out, &graph);
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded: SavedGraph = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, saved);
+
+        let LoadedGraph { output, variables, graph: loaded_graph } = loaded.load();
+        assert_eq!(output.value(), out.value());
+        assert_eq!(variables.len(), 2);
+        assert_eq!(variables[0].value(), x.value());
+        assert!(!variables[1].requires_grad());
+        assert_eq!(loaded_graph.get_grad(&variables[0]), graph.get_grad(&x));
+
+        // Gradients flow through the reloaded graph as before
+        let mut new_graph = Graph::new();
+        new_graph.backward(&output);
+        assert_eq!(new_graph.get_grad(&variables[0]), graph.get_grad(&x));
+
+        // ANodes serialize directly as well
+        let json = serde_json::to_string(&out).unwrap();
+        let node: ANode = serde_json::from_str(&json).unwrap();
+        assert_eq!(node.value(), out.value());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let x = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let out = x.matmul(&x).sum();
+        let saved = SavedGraph::new(&out);
+        assert!(saved.try_load().is_ok());
+
+        let broken = |f: &dyn Fn(&mut SavedGraph)| {
+            let mut s = saved.clone();
+            f(&mut s);
+            s.try_load().err().unwrap()
+        };
+        // Children must come earlier in the list
+        let e = broken(&|s| s.nodes[1].children = vec![0, 5]);
+        assert_eq!(e.node, 1);
+        let e = broken(&|s| s.nodes[1].children = vec![0]);
+        assert_eq!(e.message, "MatMul expects 2 children, got 1");
+        broken(&|s| s.nodes[0].value = None);
+        broken(&|s| s.nodes[0].value = Some(vec![1.]));
+        broken(&|s| s.nodes[1].op = "Unknown".to_string());
+        broken(&|s| s.nodes[1].op = "Permute".to_string());
+        broken(&|s| s.nodes[2].op = "Slice".to_string());
+        broken(&|s| { s.grads.pop(); });
+        broken(&|s| s.grads[0] = Some(vec![1.]));
+        broken(&|s| s.nodes.clear());
+
+        // Shapes which don't fit are caught before the op is built
+        let e = broken(&|s| s.nodes[0].shape = vec![4, 1]);
+        assert_eq!(e.node, 1);
+        broken(&|s| s.nodes[0].shape = vec![1; 7]);
+
+        // Through serde, errors surface from the (de)serializer
+        let mut s = saved.clone();
+        s.nodes[1].children = vec![3, 3];
+        let json = serde_json::to_string(&s).unwrap();
+        assert!(serde_json::from_str::<ANode>(&json).is_err());
+        assert!(serde_json::to_string(&x.map(|v| v * 2.)).is_err());
+        assert!(SavedGraph::try_new(&x.map(|v| v * 2.).sum()).is_err());
+    }
+}
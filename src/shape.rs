@@ -0,0 +1,302 @@
+use std::fmt;
+
+pub const MAX_DIMS: usize = 6;
+
+#[derive(Clone,Copy,Eq,Hash,PartialEq)]
+pub struct Shape {
+    dims: [usize; MAX_DIMS],
+    ndim: usize
+}
+
+impl Shape {
+    pub fn new(dims: &[usize]) -> Self {
+        if dims.len() > MAX_DIMS {
+            panic!("Shapes support at most {} dimensions, got {}!", MAX_DIMS, dims.len());
+        }
+        let mut d = [1; MAX_DIMS];
+        d[..dims.len()].copy_from_slice(dims);
+        Shape { dims: d, ndim: dims.len() }
+    }
+
+    pub fn vector(len: usize) -> Self {
+        Shape::new(&[len])
+    }
+
+    #[inline]
+    pub fn dims(&self) -> &[usize] {
+        &self.dims[..self.ndim]
+    }
+
+    #[inline]
+    pub fn ndim(&self) -> usize {
+        self.ndim
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.dims().iter().product()
+    }
+
+    // Row-major strides, in elements
+    pub fn strides(&self) -> [usize; MAX_DIMS] {
+        let mut strides = [0; MAX_DIMS];
+        let mut stride = 1;
+        for d in (0..self.ndim).rev() {
+            strides[d] = stride;
+            stride *= self.dims[d];
+        }
+        strides
+    }
+
+    pub fn offset(&self, index: &[usize]) -> usize {
+        if index.len() != self.ndim {
+            panic!("Index {:?} does not match shape {:?}!", index, self);
+        }
+        let strides = self.strides();
+        index.iter().zip(self.dims().iter()).zip(strides.iter()).map(|((i, d), s)| {
+            if i >= d {
+                panic!("Index {:?} out of bounds for shape {:?}!", index, self);
+            }
+            i * s
+        }).sum()
+    }
+
+    pub fn remove_axis(&self, axis: usize) -> Shape {
+        self.check_axis(axis);
+        if self.ndim == 1 {
+            return Shape::vector(1)
+        }
+        let mut dims = [1; MAX_DIMS];
+        let mut j = 0;
+        for (i, d) in self.dims().iter().enumerate() {
+            if i != axis {
+                dims[j] = *d;
+                j += 1;
+            }
+        }
+        Shape { dims, ndim: self.ndim - 1 }
+    }
+
+    pub fn permute(&self, axes: &[usize]) -> Shape {
+        let mut seen = [false; MAX_DIMS];
+        if axes.len() != self.ndim || axes.iter().any(|a| *a >= self.ndim || std::mem::replace(&mut seen[*a], true)) {
+            panic!("Axes {:?} are not a permutation of shape {:?}!", axes, self);
+        }
+        let mut dims = [1; MAX_DIMS];
+        for (d, axis) in axes.iter().enumerate() {
+            dims[d] = self.dims[*axis];
+        }
+        Shape { dims, ndim: self.ndim }
+    }
+
+    // Splits the shape around `axis` into (outer, axis, inner) sizes, which is
+    // all that's needed to walk a contiguous buffer along a single dimension.
+    pub(crate) fn around_axis(&self, axis: usize) -> (usize, usize, usize) {
+        self.check_axis(axis);
+        let outer = self.dims[..axis].iter().product();
+        let inner = self.dims[axis+1..self.ndim].iter().product();
+        (outer, self.dims[axis], inner)
+    }
+
+    #[inline]
+    fn check_axis(&self, axis: usize) {
+        if axis >= self.ndim {
+            panic!("Axis {} out of range for shape {:?}!", axis, self);
+        }
+    }
+
+    // NumPy rules: align dimensions on the right, each pair must either
+    // match or have one side equal to 1.
+    pub fn broadcast(&self, other: &Shape) -> Option<Shape> {
+        let ndim = self.ndim.max(other.ndim);
+        let mut dims = [1; MAX_DIMS];
+        for i in 0..ndim {
+            let l = self.dim_from_right(i);
+            let r = other.dim_from_right(i);
+            dims[ndim - 1 - i] = if l == r || r == 1 {
+                l
+            } else if l == 1 {
+                r
+            } else {
+                return None
+            };
+        }
+        Some(Shape { dims, ndim })
+    }
+
+    #[inline]
+    fn dim_from_right(&self, i: usize) -> usize {
+        if i < self.ndim { self.dims[self.ndim - 1 - i] } else { 1 }
+    }
+
+}
+
+impl fmt::Debug for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.dims())
+    }
+}
+
+// Operands an elementwise op cannot broadcast together, along with the ops
+// that produced them
+#[derive(Clone,Debug,PartialEq)]
+pub struct ShapeError {
+    pub op: &'static str,
+    pub left: (&'static str, Vec<usize>),
+    pub right: (&'static str, Vec<usize>)
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} cannot broadcast {} of shape {:?} with {} of shape {:?}",
+            self.op, self.left.0, self.left.1, self.right.0, self.right.1)
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+pub(crate) fn broadcast_shapes(left: &Shape, right: &Shape) -> Shape {
+    match left.broadcast(right) {
+        Some(s) => s,
+        None => panic!("Cannot broadcast shapes {:?} and {:?}!", left, right)
+    }
+}
+
+#[derive(Clone,Copy)]
+enum Mode {
+    Contiguous,
+    Repeated,
+    Strided
+}
+
+// Yields, for each element of `out` in row-major order, the flat index of the
+// element in `src` it was broadcast from.
+#[derive(Clone)]
+pub(crate) struct BroadcastIndex {
+    mode: Mode,
+    dims: [usize; MAX_DIMS],
+    strides: [usize; MAX_DIMS],
+    counter: [usize; MAX_DIMS],
+    ndim: usize,
+    offset: usize,
+    remaining: usize
+}
+
+impl BroadcastIndex {
+    pub(crate) fn new(src: &Shape, out: &Shape) -> Self {
+        let remaining = out.size();
+        let mut bi = BroadcastIndex {
+            mode: Mode::Contiguous,
+            dims: out.dims,
+            strides: [0; MAX_DIMS],
+            counter: [0; MAX_DIMS],
+            ndim: out.ndim,
+            offset: 0,
+            remaining
+        };
+
+        if src.size() == 1 {
+            bi.mode = Mode::Repeated;
+        } else if src.size() != remaining {
+            if src.ndim > out.ndim {
+                panic!("Cannot broadcast shape {:?} into {:?}!", src, out);
+            }
+            let strides = src.strides();
+            for i in 0..src.ndim {
+                let d = out.ndim - 1 - i;
+                let sd = src.dim_from_right(i);
+                if sd == out.dims[d] {
+                    bi.strides[d] = strides[src.ndim - 1 - i];
+                } else if sd != 1 {
+                    panic!("Cannot broadcast shape {:?} into {:?}!", src, out);
+                }
+            }
+            bi.mode = Mode::Strided;
+        }
+        bi
+    }
+
+    // Walks `src` in the order of its dimensions rearranged by `axes`.
+    pub(crate) fn permuted(src: &Shape, axes: &[usize]) -> Self {
+        let out = src.permute(axes);
+        let strides = src.strides();
+        let mut bi = BroadcastIndex::new(&out, &out);
+        for (d, axis) in axes.iter().enumerate() {
+            bi.strides[d] = strides[*axis];
+        }
+        bi.mode = Mode::Strided;
+        bi
+    }
+}
+
+impl Iterator for BroadcastIndex {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None
+        }
+        self.remaining -= 1;
+        let cur = self.offset;
+        match self.mode {
+            Mode::Contiguous => self.offset += 1,
+            Mode::Repeated => {},
+            Mode::Strided => {
+                for d in (0..self.ndim).rev() {
+                    self.counter[d] += 1;
+                    self.offset += self.strides[d];
+                    if self.counter[d] < self.dims[d] {
+                        break
+                    }
+                    self.offset -= self.strides[d] * self.dims[d];
+                    self.counter[d] = 0;
+                }
+            }
+        }
+        Some(cur)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod shape_tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast() {
+        let a = Shape::new(&[2, 1]);
+        let b = Shape::new(&[3]);
+        assert_eq!(a.broadcast(&b), Some(Shape::new(&[2, 3])));
+        assert_eq!(Shape::vector(1).broadcast(&Shape::vector(4)), Some(Shape::vector(4)));
+        assert_eq!(Shape::vector(2).broadcast(&Shape::vector(3)), None);
+    }
+
+    #[test]
+    fn test_strides() {
+        let s = Shape::new(&[2, 3, 4]);
+        assert_eq!(&s.strides()[..3], &[12, 4, 1]);
+        assert_eq!(s.offset(&[1, 2, 3]), 23);
+        assert_eq!(s.remove_axis(1), Shape::new(&[2, 4]));
+        assert_eq!(s.around_axis(1), (2, 3, 4));
+    }
+
+    #[test]
+    fn test_broadcast_index() {
+        let out = Shape::new(&[2, 3]);
+        let row = BroadcastIndex::new(&Shape::new(&[3]), &out).collect::<Vec<_>>();
+        assert_eq!(row, vec![0, 1, 2, 0, 1, 2]);
+
+        let col = BroadcastIndex::new(&Shape::new(&[2, 1]), &out).collect::<Vec<_>>();
+        assert_eq!(col, vec![0, 0, 0, 1, 1, 1]);
+
+        let t = BroadcastIndex::permuted(&out, &[1, 0]).collect::<Vec<_>>();
+        assert_eq!(t, vec![0, 3, 1, 4, 2, 5]);
+
+        let same = BroadcastIndex::new(&out, &out).collect::<Vec<_>>();
+        assert_eq!(same, vec![0, 1, 2, 3, 4, 5]);
+    }
+}
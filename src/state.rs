@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self,Read,Seek,Write,BufReader,BufWriter};
+use std::path::Path;
+
+use crate::{ANode,DType};
+
+const MAGIC: &[u8; 4] = b"SGSD";
+
+// Named parameter values, for checkpointing. Saved as a small binary file:
+// the magic bytes, the entry count and, per entry, its name, dims and values,
+// all little endian. Values are stored at DType's width and converted on load
+// if needed.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct StateDict {
+    entries: BTreeMap<String, (Vec<usize>, Vec<DType>)>
+}
+
+impl StateDict {
+    pub fn new() -> Self {
+        StateDict { entries: BTreeMap::new() }
+    }
+
+    pub fn from_nodes(nodes: &[(&str, &ANode)]) -> Self {
+        let mut sd = StateDict::new();
+        nodes.iter().for_each(|(name, node)| sd.insert(name, node));
+        sd
+    }
+
+    pub fn insert(&mut self, name: &str, node: &ANode) {
+        let entry = (node.shape().dims().to_vec(), node.value().to_vec());
+        self.entries.insert(name.to_string(), entry);
+    }
+
+    pub(crate) fn insert_values(&mut self, name: &str, dims: Vec<usize>, values: Vec<DType>) {
+        self.entries.insert(name.to_string(), (dims, values));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[DType]> {
+        self.entries.get(name).map(|(_, v)| v.as_slice())
+    }
+
+    pub fn dims(&self, name: &str) -> Option<&[usize]> {
+        self.entries.get(name).map(|(d, _)| d.as_slice())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Writes the stored values back into the given leaves
+    pub fn apply(&self, nodes: &[(&str, &ANode)]) {
+        for (name, node) in nodes.iter() {
+            let (dims, values) = match self.entries.get(*name) {
+                Some(e) => e,
+                None => panic!("No entry named {} in state dict!", name)
+            };
+            if dims.as_slice() != node.shape().dims() {
+                panic!("Entry {} has dims {:?}, node has {:?}!", name, dims, node.shape().dims());
+            }
+            node.set_value(values);
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&[std::mem::size_of::<DType>() as u8])?;
+        write_u64(&mut w, self.entries.len() as u64)?;
+        for (name, (dims, values)) in self.entries.iter() {
+            write_u64(&mut w, name.len() as u64)?;
+            w.write_all(name.as_bytes())?;
+            write_u64(&mut w, dims.len() as u64)?;
+            for d in dims.iter() {
+                write_u64(&mut w, *d as u64)?;
+            }
+            write_u64(&mut w, values.len() as u64)?;
+            for v in values.iter() {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a state dict file"))
+        }
+        let mut width = [0u8; 1];
+        r.read_exact(&mut width)?;
+        if width[0] != 4 && width[0] != 8 {
+            return Err(invalid("unsupported value width"))
+        }
+
+        let mut entries = BTreeMap::new();
+        for _ in 0..read_u64(&mut r)? {
+            let mut name = vec![0u8; read_len(&mut r, size, 1)?];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid("entry name is not utf-8"))?;
+            let dims = (0..read_len(&mut r, size, 8)?)
+                .map(|_| read_u64(&mut r).map(|d| d as usize))
+                .collect::<io::Result<Vec<_>>>()?;
+            let values = (0..read_len(&mut r, size, width[0] as u64)?)
+                .map(|_| read_value(&mut r, width[0]))
+                .collect::<io::Result<Vec<_>>>()?;
+            if element_count(&dims) != Some(values.len()) {
+                return Err(invalid("entry shape does not match its value count"))
+            }
+            entries.insert(name, (dims, values));
+        }
+        Ok(StateDict { entries })
+    }
+}
+
+// The safetensors layout: a little endian u64 header length, a JSON header
+// mapping names to dtype, shape and byte offsets, then the raw data.
+#[cfg(feature = "safetensors")]
+impl StateDict {
+    pub fn save_safetensors<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        use serde_json::{Map,Value,json};
+
+        let dtype = if std::mem::size_of::<DType>() == 4 { "F32" } else { "F64" };
+        let mut header = Map::new();
+        let mut offset = 0;
+        for (name, (dims, values)) in self.entries.iter() {
+            let end = offset + values.len() * std::mem::size_of::<DType>();
+            header.insert(name.clone(), json!({
+                "dtype": dtype,
+                "shape": dims,
+                "data_offsets": [offset, end]
+            }));
+            offset = end;
+        }
+        let mut header = Value::Object(header).to_string().into_bytes();
+        // Pad so the data starts 8 byte aligned
+        while !header.len().is_multiple_of(8) {
+            header.push(b' ');
+        }
+
+        let mut w = BufWriter::new(File::create(path)?);
+        write_u64(&mut w, header.len() as u64)?;
+        w.write_all(&header)?;
+        for (_, values) in self.entries.values() {
+            for v in values.iter() {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+
+    // Loads F32 and F64 tensors, converting them to DType
+    pub fn load_safetensors<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        use serde_json::Value;
+
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+        let mut header = vec![0u8; read_len(&mut r, size, 1)?];
+        r.read_exact(&mut header)?;
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let header: Value = serde_json::from_slice(&header)
+            .map_err(|_| invalid("malformed safetensors header"))?;
+        let header = match header {
+            Value::Object(m) => m,
+            _ => return Err(invalid("malformed safetensors header"))
+        };
+
+        let mut entries = BTreeMap::new();
+        for (name, info) in header.iter() {
+            if name == "__metadata__" {
+                continue
+            }
+            let width = match info["dtype"].as_str() {
+                Some("F32") => 4,
+                Some("F64") => 8,
+                _ => return Err(invalid("unsupported safetensors dtype"))
+            };
+            let as_usizes = |v: &Value| -> Option<Vec<usize>> {
+                v.as_array()?.iter().map(|d| d.as_u64().map(|d| d as usize)).collect()
+            };
+            let dims = as_usizes(&info["shape"]).ok_or_else(|| invalid("malformed tensor shape"))?;
+            let (start, end) = match as_usizes(&info["data_offsets"]).as_deref() {
+                Some([s, e]) if s <= e && *e <= data.len() => (*s, *e),
+                _ => return Err(invalid("malformed tensor offsets"))
+            };
+            if element_count(&dims).and_then(|n| n.checked_mul(width)) != Some(end - start) {
+                return Err(invalid("tensor shape does not match its data size"))
+            }
+            let mut bytes = &data[start..end];
+            let values = (0..(end - start) / width)
+                .map(|_| read_value(&mut bytes, width as u8))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.insert(name.clone(), (dims, values));
+        }
+        Ok(StateDict { entries })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Counts read from a file are bounded by what's left of it, so a corrupt
+// length fails rather than allocating without limit
+fn read_len<R: Read + Seek>(r: &mut R, size: u64, width: u64) -> io::Result<usize> {
+    let n = read_u64(r)?;
+    let left = size.saturating_sub(r.stream_position()?);
+    match n.checked_mul(width) {
+        Some(bytes) if bytes <= left => Ok(n as usize),
+        _ => Err(invalid("length exceeds the file size"))
+    }
+}
+
+fn element_count(dims: &[usize]) -> Option<usize> {
+    dims.iter().try_fold(1usize, |n, d| n.checked_mul(*d))
+}
+
+fn read_value<R: Read>(r: &mut R, width: u8) -> io::Result<DType> {
+    if width == 4 {
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf)?;
+        Ok(f32::from_le_bytes(buf) as DType)
+    } else {
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        Ok(f64::from_le_bytes(buf) as DType)
+    }
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::*;
+    use crate::Variable;
+
+    #[test]
+    fn test_save_load() {
+        let w = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let b = Variable::new(vec![0.5, -0.5]);
+        let sd = StateDict::from_nodes(&[("layer.weight", &w), ("layer.bias", &b)]);
+
+        let path = std::env::temp_dir().join(format!("simple_grad_state_{}.sgsd", std::process::id()));
+        sd.save(&path).unwrap();
+        let loaded = StateDict::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, sd);
+        assert_eq!(loaded.names().collect::<Vec<_>>(), vec!["layer.bias", "layer.weight"]);
+
+        let w2 = Variable::with_shape(vec![0.; 4], &[2, 2]);
+        let b2 = Variable::new(vec![0.; 2]);
+        loaded.apply(&[("layer.weight", &w2), ("layer.bias", &b2)]);
+        assert_eq!(w2.value(), w.value());
+        assert_eq!(b2.value(), b.value());
+    }
+
+    #[cfg(feature = "safetensors")]
+    #[test]
+    fn test_safetensors() {
+        let w = Variable::with_shape(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let b = Variable::new(vec![0.5]);
+        let sd = StateDict::from_nodes(&[("w", &w), ("b", &b)]);
+
+        let path = std::env::temp_dir().join(format!("simple_grad_{}.safetensors", std::process::id()));
+        sd.save_safetensors(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let loaded = StateDict::load_safetensors(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, sd);
+
+        let header_len = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+        assert_eq!(header_len % 8, 0);
+        let header = std::str::from_utf8(&bytes[8..8 + header_len]).unwrap();
+        assert!(header.contains("\"shape\":[2,3]"));
+        assert_eq!(bytes.len(), 8 + header_len + 7 * std::mem::size_of::<DType>());
+    }
+
+    #[test]
+    fn test_load_corrupt() {
+        let w = Variable::with_shape(vec![1., 2., 3., 4.], &[2, 2]);
+        let sd = StateDict::from_nodes(&[("w", &w)]);
+        let path = std::env::temp_dir().join(format!("simple_grad_corrupt_{}.sgsd", std::process::id()));
+        sd.save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        // magic, width, entry count, name length, name, dim count, dims, value count
+        let name_at = 13;
+        let dims_at = name_at + 8 + 1 + 8;
+        let count_at = dims_at + 16;
+        let load = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut b = bytes.clone();
+            edit(&mut b);
+            std::fs::write(&path, &b).unwrap();
+            StateDict::load(&path).err().unwrap().kind()
+        };
+        assert_eq!(load(&|b| b[name_at..name_at + 8].copy_from_slice(&u64::MAX.to_le_bytes())),
+            io::ErrorKind::InvalidData);
+        assert_eq!(load(&|b| b[count_at..count_at + 8].copy_from_slice(&(1u64 << 40).to_le_bytes())),
+            io::ErrorKind::InvalidData);
+        assert_eq!(load(&|b| b[dims_at + 8..dims_at + 16].copy_from_slice(&3u64.to_le_bytes())),
+            io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "safetensors")]
+    #[test]
+    fn test_safetensors_corrupt() {
+        let path = std::env::temp_dir().join(format!("simple_grad_corrupt_{}.safetensors", std::process::id()));
+        let write = |header: &str, data: usize| {
+            let mut b = (header.len() as u64).to_le_bytes().to_vec();
+            b.extend_from_slice(header.as_bytes());
+            b.extend(std::iter::repeat_n(0u8, data));
+            std::fs::write(&path, &b).unwrap();
+            StateDict::load_safetensors(&path)
+        };
+        let header = r#"{"w":{"dtype":"F32","shape":[2,2],"data_offsets":[0,16]}}"#;
+        assert!(write(header, 16).is_ok());
+        let short = r#"{"w":{"dtype":"F32","shape":[2,2],"data_offsets":[0,12]}}"#;
+        assert_eq!(write(short, 16).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(&path, u64::MAX.to_le_bytes()).unwrap();
+        assert_eq!(StateDict::load_safetensors(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_apply_shape_mismatch() {
+        let w = Variable::new(vec![1., 2.]);
+        let sd = StateDict::from_nodes(&[("w", &w)]);
+        sd.apply(&[("w", &Variable::new(vec![0.; 3]))]);
+    }
+}
@@ -0,0 +1,156 @@
+use std::rc::Rc;
+
+use ::half::{f16, bf16};
+
+use crate::{DType,ANode,NodeIdx,Node,Shape,Value};
+use crate::pool::{MPVec,allocate_vec};
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum HalfFormat {
+    F16,
+    BF16
+}
+
+impl HalfFormat {
+    #[cfg(not(feature = "f64"))]
+    #[inline]
+    fn encode(&self, v: DType) -> u16 {
+        match self {
+            HalfFormat::F16  => f16::from_f32(v).to_bits(),
+            HalfFormat::BF16 => bf16::from_f32(v).to_bits()
+        }
+    }
+
+    #[cfg(not(feature = "f64"))]
+    #[inline]
+    fn decode(&self, bits: u16) -> DType {
+        match self {
+            HalfFormat::F16  => f16::from_bits(bits).to_f32(),
+            HalfFormat::BF16 => bf16::from_bits(bits).to_f32()
+        }
+    }
+
+    #[cfg(feature = "f64")]
+    #[inline]
+    fn encode(&self, v: DType) -> u16 {
+        match self {
+            HalfFormat::F16  => f16::from_f64(v).to_bits(),
+            HalfFormat::BF16 => bf16::from_f64(v).to_bits()
+        }
+    }
+
+    #[cfg(feature = "f64")]
+    #[inline]
+    fn decode(&self, bits: u16) -> DType {
+        match self {
+            HalfFormat::F16  => f16::from_bits(bits).to_f64(),
+            HalfFormat::BF16 => bf16::from_bits(bits).to_f64()
+        }
+    }
+}
+
+// A 2-D table kept in 16-bit storage. Rows are only widened to DType when
+// they're gathered into the graph, so large embedding tables take half the
+// memory; all arithmetic still happens at full precision.
+pub struct HalfTable {
+    idx: NodeIdx,
+    format: HalfFormat,
+    data: Vec<u16>,
+    width: usize
+}
+
+impl HalfTable {
+    pub fn new(values: &[DType], rows: usize, width: usize, format: HalfFormat) -> Self {
+        if values.len() != rows * width {
+            panic!("{} values cannot fill a {}x{} table!", values.len(), rows, width);
+        }
+        let data = values.iter().map(|v| format.encode(*v)).collect();
+        HalfTable { idx: NodeIdx::new(), format, data, width }
+    }
+
+    #[inline]
+    pub fn get_id(&self) -> NodeIdx { self.idx }
+
+    #[inline]
+    pub fn shape(&self) -> Shape {
+        Shape::new(&[self.data.len() / self.width, self.width])
+    }
+
+    pub fn row(&self, row: usize) -> Vec<DType> {
+        self.data[row * self.width..(row + 1) * self.width].iter()
+            .map(|b| self.format.decode(*b))
+            .collect()
+    }
+
+    pub fn set_row(&mut self, row: usize, values: &[DType]) {
+        let format = self.format;
+        self.data[row * self.width..(row + 1) * self.width].iter_mut()
+            .zip(values.iter())
+            .for_each(|(b, v)| *b = format.encode(*v));
+    }
+
+    // Gathers rows as a full precision node. Gradients come back sparsely,
+    // through `Graph::get_sparse_grad_by_id(table.get_id())`.
+    pub fn embedding(&self, rows: &[usize]) -> ANode {
+        let mut out = allocate_vec(rows.len() * self.width);
+        out.chunks_mut(self.width).zip(rows.iter()).for_each(|(o, r)| {
+            let src = &self.data[r * self.width..(r + 1) * self.width];
+            o.iter_mut().zip(src.iter()).for_each(|(oi, b)| *oi = self.format.decode(*b));
+        });
+        let shape = Shape::new(&[rows.len(), self.width]);
+        let node = HalfEmbedding(NodeIdx::new(), self.idx, out, shape, rows.to_vec());
+        ANode::new(Rc::new(node))
+    }
+
+    pub fn to_variable(&self) -> ANode {
+        let values = self.data.iter().map(|b| self.format.decode(*b)).collect();
+        crate::Variable::with_shape(values, self.shape().dims())
+    }
+}
+
+struct HalfEmbedding(NodeIdx, NodeIdx, MPVec, Shape, Vec<usize>);
+
+impl Node for HalfEmbedding {
+    #[inline]
+    fn get_id(&self) -> NodeIdx { self.0 }
+
+    fn get_children(&self) -> Option<&[ANode]> { None }
+
+    fn is_leaf(&self) -> bool { false }
+
+    fn value(&self) -> Value<'_> {
+        Value::slice(&self.2)
+    }
+
+    fn shape(&self) -> Shape { self.3 }
+
+    fn requires_grad(&self) -> bool { false }
+
+    fn sparse_rows(&self) -> Option<(NodeIdx, &[usize])> {
+        Some((self.1, &self.4))
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use crate::Graph;
+
+    #[test]
+    fn test_half_table() {
+        for format in [HalfFormat::F16, HalfFormat::BF16] {
+            let mut table = HalfTable::new(&[0., 1., 2., 3., 4., 5.], 3, 2, format);
+            assert_eq!(table.row(2), vec![4., 5.]);
+            table.set_row(0, &[0.5, -1.]);
+            assert_eq!(table.to_variable().value(), &[0.5, -1., 2., 3., 4., 5.]);
+
+            let rows = table.embedding(&[1, 1]);
+            assert_eq!(rows.value(), &[2., 3., 2., 3.]);
+
+            let mut graph = Graph::new();
+            graph.backward(&rows);
+            let sparse = graph.get_sparse_grad_by_id(table.get_id()).unwrap();
+            assert_eq!(sparse[&1].as_slice(), &[2., 2.]);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+
+use crate::{ANode,DType,Graph};
+
+// Explicit alternative to holding a Graph: nodes are watched up front and
+// gradients asked for by name afterwards. Each request runs its own backward
+// pass over just the paths down to the requested nodes, and its buffers are
+// freed as soon as it returns. Watched nodes needn't be variables.
+#[derive(Default)]
+pub struct Tape {
+    watched: RefCell<Vec<ANode>>
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape::default()
+    }
+
+    // Records `x` and hands it back, for building the expression from
+    pub fn watch(&self, x: &ANode) -> ANode {
+        let mut watched = self.watched.borrow_mut();
+        if !watched.iter().any(|w| w.get_id() == x.get_id()) {
+            watched.push(x.clone());
+        }
+        x.clone()
+    }
+
+    pub fn is_watched(&self, x: &ANode) -> bool {
+        self.watched.borrow().iter().any(|w| w.get_id() == x.get_id())
+    }
+
+    // Gradient of sum(y) with respect to `x`, zeros if y doesn't depend on it
+    pub fn gradient(&self, y: &ANode, x: &ANode) -> Vec<DType> {
+        self.gradients(y, &[x]).pop().unwrap()
+    }
+
+    // Gradients of sum(y) with respect to each of `xs`, from a single pass
+    pub fn gradients(&self, y: &ANode, xs: &[&ANode]) -> Vec<Vec<DType>> {
+        for x in xs.iter() {
+            if !self.is_watched(x) {
+                panic!("Node {:?} is not watched by the tape!", x.get_id());
+            }
+        }
+        let mut graph = Graph::new();
+        graph.backward_wrt(y, xs);
+        xs.iter().map(|x| match graph.get_grad(x) {
+            Some(g) => g.to_vec(),
+            None => vec![0.; x.value().len()]
+        }).collect()
+    }
+
+    // Forgets the watched nodes, so the tape can be reused for the next step
+    // without keeping the last one's inputs alive
+    pub fn reset(&self) {
+        self.watched.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tape_tests {
+    use super::*;
+    use crate::{Variable,Constant};
+
+    #[test]
+    fn test_tape() {
+        let tape = Tape::new();
+        let x = tape.watch(&Variable::new(vec![1., 2.]));
+        let c = tape.watch(&Constant::new(vec![3., 4.]));
+        let w = Variable::new(vec![5., 6.]);
+        let y = &x * &x * &c + &w;
+
+        assert_eq!(tape.gradient(&y, &x), vec![6., 16.]);
+        // Constants have gradients once watched
+        assert_eq!(tape.gradient(&y, &c), vec![1., 4.]);
+        let grads = tape.gradients(&y, &[&x, &c]);
+        assert_eq!(grads, vec![vec![6., 16.], vec![1., 4.]]);
+
+        // Watched nodes out of reach get zeros
+        let z = tape.watch(&Variable::new(vec![1.]));
+        assert_eq!(tape.gradient(&y, &z), vec![0.]);
+
+        tape.reset();
+        assert!(!tape.is_watched(&x));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_tape_unwatched() {
+        let tape = Tape::new();
+        let x = Variable::new(vec![1., 2.]);
+        let y = (&x * 2.).sum();
+        tape.gradient(&y, &x);
+    }
+}
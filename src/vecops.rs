@@ -1,49 +1,237 @@
+use std::cell::Cell;
+
+use crate::DType;
+use crate::parallel::{map, update, zip_map, zip_update};
+
+// The fastmath approximations are only accurate to f32 precision, so f64 builds keep
+// using std.
+#[cfg(all(feature = "fastmath", not(feature = "f64")))]
+use crate::fastmath;
+#[cfg(feature = "blas")]
+use crate::blas;
+
+#[inline]
+pub fn add(l: &[DType], r: &[DType], out: &mut [DType]) {
+    zip_map(l, r, out, |li, ri| li + ri);
+}
+
 #[inline]
-pub fn add(l: &[f32], r: &[f32], out: &mut [f32]) {
-    l.iter().zip(r.iter()).zip(out.iter_mut()).for_each(|((li, ri), outi)| {
-        *outi = li + ri;
-    });
+pub fn iadd(l: &mut [DType], r: &[DType]) {
+    zip_update(l, r, |li, ri| *li += ri);
 }
 
 #[inline]
-pub fn iadd(l: &mut [f32], r: &[f32]) {
-    l.iter_mut().zip(r.iter()).for_each(|(li, ri)| {
-        *li += ri;
-    });
+pub fn sub(l: &[DType], r: &[DType], out: &mut [DType]) {
+    zip_map(l, r, out, |li, ri| li - ri);
 }
 
 #[inline]
-pub fn sub(l: &[f32], r: &[f32], out: &mut [f32]) {
-    l.iter().zip(r.iter()).zip(out.iter_mut()).for_each(|((li, ri), outi)| {
-        *outi = li - ri;
-    });
+pub fn mul(l: &[DType], r: &[DType], out: &mut [DType]) {
+    zip_map(l, r, out, |li, ri| li * ri);
+}
+
+#[inline]
+pub fn div(l: &[DType], r: &[DType], out: &mut [DType]) {
+    zip_map(l, r, out, |li, ri| li / ri);
+}
+
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Summation {
+    Naive,
+    // Kahan compensated summation
+    Kahan,
+    // Recursive halving; error grows with log(n) rather than n
+    Pairwise
+}
+
+thread_local! {
+    static SUMMATION: Cell<Summation> = const { Cell::new(Summation::Naive) };
+}
+
+// Selects how SumVec and BulkSum reduce, for graphs built on this thread
+pub fn set_summation(mode: Summation) {
+    SUMMATION.with(|s| s.set(mode));
+}
+
+pub fn summation() -> Summation {
+    SUMMATION.with(|s| s.get())
+}
+
+const PAIRWISE_BLOCK: usize = 128;
+
+pub fn sum(x: &[DType]) -> DType {
+    match summation() {
+        Summation::Naive => x.iter().sum(),
+        Summation::Kahan => {
+            let mut c = 0.;
+            x.iter().fold(0., |acc, xi| kahan_step(acc, *xi, &mut c))
+        },
+        Summation::Pairwise => pairwise_sum(x)
+    }
 }
 
 #[inline]
-pub fn isub(l: &mut [f32], r: &[f32]) {
-    l.iter_mut().zip(r.iter()).for_each(|(li, ri)| {
-        *li -= ri;
-    });
+fn kahan_step(acc: DType, x: DType, c: &mut DType) -> DType {
+    let y = x - *c;
+    let t = acc + y;
+    *c = (t - acc) - y;
+    t
+}
+
+fn pairwise_sum(x: &[DType]) -> DType {
+    if x.len() <= PAIRWISE_BLOCK {
+        x.iter().sum()
+    } else {
+        let mid = x.len() / 2;
+        pairwise_sum(&x[..mid]) + pairwise_sum(&x[mid..])
+    }
 }
 
+// out = sum(xs), elementwise
+pub fn sum_slices(xs: &[&[DType]], out: &mut [DType]) {
+    match summation() {
+        Summation::Naive => xs.iter().for_each(|x| iadd(out, x)),
+        Summation::Kahan => {
+            let mut c = vec![0.; out.len()];
+            for x in xs {
+                out.iter_mut().zip(x.iter()).zip(c.iter_mut()).for_each(|((oi, xi), ci)| {
+                    *oi = kahan_step(*oi, *xi, ci);
+                });
+            }
+        },
+        Summation::Pairwise => pairwise_slices(xs, out)
+    }
+}
+
+fn pairwise_slices(xs: &[&[DType]], out: &mut [DType]) {
+    if xs.len() <= 2 {
+        xs.iter().for_each(|x| iadd(out, x));
+    } else {
+        let mid = xs.len() / 2;
+        let mut right = vec![0.; out.len()];
+        pairwise_slices(&xs[..mid], out);
+        pairwise_slices(&xs[mid..], &mut right);
+        iadd(out, &right);
+    }
+}
+
+// out[m,n] += a[m,k] * b[k,n]
 #[inline]
-pub fn mul(l: &[f32], r: &[f32], out: &mut [f32]) {
-    l.iter().zip(r.iter()).zip(out.iter_mut()).for_each(|((li, ri), outi)| {
-        *outi = li * ri;
-    });
+pub fn matmul(a: &[DType], b: &[DType], out: &mut [DType], m: usize, k: usize, n: usize) {
+    #[cfg(feature = "blas")]
+    if m * k * n >= blas::MIN_WORK {
+        return blas::gemm(false, false, m, n, k, a, b, out)
+    }
+
+    for i in 0..m {
+        let row = &mut out[i*n..(i+1)*n];
+        for p in 0..k {
+            let aip = a[i*k + p];
+            iadd_scaled(row, &b[p*n..(p+1)*n], aip);
+        }
+    }
 }
 
+// out[m,k] += g[m,n] * b[k,n]^T
 #[inline]
-pub fn imul(l: &mut [f32], r: &[f32]) {
-    l.iter_mut().zip(r.iter()).for_each(|(li, ri)| {
-        *li *= ri;
-    });
+pub fn matmul_bt(g: &[DType], b: &[DType], out: &mut [DType], m: usize, n: usize, k: usize) {
+    #[cfg(feature = "blas")]
+    if m * k * n >= blas::MIN_WORK {
+        return blas::gemm(false, true, m, k, n, g, b, out)
+    }
+
+    for i in 0..m {
+        let g_row = &g[i*n..(i+1)*n];
+        for p in 0..k {
+            let b_row = &b[p*n..(p+1)*n];
+            out[i*k + p] += g_row.iter().zip(b_row.iter()).map(|(gi, bi)| gi * bi).sum::<DType>();
+        }
+    }
 }
 
+// out[k,n] += a[m,k]^T * g[m,n]
 #[inline]
-pub fn div(l: &[f32], r: &[f32], out: &mut [f32]) {
-    l.iter().zip(r.iter()).zip(out.iter_mut()).for_each(|((li, ri), outi)| {
-        *outi = li / ri;
-    });
+pub fn matmul_at(a: &[DType], g: &[DType], out: &mut [DType], m: usize, k: usize, n: usize) {
+    #[cfg(feature = "blas")]
+    if m * k * n >= blas::MIN_WORK {
+        return blas::gemm(true, false, k, n, m, a, g, out)
+    }
+
+    for i in 0..m {
+        let g_row = &g[i*n..(i+1)*n];
+        for p in 0..k {
+            let aip = a[i*k + p];
+            iadd_scaled(&mut out[p*n..(p+1)*n], g_row, aip);
+        }
+    }
 }
 
+#[inline]
+pub fn iadd_scaled(l: &mut [DType], r: &[DType], scale: DType) {
+    zip_update(l, r, |li, ri| *li += ri * scale);
+}
+
+// out[cols,rows] = src[rows,cols]^T
+#[inline]
+pub fn transpose(src: &[DType], out: &mut [DType], rows: usize, cols: usize) {
+    for i in 0..rows {
+        for j in 0..cols {
+            out[j*rows + i] = src[i*cols + j];
+        }
+    }
+}
+
+#[cfg(not(all(feature = "fastmath", not(feature = "f64"))))]
+#[inline(always)]
+pub fn exp_scalar(x: DType) -> DType { x.exp() }
+
+#[cfg(not(all(feature = "fastmath", not(feature = "f64"))))]
+#[inline(always)]
+pub fn ln_scalar(x: DType) -> DType { x.ln() }
+
+#[cfg(not(all(feature = "fastmath", not(feature = "f64"))))]
+#[inline(always)]
+pub fn tanh_scalar(x: DType) -> DType { x.tanh() }
+
+#[cfg(all(feature = "fastmath", not(feature = "f64")))]
+#[inline(always)]
+pub fn exp_scalar(x: DType) -> DType { fastmath::exp_f32(x) }
+
+#[cfg(all(feature = "fastmath", not(feature = "f64")))]
+#[inline(always)]
+pub fn ln_scalar(x: DType) -> DType { fastmath::ln_f32(x) }
+
+#[cfg(all(feature = "fastmath", not(feature = "f64")))]
+#[inline(always)]
+pub fn tanh_scalar(x: DType) -> DType { fastmath::tanh_f32(x) }
+
+#[inline]
+pub fn exp(x: &[DType], out: &mut [DType]) {
+    map(x, out, exp_scalar);
+}
+
+#[inline]
+pub fn ln(x: &[DType], out: &mut [DType]) {
+    map(x, out, ln_scalar);
+}
+
+#[inline]
+pub fn tanh(x: &[DType], out: &mut [DType]) {
+    map(x, out, tanh_scalar);
+}
+
+#[inline]
+pub fn iexp(x: &mut [DType]) {
+    update(x, exp_scalar);
+}
+
+#[inline]
+pub fn iln(x: &mut [DType]) {
+    update(x, ln_scalar);
+}
+
+#[inline]
+pub fn itanh(x: &mut [DType]) {
+    update(x, tanh_scalar);
+}
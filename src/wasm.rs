@@ -0,0 +1,142 @@
+// JavaScript bindings. Build with
+//
+//     cargo rustc --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//     wasm-bindgen --target web target/wasm32-unknown-unknown/release/simple_grad.wasm --out-dir pkg
+//
+// Values go in and out as Float32Arrays, or Float64Arrays with the f64 feature,
+// in row major order alongside their shapes.
+use wasm_bindgen::prelude::*;
+
+use crate::{ANode,Constant,DType,Graph,Pow,Shape,Variable};
+
+#[wasm_bindgen(js_name = Node)]
+#[derive(Clone)]
+pub struct JsNode(ANode);
+
+#[wasm_bindgen(js_name = Graph)]
+pub struct JsGraph(Graph);
+
+// Without a shape, values are laid out as a vector. Mismatches are thrown
+// rather than left to trap in with_shape.
+fn dims_of(values: &[DType], shape: Option<Vec<usize>>) -> Result<Vec<usize>, JsError> {
+    let dims = shape.unwrap_or_else(|| vec![values.len()]);
+    if Shape::new(&dims).size() != values.len() {
+        return Err(JsError::new(&format!("Shape {:?} does not match {} values", dims, values.len())));
+    }
+    Ok(dims)
+}
+
+#[wasm_bindgen(js_class = Node)]
+impl JsNode {
+    #[wasm_bindgen(getter)]
+    pub fn shape(&self) -> Vec<usize> {
+        self.0.shape().dims().to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn value(&self) -> Vec<DType> {
+        self.0.value().to_vec()
+    }
+
+    pub fn add(&self, other: &JsNode) -> Result<JsNode, JsError> {
+        self.0.try_add(&other.0).map(JsNode).map_err(JsError::from)
+    }
+
+    pub fn sub(&self, other: &JsNode) -> Result<JsNode, JsError> {
+        self.0.try_sub(&other.0).map(JsNode).map_err(JsError::from)
+    }
+
+    pub fn mul(&self, other: &JsNode) -> Result<JsNode, JsError> {
+        self.0.try_mul(&other.0).map(JsNode).map_err(JsError::from)
+    }
+
+    pub fn div(&self, other: &JsNode) -> Result<JsNode, JsError> {
+        self.0.try_div(&other.0).map(JsNode).map_err(JsError::from)
+    }
+
+    pub fn pow(&self, other: &JsNode) -> JsNode {
+        JsNode((&self.0).pow(&other.0))
+    }
+
+    #[wasm_bindgen(js_name = powScalar)]
+    pub fn pow_scalar(&self, c: DType) -> JsNode {
+        JsNode(self.0.pow_scalar(c))
+    }
+
+    pub fn neg(&self) -> JsNode {
+        JsNode(-&self.0)
+    }
+
+    pub fn matmul(&self, other: &JsNode) -> JsNode {
+        JsNode(self.0.matmul(&other.0))
+    }
+
+    pub fn dot(&self, other: &JsNode) -> JsNode {
+        JsNode(self.0.dot(&other.0))
+    }
+
+    pub fn sum(&self) -> JsNode {
+        JsNode(self.0.sum())
+    }
+
+    #[wasm_bindgen(js_name = sumAxis)]
+    pub fn sum_axis(&self, axis: usize) -> JsNode {
+        JsNode(self.0.sum_axis(axis))
+    }
+
+    pub fn reshape(&self, dims: Vec<usize>) -> JsNode {
+        JsNode(self.0.reshape(&dims))
+    }
+
+    pub fn transpose(&self) -> JsNode {
+        JsNode(self.0.transpose())
+    }
+
+    pub fn ln(&self) -> JsNode { JsNode(self.0.ln()) }
+    pub fn exp(&self) -> JsNode { JsNode(self.0.exp()) }
+    pub fn sin(&self) -> JsNode { JsNode(self.0.sin()) }
+    pub fn cos(&self) -> JsNode { JsNode(self.0.cos()) }
+    pub fn tanh(&self) -> JsNode { JsNode(self.0.tanh()) }
+    pub fn relu(&self) -> JsNode { JsNode(self.0.relu()) }
+    pub fn softmax(&self) -> JsNode { JsNode(self.0.softmax()) }
+}
+
+#[wasm_bindgen(js_class = Graph)]
+impl JsGraph {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> JsGraph {
+        JsGraph(Graph::new())
+    }
+
+    pub fn backward(&mut self, end_node: &JsNode) {
+        self.0.backward(&end_node.0);
+    }
+
+    #[wasm_bindgen(js_name = zeroGrads)]
+    pub fn zero_grads(&mut self) {
+        self.0.zero_grads();
+    }
+
+    // Gradients come back flat, in the layout of the node's value; undefined
+    // if the node didn't get one
+    pub fn grad(&self, node: &JsNode) -> Option<Vec<DType>> {
+        self.0.get_grad(&node.0).cloned()
+    }
+}
+
+#[wasm_bindgen]
+pub fn variable(values: Vec<DType>, shape: Option<Vec<usize>>) -> Result<JsNode, JsError> {
+    let dims = dims_of(&values, shape)?;
+    Ok(JsNode(Variable::with_shape(values, &dims)))
+}
+
+#[wasm_bindgen]
+pub fn constant(values: Vec<DType>, shape: Option<Vec<usize>>) -> Result<JsNode, JsError> {
+    let dims = dims_of(&values, shape)?;
+    Ok(JsNode(Constant::with_shape(values, &dims)))
+}
+
+#[wasm_bindgen]
+pub fn scalar(value: DType) -> JsNode {
+    JsNode(Constant::scalar(value))
+}